@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+"""Generates the golden fixtures of the `validation` feature.
+
+The reference scores are computed here in plain Python with double precision, independently of the
+crate, and rounded to f32 when written. Run from the repository root:
+
+    python3 fixtures/generate.py
+
+Fixture format: width, height and channels as little-endian u32s, followed by the values as
+little-endian f32s, row by row with interleaved channels.
+"""
+
+import os
+import struct
+
+OUT = os.path.dirname(os.path.abspath(__file__))
+
+
+class Image:
+    def __init__(self, data, width, height, channels=1):
+        assert len(data) == width * height * channels
+        self.data, self.width, self.height, self.channels = data, width, height, channels
+
+    def pixel(self, x, y):
+        start = (y * self.width + x) * self.channels
+        return self.data[start:start + self.channels]
+
+    def crop(self, x, y, width, height):
+        data = []
+        for row in range(y, y + height):
+            for col in range(x, x + width):
+                data += self.pixel(col, row)
+        return Image(data, width, height, self.channels)
+
+    def map(self, f):
+        return Image([f(v) for v in self.data], self.width, self.height, self.channels)
+
+
+def f32(value):
+    return struct.unpack("<f", struct.pack("<f", value))[0]
+
+
+def f16(value):
+    return struct.unpack("<e", struct.pack("<e", value))[0]
+
+
+def noise(width, height, channels, seed):
+    """Samples on the k/255 grid, so that they survive conversion to u8 and u16 exactly."""
+    state = seed
+    data = []
+    for _ in range(width * height * channels):
+        state = (state * 1103515245 + 12345) % 2**31
+        data.append(f32(((state >> 8) % 256) / 255))
+    return Image(data, width, height, channels)
+
+
+def scores(input, template, score, mask=None):
+    width = input.width - template.width + 1
+    height = input.height - template.height + 1
+    data = []
+    for y in range(height):
+        for x in range(width):
+            total = 0.0
+            for ty in range(template.height):
+                for tx in range(template.width):
+                    if mask is not None and not mask[ty * template.width + tx]:
+                        continue
+                    for i, t in zip(input.pixel(x + tx, y + ty), template.pixel(tx, ty)):
+                        total += score(i, t)
+            data.append(f32(total))
+    return Image(data, width, height)
+
+
+def sad(i, t):
+    return abs(i - t)
+
+
+def ssd(i, t):
+    return (i - t) ** 2
+
+
+def binary(threshold):
+    return lambda i, t: float((i > threshold) != (t > threshold))
+
+
+def save(name, image):
+    with open(os.path.join(OUT, name + ".bin"), "wb") as file:
+        file.write(struct.pack("<3I", image.width, image.height, image.channels))
+        file.write(struct.pack("<%df" % len(image.data), *image.data))
+
+
+def main():
+    gray_input = noise(48, 32, 1, seed=1)
+    # Templates are cut from the input, so that each result has a single perfect match.
+    gray_template = gray_input.crop(17, 9, 8, 6)
+    rgb_input = noise(24, 16, 3, seed=2)
+    rgb_template = rgb_input.crop(11, 7, 5, 4)
+
+    save("gray_input", gray_input)
+    save("gray_template", gray_template)
+    save("rgb_input", rgb_input)
+    save("rgb_template", rgb_template)
+
+    save("gray_sad", scores(gray_input, gray_template, sad))
+    save("gray_ssd", scores(gray_input, gray_template, ssd))
+    save("rgb_sad", scores(rgb_input, rgb_template, sad))
+    save("rgb_ssd", scores(rgb_input, rgb_template, ssd))
+
+    # 32x20 view at (4, 3), and the region (10, 5, 30, 20).
+    save("gray_view_ssd", scores(gray_input.crop(4, 3, 32, 20), gray_template, ssd))
+    save("gray_roi_ssd", scores(gray_input.crop(10, 5, 30, 20), gray_template, ssd))
+
+    # Samples rounded to half precision, as f16 images hold them.
+    save(
+        "gray_f16_ssd",
+        scores(gray_input.map(f16), gray_template.map(f16), ssd),
+    )
+
+    mask = [f32(v) for v in noise(8, 6, 1, seed=3).map(lambda v: float(v > 0.5)).data]
+    save("gray_mask", Image(mask, 8, 6))
+    save("gray_sparse_ssd", scores(gray_input, gray_template, ssd, mask))
+
+    save("gray_binary", scores(gray_input, gray_template, binary(0.5)))
+
+
+if __name__ == "__main__":
+    main()
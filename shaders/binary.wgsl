@@ -0,0 +1,101 @@
+// Matches binarized images. Both images are thresholded into rows of packed bits, a bit per
+// sample, and every position is scored by the number of template samples whose bit differs from
+// the input's, counted 32 samples at a time with XOR and popcount.
+
+struct Binary {
+    threshold: f32,
+    // Words per packed row. Input rows end with an extra zero word, so that a window can always
+    // read the word after the one it starts in.
+    input_words: u32,
+    template_words: u32,
+};
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> binary: Binary;
+
+@group(0)
+@binding(5)
+var<storage, read_write> input_bits: array<u32>;
+
+@group(0)
+@binding(6)
+var<storage, read_write> template_bits: array<u32>;
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_pack_input(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let word = global_id.x;
+    let y = global_id.y;
+    if (word >= binary.input_words || y >= uniforms.input_height) {
+        return;
+    }
+
+    let samples = uniforms.input_width * uniforms.channels;
+    var bits = 0u;
+    for (var i = 0u; i < 32u; i++) {
+        let s = word * 32u + i;
+        if (s < samples && load_input(s / uniforms.channels, y, s % uniforms.channels) > binary.threshold) {
+            bits |= 1u << i;
+        }
+    }
+    input_bits[y * binary.input_words + word] = bits;
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_pack_template(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let word = global_id.x;
+    let y = global_id.y;
+    if (word >= binary.template_words || y >= uniforms.template_height) {
+        return;
+    }
+
+    let samples = uniforms.template_width * uniforms.channels;
+    var bits = 0u;
+    for (var i = 0u; i < 32u; i++) {
+        let s = word * 32u + i;
+        if (s < samples && load_template(s / uniforms.channels, y, s % uniforms.channels) > binary.threshold) {
+            bits |= 1u << i;
+        }
+    }
+    template_bits[y * binary.template_words + word] = bits;
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_binary(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    let result_width = uniforms.input_width - uniforms.template_width + 1u;
+    let result_height = uniforms.input_height - uniforms.template_height + 1u;
+    if (x >= result_width || y >= result_height) {
+        return;
+    }
+
+    let samples = uniforms.template_width * uniforms.channels;
+    var mismatches = 0u;
+    for (var ty = 0u; ty < uniforms.template_height; ty++) {
+        let row = (y + ty) * binary.input_words;
+        for (var k = 0u; k < binary.template_words; k++) {
+            // The 32 input bits under the template's k-th word.
+            let offset = x * uniforms.channels + k * 32u;
+            let word = row + offset / 32u;
+            let shift = offset % 32u;
+            var window = input_bits[word] >> shift;
+            if (shift > 0u) {
+                window |= input_bits[word + 1u] << (32u - shift);
+            }
+
+            let remaining = samples - k * 32u;
+            let mask = select(0xffffffffu, (1u << remaining) - 1u, remaining < 32u);
+            let template_word = template_bits[ty * binary.template_words + k];
+            mismatches += countOneBits((window ^ template_word) & mask);
+        }
+    }
+    result_buf[y * result_width + x] = f32(mismatches);
+}
@@ -0,0 +1,33 @@
+// Checks whether two frames of the same size differ. The current frame is bound as the input and
+// the previous one as the template. Every thread compares one pixel, and raises a flag if any of
+// its samples differs by more than the threshold.
+
+struct Difference {
+    threshold: f32,
+};
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> difference: Difference;
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_difference(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.input_width || y >= uniforms.input_height) {
+        return;
+    }
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        if (abs(load_input(x, y, c) - load_template(x, y, c)) > difference.threshold) {
+            // Every thread that finds a difference writes the same value, so the race is harmless.
+            result_buf[0] = 1.0;
+            return;
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// Finds the smallest and largest scores of a result and their indices in two passes: every
+// workgroup of the first one reduces part of the scores into a partial result, and a single
+// workgroup then reduces the partial results. Ties go to the smaller index, and NaN scores are
+// skipped, like `find_extremes` does on the CPU.
+
+struct Extreme {
+    min_value: f32,
+    min_index: u32,
+    max_value: f32,
+    max_index: u32,
+};
+
+struct Reduction {
+    // Number of scores.
+    len: u32,
+    // Number of partial results written by the first pass.
+    partial_count: u32,
+};
+
+@group(0)
+@binding(0)
+var<storage, read> values: array<f32>;
+
+@group(0)
+@binding(1)
+var<storage, read_write> partials: array<Extreme>;
+
+@group(0)
+@binding(2)
+var<uniform> reduction: Reduction;
+
+@group(0)
+@binding(3)
+var<storage, read_write> extremes: Extreme;
+
+const WORKGROUP_LEN: u32 = 256u;
+
+var<workgroup> shared_extremes: array<Extreme, WORKGROUP_LEN>;
+
+fn empty_extreme() -> Extreme {
+    // Infinity, which `find_extremes` also starts from, so that infinite scores can be extremes.
+    let infinity = bitcast<f32>(0x7f800000u);
+    return Extreme(infinity, 0u, -infinity, 0u);
+}
+
+fn combine(a: Extreme, b: Extreme) -> Extreme {
+    var result = a;
+    if (b.min_value < a.min_value || (b.min_value == a.min_value && b.min_index < a.min_index)) {
+        result.min_value = b.min_value;
+        result.min_index = b.min_index;
+    }
+    if (b.max_value > a.max_value || (b.max_value == a.max_value && b.max_index < a.max_index)) {
+        result.max_value = b.max_value;
+        result.max_index = b.max_index;
+    }
+    return result;
+}
+
+// Combines the extremes of every thread of the workgroup.
+fn reduce_workgroup(local_index: u32, extreme: Extreme) -> Extreme {
+    shared_extremes[local_index] = extreme;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_LEN / 2u; stride > 0u; stride /= 2u) {
+        if (local_index < stride) {
+            shared_extremes[local_index] = combine(
+                shared_extremes[local_index],
+                shared_extremes[local_index + stride]
+            );
+        }
+        workgroupBarrier();
+    }
+
+    return shared_extremes[0];
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn reduce_values(
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    var extreme = empty_extreme();
+    let step = num_workgroups.x * WORKGROUP_LEN;
+    for (var i = workgroup_id.x * WORKGROUP_LEN + local_index; i < reduction.len; i += step) {
+        let value = values[i];
+        extreme = combine(extreme, Extreme(value, i, value, i));
+    }
+
+    let result = reduce_workgroup(local_index, extreme);
+    if (local_index == 0u) {
+        partials[workgroup_id.x] = result;
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn reduce_partials(@builtin(local_invocation_index) local_index: u32) {
+    var extreme = empty_extreme();
+    for (var i = local_index; i < reduction.partial_count; i += WORKGROUP_LEN) {
+        extreme = combine(extreme, partials[i]);
+    }
+
+    let result = reduce_workgroup(local_index, extreme);
+    if (local_index == 0u) {
+        extremes = result;
+    }
+}
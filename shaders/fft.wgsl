@@ -0,0 +1,225 @@
+// Matching with the sum of squared differences in the frequency domain. The score expands into
+// sum(input^2) - 2 * sum(input * template) + sum(template^2). The middle term is a
+// cross-correlation that is computed as a product of spectra, the first one is looked up from an
+// integral image of the input, and the last one is a constant.
+//
+// Images are zero-padded to power-of-two spectra, which are transformed one radix-2 stage at a
+// time. Each pass reads `src` and writes `dst`, with the buffers swapped in between.
+
+struct Fft {
+    // Size of the padded spectra.
+    width: u32,
+    height: u32,
+    // Number of elements in each transformed line, e.g. `width` when transforming rows.
+    n: u32,
+    // Size of the sub-transforms combined by this stage.
+    span: u32,
+    // Distance between consecutive elements of a line, and between the starts of lines.
+    stride: u32,
+    line_stride: u32,
+    lines: u32,
+    // -1 for the forward transform, 1 for the inverse.
+    direction: f32,
+    // What `fft_pack` writes, and the channel it reads.
+    mode: u32,
+    channel: u32,
+    // Factor of the accumulated products, or the scale of the final scores.
+    weight: f32,
+    // Constant added to the final scores.
+    offset: f32,
+    // Value subtracted from the packed images, which keeps the sums small without changing the
+    // differences between them.
+    shift: f32,
+};
+
+const PACK_INPUT: u32 = 0u;
+const PACK_TEMPLATE: u32 = 1u;
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> fft: Fft;
+
+@group(0)
+@binding(5)
+var<storage, read> src: array<vec2<f32>>;
+
+@group(0)
+@binding(6)
+var<storage, read_write> dst: array<vec2<f32>>;
+
+@group(0)
+@binding(7)
+var<storage, read> spectrum: array<vec2<f32>>;
+
+// Sums of the input samples and their squares over all channels of the pixels above and to the
+// left of each position. It has a row and a column of zeros before the input, so it is one larger
+// than the input in both dimensions.
+@group(0)
+@binding(8)
+var<storage, read_write> integral: array<vec2<f32>>;
+
+fn in_input(x: u32, y: u32) -> bool {
+    return x < uniforms.input_width && y < uniforms.input_height;
+}
+
+fn in_template(x: u32, y: u32) -> bool {
+    return x < uniforms.template_width && y < uniforms.template_height;
+}
+
+// Writes one channel of the input or template, zero-padded to the size of the spectra.
+@compute
+@workgroup_size(16, 16, 1)
+fn fft_pack(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    if (x >= fft.width || y >= fft.height) {
+        return;
+    }
+
+    var value = 0.0;
+    if (fft.mode == PACK_INPUT) {
+        if (in_input(x, y)) {
+            value = load_input(x, y, fft.channel) - fft.shift;
+        }
+    } else if (fft.mode == PACK_TEMPLATE) {
+        if (in_template(x, y)) {
+            value = load_template(x, y, fft.channel) - fft.shift;
+        }
+    }
+
+    dst[y * fft.width + x] = vec2<f32>(value, 0.0);
+}
+
+// One stage of a Stockham radix-2 transform of every line.
+@compute
+@workgroup_size(64, 1, 1)
+fn fft_stage(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let half = fft.n / 2u;
+    let j = global_id.x;
+    let line = global_id.y;
+
+    if (j >= half || line >= fft.lines) {
+        return;
+    }
+
+    let base = line * fft.line_stride;
+
+    let a = src[base + j * fft.stride];
+    let b = src[base + (j + half) * fft.stride];
+
+    let k = j % fft.span;
+    let angle = fft.direction * 3.141592653589793 * f32(k) / f32(fft.span);
+    let twiddle = vec2<f32>(cos(angle), sin(angle));
+    let b_twiddled = vec2<f32>(
+        b.x * twiddle.x - b.y * twiddle.y,
+        b.x * twiddle.y + b.y * twiddle.x
+    );
+
+    let out = (j - k) * 2u + k;
+    dst[base + out * fft.stride] = a + b_twiddled;
+    dst[base + (out + fft.span) * fft.stride] = a - b_twiddled;
+}
+
+// Adds `weight` times the cross-correlation spectrum of `src` and `spectrum` to `dst`.
+@compute
+@workgroup_size(16, 16, 1)
+fn fft_accumulate(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    if (x >= fft.width || y >= fft.height) {
+        return;
+    }
+
+    let idx = y * fft.width + x;
+    let a = src[idx];
+    let b = spectrum[idx];
+
+    // a * conj(b)
+    let product = vec2<f32>(a.x * b.x + a.y * b.y, a.y * b.x - a.x * b.y);
+    dst[idx] += fft.weight * product;
+}
+
+// Sums each row of the input into the integral image.
+@compute
+@workgroup_size(64, 1, 1)
+fn fft_integral_rows(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let y = global_id.x;
+    let width = uniforms.input_width + 1u;
+
+    if (y == 0u) {
+        for (var x = 0u; x < width; x++) {
+            integral[x] = vec2<f32>(0.0);
+        }
+    }
+
+    if (y >= uniforms.input_height) {
+        return;
+    }
+
+    let row = (y + 1u) * width;
+    var sum = vec2<f32>(0.0);
+    integral[row] = sum;
+
+    for (var x = 0u; x < uniforms.input_width; x++) {
+        for (var c = 0u; c < uniforms.channels; c++) {
+            let input_val = load_input(x, y, c);
+            sum += vec2<f32>(input_val, input_val * input_val);
+        }
+        integral[row + x + 1u] = sum;
+    }
+}
+
+// Sums the row sums down each column of the integral image.
+@compute
+@workgroup_size(64, 1, 1)
+fn fft_integral_columns(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let width = uniforms.input_width + 1u;
+
+    if (x >= width) {
+        return;
+    }
+
+    var sum = vec2<f32>(0.0);
+    for (var y = 1u; y <= uniforms.input_height; y++) {
+        sum += integral[y * width + x];
+        integral[y * width + x] = sum;
+    }
+}
+
+// Writes the scaled real part of the inverse transform, plus the energy of the input under the
+// template and the constant term, as the scores.
+@compute
+@workgroup_size(16, 16, 1)
+fn fft_finalize(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    let result_width = uniforms.input_width - uniforms.template_width + 1u;
+    let result_height = uniforms.input_height - uniforms.template_height + 1u;
+
+    if (x >= result_width || y >= result_height) {
+        return;
+    }
+
+    // Sums of the input and its squares under the template, from which the energy of the input
+    // minus the shift follows.
+    let width = uniforms.input_width + 1u;
+    let right = x + uniforms.template_width;
+    let bottom = y + uniforms.template_height;
+    let window = integral[bottom * width + right] - integral[y * width + right]
+        - integral[bottom * width + x] + integral[y * width + x];
+    let samples = f32(uniforms.template_width * uniforms.template_height * uniforms.channels);
+    let energy = window.y - 2.0 * fft.shift * window.x + samples * fft.shift * fft.shift;
+
+    let score = src[y * fft.width + x].x * fft.weight + energy + fft.offset;
+
+    // Rounding errors can make near-perfect matches slightly negative.
+    result_buf[y * result_width + x] = max(score, 0.0);
+}
@@ -0,0 +1,164 @@
+// Estimates the rotation and scale of the template at candidate windows of the input. The
+// template and each window are resampled on log-polar grids, on which a rotation is a circular
+// shift along the angles and a scaling is a shift along the rings. Each workgroup resamples one
+// window and finds the shift that best matches the template's grid.
+
+struct Search {
+    first_shift: i32,
+    shift_count: u32,
+    stride: u32,
+    origin_x: u32,
+    origin_y: u32,
+    candidates_x: u32,
+    candidates_y: u32,
+    min_radius: f32,
+    ring_base: f32,
+};
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> search: Search;
+
+const WORKGROUP_LEN: u32 = 64u;
+const PI: f32 = 3.141592653589793;
+
+var<workgroup> template_grid: array<f32, TEMPLATE_GRID_LEN>;
+var<workgroup> window_grid: array<f32, WINDOW_GRID_LEN>;
+var<workgroup> best_scores: array<f32, WORKGROUP_LEN>;
+var<workgroup> best_shifts: array<u32, WORKGROUP_LEN>;
+
+fn clamp_index(value: f32, size: u32) -> u32 {
+    return u32(clamp(i32(value), 0, i32(size) - 1));
+}
+
+// Offset of the sample at the given ring and angle from the center of the grid.
+fn grid_offset(ring: i32, angle: u32) -> vec2<f32> {
+    let radius = search.min_radius * pow(search.ring_base, f32(ring));
+    let phi = f32(angle) * 2.0 * PI / f32(ANGLES);
+    return radius * vec2<f32>(cos(phi), sin(phi)) - 0.5;
+}
+
+fn sample_input(position: vec2<f32>) -> f32 {
+    let corner = floor(position);
+    let f = position - corner;
+    let x0 = clamp_index(corner.x, uniforms.input_width);
+    let x1 = clamp_index(corner.x + 1.0, uniforms.input_width);
+    let y0 = clamp_index(corner.y, uniforms.input_height);
+    let y1 = clamp_index(corner.y + 1.0, uniforms.input_height);
+
+    var sum = 0.0;
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let upper = mix(load_input(x0, y0, c), load_input(x1, y0, c), f.x);
+        let lower = mix(load_input(x0, y1, c), load_input(x1, y1, c), f.x);
+        sum += mix(upper, lower, f.y);
+    }
+    return sum / f32(uniforms.channels);
+}
+
+fn sample_template(position: vec2<f32>) -> f32 {
+    let corner = floor(position);
+    let f = position - corner;
+    let x0 = clamp_index(corner.x, uniforms.template_width);
+    let x1 = clamp_index(corner.x + 1.0, uniforms.template_width);
+    let y0 = clamp_index(corner.y, uniforms.template_height);
+    let y1 = clamp_index(corner.y + 1.0, uniforms.template_height);
+
+    var sum = 0.0;
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let upper = mix(load_template(x0, y0, c), load_template(x1, y0, c), f.x);
+        let lower = mix(load_template(x0, y1, c), load_template(x1, y1, c), f.x);
+        sum += mix(upper, lower, f.y);
+    }
+    return sum / f32(uniforms.channels);
+}
+
+fn log_polar(local_index: u32, workgroup_id: vec3<u32>, squared: bool) {
+    let candidate = workgroup_id.y * search.candidates_x + workgroup_id.x;
+    let window_center = vec2<f32>(
+        f32(search.origin_x + workgroup_id.x * search.stride),
+        f32(search.origin_y + workgroup_id.y * search.stride)
+    );
+    let template_center = vec2<f32>(f32(uniforms.template_width), f32(uniforms.template_height)) * 0.5;
+
+    for (var i = local_index; i < RINGS * ANGLES; i += WORKGROUP_LEN) {
+        template_grid[i] = sample_template(template_center + grid_offset(i32(i / ANGLES), i % ANGLES));
+    }
+    let window_rings = RINGS + search.shift_count - 1u;
+    for (var i = local_index; i < window_rings * ANGLES; i += WORKGROUP_LEN) {
+        let ring = search.first_shift + i32(i / ANGLES);
+        window_grid[i] = sample_input(window_center + grid_offset(ring, i % ANGLES));
+    }
+    workgroupBarrier();
+
+    // Each thread scores every WORKGROUP_LEN-th combination of a ring shift and an angle.
+    var best_score = bitcast<f32>(0x7f800000u);
+    var best_shift = 0u;
+    for (var k = local_index; k < search.shift_count * ANGLES; k += WORKGROUP_LEN) {
+        let shift = k / ANGLES;
+        let angle = k % ANGLES;
+
+        var total_sum = 0.0;
+        for (var ring = 0u; ring < RINGS; ring++) {
+            for (var j = 0u; j < ANGLES; j++) {
+                let diff = window_grid[(ring + shift) * ANGLES + (j + angle) % ANGLES]
+                    - template_grid[ring * ANGLES + j];
+
+                if (squared) {
+                    total_sum += diff * diff;
+                } else {
+                    total_sum += abs(diff);
+                }
+            }
+        }
+
+        if (total_sum < best_score) {
+            best_score = total_sum;
+            best_shift = k;
+        }
+    }
+
+    // Ties go to the smaller shift, like on the CPU.
+    best_scores[local_index] = best_score;
+    best_shifts[local_index] = best_shift;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_LEN / 2u; stride > 0u; stride /= 2u) {
+        if (local_index < stride) {
+            let other = local_index + stride;
+            if (best_scores[other] < best_scores[local_index]
+                || (best_scores[other] == best_scores[local_index]
+                    && best_shifts[other] < best_shifts[local_index])) {
+                best_scores[local_index] = best_scores[other];
+                best_shifts[local_index] = best_shifts[other];
+            }
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        result_buf[candidate * 2u] = best_scores[0];
+        result_buf[candidate * 2u + 1u] = f32(best_shifts[0]);
+    }
+}
+
+@compute
+@workgroup_size(64, 1, 1)
+fn main_sad_log_polar(
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    log_polar(local_index, workgroup_id, false);
+}
+
+@compute
+@workgroup_size(64, 1, 1)
+fn main_ssd_log_polar(
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    log_polar(local_index, workgroup_id, true);
+}
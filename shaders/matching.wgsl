@@ -1,90 +1,132 @@
-struct Uniforms {
-    input_width: u32,
-    input_height: u32,
-    template_width: u32,
-    template_height: u32,
-};
-
-@group(0)
-@binding(0)
-var<storage, read> input_buf: array<f32>;
-
-@group(0)
-@binding(1)
-var<storage, read> template_buf: array<f32>;
-
 @group(0)
 @binding(2)
 var<storage, read_write> result_buf: array<f32>;
 
-@group(0)
-@binding(3)
-var<uniform> uniforms: Uniforms;
-
-@compute
-@workgroup_size(16, 16, 1)
-fn main_sad(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    var x = global_id.x;
-    var y = global_id.y;
+// The workgroup size of the kernels is replaced with the one tuned for the device when the shader
+// is built, and the `WORKGROUP_*`, `TILE_*` and `INPUT_TILE_*` constants are declared to match it.
+//
+// Each workgroup computes a block of results. The template is processed in tiles of
+// `TILE_WIDTH` x `TILE_HEIGHT` one channel at a time: the workgroup first loads the template tile
+// and the part of the input that its results cover into shared memory, and then every thread
+// reads its window from there instead of from the input and template themselves.
+
+var<workgroup> input_tile: array<f32, INPUT_TILE_LEN>;
+var<workgroup> template_tile: array<f32, TEMPLATE_TILE_LEN>;
+
+// Loads channel `c` of the template tile at `(tile_x, tile_y)` and of the input below it into
+// shared memory. Pixels outside the images are loaded as zero.
+fn load_tiles(
+    local_index: u32,
+    block: vec2<u32>,
+    tile_x: u32,
+    tile_y: u32,
+    c: u32,
+) {
+    let template_width = template_size().x;
+    let template_height = template_size().y;
+
+    for (var k = local_index; k < INPUT_TILE_LEN; k += WORKGROUP_LEN) {
+        let x = block.x + tile_x + k % INPUT_TILE_WIDTH;
+        let y = block.y + tile_y + k / INPUT_TILE_WIDTH;
+
+        var value = 0.0;
+        if (x < uniforms.input_width && y < uniforms.input_height) {
+            value = load_input(x, y, c);
+        }
+        input_tile[k] = value;
+    }
 
-    var input_width = uniforms.input_width;
-    var input_height = uniforms.input_height;
+    for (var k = local_index; k < TEMPLATE_TILE_LEN; k += WORKGROUP_LEN) {
+        let x = tile_x + k % TILE_WIDTH;
+        let y = tile_y + k / TILE_WIDTH;
 
-    var template_width = uniforms.template_width;
-    var template_height = uniforms.template_height;
+        var value = 0.0;
+        if (x < template_width && y < template_height) {
+            value = load_template(x, y, c);
+        }
+        template_tile[k] = value;
+    }
+}
 
-    var match_width = min(template_width, input_width - x);
-    var match_height = min(template_height, input_height - y);
+// Sums the absolute or squared differences between the template and the window of the input at
+// the result of this thread. Every thread of the workgroup must call this, including the ones
+// outside the result, since they help load the tiles.
+fn window_sum(
+    local_id: vec3<u32>,
+    local_index: u32,
+    workgroup_id: vec3<u32>,
+    squared: bool,
+) -> f32 {
+    let template_width = template_size().x;
+    let template_height = template_size().y;
+
+    let block = workgroup_id.xy * vec2<u32>(WORKGROUP_WIDTH, WORKGROUP_HEIGHT);
+    input_offset = workgroup_id.z * uniforms.input_batch_stride;
 
     var total_sum = 0.0;
-    for (var i = 0u; i < match_width; i++) {
-        for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+    for (var tile_y = 0u; tile_y < template_height; tile_y += TILE_HEIGHT) {
+        for (var tile_x = 0u; tile_x < template_width; tile_x += TILE_WIDTH) {
+            let tile_width = min(TILE_WIDTH, template_width - tile_x);
+            let tile_height = min(TILE_HEIGHT, template_height - tile_y);
+
+            for (var c = 0u; c < uniforms.channels; c++) {
+                workgroupBarrier();
+                load_tiles(local_index, block, tile_x, tile_y, c);
+                workgroupBarrier();
+
+                for (var j = 0u; j < tile_height; j++) {
+                    let input_row = (local_id.y + j) * INPUT_TILE_WIDTH + local_id.x;
+                    let template_row = j * TILE_WIDTH;
+
+                    for (var i = 0u; i < tile_width; i++) {
+                        let diff = sample_difference(input_tile[input_row + i], template_tile[template_row + i]);
+
+                        if (squared) {
+                            total_sum += diff * diff;
+                        } else {
+                            total_sum += abs(diff);
+                        }
+                    }
+                }
+            }
+        }
+    }
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+    return total_sum;
+}
 
-            var diff = abs(input_val - template_val);
+fn store_result(global_id: vec3<u32>, total_sum: f32) {
+    let result_width = uniforms.input_width - template_size().x + 1u;
+    let result_height = uniforms.input_height - template_size().y + 1u;
 
-            total_sum += diff;
-        }
+    if (global_id.x >= result_width || global_id.y >= result_height) {
+        return;
     }
 
-    var result_idx = y * (input_width - template_width + 1u) + x;
+    let result_idx = (global_id.z * result_height + global_id.y) * result_width + global_id.x;
     result_buf[result_idx] = total_sum;
 }
 
 @compute
 @workgroup_size(16, 16, 1)
-fn main_ssd(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    var x = global_id.x;
-    var y = global_id.y;
-
-    var input_width = uniforms.input_width;
-    var input_height = uniforms.input_height;
-
-    var template_width = uniforms.template_width;
-    var template_height = uniforms.template_height;
-
-    var match_width = min(template_width, input_width - x);
-    var match_height = min(template_height, input_height - y);
-
-    var total_sum = 0.0;
-    for (var i = 0u; i < match_width; i++) {
-        for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
-
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
-
-            var sqdiff = pow(input_val - template_val, 2.0);
-
-            total_sum += sqdiff;
-        }
-    }
+fn main_sad(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    let total_sum = window_sum(local_id, local_index, workgroup_id, false);
+    store_result(global_id, total_sum);
+}
 
-    var result_idx = y * (input_width - template_width + 1u) + x;
-    result_buf[result_idx] = total_sum;
+@compute
+@workgroup_size(16, 16, 1)
+fn main_ssd(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    let total_sum = window_sum(local_id, local_index, workgroup_id, true);
+    store_result(global_id, total_sum);
 }
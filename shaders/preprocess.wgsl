@@ -0,0 +1,386 @@
+// Preprocessing steps that run before matching. Each reads the image bound as the input and
+// writes the processed image, whose size the uniforms give as the template's, tightly packed into
+// the output buffer. The uniforms give the input's number of channels, which the output has too
+// unless the step converts to a single channel. Most passes run a thread per pixel of the output, while passes that gather
+// statistics of the whole image run as a single workgroup.
+
+@group(0)
+@binding(2)
+var<storage, read_write> output_buf: array<f32>;
+
+struct Parameters {
+    values: vec4<f32>,
+};
+
+@group(0)
+@binding(4)
+var<uniform> parameters: Parameters;
+
+// Values that passes keep for the following passes of their step, or that the background step
+// keeps across calls.
+@group(0)
+@binding(5)
+var<storage, read_write> statistics_buf: array<f32>;
+
+fn store_output(x: u32, y: u32, c: u32, value: f32) {
+    output_buf[(y * uniforms.template_width + x) * uniforms.channels + c] = value;
+}
+
+// Converts color to luma with the Rec. 601 weights, or copies a single channel.
+@compute
+@workgroup_size(16, 16, 1)
+fn main_grayscale(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    var luma = load_input(x, y, 0u);
+    if (uniforms.channels >= 3u) {
+        let color = vec3<f32>(luma, load_input(x, y, 1u), load_input(x, y, 2u));
+        luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    }
+    output_buf[y * uniforms.template_width + x] = luma;
+}
+
+// Ratio of the input's size to the output's.
+fn resize_scale() -> vec2<f32> {
+    return vec2<f32>(
+        f32(uniforms.input_width) / f32(uniforms.template_width),
+        f32(uniforms.input_height) / f32(uniforms.template_height)
+    );
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_resize_bilinear(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    // Samples the input at the center of the output pixel.
+    let last = vec2<u32>(uniforms.input_width, uniforms.input_height) - 1u;
+    let position = clamp(
+        (vec2<f32>(f32(x), f32(y)) + 0.5) * resize_scale() - 0.5,
+        vec2<f32>(0.0),
+        vec2<f32>(last)
+    );
+    let first = vec2<u32>(position);
+    let second = min(first + 1u, last);
+    let f = fract(position);
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let upper = mix(load_input(first.x, first.y, c), load_input(second.x, first.y, c), f.x);
+        let lower = mix(load_input(first.x, second.y, c), load_input(second.x, second.y, c), f.x);
+        store_output(x, y, c, mix(upper, lower, f.y));
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_resize_area(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    // Averages the input pixels that the output pixel covers, weighed by the covered area.
+    let scale = resize_scale();
+    let start = vec2<f32>(f32(x), f32(y)) * scale;
+    let end = min(
+        (vec2<f32>(f32(x), f32(y)) + 1.0) * scale,
+        vec2<f32>(f32(uniforms.input_width), f32(uniforms.input_height))
+    );
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        var sum = 0.0;
+        var weight = 0.0;
+        for (var sy = u32(start.y); f32(sy) < end.y; sy++) {
+            let wy = min(end.y, f32(sy) + 1.0) - max(start.y, f32(sy));
+            for (var sx = u32(start.x); f32(sx) < end.x; sx++) {
+                let wx = min(end.x, f32(sx) + 1.0) - max(start.x, f32(sx));
+                sum += wx * wy * load_input(sx, sy, c);
+                weight += wx * wy;
+            }
+        }
+        store_output(x, y, c, sum / weight);
+    }
+}
+
+// Global normalization, in two passes: a single workgroup computes the mean and standard deviation
+// of each channel into the statistics, and then every pixel is normalized with them.
+
+const WORKGROUP_LEN: u32 = 256u;
+
+var<workgroup> partial_sums: array<f32, 256>;
+
+// Sums a value over the workgroup and returns the total to every thread.
+fn workgroup_sum(local_index: u32, value: f32) -> f32 {
+    partial_sums[local_index] = value;
+    workgroupBarrier();
+    for (var stride = WORKGROUP_LEN / 2u; stride > 0u; stride /= 2u) {
+        if (local_index < stride) {
+            partial_sums[local_index] += partial_sums[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+    let total = partial_sums[0];
+    workgroupBarrier();
+    return total;
+}
+
+fn normalized(value: f32, mean: f32, deviation: f32) -> f32 {
+    return select(0.0, (value - mean) / deviation, deviation > 0.0);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_normalize_statistics(@builtin(local_invocation_index) local_index: u32) {
+    let pixel_count = uniforms.input_width * uniforms.input_height;
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        var sum = 0.0;
+        for (var pixel = local_index; pixel < pixel_count; pixel += WORKGROUP_LEN) {
+            sum += load_input(pixel % uniforms.input_width, pixel / uniforms.input_width, c);
+        }
+        let mean = workgroup_sum(local_index, sum) / f32(pixel_count);
+
+        var squares = 0.0;
+        for (var pixel = local_index; pixel < pixel_count; pixel += WORKGROUP_LEN) {
+            let d = load_input(pixel % uniforms.input_width, pixel / uniforms.input_width, c) - mean;
+            squares += d * d;
+        }
+        let deviation = sqrt(workgroup_sum(local_index, squares) / f32(pixel_count));
+
+        if (local_index == 0u) {
+            statistics_buf[2u * c] = mean;
+            statistics_buf[2u * c + 1u] = deviation;
+        }
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_normalize(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let mean = statistics_buf[2u * c];
+        let deviation = statistics_buf[2u * c + 1u];
+        store_output(x, y, c, normalized(load_input(x, y, c), mean, deviation));
+    }
+}
+
+// Normalizes every pixel with the statistics of the window around it, whose radius is the first
+// parameter value.
+@compute
+@workgroup_size(16, 16, 1)
+fn main_normalize_local(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    let radius = u32(parameters.values.x);
+    let left = max(x, radius) - radius;
+    let top = max(y, radius) - radius;
+    let right = min(x + radius, uniforms.input_width - 1u);
+    let bottom = min(y + radius, uniforms.input_height - 1u);
+    let count = f32((right - left + 1u) * (bottom - top + 1u));
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        var sum = 0.0;
+        for (var wy = top; wy <= bottom; wy++) {
+            for (var wx = left; wx <= right; wx++) {
+                sum += load_input(wx, wy, c);
+            }
+        }
+        let mean = sum / count;
+
+        var squares = 0.0;
+        for (var wy = top; wy <= bottom; wy++) {
+            for (var wx = left; wx <= right; wx++) {
+                let d = load_input(wx, wy, c) - mean;
+                squares += d * d;
+            }
+        }
+        store_output(x, y, c, normalized(load_input(x, y, c), mean, sqrt(squares / count)));
+    }
+}
+
+// Histogram equalization, in two passes: a single workgroup counts the histogram of each channel
+// and turns its cumulative counts into a lookup table in the statistics, and then every sample
+// is mapped through the table of its channel.
+
+const HISTOGRAM_BINS: u32 = 256u;
+
+var<workgroup> histogram: array<atomic<u32>, 256>;
+
+fn histogram_bin(value: f32) -> u32 {
+    return min(u32(clamp(value, 0.0, 1.0) * f32(HISTOGRAM_BINS)), HISTOGRAM_BINS - 1u);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_equalize_histogram(@builtin(local_invocation_index) local_index: u32) {
+    let pixel_count = uniforms.input_width * uniforms.input_height;
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        // The workgroup has a thread per bin.
+        atomicStore(&histogram[local_index], 0u);
+        workgroupBarrier();
+
+        for (var pixel = local_index; pixel < pixel_count; pixel += WORKGROUP_LEN) {
+            let value = load_input(pixel % uniforms.input_width, pixel / uniforms.input_width, c);
+            atomicAdd(&histogram[histogram_bin(value)], 1u);
+        }
+        workgroupBarrier();
+
+        if (local_index == 0u) {
+            var cumulative = 0u;
+            var first = 0u;
+            for (var i = 0u; i < HISTOGRAM_BINS; i++) {
+                cumulative += atomicLoad(&histogram[i]);
+                if (first == 0u) {
+                    first = cumulative;
+                }
+                // Stored as counts for now, as the range isn't known until the last bin.
+                statistics_buf[c * HISTOGRAM_BINS + i] = f32(cumulative);
+            }
+
+            let range = f32(cumulative - first);
+            for (var i = 0u; i < HISTOGRAM_BINS; i++) {
+                let count = statistics_buf[c * HISTOGRAM_BINS + i];
+                statistics_buf[c * HISTOGRAM_BINS + i] =
+                    select(0.0, (count - f32(first)) / range, range > 0.0);
+            }
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_map_histogram(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let bin = histogram_bin(load_input(x, y, c));
+        store_output(x, y, c, statistics_buf[c * HISTOGRAM_BINS + bin]);
+    }
+}
+
+// Loads a sample of the input with its edges extended.
+fn load_clamped(x: i32, y: i32, c: u32) -> f32 {
+    let last = vec2<i32>(i32(uniforms.input_width), i32(uniforms.input_height)) - 1;
+    let position = clamp(vec2<i32>(x, y), vec2<i32>(0), last);
+    return load_input(u32(position.x), u32(position.y), c);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_sobel(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= uniforms.template_width || global_id.y >= uniforms.template_height) {
+        return;
+    }
+    let x = i32(global_id.x);
+    let y = i32(global_id.y);
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let gx = load_clamped(x + 1, y - 1, c) + 2.0 * load_clamped(x + 1, y, c)
+            + load_clamped(x + 1, y + 1, c) - load_clamped(x - 1, y - 1, c)
+            - 2.0 * load_clamped(x - 1, y, c) - load_clamped(x - 1, y + 1, c);
+        let gy = load_clamped(x - 1, y + 1, c) + 2.0 * load_clamped(x, y + 1, c)
+            + load_clamped(x + 1, y + 1, c) - load_clamped(x - 1, y - 1, c)
+            - 2.0 * load_clamped(x, y - 1, c) - load_clamped(x + 1, y - 1, c);
+        store_output(global_id.x, global_id.y, c, sqrt(gx * gx + gy * gy));
+    }
+}
+
+// Maps every sample through a power curve, with the gamma, gain and offset as the parameter
+// values.
+@compute
+@workgroup_size(16, 16, 1)
+fn main_tone(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    let tone = parameters.values;
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let value = pow(max(load_input(x, y, c), 0.0), tone.x);
+        store_output(x, y, c, tone.y * value + tone.z);
+    }
+}
+
+// Smooths every pixel with a Gaussian filter whose standard deviation is the first parameter
+// value, cut off at three standard deviations.
+@compute
+@workgroup_size(16, 16, 1)
+fn main_blur(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= uniforms.template_width || global_id.y >= uniforms.template_height) {
+        return;
+    }
+    let x = i32(global_id.x);
+    let y = i32(global_id.y);
+
+    let sigma = parameters.values.x;
+    let radius = i32(ceil(3.0 * sigma));
+    var total = 0.0;
+    for (var d = -radius; d <= radius; d++) {
+        total += exp(-f32(d * d) / (2.0 * sigma * sigma));
+    }
+
+    for (var c = 0u; c < uniforms.channels; c++) {
+        var sum = 0.0;
+        for (var dy = -radius; dy <= radius; dy++) {
+            let wy = exp(-f32(dy * dy) / (2.0 * sigma * sigma));
+            for (var dx = -radius; dx <= radius; dx++) {
+                let wx = exp(-f32(dx * dx) / (2.0 * sigma * sigma));
+                sum += wx * wy * load_clamped(x + dx, y + dy, c);
+            }
+        }
+        store_output(global_id.x, global_id.y, c, sum / (total * total));
+    }
+}
+
+// Subtracts the running background, bound as the statistics, and moves it towards the input by
+// the rate in the first parameter value. A nonzero second value starts the background from the
+// input.
+@compute
+@workgroup_size(16, 16, 1)
+fn main_subtract_background(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if (x >= uniforms.template_width || y >= uniforms.template_height) {
+        return;
+    }
+
+    let rate = parameters.values.x;
+    let start = parameters.values.y != 0.0;
+    for (var c = 0u; c < uniforms.channels; c++) {
+        let index = (y * uniforms.template_width + x) * uniforms.channels + c;
+        let value = load_input(x, y, c);
+        if (start) {
+            statistics_buf[index] = value;
+        }
+        let foreground = value - statistics_buf[index];
+        statistics_buf[index] += rate * foreground;
+        store_output(x, y, c, foreground);
+    }
+}
@@ -0,0 +1,71 @@
+// Matching that gives up on positions that can't beat the best score found so far, for when only
+// the best match is needed. Scores are non-negative, so their bit patterns compare like the
+// floats themselves and the best one can be kept with an atomic minimum.
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+// Bit pattern of the best score so far, initialized to the bound given by the caller.
+@group(0)
+@binding(4)
+var<storage, read_write> best: atomic<u32>;
+
+fn pruned_sum(x: u32, y: u32, squared: bool) -> f32 {
+    let template_width = template_size().x;
+    let template_height = template_size().y;
+
+    var total_sum = 0.0;
+    for (var j = 0u; j < template_height; j++) {
+        for (var i = 0u; i < template_width; i++) {
+            for (var c = 0u; c < uniforms.channels; c++) {
+                let diff = sample_difference(load_input(x + i, y + j, c), load_template(i, j, c));
+
+                if (squared) {
+                    total_sum += diff * diff;
+                } else {
+                    total_sum += abs(diff);
+                }
+            }
+        }
+
+        // Checked once per row to keep the atomic loads rare. The partial sum only grows, so
+        // the position can't become the best one anymore.
+        if (total_sum > bitcast<f32>(atomicLoad(&best))) {
+            return total_sum;
+        }
+    }
+
+    // Deterministic matching only prunes with the bound given by the caller, since the best score
+    // so far depends on the order that positions happen to finish in.
+    if (uniforms.deterministic == 0u) {
+        atomicMin(&best, bitcast<u32>(total_sum));
+    }
+    return total_sum;
+}
+
+fn main_pruned(global_id: vec3<u32>, squared: bool) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    let result_width = uniforms.input_width - template_size().x + 1u;
+    let result_height = uniforms.input_height - template_size().y + 1u;
+
+    if (x >= result_width || y >= result_height) {
+        return;
+    }
+
+    result_buf[y * result_width + x] = pruned_sum(x, y, squared);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_sad_pruned(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_pruned(global_id, false);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_ssd_pruned(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_pruned(global_id, true);
+}
@@ -0,0 +1,115 @@
+// Matching of images quantized to u8, which are read four samples at a time from packed words and
+// compared with integer arithmetic. Rows of the input and template can start at any byte, so each
+// word is assembled from the two words it overlaps. Scores are scaled to match those of the
+// normalized samples that the other kernels compute.
+
+@group(0)
+@binding(0)
+var<storage, read> input_buf: array<u32>;
+
+@group(0)
+@binding(1)
+var<storage, read> template_buf: array<u32>;
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+// Joins the bytes of `low` from `shift` bits on with the lowest bytes of `high`.
+fn join_words(low: u32, high: u32, shift: u32) -> u32 {
+    if (shift == 0u) {
+        return low;
+    }
+    return (low >> shift) | (high << (32u - shift));
+}
+
+// The four input samples that start at the given byte.
+fn input_word(byte: u32) -> u32 {
+    let index = byte / 4u;
+    var high = 0u;
+    if (index + 1u < arrayLength(&input_buf)) {
+        high = input_buf[index + 1u];
+    }
+    return join_words(input_buf[index], high, (byte % 4u) * 8u);
+}
+
+// The four template samples that start at the given byte.
+fn template_word(byte: u32) -> u32 {
+    let index = byte / 4u;
+    var high = 0u;
+    if (index + 1u < arrayLength(&template_buf)) {
+        high = template_buf[index + 1u];
+    }
+    return join_words(template_buf[index], high, (byte % 4u) * 8u);
+}
+
+// Sum of the absolute or squared differences between the bytes of two words.
+fn word_sum(a: u32, b: u32, squared: bool) -> u32 {
+    var sum = 0u;
+    for (var shift = 0u; shift < 32u; shift += 8u) {
+        let diff = u32(abs(i32((a >> shift) & 0xffu) - i32((b >> shift) & 0xffu)));
+
+        if (squared) {
+            sum += diff * diff;
+        } else {
+            sum += diff;
+        }
+    }
+    return sum;
+}
+
+fn quantized_sum(x: u32, y: u32, squared: bool) -> f32 {
+    let row_len = uniforms.template_width * uniforms.channels;
+
+    var total_sum = 0.0;
+    for (var j = 0u; j < uniforms.template_height; j++) {
+        let input_start = (y + j) * uniforms.input_stride + x * uniforms.channels;
+        let template_start = j * uniforms.template_stride;
+
+        // Summed as integers within a row, which can't overflow for any row that fits in a buffer.
+        var row_sum = 0u;
+        for (var i = 0u; i < row_len; i += 4u) {
+            // The bytes past the end of the row are masked out of both words.
+            var mask = 0xffffffffu;
+            if (row_len - i < 4u) {
+                mask = (1u << ((row_len - i) * 8u)) - 1u;
+            }
+
+            let a = input_word(input_start + i) & mask;
+            let b = template_word(template_start + i) & mask;
+            row_sum += word_sum(a, b, squared);
+        }
+        total_sum += f32(row_sum);
+    }
+
+    if (squared) {
+        return total_sum / (255.0 * 255.0);
+    }
+    return total_sum / 255.0;
+}
+
+fn main_quantized(global_id: vec3<u32>, squared: bool) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    let result_width = uniforms.input_width - uniforms.template_width + 1u;
+    let result_height = uniforms.input_height - uniforms.template_height + 1u;
+
+    if (x >= result_width || y >= result_height) {
+        return;
+    }
+
+    result_buf[y * result_width + x] = quantized_sum(x, y, squared);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_sad_quantized(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_quantized(global_id, false);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_ssd_quantized(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_quantized(global_id, true);
+}
@@ -0,0 +1,186 @@
+// Refines a match into an affine alignment with Lucas–Kanade style Gauss–Newton steps on the sum
+// of squared differences. A single workgroup accumulates the Hessian and the steepest descent
+// image over the template's pixels, and its first thread solves for each step. The template's
+// pixel p is compared to the input at A * (p - c) + t, where c is the template's center.
+
+struct Refinement {
+    x: f32,
+    y: f32,
+    max_iterations: u32,
+};
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> refinement: Refinement;
+
+const WORKGROUP_LEN: u32 = 64u;
+// Upper triangle of the 6x6 Hessian, the right-hand side and the squared error.
+const SUMS_LEN: u32 = 28u;
+const CONVERGED: f32 = 0.01;
+
+var<workgroup> sums: array<f32, 1792>;
+// A00, A10, A01, A11, t_x and t_y.
+var<workgroup> params: array<f32, 6>;
+var<workgroup> done: u32;
+var<workgroup> iterations: u32;
+
+fn clamp_index(value: f32, size: u32) -> u32 {
+    return u32(clamp(i32(value), 0, i32(size) - 1));
+}
+
+fn sample_input(position: vec2<f32>, c: u32) -> f32 {
+    let corner = floor(position);
+    let f = position - corner;
+    let x0 = clamp_index(corner.x, uniforms.input_width);
+    let x1 = clamp_index(corner.x + 1.0, uniforms.input_width);
+    let y0 = clamp_index(corner.y, uniforms.input_height);
+    let y1 = clamp_index(corner.y + 1.0, uniforms.input_height);
+
+    let upper = mix(load_input(x0, y0, c), load_input(x1, y0, c), f.x);
+    let lower = mix(load_input(x0, y1, c), load_input(x1, y1, c), f.x);
+    return mix(upper, lower, f.y);
+}
+
+// Solves the system accumulated in the first SUMS_LEN sums and applies the step to the
+// parameters, or marks the refinement done if it is singular or the step is small enough.
+fn gauss_newton_step() {
+    var m: array<array<f32, 7>, 6>;
+    var k = 0u;
+    for (var row = 0u; row < 6u; row++) {
+        for (var col = row; col < 6u; col++) {
+            m[row][col] = sums[k];
+            m[col][row] = sums[k];
+            k++;
+        }
+        m[row][6] = sums[21u + row];
+    }
+
+    for (var col = 0u; col < 6u; col++) {
+        var pivot = col;
+        for (var row = col + 1u; row < 6u; row++) {
+            if (abs(m[row][col]) > abs(m[pivot][col])) {
+                pivot = row;
+            }
+        }
+        if (abs(m[pivot][col]) < 1e-6) {
+            done = 1u;
+            return;
+        }
+        let swapped = m[col];
+        m[col] = m[pivot];
+        m[pivot] = swapped;
+
+        for (var row = col + 1u; row < 6u; row++) {
+            let factor = m[row][col] / m[col][col];
+            for (var i = col; i < 7u; i++) {
+                m[row][i] -= factor * m[col][i];
+            }
+        }
+    }
+
+    var delta: array<f32, 6>;
+    for (var r = 0u; r < 6u; r++) {
+        let row = 5u - r;
+        var sum = 0.0;
+        for (var i = row + 1u; i < 6u; i++) {
+            sum += m[row][i] * delta[i];
+        }
+        delta[row] = (m[row][6] - sum) / m[row][row];
+    }
+
+    for (var i = 0u; i < 6u; i++) {
+        params[i] -= delta[i];
+    }
+    iterations++;
+
+    let radius = f32(max(uniforms.template_width, uniforms.template_height)) * 0.5;
+    let linear = abs(delta[0]) + abs(delta[1]) + abs(delta[2]) + abs(delta[3]);
+    if (max(abs(delta[4]), abs(delta[5])) + linear * radius < CONVERGED) {
+        done = 1u;
+    }
+}
+
+@compute
+@workgroup_size(64, 1, 1)
+fn main_refine(@builtin(local_invocation_index) local_index: u32) {
+    let center = vec2<f32>(
+        f32(uniforms.template_width) - 1.0,
+        f32(uniforms.template_height) - 1.0
+    ) * 0.5;
+
+    if (local_index == 0u) {
+        params = array<f32, 6>(1.0, 0.0, 0.0, 1.0, refinement.x + center.x, refinement.y + center.y);
+        done = 0u;
+        iterations = 0u;
+    }
+    workgroupBarrier();
+
+    let pixel_count = uniforms.template_width * uniforms.template_height;
+    let base = local_index * SUMS_LEN;
+
+    // The last pass only measures the score at the final parameters.
+    for (var iteration = 0u; iteration <= refinement.max_iterations; iteration++) {
+        var local: array<f32, 28>;
+        for (var i = 0u; i < SUMS_LEN; i++) {
+            local[i] = 0.0;
+        }
+        let p = params;
+
+        for (var pixel = local_index; pixel < pixel_count; pixel += WORKGROUP_LEN) {
+            let x = pixel % uniforms.template_width;
+            let y = pixel / uniforms.template_width;
+            let d = vec2<f32>(f32(x), f32(y)) - center;
+            let w = vec2<f32>(p[0] * d.x + p[2] * d.y + p[4], p[1] * d.x + p[3] * d.y + p[5]);
+
+            for (var c = 0u; c < uniforms.channels; c++) {
+                let error = sample_input(w, c) - load_template(x, y, c);
+                let gx = (sample_input(w + vec2<f32>(1.0, 0.0), c)
+                    - sample_input(w - vec2<f32>(1.0, 0.0), c)) * 0.5;
+                let gy = (sample_input(w + vec2<f32>(0.0, 1.0), c)
+                    - sample_input(w - vec2<f32>(0.0, 1.0), c)) * 0.5;
+                var s = array<f32, 6>(gx * d.x, gy * d.x, gx * d.y, gy * d.y, gx, gy);
+
+                var k = 0u;
+                for (var row = 0u; row < 6u; row++) {
+                    for (var col = row; col < 6u; col++) {
+                        local[k] += s[row] * s[col];
+                        k++;
+                    }
+                    local[21u + row] += s[row] * error;
+                }
+                local[27] += error * error;
+            }
+        }
+
+        for (var i = 0u; i < SUMS_LEN; i++) {
+            sums[base + i] = local[i];
+        }
+        workgroupBarrier();
+
+        for (var stride = WORKGROUP_LEN / 2u; stride > 0u; stride /= 2u) {
+            if (local_index < stride) {
+                for (var i = 0u; i < SUMS_LEN; i++) {
+                    sums[base + i] += sums[base + stride * SUMS_LEN + i];
+                }
+            }
+            workgroupBarrier();
+        }
+
+        if (local_index == 0u && done == 0u && iteration < refinement.max_iterations) {
+            gauss_newton_step();
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        for (var i = 0u; i < 6u; i++) {
+            result_buf[i] = params[i];
+        }
+        result_buf[6] = sums[27];
+        result_buf[7] = f32(iterations);
+    }
+}
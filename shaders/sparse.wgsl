@@ -0,0 +1,63 @@
+// Matching with a template given as a list of its matched pixels. Each pixel takes
+// `2 + channels` values: its position in the template, followed by the bit patterns of its samples.
+
+@group(0)
+@binding(1)
+var<storage, read> pixels: array<u32>;
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+fn sparse_sum(x: u32, y: u32, squared: bool) -> f32 {
+    let pixel_len = 2u + uniforms.channels;
+    let pixel_count = arrayLength(&pixels) / pixel_len;
+
+    var total_sum = 0.0;
+    for (var k = 0u; k < pixel_count; k++) {
+        let base = k * pixel_len;
+        let dx = pixels[base];
+        let dy = pixels[base + 1u];
+
+        for (var c = 0u; c < uniforms.channels; c++) {
+            let diff = sample_difference(
+                load_input(x + dx, y + dy, c),
+                bitcast<f32>(pixels[base + 2u + c])
+            );
+
+            if (squared) {
+                total_sum += diff * diff;
+            } else {
+                total_sum += abs(diff);
+            }
+        }
+    }
+
+    return total_sum;
+}
+
+fn main_sparse(global_id: vec3<u32>, squared: bool) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    let result_width = uniforms.input_width - uniforms.template_width + 1u;
+    let result_height = uniforms.input_height - uniforms.template_height + 1u;
+
+    if (x >= result_width || y >= result_height) {
+        return;
+    }
+
+    result_buf[y * result_width + x] = sparse_sum(x, y, squared);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_sad_sparse(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_sparse(global_id, false);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_ssd_sparse(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_sparse(global_id, true);
+}
@@ -0,0 +1,159 @@
+// Matching with rotated and scaled copies of the template. `transform_template` resamples the
+// template for each variant over the pixels of the circle inscribed in the variant, which the
+// matching entry points then compare like a sparse template. Each position keeps the best score
+// of the variants that fit there, compared by their mean difference per sample.
+
+struct Variant {
+    angle: f32,
+    // Factors from the size of the variant to the size of the template.
+    inverse_scale_x: f32,
+    inverse_scale_y: f32,
+    width: u32,
+    height: u32,
+    // Range of the variant's pixels in `offsets`.
+    first_pixel: u32,
+    pixel_count: u32,
+    // 1 if flipped left to right and 2 if flipped upside down, before rotating.
+    mirror: u32,
+};
+
+struct Search {
+    result_width: u32,
+    result_height: u32,
+};
+
+@group(0)
+@binding(2)
+var<storage, read_write> result_buf: array<f32>;
+
+@group(0)
+@binding(4)
+var<storage, read> variants: array<Variant>;
+
+// Positions of the matched pixels of all variants, two values for each.
+@group(0)
+@binding(5)
+var<storage, read> offsets: array<u32>;
+
+// Samples of the matched pixels of all variants, `channels` for each pixel.
+@group(0)
+@binding(6)
+var<storage, read_write> transformed: array<f32>;
+
+@group(0)
+@binding(7)
+var<uniform> search: Search;
+
+fn clamp_index(value: f32, size: u32) -> u32 {
+    return u32(clamp(i32(value), 0, i32(size) - 1));
+}
+
+// Samples the template bilinearly at each matched pixel of each variant, mapped back onto the
+// template.
+@compute
+@workgroup_size(64, 1, 1)
+fn transform_template(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let pixel = global_id.x;
+    let variant = variants[global_id.y];
+
+    if (pixel >= variant.pixel_count) {
+        return;
+    }
+
+    let k = variant.first_pixel + pixel;
+    let center = vec2<f32>(f32(uniforms.template_width), f32(uniforms.template_height)) * 0.5;
+    let d = vec2<f32>(f32(offsets[k * 2u]), f32(offsets[k * 2u + 1u])) + 0.5
+        - vec2<f32>(f32(variant.width), f32(variant.height)) * 0.5;
+    let s = sin(variant.angle);
+    let c = cos(variant.angle);
+    let rotated = vec2<f32>(c * d.x + s * d.y, c * d.y - s * d.x);
+    var unscaled = rotated * vec2<f32>(variant.inverse_scale_x, variant.inverse_scale_y);
+    if (variant.mirror == 1u) {
+        unscaled.x = -unscaled.x;
+    } else if (variant.mirror == 2u) {
+        unscaled.y = -unscaled.y;
+    }
+    let source = unscaled + center - 0.5;
+
+    let corner = floor(source);
+    let f = source - corner;
+    let x0 = clamp_index(corner.x, uniforms.template_width);
+    let x1 = clamp_index(corner.x + 1.0, uniforms.template_width);
+    let y0 = clamp_index(corner.y, uniforms.template_height);
+    let y1 = clamp_index(corner.y + 1.0, uniforms.template_height);
+
+    let base = k * uniforms.channels;
+    for (var ch = 0u; ch < uniforms.channels; ch++) {
+        let upper = mix(load_template(x0, y0, ch), load_template(x1, y0, ch), f.x);
+        let lower = mix(load_template(x0, y1, ch), load_template(x1, y1, ch), f.x);
+        transformed[base + ch] = mix(upper, lower, f.y);
+    }
+}
+
+fn variant_sum(x: u32, y: u32, variant: Variant, squared: bool) -> f32 {
+    var total_sum = 0.0;
+    for (var k = variant.first_pixel; k < variant.first_pixel + variant.pixel_count; k++) {
+        let dx = offsets[k * 2u];
+        let dy = offsets[k * 2u + 1u];
+        let base = k * uniforms.channels;
+
+        for (var c = 0u; c < uniforms.channels; c++) {
+            let diff = sample_difference(load_input(x + dx, y + dy, c), transformed[base + c]);
+
+            if (squared) {
+                total_sum += diff * diff;
+            } else {
+                total_sum += abs(diff);
+            }
+        }
+    }
+
+    return total_sum;
+}
+
+// Writes the best scores followed by the indices of their variants.
+fn main_transformed(global_id: vec3<u32>, squared: bool) {
+    let x = global_id.x;
+    let y = global_id.y;
+
+    if (x >= search.result_width || y >= search.result_height) {
+        return;
+    }
+
+    let infinity = bitcast<f32>(0x7f800000u);
+    var best_score = infinity;
+    var best_mean = infinity;
+    var best_variant = 0u;
+
+    let variant_count = arrayLength(&variants);
+    for (var i = 0u; i < variant_count; i++) {
+        let variant = variants[i];
+        if (x + variant.width > uniforms.input_width || y + variant.height > uniforms.input_height) {
+            continue;
+        }
+
+        let score = variant_sum(x, y, variant, squared);
+        let mean = score / f32(variant.pixel_count * uniforms.channels);
+        if (mean < best_mean) {
+            best_score = score;
+            best_mean = mean;
+            best_variant = i;
+        }
+    }
+
+    let idx = y * search.result_width + x;
+    result_buf[idx] = best_score;
+    result_buf[search.result_width * search.result_height + idx] = f32(best_variant);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_sad_transformed(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_transformed(global_id, false);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn main_ssd_transformed(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    main_transformed(global_id, true);
+}
@@ -0,0 +1,37 @@
+struct Uniforms {
+    input_width: u32,
+    input_height: u32,
+    template_width: u32,
+    template_height: u32,
+    input_stride: u32,
+    template_stride: u32,
+    channels: u32,
+    input_batch_stride: u32,
+    ignore_non_finite: u32,
+    deterministic: u32,
+};
+
+// The input and template bindings, along with `load_input` and `load_template` functions for
+// reading them as f32, are declared in a prelude generated for each source and sample format.
+// The prelude of the matching shader also declares `template_size`, which returns constants for
+// small templates so that the loops over the template can be unrolled.
+
+// Offsets of the current input and template in their buffers, in values. Inputs can be batched
+// along the z dimension of the dispatch, while the template is always at the start.
+var<private> input_offset: u32;
+var<private> template_offset: u32;
+
+@group(0)
+@binding(3)
+var<uniform> uniforms: Uniforms;
+
+// Difference between an input and a template sample. If `ignore_non_finite` is set, differences
+// that are NaN or infinite, whose exponent bits are all set, count as zero.
+fn sample_difference(input_value: f32, template_value: f32) -> f32 {
+    let diff = input_value - template_value;
+    if (uniforms.ignore_non_finite != 0u && (bitcast<u32>(diff) & 0x7f800000u) == 0x7f800000u) {
+        return 0.0;
+    }
+    return diff;
+}
+
@@ -0,0 +1,43 @@
+//! Transformed variants of a template that are rendered once and matched against many inputs.
+
+use crate::{transform::Variant, ImageLayout};
+
+/// Rotated, scaled and mirrored variants of a template, rendered once by
+/// [create_bank](crate::TemplateMatcher::create_bank) and matched against any number of inputs
+/// with [match_bank](crate::TemplateMatcher::match_bank), e.g. the frames of a video. With the GPU
+/// engine the rendered variants stay on the device, so matching a frame only uploads the frame.
+///
+/// A bank can only be matched by the matcher that created it.
+pub struct TemplateBank {
+    pub(crate) variants: Vec<Variant>,
+    pub(crate) channels: u32,
+    pub(crate) rendered: Rendered,
+}
+
+/// Samples of the variants of a bank, where its matcher keeps them.
+pub(crate) enum Rendered {
+    Gpu(Box<GpuVariants>),
+    /// Samples of each variant's matched pixels.
+    Cpu(Vec<Vec<f32>>),
+}
+
+/// Buffers of variants rendered on the GPU, as bound by the transformed shader.
+pub(crate) struct GpuVariants {
+    /// Layout of the template the variants were rendered from, which the shader's uniforms
+    /// describe when matching them.
+    pub template_layout: ImageLayout,
+    pub variants: wgpu::Buffer,
+    pub offsets: wgpu::Buffer,
+    pub samples: wgpu::Buffer,
+}
+
+impl TemplateBank {
+    /// Number of variants in the bank.
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+}
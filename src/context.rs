@@ -0,0 +1,453 @@
+//! GPU device shared between matchers.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::{mpsc, Mutex, PoisonError},
+    thread::JoinHandle,
+};
+use std::{
+    sync::{Arc, OnceLock},
+    task::Waker,
+};
+
+use crate::{pipeline::tune_workgroup_size, Engine, Error, MultiMatcher, TemplateMatcher};
+
+/// The device and queue that matchers run on. Creating a device is slow, so a context can be
+/// wrapped in an [Arc] and shared by any number of [TemplateMatcher](crate::TemplateMatcher)s,
+/// e.g. one per template or per thread.
+pub struct GpuContext {
+    instance: Option<Arc<wgpu::Instance>>,
+    adapter: Option<Arc<wgpu::Adapter>>,
+    /// Options the device was created with, or [None] if the context was created around an
+    /// existing device, which can't be replaced.
+    options: Option<TemplateMatcherBuilder>,
+    pub(crate) device: Arc<wgpu::Device>,
+    pub(crate) queue: Arc<wgpu::Queue>,
+    workgroup_size: OnceLock<(u32, u32)>,
+    /// Wakes the thread that polls the device while callbacks are waiting, once it has been
+    /// started, and the thread itself.
+    #[cfg(not(target_arch = "wasm32"))]
+    poller: Mutex<Option<(mpsc::Sender<()>, JoinHandle<()>)>>,
+    /// Tasks waiting for the polling thread, which are woken if it finds the device lost.
+    #[cfg(not(target_arch = "wasm32"))]
+    watchers: Arc<Mutex<Watchers>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for GpuContext {
+    fn drop(&mut self) {
+        // The polling thread is waited for, so that it doesn't hold on to the device after the
+        // context is gone, e.g. while the process exits. A callback run by the thread may drop the
+        // last matcher on the context, in which case the thread exits on its own.
+        let poller = self
+            .poller
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some((sender, thread)) = poller.take() {
+            drop(sender);
+            if thread.thread().id() != std::thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Tasks waiting for buffers that the polling thread maps.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Watchers {
+    /// Whether polling failed because the device was lost.
+    lost: bool,
+    /// Number of polls the thread has started.
+    polls: u64,
+    /// Wakers of the waiting tasks, with the number of polls started when each was registered.
+    wakers: Vec<(u64, Waker)>,
+}
+
+impl Default for GpuContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpuContext {
+    /// Creates a context on the highest-performance adapter available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no adapter or device is available. Use [try_new](Self::try_new) to handle that.
+    pub fn new() -> Self {
+        Self::try_new().expect("GPU context creation failed")
+    }
+
+    /// Like [new](Self::new), but returns an error if no adapter or device is available.
+    pub fn try_new() -> Result<Self, Error> {
+        pollster::block_on(Self::try_new_async())
+    }
+
+    /// Async version of [new](Self::new), for environments where blocking isn't possible, e.g. wasm.
+    pub async fn new_async() -> Self {
+        Self::try_new_async()
+            .await
+            .expect("GPU context creation failed")
+    }
+
+    /// Async version of [try_new](Self::try_new).
+    pub async fn try_new_async() -> Result<Self, Error> {
+        TemplateMatcherBuilder::new().build_context_async().await
+    }
+
+    /// Creates a context around an existing device and queue, e.g. the ones of a renderer.
+    pub fn from_device(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        Self {
+            instance: None,
+            adapter: None,
+            options: None,
+            device,
+            queue,
+            workgroup_size: OnceLock::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            poller: Mutex::new(None),
+            #[cfg(not(target_arch = "wasm32"))]
+            watchers: Arc::default(),
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Returns the adapter the device was created on, if the context created the device itself.
+    pub fn adapter(&self) -> Option<&wgpu::Adapter> {
+        self.adapter.as_deref()
+    }
+
+    /// Returns the workgroup size of the matching shader. Unless it was set with
+    /// [TemplateMatcherBuilder::workgroup_size], the first call times a few sizes on the device and
+    /// picks the fastest, which happens when the first matcher is created on the context.
+    pub fn workgroup_size(&self) -> (u32, u32) {
+        *self
+            .workgroup_size
+            .get_or_init(|| tune_workgroup_size(&self.device, &self.queue))
+    }
+
+    /// Creates a new device on the same adapter and with the same options, to replace one that was
+    /// lost. Fails with [Error::DeviceLost] if the context was created around an existing device,
+    /// or on wasm, where blocking on the request isn't possible.
+    pub(crate) fn recreate(&self) -> Result<Self, Error> {
+        let (Some(options), Some(adapter)) = (&self.options, &self.adapter) else {
+            return Err(Error::DeviceLost);
+        };
+        if cfg!(target_arch = "wasm32") {
+            return Err(Error::DeviceLost);
+        }
+        let mut context =
+            pollster::block_on(options.create_context(self.instance.clone(), adapter.clone()))?;
+        // The new device is the same GPU, so the tuned size still applies.
+        context.workgroup_size = self.workgroup_size.clone();
+        Ok(context)
+    }
+
+    /// Has the device polled on a background thread until all work submitted so far is done, so
+    /// that buffer mapping callbacks run without the application polling the device. The thread is
+    /// started on first use and exits when the context is dropped.
+    ///
+    /// The browser maps buffers on its own, so this does nothing on wasm.
+    pub(crate) fn poll_in_background(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut poller = self.poller.lock().unwrap_or_else(PoisonError::into_inner);
+            let (sender, _) = poller.get_or_insert_with(|| {
+                let (sender, receiver) = mpsc::channel();
+                let device = self.device.clone();
+                let watchers = self.watchers.clone();
+                let thread = std::thread::Builder::new()
+                    .name("template-matching-poller".to_string())
+                    .spawn(move || {
+                        while receiver.recv().is_ok() {
+                            let polls = {
+                                let mut watchers = lock(&watchers);
+                                watchers.polls += 1;
+                                watchers.polls
+                            };
+
+                            // wgpu panics when polling a lost device, and the mapping callbacks of
+                            // the device are never called, so the waiting tasks are woken instead.
+                            let polled =
+                                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                                    device.poll(wgpu::Maintain::Wait);
+                                }));
+
+                            let mut watchers = lock(&watchers);
+                            if polled.is_err() {
+                                watchers.lost = true;
+                                watchers
+                                    .wakers
+                                    .drain(..)
+                                    .for_each(|(_, waker)| waker.wake());
+                                break;
+                            }
+                            // Buffers of tasks that were waiting before the poll started have
+                            // been mapped, which woke the tasks through the mapping callbacks.
+                            watchers
+                                .wakers
+                                .retain(|&(registered, _)| registered >= polls);
+                        }
+                    })
+                    .expect("failed to spawn the device polling thread");
+                (sender, thread)
+            });
+            let _ = sender.send(());
+        }
+    }
+
+    /// Returns whether the thread polling the device in the background has found it lost.
+    /// Otherwise the waker is woken once it does, unless a poll started after this call completes
+    /// first.
+    ///
+    /// Devices aren't polled in the background on wasm, so this always returns false there.
+    pub(crate) fn watch_for_loss(&self, waker: &Waker) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut watchers = lock(&self.watchers);
+            if watchers.lost {
+                return true;
+            }
+
+            let polls = watchers.polls;
+            match watchers
+                .wakers
+                .iter_mut()
+                .find(|(_, other)| other.will_wake(waker))
+            {
+                Some((registered, _)) => *registered = polls,
+                None => watchers.wakers.push((polls, waker.clone())),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = waker;
+
+        false
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn lock(watchers: &Mutex<Watchers>) -> std::sync::MutexGuard<'_, Watchers> {
+    watchers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Configures the engine, adapter and device that a [TemplateMatcher] or [GpuContext] is created on.
+///
+/// The defaults match [TemplateMatcher::try_new]: the GPU engine on the highest-performance adapter
+/// of any backend, with no extra features and the default limits.
+#[derive(Clone, Debug)]
+pub struct TemplateMatcherBuilder {
+    power_preference: wgpu::PowerPreference,
+    backends: wgpu::Backends,
+    features: wgpu::Features,
+    limits: wgpu::Limits,
+    label: Option<String>,
+    force_fallback_adapter: bool,
+    engine: Engine,
+    cpu_fallback: bool,
+    workgroup_size: Option<(u32, u32)>,
+}
+
+impl Default for TemplateMatcherBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemplateMatcherBuilder {
+    pub fn new() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            backends: wgpu::Backends::all(),
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            label: None,
+            force_fallback_adapter: false,
+            engine: Engine::Gpu,
+            cpu_fallback: false,
+            workgroup_size: None,
+        }
+    }
+
+    /// Sets which adapter is preferred, e.g. [LowPower](wgpu::PowerPreference::LowPower) to use
+    /// an integrated GPU instead of waking a discrete one.
+    pub fn power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    /// Sets the backends that adapters are searched from.
+    pub fn backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    /// Sets the features the device must support.
+    pub fn features(mut self, features: wgpu::Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Sets the limits the device must support.
+    pub fn limits(mut self, limits: wgpu::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the debug label of the device.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Forces a software adapter, e.g. lavapipe or WARP, for running on machines without a GPU such
+    /// as CI containers and VMs. Building fails with [Error::NoAdapter] if none of the enabled
+    /// backends has one.
+    pub fn force_fallback_adapter(mut self, force: bool) -> Self {
+        self.force_fallback_adapter = force;
+        self
+    }
+
+    /// Sets the engine that matchers run on. The device options only apply to the GPU engine.
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Makes [build](Self::build) fall back to the CPU engine instead of failing if no adapter or
+    /// device is available.
+    pub fn cpu_fallback(mut self, fallback: bool) -> Self {
+        self.cpu_fallback = fallback;
+        self
+    }
+
+    /// Sets the workgroup size of the matching shader instead of tuning it for the device. The
+    /// size must be within the device's compute limits.
+    pub fn workgroup_size(mut self, size: (u32, u32)) -> Self {
+        self.workgroup_size = Some(size);
+        self
+    }
+
+    /// Creates a matcher with its own device, or one running on the CPU.
+    pub fn build(&self) -> Result<TemplateMatcher, Error> {
+        pollster::block_on(self.build_async())
+    }
+
+    /// Async version of [build](Self::build).
+    pub async fn build_async(&self) -> Result<TemplateMatcher, Error> {
+        if self.engine == Engine::Cpu {
+            return Ok(TemplateMatcher::new_cpu());
+        }
+
+        match self.build_context_async().await {
+            Ok(context) => Ok(TemplateMatcher::with_context(Arc::new(context))),
+            Err(_) if self.cpu_fallback => Ok(TemplateMatcher::new_cpu()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Creates a context for sharing a device between matchers.
+    pub fn build_context(&self) -> Result<GpuContext, Error> {
+        pollster::block_on(self.build_context_async())
+    }
+
+    /// Async version of [build_context](Self::build_context).
+    pub async fn build_context_async(&self) -> Result<GpuContext, Error> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends,
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: self.power_preference,
+                compatible_surface: None,
+                force_fallback_adapter: self.force_fallback_adapter,
+            })
+            .await
+            .ok_or(Error::NoAdapter)?;
+
+        self.create_context(Some(Arc::new(instance)), Arc::new(adapter))
+            .await
+    }
+
+    /// Creates a matcher on each adapter of the enabled backends, combined into a [MultiMatcher]
+    /// that splits every match between them.
+    ///
+    /// Adapters of the backend that is found first are used, since the other backends usually
+    /// expose the same GPUs again. Software adapters are only used if there is no other adapter,
+    /// or if [force_fallback_adapter](Self::force_fallback_adapter) is set.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn build_multi(&self) -> Result<MultiMatcher, Error> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends,
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let adapters: Vec<_> = instance.enumerate_adapters(self.backends).collect();
+        let is_software =
+            |adapter: &wgpu::Adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu;
+
+        let software = self.force_fallback_adapter || adapters.iter().all(is_software);
+        let adapters: Vec<_> = adapters
+            .into_iter()
+            .filter(|adapter| is_software(adapter) == software)
+            .collect();
+        let backend = adapters.first().ok_or(Error::NoAdapter)?.get_info().backend;
+
+        // The adapters keep what they need of the instance alive, so one context holding it is
+        // enough.
+        let mut instance = Some(Arc::new(instance));
+        let mut matchers = Vec::new();
+        for adapter in adapters {
+            if adapter.get_info().backend != backend {
+                continue;
+            }
+
+            let context =
+                pollster::block_on(self.create_context(instance.take(), Arc::new(adapter)))?;
+            matchers.push(TemplateMatcher::with_context(Arc::new(context)));
+        }
+
+        Ok(MultiMatcher::new(matchers))
+    }
+
+    async fn create_context(
+        &self,
+        instance: Option<Arc<wgpu::Instance>>,
+        adapter: Arc<wgpu::Adapter>,
+    ) -> Result<GpuContext, Error> {
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: self.label.as_deref(),
+                    features: self.features,
+                    limits: self.limits.clone(),
+                },
+                None,
+            )
+            .await?;
+
+        Ok(GpuContext {
+            instance,
+            adapter: Some(adapter),
+            options: Some(self.clone()),
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            workgroup_size: self.workgroup_size.map(OnceLock::from).unwrap_or_default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            poller: Mutex::new(None),
+            #[cfg(not(target_arch = "wasm32"))]
+            watchers: Arc::default(),
+        })
+    }
+}
@@ -0,0 +1,916 @@
+//! Matching on the CPU, for machines where no GPU device can be created.
+
+use wide::f32x8;
+
+use crate::{
+    log_polar::LogPolarSearch,
+    packed_samples,
+    pipeline::MAX_TILE_SIZE,
+    preprocess::{self, Preprocess},
+    refine::Refinement,
+    transform,
+    transform::Variant,
+    Capabilities, Image, MatchJob, MatchTemplateMethod, NonFinite, Sample, SparseTemplate,
+};
+
+/// CPU counterpart of the GPU matcher. Matching runs to completion in
+/// [match_template](Self::match_template), and each result is kept until it is collected.
+pub(crate) struct CpuMatcher {
+    capabilities: Capabilities,
+    results: Vec<(MatchJob, Image<'static>)>,
+    next_job_id: u64,
+    /// Input given to `set_input`, converted to `f32`.
+    input: Option<Image<'static>>,
+    /// Template given to `set_template`, converted to `f32`.
+    template: Option<Image<'static>>,
+    /// Running background of [Preprocess::SubtractBackground].
+    background: Option<Image<'static>>,
+    summation: Summation,
+}
+
+/// How the differences between the template and the windows of the input are summed.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Summation {
+    pub non_finite: NonFinite,
+    /// Whether each window is summed one sample at a time in the order of the shaders instead of
+    /// with SIMD, see [set_deterministic](crate::TemplateMatcher::set_deterministic).
+    pub deterministic: bool,
+}
+
+impl CpuMatcher {
+    pub fn new() -> Self {
+        Self {
+            capabilities: Capabilities::default(),
+            results: Vec::new(),
+            next_job_id: 0,
+            input: None,
+            template: None,
+            background: None,
+            summation: Summation::default(),
+        }
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn set_non_finite(&mut self, policy: NonFinite) {
+        self.summation.non_finite = policy;
+    }
+
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.summation.deterministic = deterministic;
+    }
+
+    pub fn summation(&self) -> Summation {
+        self.summation
+    }
+
+    pub fn match_template<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        // Like on the GPU, a new input or template replaces the one that was set.
+        self.input = None;
+        self.template = None;
+        self.push_result(match_template(input, template, method, self.summation))
+    }
+
+    pub fn match_template_pruned<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        bound: Option<f32>,
+    ) -> MatchJob {
+        self.input = None;
+        self.template = None;
+        let bound = bound.unwrap_or(f32::INFINITY);
+        self.push_result(score_positions(
+            input,
+            template,
+            method,
+            self.summation,
+            Some(bound),
+        ))
+    }
+
+    pub fn match_sparse_template<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &SparseTemplate,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        self.input = None;
+        self.push_result(match_sparse_template(
+            input,
+            template,
+            method,
+            self.summation.non_finite,
+        ))
+    }
+
+    pub fn match_template_binary<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        threshold: f32,
+    ) -> MatchJob {
+        self.input = None;
+        self.template = None;
+        self.push_result(match_template_binary(input, template, threshold))
+    }
+
+    pub fn match_variants<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+    ) -> (Image<'static>, Vec<u32>) {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+
+        let samples = self.render_variants(template, variants);
+        self.match_rendered(input, method, variants, &samples)
+    }
+
+    pub fn render_variants<T: Sample>(
+        &mut self,
+        template: &Image<'_, T>,
+        variants: &[Variant],
+    ) -> Vec<Vec<f32>> {
+        self.template = None;
+        let template = to_f32_image(template, "template");
+        variants
+            .iter()
+            .map(|variant| variant.samples_of(&template))
+            .collect()
+    }
+
+    pub fn match_rendered<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+        samples: &[Vec<f32>],
+    ) -> (Image<'static>, Vec<u32>) {
+        self.input = None;
+        match_rendered(input, method, self.summation.non_finite, variants, samples)
+    }
+
+    pub fn estimate_log_polar<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        search: &LogPolarSearch,
+    ) -> (u32, u32, u32) {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+        self.input = None;
+        self.template = None;
+
+        let input = to_f32_image(input, "input");
+        let template = to_f32_image(template, "template");
+        search.estimate(&input, &template, method)
+    }
+
+    pub fn refine<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        refinement: &Refinement,
+    ) -> Vec<f32> {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+        self.input = None;
+        self.template = None;
+
+        let input = to_f32_image(input, "input");
+        let template = to_f32_image(template, "template");
+        refinement.refine(&input, &template).to_vec()
+    }
+
+    pub fn match_templates<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        templates: &[Image<'_, T>],
+        method: MatchTemplateMethod,
+    ) -> Vec<Image<'static>> {
+        self.input = None;
+        templates
+            .iter()
+            .map(|template| match_template(input, template, method, self.summation))
+            .collect()
+    }
+
+    pub fn set_input<I: Sample>(&mut self, input: &Image<'_, I>) {
+        self.input = Some(to_f32_image(input, "input"));
+    }
+
+    pub fn preprocess<T: Sample>(
+        &mut self,
+        image: &Image<'_, T>,
+        steps: &[Preprocess],
+    ) -> Image<'static> {
+        preprocess::apply(image, steps, &mut self.background)
+    }
+
+    pub fn reset_background(&mut self) {
+        self.background = None;
+    }
+
+    pub fn match_uploaded<T: Sample>(
+        &mut self,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input = self
+            .input
+            .as_ref()
+            .expect("no input has been set with set_input");
+        let result = match_template(input, template, method, self.summation);
+        self.template = None;
+        self.push_result(result)
+    }
+
+    pub fn set_template<T: Sample>(&mut self, template: &Image<'_, T>) {
+        self.template = Some(to_f32_image(template, "template"));
+    }
+
+    pub fn match_uploaded_template<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let template = self
+            .template
+            .as_ref()
+            .expect("no template has been set with set_template");
+        let result = match_template(input, template, method, self.summation);
+        self.input = None;
+        self.push_result(result)
+    }
+
+    fn push_result(&mut self, result: Image<'static>) -> MatchJob {
+        let job = MatchJob(self.next_job_id);
+        self.next_job_id += 1;
+        self.results.push((job, result));
+        job
+    }
+
+    pub fn latest_job(&self) -> Option<MatchJob> {
+        let latest = MatchJob(self.next_job_id.checked_sub(1)?);
+        self.results
+            .iter()
+            .any(|(job, _)| *job == latest)
+            .then_some(latest)
+    }
+
+    pub fn pending_jobs(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Takes the result of the given job, if it hasn't been collected yet.
+    pub fn take_result(&mut self, job: MatchJob) -> Option<Image<'static>> {
+        let index = self
+            .results
+            .iter()
+            .position(|(pending, _)| *pending == job)?;
+        Some(self.results.remove(index).1)
+    }
+
+    pub fn clear_results(&mut self) {
+        self.results.clear();
+    }
+}
+
+/// Copies the image into tightly packed `f32` samples.
+fn to_f32_image<T: Sample>(image: &Image<'_, T>, label: &str) -> Image<'static> {
+    image.assert_valid(label);
+
+    let samples = packed_samples(image)
+        .map(Sample::to_f32)
+        .collect::<Vec<_>>();
+    Image::with_channels(samples, image.width, image.height, image.channels)
+}
+
+/// Lanes processed at once by the row kernels.
+const LANES: usize = 8;
+
+fn lanes(chunk: &[f32]) -> f32x8 {
+    f32x8::new(chunk.try_into().unwrap())
+}
+
+/// Difference between an input and a template sample, which counts as zero if it isn't finite
+/// and the policy ignores such differences, like in the shaders.
+fn difference(input: f32, template: f32, non_finite: NonFinite) -> f32 {
+    let diff = input - template;
+    if non_finite == NonFinite::Ignore && !diff.is_finite() {
+        0.0
+    } else {
+        diff
+    }
+}
+
+/// Differences between the lanes of two chunks, see [difference].
+fn lane_differences(a: &[f32], b: &[f32], non_finite: NonFinite) -> f32x8 {
+    let diff = lanes(a) - lanes(b);
+    match non_finite {
+        NonFinite::Propagate => diff,
+        NonFinite::Ignore => diff.is_finite().blend(diff, f32x8::ZERO),
+    }
+}
+
+/// Sum of absolute differences between two rows of equal length.
+fn row_sad(a: &[f32], b: &[f32], non_finite: NonFinite) -> f32 {
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(&a, &b)| difference(a, b, non_finite).abs())
+        .sum();
+
+    let sum = a_chunks.zip(b_chunks).fold(f32x8::ZERO, |sum, (a, b)| {
+        sum + lane_differences(a, b, non_finite).abs()
+    });
+
+    sum.reduce_add() + tail
+}
+
+/// Sum of squared differences between two rows of equal length.
+fn row_ssd(a: &[f32], b: &[f32], non_finite: NonFinite) -> f32 {
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(&a, &b)| difference(a, b, non_finite).powi(2))
+        .sum();
+
+    let sum = a_chunks.zip(b_chunks).fold(f32x8::ZERO, |sum, (a, b)| {
+        let diff = lane_differences(a, b, non_finite);
+        diff.mul_add(diff, sum)
+    });
+
+    sum.reduce_add() + tail
+}
+
+/// Returns whether any sample of the frames differs by more than `threshold`, like the difference
+/// shader does.
+pub(crate) fn has_changed<P: Sample, C: Sample>(
+    previous: &Image<'_, P>,
+    current: &Image<'_, C>,
+    threshold: f32,
+) -> bool {
+    packed_samples(previous)
+        .zip(packed_samples(current))
+        .any(|(previous, current)| (current.to_f32() - previous.to_f32()).abs() > threshold)
+}
+
+/// Scores the template at each position of the input, like the matching shaders do.
+pub(crate) fn match_template<I: Sample, T: Sample>(
+    input: &Image<'_, I>,
+    template: &Image<'_, T>,
+    method: MatchTemplateMethod,
+    summation: Summation,
+) -> Image<'static> {
+    score_positions(input, template, method, summation, None)
+}
+
+/// Scores the template at each position of the input. If a pruning bound is given, scoring a
+/// position stops at the first row after which the score exceeds the best one so far, which
+/// starts at the bound, like the pruned shader does. Deterministic summation only prunes with the
+/// bound itself.
+fn score_positions<I: Sample, T: Sample>(
+    input: &Image<'_, I>,
+    template: &Image<'_, T>,
+    method: MatchTemplateMethod,
+    summation: Summation,
+    mut pruning_bound: Option<f32>,
+) -> Image<'static> {
+    assert_eq!(
+        input.channels, template.channels,
+        "input and template must have the same number of channels"
+    );
+    input.assert_valid("input");
+    template.assert_valid("template");
+
+    let input_data: Vec<f32> = packed_samples(input).map(Sample::to_f32).collect();
+    let template_data: Vec<f32> = packed_samples(template).map(Sample::to_f32).collect();
+
+    let channels = input.channels as usize;
+    let input_row_len = input.width as usize * channels;
+    let template_row_len = template.width as usize * channels;
+
+    crate::assert_template_fits(
+        (input.width, input.height),
+        (template.width, template.height),
+    );
+    let result_width = input.width - template.width + 1;
+    let result_height = input.height - template.height + 1;
+
+    let (template_width, template_height) = (template.width as usize, template.height as usize);
+    let tile_size = MAX_TILE_SIZE as usize;
+    let non_finite = summation.non_finite;
+
+    // Adds the difference between an input and a template sample to a sum, one at a time.
+    let accumulate = |sum: f32, (&input_val, &template_val): (&f32, &f32)| {
+        let diff = difference(input_val, template_val, non_finite);
+        match method {
+            MatchTemplateMethod::SumOfAbsoluteDifferences => sum + diff.abs(),
+            MatchTemplateMethod::SumOfSquaredDifferences => sum + diff * diff,
+        }
+    };
+
+    let mut result = Vec::with_capacity((result_width * result_height) as usize);
+
+    for y in 0..result_height as usize {
+        for x in 0..result_width as usize {
+            let mut total_sum = 0.0;
+
+            if summation.deterministic && pruning_bound.is_none() {
+                // In the order of the matching shader: by template tiles, one channel at a time,
+                // and row by row within a tile.
+                for tile_y in (0..template_height).step_by(tile_size) {
+                    for tile_x in (0..template_width).step_by(tile_size) {
+                        let tile_width = tile_size.min(template_width - tile_x);
+                        let tile_rows = tile_y..(tile_y + tile_size).min(template_height);
+
+                        for c in 0..channels {
+                            for j in tile_rows.clone() {
+                                let input_start = (y + j) * input_row_len + (x + tile_x) * channels;
+                                let template_start = j * template_row_len + tile_x * channels;
+                                let len = tile_width * channels;
+
+                                total_sum = input_data[input_start..input_start + len]
+                                    .iter()
+                                    .zip(&template_data[template_start..template_start + len])
+                                    .skip(c)
+                                    .step_by(channels)
+                                    .fold(total_sum, accumulate);
+                            }
+                        }
+                    }
+                }
+
+                result.push(total_sum);
+                continue;
+            }
+
+            for j in 0..template_height {
+                let input_start = (y + j) * input_row_len + x * channels;
+                let input_row = &input_data[input_start..input_start + template_row_len];
+                let template_row = &template_data[j * template_row_len..(j + 1) * template_row_len];
+
+                total_sum = if summation.deterministic {
+                    // In the order of the pruned shader, sample by sample.
+                    input_row
+                        .iter()
+                        .zip(template_row)
+                        .fold(total_sum, accumulate)
+                } else {
+                    total_sum
+                        + match method {
+                            MatchTemplateMethod::SumOfAbsoluteDifferences => {
+                                row_sad(input_row, template_row, non_finite)
+                            }
+                            MatchTemplateMethod::SumOfSquaredDifferences => {
+                                row_ssd(input_row, template_row, non_finite)
+                            }
+                        }
+                };
+
+                if pruning_bound.is_some_and(|bound| total_sum > bound) {
+                    break;
+                }
+            }
+
+            if let Some(bound) = pruning_bound.as_mut().filter(|_| !summation.deterministic) {
+                *bound = bound.min(total_sum);
+            }
+            result.push(total_sum);
+        }
+    }
+
+    Image::new(result, result_width, result_height)
+}
+
+/// Scores the pixels of a sparse template at each position of the input, like the sparse shader
+/// does.
+fn match_sparse_template<I: Sample>(
+    input: &Image<'_, I>,
+    template: &SparseTemplate,
+    method: MatchTemplateMethod,
+    non_finite: NonFinite,
+) -> Image<'static> {
+    assert_eq!(
+        input.channels,
+        template.channels(),
+        "input and template must have the same number of channels"
+    );
+
+    let input = to_f32_image(input, "input");
+    let channels = input.channels as usize;
+    let row_len = input.width as usize * channels;
+
+    let result_width = input.width - template.width() + 1;
+    let result_height = input.height - template.height() + 1;
+
+    let mut result = Vec::with_capacity((result_width * result_height) as usize);
+
+    for y in 0..result_height as usize {
+        for x in 0..result_width as usize {
+            let mut total_sum = 0.0;
+
+            for ((dx, dy), samples) in template.pixels() {
+                let start = (y + dy as usize) * row_len + (x + dx as usize) * channels;
+                for (input_val, template_val) in
+                    input.data[start..start + channels].iter().zip(samples)
+                {
+                    let diff = difference(*input_val, *template_val, non_finite);
+                    total_sum += match method {
+                        MatchTemplateMethod::SumOfAbsoluteDifferences => diff.abs(),
+                        MatchTemplateMethod::SumOfSquaredDifferences => diff * diff,
+                    };
+                }
+            }
+
+            result.push(total_sum);
+        }
+    }
+
+    Image::new(result, result_width, result_height)
+}
+
+/// Counts the samples of the template whose thresholded value differs from the input's at each
+/// position of the input, like the binary shader does.
+fn match_template_binary<I: Sample, T: Sample>(
+    input: &Image<'_, I>,
+    template: &Image<'_, T>,
+    threshold: f32,
+) -> Image<'static> {
+    assert_eq!(
+        input.channels, template.channels,
+        "input and template must have the same number of channels"
+    );
+
+    let binarize = |image: Image<'static>| -> Vec<bool> {
+        image.data.iter().map(|&value| value > threshold).collect()
+    };
+    let input_bits = binarize(to_f32_image(input, "input"));
+    let template_bits = binarize(to_f32_image(template, "template"));
+
+    let channels = input.channels as usize;
+    let input_row_len = input.width as usize * channels;
+    let template_row_len = template.width as usize * channels;
+
+    crate::assert_template_fits(
+        (input.width, input.height),
+        (template.width, template.height),
+    );
+    let result_width = input.width - template.width + 1;
+    let result_height = input.height - template.height + 1;
+
+    let mut result = Vec::with_capacity((result_width * result_height) as usize);
+
+    for y in 0..result_height as usize {
+        for x in 0..result_width as usize {
+            let mut mismatches = 0u32;
+
+            for (ty, template_row) in template_bits.chunks_exact(template_row_len).enumerate() {
+                let start = (y + ty) * input_row_len + x * channels;
+                let input_row = &input_bits[start..start + template_row_len];
+                mismatches += input_row
+                    .iter()
+                    .zip(template_row)
+                    .filter(|(a, b)| a != b)
+                    .count() as u32;
+            }
+
+            result.push(mismatches as f32);
+        }
+    }
+
+    Image::new(result, result_width, result_height)
+}
+
+/// Scores each rendered variant at each position of the input where it fits, keeping the best
+/// score by mean difference per sample and the index of its variant, like the transformed shader
+/// does.
+fn match_rendered<I: Sample>(
+    input: &Image<'_, I>,
+    method: MatchTemplateMethod,
+    non_finite: NonFinite,
+    variants: &[Variant],
+    samples: &[Vec<f32>],
+) -> (Image<'static>, Vec<u32>) {
+    let input = to_f32_image(input, "input");
+    let channels = input.channels as usize;
+    let row_len = input.width as usize * channels;
+
+    let (result_width, result_height) =
+        transform::result_size((input.width, input.height), variants);
+
+    let mut scores = Vec::with_capacity((result_width * result_height) as usize);
+    let mut indices = Vec::with_capacity(scores.capacity());
+
+    for y in 0..result_height {
+        for x in 0..result_width {
+            let mut best = (f32::INFINITY, f32::INFINITY, 0);
+
+            for (index, (variant, samples)) in variants.iter().zip(samples).enumerate() {
+                if x + variant.width > input.width || y + variant.height > input.height {
+                    continue;
+                }
+
+                let mut total_sum = 0.0;
+                for (&(dx, dy), template_pixel) in
+                    variant.pixels.iter().zip(samples.chunks_exact(channels))
+                {
+                    let start = (y + dy) as usize * row_len + (x + dx) as usize * channels;
+                    for (input_val, template_val) in input.data[start..start + channels]
+                        .iter()
+                        .zip(template_pixel)
+                    {
+                        let diff = difference(*input_val, *template_val, non_finite);
+                        total_sum += match method {
+                            MatchTemplateMethod::SumOfAbsoluteDifferences => diff.abs(),
+                            MatchTemplateMethod::SumOfSquaredDifferences => diff * diff,
+                        };
+                    }
+                }
+
+                let mean = total_sum / variant.samples(input.channels) as f32;
+                if mean < best.1 {
+                    best = (total_sum, mean, index as u32);
+                }
+            }
+
+            scores.push(best.0);
+            indices.push(best.2);
+        }
+    }
+
+    (Image::new(scores, result_width, result_height), indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random samples in `[0, 1)`.
+    fn noise(width: u32, height: u32, channels: u32, seed: u32) -> Image<'static> {
+        let mut state = seed;
+        let data = (0..width * height * channels)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 8) as f32 / (1 << 24) as f32
+            })
+            .collect::<Vec<_>>();
+        Image::with_channels(data, width, height, channels)
+    }
+
+    /// Scores summed in double precision, one sample at a time.
+    fn reference(input: &Image<'_>, template: &Image<'_>, method: MatchTemplateMethod) -> Vec<f32> {
+        let channels = input.channels;
+        let mut result = Vec::new();
+        for y in 0..=input.height - template.height {
+            for x in 0..=input.width - template.width {
+                let mut sum = 0.0f64;
+                for j in 0..template.height {
+                    for i in 0..template.width {
+                        for c in 0..channels {
+                            let a = input.get_channel(x + i, y + j, c).unwrap() as f64;
+                            let b = template.get_channel(i, j, c).unwrap() as f64;
+                            sum += match method {
+                                MatchTemplateMethod::SumOfAbsoluteDifferences => (a - b).abs(),
+                                MatchTemplateMethod::SumOfSquaredDifferences => (a - b).powi(2),
+                            };
+                        }
+                    }
+                }
+                result.push(sum as f32);
+            }
+        }
+        result
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() <= tolerance, "{a} != {e} at {i}");
+        }
+    }
+
+    const METHODS: [MatchTemplateMethod; 2] = [
+        MatchTemplateMethod::SumOfAbsoluteDifferences,
+        MatchTemplateMethod::SumOfSquaredDifferences,
+    ];
+
+    #[test]
+    fn scores_match_reference() {
+        let input = noise(23, 14, 3, 1);
+        let template = input.crop(9, 5, 11, 4);
+        let mut matcher = CpuMatcher::new();
+
+        for method in METHODS {
+            for deterministic in [false, true] {
+                matcher.set_deterministic(deterministic);
+                let job = matcher.match_template(&input, &template, method);
+                let result = matcher.take_result(job).unwrap();
+
+                assert_eq!((result.width, result.height), (13, 11));
+                assert_close(&result.data, &reference(&input, &template, method), 1e-4);
+                assert_eq!(result.at(9, 5), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_summation_spans_tiles() {
+        // Wider and taller than a tile, so that windows are summed tile by tile.
+        let input = noise(40, 24, 1, 2);
+        let template = input.crop(3, 2, MAX_TILE_SIZE + 3, MAX_TILE_SIZE + 1);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+        let expected = reference(&input, &template, method);
+
+        let summation = Summation {
+            deterministic: true,
+            ..Summation::default()
+        };
+        let first = match_template(&input, &template, method, summation);
+        let second = match_template(&input, &template, method, summation);
+
+        assert_close(&first.data, &expected, 1e-3);
+        assert_eq!(first.data, second.data);
+    }
+
+    #[test]
+    fn pruning_keeps_best_score() {
+        let input = noise(20, 12, 1, 3);
+        let template = input.crop(7, 4, 5, 5);
+        let mut matcher = CpuMatcher::new();
+
+        for method in METHODS {
+            let job = matcher.match_template_pruned(&input, &template, method, None);
+            let result = matcher.take_result(job).unwrap();
+            let expected = reference(&input, &template, method);
+
+            let (best, _) = result
+                .iter_pixels()
+                .map(|(x, y, value)| ((x, y), value))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            assert_eq!(best, (7, 4));
+
+            // Pruned positions stop early, so their scores are at most the full ones.
+            for (actual, expected) in result.data.iter().zip(&expected) {
+                assert!(*actual <= expected + 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_template_with_full_mask_matches_dense() {
+        let input = noise(16, 10, 2, 4);
+        let template = input.crop(4, 3, 6, 4);
+        let mask = vec![true; 24];
+        let sparse = SparseTemplate::new(&template, &mask);
+
+        for method in METHODS {
+            let result = match_sparse_template(&input, &sparse, method, NonFinite::Propagate);
+            assert_close(&result.data, &reference(&input, &template, method), 1e-4);
+        }
+    }
+
+    #[test]
+    fn sparse_template_skips_masked_pixels() {
+        let input = noise(12, 8, 1, 5);
+        let mut template = input.crop(2, 1, 3, 3).into_owned();
+        // Garbage in the masked-out pixel doesn't change the scores.
+        template.data.to_mut()[4] = 100.0;
+        let mut mask = vec![true; 9];
+        mask[4] = false;
+
+        let result = match_sparse_template(
+            &input,
+            &SparseTemplate::new(&template, &mask),
+            MatchTemplateMethod::SumOfSquaredDifferences,
+            NonFinite::Propagate,
+        );
+        assert_eq!(result.at(2, 1), 0.0);
+        assert!(result.data.iter().all(|&score| score < 100.0));
+    }
+
+    #[test]
+    fn binary_counts_differing_samples() {
+        let input = Image::new(vec![0.0, 1.0, 1.0, 0.0, 0.0, 1.0], 3, 2);
+        let template = Image::new(vec![1.0, 1.0, 0.0, 0.0], 2, 2);
+
+        let result = match_template_binary(&input, &template, 0.5);
+
+        assert_eq!((result.width, result.height), (2, 1));
+        assert_eq!(*result.data, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn integer_samples_are_normalized() {
+        let input = Image::new(vec![0u8, 255, 51, 102], 4, 1);
+        let template = Image::new(vec![255u8], 1, 1);
+
+        let result = match_template(
+            &input,
+            &template,
+            MatchTemplateMethod::SumOfAbsoluteDifferences,
+            Summation::default(),
+        );
+        assert_close(&result.data, &[1.0, 0.0, 0.8, 0.6], 1e-6);
+    }
+
+    #[test]
+    fn row_kernels_match_scalar_sums() {
+        let samples = noise(2 * LANES as u32 + 5, 2, 1, 7);
+        let (a, b) = samples.data.split_at(samples.width as usize);
+
+        // Lengths with and without full lanes and a tail.
+        for len in 0..=a.len() {
+            let (a, b) = (&a[..len], &b[..len]);
+            let sad: f32 = a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum();
+            let ssd: f32 = a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum();
+
+            assert!(
+                (row_sad(a, b, NonFinite::Propagate) - sad).abs() <= 1e-5,
+                "{len}"
+            );
+            assert!(
+                (row_ssd(a, b, NonFinite::Propagate) - ssd).abs() <= 1e-5,
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn row_kernels_handle_non_finite_differences() {
+        // One NaN in the lanes and one infinity in the tail.
+        let mut a = vec![0.5; LANES + 3];
+        a[2] = f32::NAN;
+        a[LANES + 1] = f32::INFINITY;
+        let b = vec![0.25; LANES + 3];
+
+        assert!(row_sad(&a, &b, NonFinite::Propagate).is_nan());
+        assert!(row_ssd(&a, &b, NonFinite::Propagate).is_nan());
+
+        let finite = (LANES + 1) as f32;
+        assert_eq!(row_sad(&a, &b, NonFinite::Ignore), finite * 0.25);
+        assert_eq!(row_ssd(&a, &b, NonFinite::Ignore), finite * 0.0625);
+    }
+
+    #[test]
+    fn simd_and_deterministic_summation_agree() {
+        let input = noise(37, 9, 3, 8);
+        let template = input.crop(4, 2, 13, 6);
+
+        for method in METHODS {
+            let simd = match_template(&input, &template, method, Summation::default());
+            let deterministic = match_template(
+                &input,
+                &template,
+                method,
+                Summation {
+                    deterministic: true,
+                    ..Summation::default()
+                },
+            );
+            assert_close(&simd.data, &deterministic.data, 1e-4);
+        }
+    }
+
+    #[test]
+    fn results_are_kept_until_taken() {
+        let input = noise(8, 8, 1, 6);
+        let template = input.crop(1, 1, 3, 3);
+        let mut matcher = CpuMatcher::new();
+
+        let first = matcher.match_template(&input, &template, METHODS[0]);
+        let second = matcher.match_template(&input, &template, METHODS[1]);
+
+        assert!(matcher.take_result(second).is_some());
+        assert!(matcher.take_result(second).is_none());
+        assert!(matcher.take_result(first).is_some());
+    }
+}
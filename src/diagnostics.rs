@@ -0,0 +1,237 @@
+//! Preflight checks for matching inputs.
+
+use std::{fmt, mem::size_of};
+
+use crate::Image;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Matching will work, but the result is likely not what was intended.
+    Warning,
+    /// Matching will fail or produce garbage.
+    Error,
+}
+
+/// A single problem found by [diagnose].
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Report returned by [diagnose].
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    pub issues: Vec<Diagnostic>,
+    /// Number of per-pixel difference operations the matching will perform.
+    pub estimated_operations: u64,
+    /// Bytes of GPU memory needed for the input, template, result and readback buffers.
+    pub estimated_memory: u64,
+}
+
+impl Diagnostics {
+    /// Returns `true` if any of the issues would make the matching fail.
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    fn warning(&mut self, message: String) {
+        self.issues.push(Diagnostic {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    fn error(&mut self, message: String) {
+        self.issues.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+        });
+    }
+}
+
+struct ValueStats {
+    min: f32,
+    max: f32,
+    non_finite: usize,
+}
+
+fn value_stats(image: &Image<'_>) -> ValueStats {
+    let mut stats = ValueStats {
+        min: f32::MAX,
+        max: f32::MIN,
+        non_finite: 0,
+    };
+
+    let stride = image.row_stride() as usize;
+    let row_len = (image.width * image.channels) as usize;
+    let rows =
+        (0..image.height as usize).filter_map(|y| image.data.get(y * stride..y * stride + row_len));
+
+    for &value in rows.flatten() {
+        if value.is_finite() {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+        } else {
+            stats.non_finite += 1;
+        }
+    }
+
+    stats
+}
+
+/// Default `max_storage_buffer_binding_size` of wgpu.
+const MAX_STORAGE_BUFFER_BINDING_SIZE: u64 = 128 << 20;
+
+/// Default `max_buffer_size` of wgpu.
+const MAX_BUFFER_SIZE: u64 = 256 << 20;
+
+/// Largest integer that an `f32` represents exactly. Scores above this lose precision.
+const F32_EXACT_LIMIT: f64 = (1u64 << 24) as f64;
+
+/// Checks the input and template for likely problems before matching them.
+pub fn diagnose(input: &Image<'_>, template: &Image<'_>) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+
+    for (name, image) in [("input", input), ("template", template)] {
+        if image.width == 0 || image.height == 0 {
+            diagnostics.error(format!(
+                "{name} has zero size ({}x{})",
+                image.width, image.height
+            ));
+        }
+
+        if image.channels == 0 {
+            diagnostics.error(format!("{name} has zero channels"));
+        }
+
+        if image.row_stride() < image.width * image.channels {
+            diagnostics.error(format!(
+                "{name} stride {} is smaller than its row length {}",
+                image.row_stride(),
+                image.width * image.channels
+            ));
+        }
+
+        let expected_len = image.required_len();
+        if image.data.len() < expected_len {
+            diagnostics.error(format!(
+                "{name} has {} values but {}x{}x{} requires {expected_len}",
+                image.data.len(),
+                image.width,
+                image.height,
+                image.channels
+            ));
+        } else if image.data.len() > expected_len && image.stride.is_none() {
+            diagnostics.warning(format!(
+                "{name} has {} values but {}x{}x{} only uses {expected_len}",
+                image.data.len(),
+                image.width,
+                image.height,
+                image.channels
+            ));
+        }
+    }
+
+    if input.channels != template.channels {
+        diagnostics.error(format!(
+            "input has {} channels but template has {}",
+            input.channels, template.channels
+        ));
+    }
+
+    if template.width > input.width || template.height > input.height {
+        diagnostics.error(format!(
+            "template ({}x{}) is larger than input ({}x{})",
+            template.width, template.height, input.width, input.height
+        ));
+    }
+
+    let input_stats = value_stats(input);
+    let template_stats = value_stats(template);
+
+    for (name, stats) in [("input", &input_stats), ("template", &template_stats)] {
+        if stats.non_finite > 0 {
+            diagnostics.error(format!(
+                "{name} contains {} NaN or infinite values",
+                stats.non_finite
+            ));
+        }
+
+        if stats.min == stats.max {
+            diagnostics.warning(format!(
+                "{name} is constant ({}), every location will score the same",
+                stats.min
+            ));
+        }
+    }
+
+    // Mixing normalized [0, 1] data with 8-bit [0, 255] data is the most common conversion mistake.
+    let input_normalized = input_stats.max <= 1.0;
+    let template_normalized = template_stats.max <= 1.0;
+    if input_normalized != template_normalized {
+        diagnostics.warning(format!(
+            "value ranges differ: input is [{}, {}] but template is [{}, {}], \
+             one of them is possibly not normalized",
+            input_stats.min, input_stats.max, template_stats.min, template_stats.max
+        ));
+    }
+
+    let range = (input_stats.max.max(template_stats.max) - input_stats.min.min(template_stats.min))
+        .max(0.0) as f64;
+    let template_values = template.width as f64 * template.height as f64 * template.channels as f64;
+    let max_score = range * range * template_values;
+    if max_score > F32_EXACT_LIMIT {
+        diagnostics.warning(format!(
+            "scores may reach {max_score:e}, which exceeds f32 precision; \
+             consider normalizing the data to [0, 1]"
+        ));
+    }
+
+    if !diagnostics.has_errors() {
+        let result_width = (input.width - template.width + 1) as u64;
+        let result_height = (input.height - template.height + 1) as u64;
+
+        diagnostics.estimated_operations = result_width
+            * result_height
+            * template.width as u64
+            * template.height as u64
+            * template.channels as u64;
+
+        let input_size = (input.required_len() * size_of::<f32>()) as u64;
+        let template_size = (template.required_len() * size_of::<f32>()) as u64;
+        let result_size = result_width * result_height * size_of::<f32>() as u64;
+        diagnostics.estimated_memory = input_size + template_size + 2 * result_size;
+
+        // Inputs and results that exceed a storage buffer binding are split into tiles, but a tile
+        // needs the input under the template at one position, and the whole result is read back
+        // through one buffer.
+        let position_size = template.width as u64
+            * template.height as u64
+            * template.channels as u64
+            * size_of::<f32>() as u64;
+        if position_size > MAX_STORAGE_BUFFER_BINDING_SIZE {
+            diagnostics.error(format!(
+                "input under the template needs {position_size} bytes, \
+                 which exceeds the storage buffer limit of {MAX_STORAGE_BUFFER_BINDING_SIZE} bytes"
+            ));
+        }
+        if result_size > MAX_BUFFER_SIZE {
+            diagnostics.error(format!(
+                "result buffer needs {result_size} bytes, \
+                 which exceeds the buffer size limit of {MAX_BUFFER_SIZE} bytes"
+            ));
+        }
+    }
+
+    diagnostics
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+/// Errors that can occur when setting up the GPU, running matches on it and reading their results
+/// back, or matching images whose data or sizes are invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No adapter matching the requested options was found, e.g. on a machine without a GPU.
+    NoAdapter,
+    /// The adapter couldn't provide a device with the requested features and limits.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The result buffer couldn't be mapped for reading.
+    BufferMapping(wgpu::BufferAsyncError),
+    /// The GPU rejected the work of a match as invalid. Holds wgpu's description of the error.
+    Validation(String),
+    /// The GPU ran out of memory for the buffers or work of a match.
+    OutOfMemory,
+    /// The device was lost while the match was in flight, e.g. because the driver reset the GPU,
+    /// and the match wasn't rerun on a new one.
+    DeviceLost,
+    /// The data of an image doesn't have the number of samples its dimensions need.
+    DataLength { expected: usize, actual: usize },
+    /// The template is wider or higher than the input, so it fits nowhere in it. Sizes are
+    /// `(width, height)`.
+    TemplateTooLarge {
+        input: (u32, u32),
+        template: (u32, u32),
+    },
+    /// The input and the template have different numbers of channels, so their samples can't be
+    /// compared.
+    ChannelMismatch { input: u32, template: u32 },
+    /// The operation needs the GPU engine, but the matcher runs on the CPU engine, e.g. because it
+    /// fell back to it on a machine without a GPU.
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoAdapter => write!(f, "no suitable GPU adapter found"),
+            Error::RequestDevice(e) => write!(f, "device request failed: {e}"),
+            Error::BufferMapping(e) => write!(f, "reading the result failed: {e}"),
+            Error::Validation(description) => write!(f, "GPU validation failed: {description}"),
+            Error::OutOfMemory => write!(f, "the GPU ran out of memory"),
+            Error::DeviceLost => write!(f, "the GPU device was lost"),
+            Error::DataLength { expected, actual } => write!(
+                f,
+                "image data has {actual} samples, but its dimensions need {expected}"
+            ),
+            Error::TemplateTooLarge { input, template } => write!(
+                f,
+                "template of {}x{} is larger than the input of {}x{}",
+                template.0, template.1, input.0, input.1
+            ),
+            Error::ChannelMismatch { input, template } => write!(
+                f,
+                "template has {template} channels, but the input has {input}"
+            ),
+            Error::Unsupported => write!(f, "not available on the CPU engine"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoAdapter
+            | Error::Validation(_)
+            | Error::OutOfMemory
+            | Error::DeviceLost
+            | Error::DataLength { .. }
+            | Error::TemplateTooLarge { .. }
+            | Error::ChannelMismatch { .. }
+            | Error::Unsupported => None,
+            Error::RequestDevice(e) => Some(e),
+            Error::BufferMapping(e) => Some(e),
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        Error::RequestDevice(e)
+    }
+}
+
+impl From<wgpu::Error> for Error {
+    fn from(e: wgpu::Error) -> Self {
+        match e {
+            wgpu::Error::OutOfMemory { .. } => Error::OutOfMemory,
+            wgpu::Error::Validation { description, .. } => Error::Validation(description),
+        }
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for Error {
+    fn from(e: wgpu::BufferAsyncError) -> Self {
+        Error::BufferMapping(e)
+    }
+}
@@ -0,0 +1,37 @@
+//! Matching with prior knowledge of where the template is.
+
+/// Where a template is expected to be, e.g. where it was found in the previous frame, for
+/// [match_template_hinted](crate::TemplateMatcher::match_template_hinted).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchHint {
+    /// Top-left corner of the template where it was last found.
+    pub position: (u32, u32),
+    /// Largest distance in pixels that the template may have moved in either direction.
+    pub radius: u32,
+    /// Score of the template at the position when it was found there.
+    pub last_score: f32,
+}
+
+/// Best match found by [match_template_hinted](crate::TemplateMatcher::match_template_hinted).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HintedMatch {
+    /// Top-left corner of the template at the best match.
+    pub location: (u32, u32),
+    pub score: f32,
+    /// Whether the template was accepted at the hinted position without searching around it.
+    pub at_hint: bool,
+}
+
+impl HintedMatch {
+    /// Hint for matching the next frame, which expects the template to stay within `radius`
+    /// pixels of this match.
+    pub fn hint(&self, radius: u32) -> MatchHint {
+        MatchHint {
+            position: self.location,
+            radius,
+            last_score: self.score,
+        }
+    }
+}
@@ -6,10 +6,86 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use std::{borrow::Cow, mem::size_of};
+use std::{
+    borrow::Cow,
+    mem::size_of,
+    ops::{Index, Range, RangeInclusive},
+    sync::Arc,
+    time::Duration,
+};
 use wgpu::util::DeviceExt;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Re-export of the half-precision float type accepted by [Image].
+pub use half::f16;
+
+mod bank;
+mod context;
+mod cpu;
+pub mod diagnostics;
+mod error;
+mod hint;
+pub mod library;
+mod log_polar;
+mod motion;
+mod multi;
+#[cfg(feature = "npy")]
+mod npy;
+mod pipeline;
+mod pipelined;
+mod preprocess;
+mod pyramid;
+mod refine;
+mod scale;
+pub mod service;
+mod shared;
+mod sparse;
+mod stereo;
+mod stream;
+pub mod tracker;
+mod transform;
+#[cfg(feature = "validation")]
+pub mod validation;
+mod yuv;
+
+/// Re-export of the wgpu version used by this crate, for sharing devices and buffers with it.
+pub use wgpu;
+
+pub use bank::TemplateBank;
+pub use context::{GpuContext, TemplateMatcherBuilder};
+pub use diagnostics::{diagnose, Diagnostic, Diagnostics, Severity};
+pub use error::Error;
+pub use hint::{HintedMatch, MatchHint};
+pub use library::{Anchor, LibraryMatch, Template, TemplateLibrary};
+pub use motion::{MotionField, MotionVector};
+pub use multi::MultiMatcher;
+pub use pipeline::Capabilities;
+pub use pipelined::PipelinedMatcher;
+pub use preprocess::{Preprocess, PreprocessPipeline};
+pub use pyramid::{PyramidMatch, TemplatePyramid};
+pub use refine::AffineMatch;
+pub use scale::ScaledMatch;
+pub use service::{FrameResult, MatchService};
+pub use shared::SharedMatcher;
+pub use sparse::SparseTemplate;
+pub use stereo::DisparityMap;
+pub use stream::MatchStream;
+pub use tracker::{MultiTracker, TemplateTracker, TrackState, TrackUpdate};
+pub use transform::{Mirror, MirroredMatch, RotatedMatch, TransformMatch};
+pub use yuv::{PlanarFormat, PlanarFrame};
+
+use bank::{GpuVariants, Rendered};
+use cpu::CpuMatcher;
+use log_polar::LogPolarSearch;
+use pipeline::{
+    BinaryImages, DifferenceImages, FftImages, FftSizes, Kernels, LogPolarImages, PipelineKey,
+    PreprocessImages, PrunedImages, QuantizedImages, RefineImages, RenderImages, Source,
+    SparseImages, TemplateSums, TransformedImages,
+};
+use refine::Refinement;
+use transform::{Transform, Variant};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MatchTemplateMethod {
     SumOfAbsoluteDifferences,
     SumOfSquaredDifferences,
@@ -35,26 +111,25 @@ pub fn match_template<'a>(
 }
 
 /// Finds the smallest and largest values and their locations in an image.
+///
+/// NaN values are skipped, e.g. the scores of positions whose window covers a NaN sample with
+/// [NonFinite::Propagate]. If every value is NaN, the smallest value is reported as infinity and
+/// the largest as negative infinity, both at `(0, 0)`.
 pub fn find_extremes(input: &Image<'_>) -> Extremes {
-    let mut min_value = f32::MAX;
+    let mut min_value = f32::INFINITY;
     let mut min_value_location = (0, 0);
-    let mut max_value = f32::MIN;
+    let mut max_value = f32::NEG_INFINITY;
     let mut max_value_location = (0, 0);
 
-    for y in 0..input.height {
-        for x in 0..input.width {
-            let idx = (y * input.width) + x;
-            let value = input.data[idx as usize];
-
-            if value < min_value {
-                min_value = value;
-                min_value_location = (x, y);
-            }
+    for (x, y, value) in input.iter_pixels() {
+        if value < min_value {
+            min_value = value;
+            min_value_location = (x, y);
+        }
 
-            if value > max_value {
-                max_value = value;
-                max_value_location = (x, y);
-            }
+        if value > max_value {
+            max_value = value;
+            max_value_location = (x, y);
         }
     }
 
@@ -66,393 +141,6593 @@ pub fn find_extremes(input: &Image<'_>) -> Extremes {
     }
 }
 
-pub struct Image<'a> {
-    pub data: Cow<'a, [f32]>,
+/// Storage format of image samples on the GPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SampleFormat {
+    F32,
+    /// Half-precision floats, two per 32-bit word.
+    F16,
+    /// Unsigned bytes normalized to `[0, 1]`, four per 32-bit word.
+    U8,
+    /// Unsigned 16-bit integers normalized to `[0, 1]`, two per 32-bit word.
+    U16,
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for f32 {}
+    impl Sealed for half::f16 {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+}
+
+/// Sample types that images can be made of. Samples are uploaded to the GPU as they are and
+/// converted to `f32` in the shader.
+pub trait Sample: bytemuck::Pod + Send + Sync + private::Sealed {
+    const FORMAT: SampleFormat;
+
+    /// Converts the sample to the `f32` value it is matched as.
+    fn to_f32(self) -> f32;
+}
+
+impl Sample for f32 {
+    const FORMAT: SampleFormat = SampleFormat::F32;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+/// `f16` samples are uploaded packed two to a word and unpacked with `unpack2x16float`, so they
+/// don't need the `shader-f16` feature. The WGSL front end of wgpu 0.16 can't compile `f16`
+/// arithmetic, so differences are computed in `f32` even on adapters that support it.
+impl Sample for f16 {
+    const FORMAT: SampleFormat = SampleFormat::F16;
+
+    fn to_f32(self) -> f32 {
+        f16::to_f32(self)
+    }
+}
+
+impl Sample for u8 {
+    const FORMAT: SampleFormat = SampleFormat::U8;
+
+    /// `u8` samples are normalized to `[0, 1]`.
+    fn to_f32(self) -> f32 {
+        self as f32 / 255.0
+    }
+}
+
+impl Sample for u16 {
+    const FORMAT: SampleFormat = SampleFormat::U16;
+
+    /// `u16` samples are normalized to `[0, 1]`.
+    fn to_f32(self) -> f32 {
+        self as f32 / 65535.0
+    }
+}
+
+/// Image data with interleaved channels, e.g. `[r, g, b, r, g, b, ...]` for an RGB image.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Image<'a, T: Sample = f32> {
+    pub data: Cow<'a, [T]>,
     pub width: u32,
     pub height: u32,
+    pub channels: u32,
+    /// Distance between the starts of consecutive rows, in values.
+    /// [None] means the rows are tightly packed, i.e. `width * channels`.
+    pub stride: Option<u32>,
 }
 
-impl<'a> Image<'a> {
-    pub fn new(data: impl Into<Cow<'a, [f32]>>, width: u32, height: u32) -> Self {
+/// An [Image] that owns its samples, like the results of matches, which can be stored anywhere
+/// without tying it to the lifetime of borrowed data. [Image::into_owned] and [Image::to_owned]
+/// turn borrowed images into owned ones.
+pub type ImageOwned<T = f32> = Image<'static, T>;
+
+impl<'a, T: Sample> Image<'a, T> {
+    /// Creates a single-channel (grayscale) image.
+    pub fn new(data: impl Into<Cow<'a, [T]>>, width: u32, height: u32) -> Self {
+        Self::with_channels(data, width, height, 1)
+    }
+
+    /// Creates an image with the given number of interleaved channels.
+    pub fn with_channels(
+        data: impl Into<Cow<'a, [T]>>,
+        width: u32,
+        height: u32,
+        channels: u32,
+    ) -> Self {
         Self {
             data: data.into(),
             width,
             height,
+            channels,
+            stride: None,
         }
     }
-}
 
-#[cfg(feature = "image")]
-impl<'a> From<&'a image::ImageBuffer<image::Luma<f32>, Vec<f32>>> for Image<'a> {
-    fn from(img: &'a image::ImageBuffer<image::Luma<f32>, Vec<f32>>) -> Self {
-        Self {
-            data: Cow::Borrowed(img),
-            width: img.width(),
-            height: img.height(),
+    /// Like [new](Self::new), but returns [Error::DataLength] unless `data` holds exactly
+    /// `width * height` samples, e.g. because of a wrong width.
+    pub fn try_new(data: impl Into<Cow<'a, [T]>>, width: u32, height: u32) -> Result<Self, Error> {
+        Self::try_with_channels(data, width, height, 1)
+    }
+
+    /// Like [with_channels](Self::with_channels), but returns [Error::DataLength] unless `data`
+    /// holds exactly `width * height * channels` samples.
+    pub fn try_with_channels(
+        data: impl Into<Cow<'a, [T]>>,
+        width: u32,
+        height: u32,
+        channels: u32,
+    ) -> Result<Self, Error> {
+        let image = Self::with_channels(data, width, height, channels);
+        let expected = image.required_len();
+        if image.data.len() != expected {
+            return Err(Error::DataLength {
+                expected,
+                actual: image.data.len(),
+            });
         }
+        Ok(image)
     }
-}
 
-#[derive(Copy, Clone, Debug)]
-pub struct Extremes {
-    pub min_value: f32,
-    pub max_value: f32,
-    pub min_value_location: (u32, u32),
-    pub max_value_location: (u32, u32),
-}
+    /// Checks that the data holds enough samples for the dimensions and the stride of the image,
+    /// which matching requires. Longer data is allowed, e.g. for views into a larger frame.
+    pub fn validate(&self) -> Result<(), Error> {
+        let expected = self.required_len();
+        if self.data.len() < expected {
+            return Err(Error::DataLength {
+                expected,
+                actual: self.data.len(),
+            });
+        }
+        Ok(())
+    }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct ShaderUniforms {
-    input_width: u32,
-    input_height: u32,
-    template_width: u32,
-    template_height: u32,
-}
+    /// Panics with the error of [validate](Self::validate), naming the image with `label`.
+    #[track_caller]
+    pub(crate) fn assert_valid(&self, label: &str) {
+        if let Err(error) = self.validate() {
+            panic!("invalid {label}: {error}");
+        }
+    }
 
-pub struct TemplateMatcher {
-    instance: wgpu::Instance,
-    adapter: wgpu::Adapter,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    shader: wgpu::ShaderModule,
-    bind_group_layout: wgpu::BindGroupLayout,
-    pipeline_layout: wgpu::PipelineLayout,
-
-    last_pipeline: Option<wgpu::ComputePipeline>,
-    last_method: Option<MatchTemplateMethod>,
-
-    last_input_size: (u32, u32),
-    last_template_size: (u32, u32),
-    last_result_size: (u32, u32),
+    /// Sets the row stride (pitch) of the image, in values. Use this for padded rows or for
+    /// sub-views of a larger frame.
+    pub fn with_stride(mut self, stride: u32) -> Self {
+        self.stride = Some(stride);
+        self
+    }
 
-    uniform_buffer: wgpu::Buffer,
-    input_buffer: Option<wgpu::Buffer>,
-    template_buffer: Option<wgpu::Buffer>,
-    result_buffer: Option<wgpu::Buffer>,
-    staging_buffer: Option<wgpu::Buffer>,
-    bind_group: Option<wgpu::BindGroup>,
+    /// Distance between the starts of consecutive rows, in values.
+    pub fn row_stride(&self) -> u32 {
+        self.stride.unwrap_or(self.width * self.channels)
+    }
 
-    matching_ongoing: bool,
-}
+    /// Number of values needed to hold all pixels of the image, taking the stride into account.
+    pub fn required_len(&self) -> usize {
+        if self.width == 0 || self.height == 0 {
+            return 0;
+        }
 
-impl Default for TemplateMatcher {
-    fn default() -> Self {
-        Self::new()
+        (self.height as usize - 1) * self.row_stride() as usize
+            + (self.width * self.channels) as usize
     }
-}
-
-impl TemplateMatcher {
-    pub fn new() -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
 
-        let adapter = pollster::block_on(async {
-            instance
-                .request_adapter(&wgpu::RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::HighPerformance,
-                    compatible_surface: None,
-                    force_fallback_adapter: false,
-                })
-                .await
-                .expect("Adapter request failed")
-        });
+    /// Index in [data](Self::data) of channel `c` of the pixel at `(x, y)`, taking the stride into
+    /// account, or [None] if the pixel or the channel is out of bounds.
+    fn sample_index(&self, x: u32, y: u32, c: u32) -> Option<usize> {
+        (x < self.width && y < self.height && c < self.channels)
+            .then(|| y as usize * self.row_stride() as usize + (x * self.channels + c) as usize)
+    }
 
-        let (device, queue) = pollster::block_on(async {
-            adapter
-                .request_device(
-                    &wgpu::DeviceDescriptor {
-                        label: None,
-                        features: wgpu::Features::empty(),
-                        limits: wgpu::Limits::default(),
-                    },
-                    None,
-                )
-                .await
-                .expect("Device request failed")
-        });
+    /// Value of the pixel at `(x, y)`, e.g. the score of a match at that location in a result, or
+    /// its first channel if the image has several. [u8] and [u16] samples are normalized to
+    /// `[0, 1]`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `(x, y)` is outside the image.
+    pub fn at(&self, x: u32, y: u32) -> f32 {
+        self.get(x, y).unwrap_or_else(|| {
+            panic!(
+                "pixel ({x}, {y}) is outside the {}x{} image",
+                self.width, self.height
+            )
+        })
+    }
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/matching.wgsl"));
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
+    /// Like [at](Self::at), but returns [None] if `(x, y)` is outside the image.
+    pub fn get(&self, x: u32, y: u32) -> Option<f32> {
+        self.get_channel(x, y, 0)
+    }
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    /// Value of channel `c` of the pixel at `(x, y)`, or [None] if either is out of bounds.
+    pub fn get_channel(&self, x: u32, y: u32, c: u32) -> Option<f32> {
+        let index = self.sample_index(x, y, c)?;
+        self.data.get(index).map(|&sample| sample.to_f32())
+    }
 
-        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("uniform_buffer"),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            size: size_of::<ShaderUniforms>() as _,
-            mapped_at_creation: false,
-        });
+    /// Iterates over the rows of the image from top to bottom. Each row holds the interleaved
+    /// samples of its pixels, without the padding of the stride.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        let stride = self.row_stride() as usize;
+        let row_len = (self.width * self.channels) as usize;
+        (0..self.height as usize).map(move |y| &self.data[y * stride..y * stride + row_len])
+    }
 
-        Self {
-            instance,
-            adapter,
-            device,
-            queue,
-            shader,
-            pipeline_layout,
-            bind_group_layout,
-            last_pipeline: None,
-            last_method: None,
-            last_input_size: (0, 0),
-            last_template_size: (0, 0),
-            last_result_size: (0, 0),
-            uniform_buffer,
-            input_buffer: None,
-            template_buffer: None,
-            result_buffer: None,
-            staging_buffer: None,
-            bind_group: None,
-            matching_ongoing: false,
-        }
+    /// Iterates over the pixels of the image row by row, yielding `(x, y, value)` with the value
+    /// that [at](Self::at) returns. Images without channels have no values, so nothing is yielded
+    /// for them.
+    pub fn iter_pixels(&self) -> impl Iterator<Item = (u32, u32, f32)> + '_ {
+        // Rows of images without channels are empty, so any chunk size works for them.
+        let channels = self.channels.max(1) as usize;
+        self.rows().zip(0..).flat_map(move |(row, y)| {
+            row.chunks_exact(channels)
+                .zip(0..)
+                .map(move |(pixel, x)| (x, y, pixel[0].to_f32()))
+        })
     }
 
-    /// Waits for the latest [match_template] execution and returns the result.
-    /// Returns [None] if no matching was started.
-    pub fn wait_for_result(&mut self) -> Option<Image<'static>> {
-        if !self.matching_ongoing {
+    /// Borrows a rectangular region of the image without copying, or returns [None] if the region
+    /// isn't within the image.
+    fn region(&self, region: Region) -> Option<Image<'_, T>> {
+        let right = region.x.checked_add(region.width)?;
+        let bottom = region.y.checked_add(region.height)?;
+        if right > self.width || bottom > self.height {
             return None;
         }
-        self.matching_ongoing = false;
 
-        let (result_width, result_height) = self.last_result_size;
+        let stride = self.row_stride();
+        let offset =
+            region.y as usize * stride as usize + region.x as usize * self.channels as usize;
 
-        let buffer_slice = self.staging_buffer.as_ref().unwrap().slice(..);
-        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
-        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
-
-        self.device.poll(wgpu::Maintain::Wait);
+        Some(Image {
+            data: Cow::Borrowed(&self.data[offset.min(self.data.len())..]),
+            width: region.width,
+            height: region.height,
+            channels: self.channels,
+            stride: Some(stride),
+        })
+    }
 
-        pollster::block_on(async {
-            let result;
+    /// Like [region](Self::region), but panics if the region isn't within the image.
+    #[track_caller]
+    fn expect_region(&self, region: Region) -> Image<'_, T> {
+        self.region(region).unwrap_or_else(|| {
+            panic!(
+                "region {region:?} is outside the {}x{} image",
+                self.width, self.height
+            )
+        })
+    }
 
-            if let Some(Ok(())) = receiver.receive().await {
-                let data = buffer_slice.get_mapped_range();
-                result = bytemuck::cast_slice(&data).to_vec();
-                drop(data);
-                self.staging_buffer.as_ref().unwrap().unmap();
-            } else {
-                result = vec![0.0; (result_width * result_height) as usize]
-            };
+    /// Borrows the `width` by `height` pixels whose top-left corner is at `(x, y)` as an image of
+    /// its own, without copying them. The view keeps the row stride of this image.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the area isn't within the image.
+    #[track_caller]
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> Image<'_, T> {
+        self.expect_region(Region::new(x, y, width, height))
+    }
 
-            Some(Image::new(result, result_width as _, result_height as _))
-        })
+    /// Like [view](Self::view), but returns [None] if the area isn't within the image.
+    pub fn get_view(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Image<'_, T>> {
+        self.region(Region::new(x, y, width, height))
     }
 
-    /// Slides a template over the input and scores the match at each point using the requested method.
-    /// To get the result of the matching, call [wait_for_result].
-    pub fn match_template<'a>(
-        &mut self,
-        input: impl Into<Image<'a>>,
-        template: impl Into<Image<'a>>,
-        method: MatchTemplateMethod,
-    ) {
-        if self.matching_ongoing {
-            // Discard previous result if not collected.
-            self.wait_for_result();
-        }
+    /// Smallest and largest sample, mean and standard deviation over all samples of all channels,
+    /// with samples converted like [at](Self::at) does. Useful for choosing thresholds for the
+    /// scores of a result. All of them are NaN for an empty image.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data is shorter than the dimensions of the image need, see
+    /// [validate](Self::validate).
+    #[track_caller]
+    pub fn stats(&self) -> ImageStats {
+        self.assert_valid("image");
 
-        let input = input.into();
-        let template = template.into();
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+        let (mut sum, mut sum_sq, mut count) = (0.0f64, 0.0f64, 0usize);
 
-        if self.last_pipeline.is_none() || self.last_method != Some(method) {
-            self.last_method = Some(method);
+        for value in self.rows().flatten().map(|sample| sample.to_f32()) {
+            min = min.min(value);
+            max = max.max(value);
+            sum += value as f64;
+            sum_sq += value as f64 * value as f64;
+            count += 1;
+        }
 
-            let entry_point = match method {
-                MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad",
-                MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd",
+        if count == 0 {
+            return ImageStats {
+                min: f32::NAN,
+                max: f32::NAN,
+                mean: f32::NAN,
+                std_dev: f32::NAN,
             };
+        }
 
-            self.last_pipeline = Some(self.device.create_compute_pipeline(
-                &wgpu::ComputePipelineDescriptor {
-                    label: None,
-                    layout: Some(&self.pipeline_layout),
-                    module: &self.shader,
-                    entry_point,
-                },
-            ));
+        let mean = sum / count as f64;
+        let variance = (sum_sq / count as f64 - mean * mean).max(0.0);
+        ImageStats {
+            min,
+            max,
+            mean: mean as f32,
+            std_dev: variance.sqrt() as f32,
         }
+    }
 
-        let mut buffers_changed = false;
+    /// Converts the image into one that owns its samples. Owned samples are moved without copying,
+    /// and borrowed ones are copied into a tightly packed image, like [crop](Self::crop) does.
+    pub fn into_owned(self) -> ImageOwned<T> {
+        match self.data {
+            Cow::Owned(data) => Image {
+                data: Cow::Owned(data),
+                width: self.width,
+                height: self.height,
+                channels: self.channels,
+                stride: self.stride,
+            },
+            Cow::Borrowed(_) => self.to_owned(),
+        }
+    }
 
-        let input_size = (input.width, input.height);
-        if self.input_buffer.is_none() || self.last_input_size != input_size {
-            buffers_changed = true;
+    /// Copies the samples into a tightly packed image that owns them.
+    pub fn to_owned(&self) -> ImageOwned<T> {
+        self.crop(0, 0, self.width, self.height)
+    }
 
-            self.last_input_size = input_size;
+    /// Like [view](Self::view), but copies the pixels into a tightly packed image, e.g. to keep a
+    /// part of a frame as a template after the frame is gone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the area isn't within the image.
+    #[track_caller]
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image<'static, T> {
+        let view = self.view(x, y, width, height);
+        let data: Vec<T> = view.rows().flatten().copied().collect();
+        Image::with_channels(data, width, height, self.channels)
+    }
+}
 
-            self.input_buffer = Some(self.device.create_buffer_init(
-                &wgpu::util::BufferInitDescriptor {
-                    label: Some("input_buffer"),
-                    contents: bytemuck::cast_slice(&input.data),
-                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-                },
-            ));
-        } else {
-            self.queue.write_buffer(
-                self.input_buffer.as_ref().unwrap(),
-                0,
-                bytemuck::cast_slice(&input.data),
-            );
+/// A rectangular area of an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
         }
+    }
 
-        let template_size = (template.width, template.height);
-        if self.template_buffer.is_none() || self.last_template_size != template_size {
-            self.queue.write_buffer(
-                &self.uniform_buffer,
-                0,
-                bytemuck::cast_slice(&[ShaderUniforms {
-                    input_width: input.width,
-                    input_height: input.height,
-                    template_width: template.width,
-                    template_height: template.height,
-                }]),
-            );
-            buffers_changed = true;
+    /// Region of the input searched by [match_template_near](TemplateMatcher::match_template_near):
+    /// the template positions at most `radius` pixels away from `center` in both directions,
+    /// clamped to the input, and the pixels under them. Its top-left corner is the origin of the
+    /// result.
+    pub fn search_window(
+        (input_width, input_height): (u32, u32),
+        (template_width, template_height): (u32, u32),
+        center: (u32, u32),
+        radius: u32,
+    ) -> Self {
+        assert_template_fits(
+            (input_width, input_height),
+            (template_width, template_height),
+        );
 
-            self.last_template_size = template_size;
+        let last_x = input_width - template_width;
+        let last_y = input_height - template_height;
+        let (center_x, center_y) = (center.0.min(last_x), center.1.min(last_y));
 
-            self.template_buffer = Some(self.device.create_buffer_init(
-                &wgpu::util::BufferInitDescriptor {
-                    label: Some("template_buffer"),
-                    contents: bytemuck::cast_slice(&template.data),
-                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-                },
-            ));
-        } else {
-            self.queue.write_buffer(
-                self.template_buffer.as_ref().unwrap(),
-                0,
-                bytemuck::cast_slice(&template.data),
-            );
+        let x = center_x.saturating_sub(radius);
+        let y = center_y.saturating_sub(radius);
+        let right = center_x.saturating_add(radius).min(last_x);
+        let bottom = center_y.saturating_add(radius).min(last_y);
+
+        Self {
+            x,
+            y,
+            width: right - x + template_width,
+            height: bottom - y + template_height,
         }
+    }
+}
 
-        let result_width = input.width - template.width + 1;
-        let result_height = input.height - template.height + 1;
-        let result_buf_size = (result_width * result_height) as u64 * size_of::<f32>() as u64;
+impl<T: Sample> std::fmt::Debug for Image<'_, T> {
+    /// Prints the dimensions and [statistics](Image::stats) of the image instead of its samples.
+    /// Images whose data is too short for their dimensions, which the unchecked constructors can
+    /// create, get the length of their data printed instead of statistics.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Image");
+        debug
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("channels", &self.channels)
+            .field("stride", &self.row_stride())
+            .field("format", &T::FORMAT);
+        match self.validate() {
+            Ok(()) => debug.field("stats", &self.stats()),
+            Err(_) => debug.field("data_len", &self.data.len()),
+        };
+        debug.finish()
+    }
+}
 
-        if buffers_changed {
-            self.last_result_size = (result_width, result_height);
+impl<T: Sample> Index<(u32, u32)> for Image<'_, T> {
+    type Output = T;
 
-            self.result_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("result_buffer"),
-                usage: wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::COPY_SRC
-                    | wgpu::BufferUsages::COPY_DST,
-                size: result_buf_size,
-                mapped_at_creation: false,
-            }));
+    /// Sample of the pixel at `(x, y)`, or of its first channel if the image has several, as it is
+    /// stored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `(x, y)` is outside the image.
+    fn index(&self, (x, y): (u32, u32)) -> &T {
+        match self.sample_index(x, y, 0) {
+            Some(index) => &self.data[index],
+            None => panic!(
+                "pixel ({x}, {y}) is outside the {}x{} image",
+                self.width, self.height
+            ),
+        }
+    }
+}
 
-            self.staging_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("staging_buffer"),
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                size: result_buf_size,
-                mapped_at_creation: false,
-            }));
+impl<'a, T: Sample> From<&'a Image<'_, T>> for Image<'a, T> {
+    fn from(img: &'a Image<'_, T>) -> Self {
+        Self {
+            data: Cow::Borrowed(&img.data),
+            width: img.width,
+            height: img.height,
+            channels: img.channels,
+            stride: img.stride,
+        }
+    }
+}
 
-            self.bind_group = Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &self.bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: self.input_buffer.as_ref().unwrap().as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: self.template_buffer.as_ref().unwrap().as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: self.result_buffer.as_ref().unwrap().as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: self.uniform_buffer.as_entire_binding(),
-                    },
-                ],
-            }));
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::ImageBuffer<image::Luma<f32>, Vec<f32>>> for Image<'a> {
+    fn from(img: &'a image::ImageBuffer<image::Luma<f32>, Vec<f32>>) -> Self {
+        Self {
+            data: Cow::Borrowed(img),
+            width: img.width(),
+            height: img.height(),
+            channels: 1,
+            stride: None,
         }
+    }
+}
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("encoder"),
-            });
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::ImageBuffer<image::Rgb<f32>, Vec<f32>>> for Image<'a> {
+    fn from(img: &'a image::ImageBuffer<image::Rgb<f32>, Vec<f32>>) -> Self {
+        Self {
+            data: Cow::Borrowed(img),
+            width: img.width(),
+            height: img.height(),
+            channels: 3,
+            stride: None,
+        }
+    }
+}
 
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("compute_pass"),
-            });
-            compute_pass.set_pipeline(self.last_pipeline.as_ref().unwrap());
-            compute_pass.set_bind_group(0, self.bind_group.as_ref().unwrap(), &[]);
-            compute_pass.dispatch_workgroups(
-                (result_width as f32 / 16.0).ceil() as u32,
-                (result_height as f32 / 16.0).ceil() as u32,
-                1,
-            );
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::ImageBuffer<image::Rgba<f32>, Vec<f32>>> for Image<'a> {
+    fn from(img: &'a image::ImageBuffer<image::Rgba<f32>, Vec<f32>>) -> Self {
+        Self {
+            data: Cow::Borrowed(img),
+            width: img.width(),
+            height: img.height(),
+            channels: 4,
+            stride: None,
         }
+    }
+}
 
-        encoder.copy_buffer_to_buffer(
-            self.result_buffer.as_ref().unwrap(),
-            0,
-            self.staging_buffer.as_ref().unwrap(),
-            0,
-            result_buf_size,
-        );
+/// Converts 8-bit RGB(A) pixels into normalized luma using the same weights as the `image` crate.
+#[cfg(feature = "image")]
+fn rgb8_to_luma32f(pixels: &[u8], channels: usize) -> Vec<f32> {
+    pixels
+        .chunks_exact(channels)
+        .map(|p| (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 255.0)
+        .collect()
+}
+
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::GrayImage> for Image<'a> {
+    fn from(img: &'a image::GrayImage) -> Self {
+        let data: Vec<f32> = img.iter().map(|&v| v as f32 / 255.0).collect();
+        Self::new(data, img.width(), img.height())
+    }
+}
+
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::RgbImage> for Image<'a> {
+    /// Converts the image to grayscale.
+    fn from(img: &'a image::RgbImage) -> Self {
+        Self::new(rgb8_to_luma32f(img, 3), img.width(), img.height())
+    }
+}
+
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::RgbaImage> for Image<'a> {
+    /// Converts the image to grayscale. Alpha is ignored.
+    fn from(img: &'a image::RgbaImage) -> Self {
+        Self::new(rgb8_to_luma32f(img, 4), img.width(), img.height())
+    }
+}
+
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::DynamicImage> for Image<'a> {
+    /// Converts the image to grayscale.
+    fn from(img: &'a image::DynamicImage) -> Self {
+        let luma = img.to_luma32f();
+        let (width, height) = luma.dimensions();
+        Self::new(luma.into_raw(), width, height)
+    }
+}
+
+#[cfg(feature = "image")]
+impl<'a> Image<'a, u8> {
+    /// Borrows the bytes of a grayscale image without converting them, so that they are uploaded
+    /// as they are and normalized on the GPU.
+    pub fn from_luma8(img: &'a image::GrayImage) -> Self {
+        Self::new(img.as_raw().as_slice(), img.width(), img.height())
+    }
+
+    /// Borrows the bytes of an RGB image as a three-channel image without converting them.
+    pub fn from_rgb8(img: &'a image::RgbImage) -> Self {
+        Self::with_channels(img.as_raw().as_slice(), img.width(), img.height(), 3)
+    }
+
+    /// Borrows the bytes of an RGBA image as a four-channel image without converting them.
+    pub fn from_rgba8(img: &'a image::RgbaImage) -> Self {
+        Self::with_channels(img.as_raw().as_slice(), img.width(), img.height(), 4)
+    }
+}
+
+#[cfg(feature = "image")]
+impl<'a> Image<'a, u16> {
+    /// Borrows the samples of a 16-bit grayscale image without converting them.
+    pub fn from_luma16(img: &'a image::ImageBuffer<image::Luma<u16>, Vec<u16>>) -> Self {
+        Self::new(img.as_raw().as_slice(), img.width(), img.height())
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl<'a> From<ndarray::ArrayView2<'a, f32>> for Image<'a> {
+    /// Creates a single-channel image from a `(height, width)` array.
+    /// The data is borrowed if the array is in standard layout, and copied otherwise.
+    fn from(array: ndarray::ArrayView2<'a, f32>) -> Self {
+        let (height, width) = array.dim();
+        let data = match array.to_slice() {
+            Some(slice) => Cow::Borrowed(slice),
+            None => Cow::Owned(array.iter().copied().collect()),
+        };
+
+        Self::new(data, width as u32, height as u32)
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl Image<'_> {
+    /// Copies a single-channel image into a `(height, width)` array.
+    ///
+    /// # Panics
+    /// Panics if the image has more than one channel.
+    pub fn to_array2(&self) -> ndarray::Array2<f32> {
+        assert_eq!(
+            self.channels, 1,
+            "only single-channel images can be converted to Array2"
+        );
+
+        let stride = self.row_stride() as usize;
+        let width = self.width as usize;
+
+        ndarray::Array2::from_shape_fn((self.height as usize, width), |(y, x)| {
+            self.data[y * stride + x]
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extremes {
+    pub min_value: f32,
+    pub max_value: f32,
+    pub min_value_location: (u32, u32),
+    pub max_value_location: (u32, u32),
+}
+
+/// Summary statistics of the samples of an image, returned by [Image::stats].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Population standard deviation.
+    pub std_dev: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShaderUniforms {
+    input_width: u32,
+    input_height: u32,
+    template_width: u32,
+    template_height: u32,
+    input_stride: u32,
+    template_stride: u32,
+    channels: u32,
+    input_batch_stride: u32,
+    /// Whether differences that aren't finite count as zero, see [NonFinite::Ignore].
+    ignore_non_finite: u32,
+    /// Whether pruned matching only prunes with the bound it was given, see
+    /// [set_deterministic](TemplateMatcher::set_deterministic).
+    deterministic: u32,
+    padding: [u32; 2],
+}
+
+impl ShaderUniforms {
+    fn new(input: &ImageLayout, template: &ImageLayout) -> Self {
+        Self {
+            input_width: input.width,
+            input_height: input.height,
+            template_width: template.width,
+            template_height: template.height,
+            input_stride: input.stride,
+            template_stride: template.stride,
+            channels: template.channels,
+            input_batch_stride: input.batch_stride,
+            ignore_non_finite: 0,
+            deterministic: 0,
+            padding: [0; 2],
+        }
+    }
+
+    fn with_non_finite(self, policy: NonFinite) -> Self {
+        Self {
+            ignore_non_finite: (policy == NonFinite::Ignore) as u32,
+            ..self
+        }
+    }
+
+    fn with_deterministic(self, deterministic: bool) -> Self {
+        Self {
+            deterministic: deterministic as u32,
+            ..self
+        }
+    }
+}
+
+/// Checks that a template of the given size fits in the input, i.e. that the result has at least
+/// one position.
+fn check_template_fits(input: (u32, u32), template: (u32, u32)) -> Result<(), Error> {
+    if template.0 > input.0 || template.1 > input.1 {
+        return Err(Error::TemplateTooLarge { input, template });
+    }
+    Ok(())
+}
+
+/// Checks that the template can be matched against the input: that the data of both is long
+/// enough for their dimensions, that they have the same number of channels, and that the template
+/// fits in the input.
+fn check_images<I: Sample, T: Sample>(
+    input: &Image<'_, I>,
+    template: &Image<'_, T>,
+) -> Result<(), Error> {
+    input.validate()?;
+    template.validate()?;
+    if input.channels != template.channels {
+        return Err(Error::ChannelMismatch {
+            input: input.channels,
+            template: template.channels,
+        });
+    }
+    check_template_fits(
+        (input.width, input.height),
+        (template.width, template.height),
+    )
+}
+
+/// Panics with the error of [check_template_fits].
+#[track_caller]
+fn assert_template_fits(input: (u32, u32), template: (u32, u32)) {
+    if let Err(error) = check_template_fits(input, template) {
+        panic!("{error}");
+    }
+}
+
+/// Returns the bytes of the samples, padded to a multiple of 4 bytes as required for buffer copies.
+fn upload_bytes<T: Sample>(samples: &[T]) -> Cow<'_, [u8]> {
+    let bytes: &[u8] = bytemuck::cast_slice(samples);
+
+    if bytes.len().is_multiple_of(4) {
+        Cow::Borrowed(bytes)
+    } else {
+        let mut padded = bytes.to_vec();
+        padded.resize(bytes.len().next_multiple_of(4), 0);
+        Cow::Owned(padded)
+    }
+}
+
+/// A single-channel `f32` image that lives in a GPU buffer. The samples are tightly packed at the
+/// start of the buffer, which can be larger than the image.
+pub struct GpuImage {
+    pub buffer: wgpu::Buffer,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Handle to a match started with [TemplateMatcher::match_template], for collecting its result with
+/// [TemplateMatcher::wait_for_job] or [TemplateMatcher::poll_job]. Handles are only meaningful to
+/// the matcher that returned them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MatchJob(u64);
+
+/// Results of matching every template of a set against every input of a batch, indexed by
+/// `(input_index, template_index)`.
+pub struct MatchMatrix {
+    results: Vec<Image<'static>>,
+    templates: usize,
+}
+
+impl MatchMatrix {
+    /// Number of inputs that were matched.
+    pub fn inputs(&self) -> usize {
+        self.results.len().checked_div(self.templates).unwrap_or(0)
+    }
+
+    /// Number of templates that were matched.
+    pub fn templates(&self) -> usize {
+        self.templates
+    }
+
+    /// Result of matching the given template against the given input.
+    pub fn get(&self, input: usize, template: usize) -> Option<&Image<'static>> {
+        if template >= self.templates {
+            return None;
+        }
+        self.results.get(input * self.templates + template)
+    }
+
+    /// Results of matching all templates against the given input, in the order of the templates.
+    pub fn input_results(&self, input: usize) -> &[Image<'static>] {
+        &self.results[input * self.templates..(input + 1) * self.templates]
+    }
+
+    /// Returns the results ordered by input and then by template.
+    pub fn into_vec(self) -> Vec<Image<'static>> {
+        self.results
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for MatchMatrix {
+    type Output = Image<'static>;
+
+    fn index(&self, (input, template): (usize, usize)) -> &Self::Output {
+        self.get(input, template)
+            .expect("match matrix index out of bounds")
+    }
+}
+
+/// Describes how an image is laid out on the GPU. Buffers and bind groups are recreated when this
+/// changes between calls.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct ImageLayout {
+    width: u32,
+    height: u32,
+    channels: u32,
+    stride: u32,
+    source: Source,
+    /// Number of equally laid out images stored one after another.
+    batch: u32,
+    /// Distance between the starts of consecutive images of a batch, in values.
+    batch_stride: u32,
+}
+
+impl ImageLayout {
+    /// Layout of an image uploaded into a storage buffer.
+    fn of<T: Sample>(image: &Image<'_, T>) -> Self {
+        Self {
+            width: image.width,
+            height: image.height,
+            channels: image.channels,
+            stride: image.row_stride(),
+            source: Source::Buffer(T::FORMAT),
+            batch: 1,
+            batch_stride: 0,
+        }
+    }
+}
+
+/// Parameters of a single match, from which its result size, pipeline and uniforms are derived.
+/// They are built anew from the layouts of the images of each call, so that nothing derived from
+/// the images of an earlier call, e.g. with an input of another size, is used by a later one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct MatchParams {
+    input: ImageLayout,
+    template: ImageLayout,
+    method: MatchTemplateMethod,
+}
+
+impl MatchParams {
+    /// # Panics
+    ///
+    /// Panics if the images have different numbers of channels, or if the template doesn't fit in
+    /// the input.
+    #[track_caller]
+    fn new(input: ImageLayout, template: ImageLayout, method: MatchTemplateMethod) -> Self {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+        assert_template_fits(
+            (input.width, input.height),
+            (template.width, template.height),
+        );
+        Self {
+            input,
+            template,
+            method,
+        }
+    }
+
+    fn result_size(&self) -> (u32, u32) {
+        (
+            self.input.width - self.template.width + 1,
+            self.input.height - self.template.height + 1,
+        )
+    }
+
+    /// Size of the result in bytes.
+    fn result_bytes(&self) -> u64 {
+        let (width, height) = self.result_size();
+        (width * height) as u64 * size_of::<f32>() as u64
+    }
+
+    fn key(&self) -> PipelineKey {
+        PipelineKey::new(
+            self.method,
+            self.input.source,
+            self.template.source,
+            (self.template.width, self.template.height),
+        )
+    }
+
+    fn uniforms(&self, non_finite: NonFinite) -> ShaderUniforms {
+        ShaderUniforms::new(&self.input, &self.template).with_non_finite(non_finite)
+    }
+}
+
+impl Default for ImageLayout {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            channels: 0,
+            stride: 0,
+            source: Source::Buffer(SampleFormat::F32),
+            batch: 1,
+            batch_stride: 0,
+        }
+    }
+}
+
+/// Where images are uploaded to for matching.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageStorage {
+    /// Storage buffers, read with linear indexing.
+    #[default]
+    Buffer,
+    /// 2D textures, which can be faster on GPUs whose caches are optimized for 2D locality.
+    /// Images that have no matching texture format, e.g. 3-channel ones, or that exceed the
+    /// device's texture size limit still use buffers.
+    Texture,
+}
+
+/// Precision that images are uploaded and stored with for matching. This only sets the storage
+/// format of the samples: they are converted to `f32` when loaded in the shader, and differences
+/// and scores are computed and accumulated in `f32` with every precision.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Precision {
+    /// Samples are uploaded in their own format.
+    #[default]
+    Full,
+    /// `f32` samples are stored as `f16`, which halves the memory traffic of matching. The samples
+    /// keep about three significant digits, and must be within the range of `f16`, i.e. below 65504
+    /// in magnitude. `u8` and `f16` samples are uploaded as they are.
+    ///
+    /// Computing in `f16` isn't supported, since the WGSL front end of wgpu 0.16 can't compile
+    /// `f16` arithmetic.
+    HalfStorage,
+    /// `f32` and `f16` samples are quantized to `u8` before uploading, mapping `[0, 1]` to
+    /// `0..=255` like `u8` samples are normalized, and clamping samples outside that range. This
+    /// quarters the memory traffic of `f32` samples.
+    ///
+    /// Images matched from storage buffers, e.g. by [match_template](TemplateMatcher::match_template),
+    /// are scored with integer arithmetic on four packed samples at a time. Scores are scaled like
+    /// those of the normalized samples, but only as accurate as the quantized samples are, which
+    /// is usually enough to find the location of the best match.
+    Quantized,
+}
+
+/// How matching treats samples that are NaN or infinite, e.g. from a bad conversion.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum NonFinite {
+    /// Differences are summed as they are, so every position whose window covers a non-finite
+    /// sample gets a NaN or infinite score. [find_extremes] skips NaN scores. With
+    /// [MatchAlgorithm::Fft], a non-finite sample spoils the scores of all positions instead.
+    #[default]
+    Propagate,
+    /// Differences that aren't finite, i.e. those involving a NaN or infinite sample, count as
+    /// zero, as if the sample were masked out. Matching doesn't use FFTs with this policy, and
+    /// [Precision::Quantized] has no non-finite samples left to ignore after quantizing them.
+    Ignore,
+}
+
+/// What a GPU matcher does when its device is lost, e.g. when the driver resets the GPU after a
+/// timeout.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceRecovery {
+    /// The matcher keeps the lost device, so the results of matches fail to be read back from then
+    /// on.
+    #[default]
+    Disabled,
+    /// The matcher replaces the lost device with a new one and reruns each match that was in
+    /// flight on it up to `retries` times. Matches that aren't rerun fail with
+    /// [Error::DeviceLost].
+    Recreate { retries: u32 },
+}
+
+/// Time the GPU spent on the stages of a match, measured with timestamp queries.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Copying the images uploaded by the match call into GPU memory, from when the first upload
+    /// was submitted. Zero if the images were uploaded beforehand, e.g. with
+    /// [set_input](TemplateMatcher::set_input).
+    pub upload: Duration,
+    /// Computing the scores.
+    pub compute: Duration,
+    /// Copying the scores into memory that the CPU can read.
+    pub readback: Duration,
+}
+
+/// How the scores are computed on the GPU.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MatchAlgorithm {
+    /// Picks the algorithm that is expected to be faster for the sizes of the input and template.
+    #[default]
+    Auto,
+    /// Slides the template over the input, which takes time proportional to the template area.
+    Direct,
+    /// Computes the sum of squared differences in the frequency domain with FFTs, which takes time
+    /// that barely depends on the template size. The input is zero-padded to power-of-two sizes.
+    ///
+    /// The scores are computed from sums over the whole template, so they can be off by a small
+    /// fraction of the sum of the squared template samples, and near-perfect matches may not
+    /// score exactly zero.
+    ///
+    /// The sum of absolute differences, batches of inputs and inputs too large for the device's
+    /// buffer size limits still use [Direct](Self::Direct).
+    Fft,
+}
+
+impl MatchAlgorithm {
+    /// Whether to compute the scores in the frequency domain.
+    fn use_fft(
+        self,
+        kernels: &Kernels,
+        method: MatchTemplateMethod,
+        input: &ImageLayout,
+        template: &ImageLayout,
+    ) -> bool {
+        if method != MatchTemplateMethod::SumOfSquaredDifferences || input.batch != 1 {
+            return false;
+        }
+
+        let sizes = FftSizes::new(input, template);
+        match self {
+            MatchAlgorithm::Auto => kernels.fft_is_faster(sizes),
+            MatchAlgorithm::Direct => false,
+            MatchAlgorithm::Fft => kernels.fft_supported(sizes.input),
+        }
+    }
+}
+
+impl FftSizes {
+    fn new(input: &ImageLayout, template: &ImageLayout) -> Self {
+        Self {
+            input: (input.width, input.height),
+            template: (template.width, template.height),
+            channels: template.channels,
+        }
+    }
+}
+
+impl TemplateSums {
+    fn of<T: Sample>(template: &Image<'_, T>) -> Self {
+        let count = (template.width * template.height * template.channels).max(1) as f64;
+        let (sum, sum_sq) = packed_samples(template)
+            .map(|sample| sample.to_f32() as f64)
+            .fold((0.0, 0.0), |(sum, sum_sq), sample| {
+                (sum + sample, sum_sq + sample * sample)
+            });
+        let mean = sum / count;
+
+        Self {
+            mean: mean as f32,
+            sum_sq: (sum_sq - sum * mean) as f32,
+        }
+    }
+}
+
+/// Texture format that stores samples of the given format and channel count as they are.
+fn texture_format(format: SampleFormat, channels: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+
+    Some(match (format, channels) {
+        (SampleFormat::F32, 1) => R32Float,
+        (SampleFormat::F32, 2) => Rg32Float,
+        (SampleFormat::F32, 4) => Rgba32Float,
+        (SampleFormat::F16, 1) => R16Float,
+        (SampleFormat::F16, 2) => Rg16Float,
+        (SampleFormat::F16, 4) => Rgba16Float,
+        (SampleFormat::U8, 1) => R8Unorm,
+        (SampleFormat::U8, 2) => Rg8Unorm,
+        (SampleFormat::U8, 4) => Rgba8Unorm,
+        // 16-bit normalized textures need an optional device feature, so `u16` images are always
+        // uploaded into buffers.
+        _ => return None,
+    })
+}
+
+/// GPU copy of an image. The buffer or texture is reused for any image that fits in it, and only
+/// replaced by a larger one when needed, so that alternating between image sizes doesn't reallocate.
+#[derive(Default)]
+struct ImageSlot {
+    layout: ImageLayout,
+    /// Incremented whenever the contents change, for caching data derived from them.
+    version: u64,
+    buffer: Option<wgpu::Buffer>,
+    texture: Option<(wgpu::Texture, wgpu::TextureView)>,
+}
+
+impl ImageSlot {
+    /// Uploads the image, recreating the buffer or texture if the image doesn't fit in it.
+    /// Returns the layout and whether it was recreated.
+    fn upload<T: Sample>(
+        &mut self,
+        context: &GpuContext,
+        storage: ImageStorage,
+        image: &Image<'_, T>,
+        label: &str,
+    ) -> (ImageLayout, bool) {
+        image.assert_valid(label);
+
+        let data = &image.data[..image.required_len()];
+        let max_size = context.device.limits().max_texture_dimension_2d;
+        self.version += 1;
+
+        let texture_format = texture_format(T::FORMAT, image.channels).filter(|_| {
+            storage == ImageStorage::Texture
+                && image.width <= max_size
+                && image.height <= max_size
+                && image.row_stride().is_multiple_of(image.channels)
+        });
+
+        let Some(texture_format) = texture_format else {
+            let layout = ImageLayout::of(image);
+            let data = upload_bytes(data);
+
+            self.layout = layout;
+            return match &self.buffer {
+                Some(buffer) if buffer.size() >= data.len() as u64 => {
+                    context.queue.write_buffer(buffer, 0, &data);
+                    (layout, false)
+                }
+                _ => {
+                    self.texture = None;
+                    self.buffer = Some(context.device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some(label),
+                            contents: &data,
+                            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                        },
+                    ));
+                    (layout, true)
+                }
+            };
+        };
+
+        let layout = ImageLayout {
+            source: Source::Texture,
+            ..ImageLayout::of(image)
+        };
+
+        // The shader only reads the part of the texture that the layout covers.
+        let recreate = !matches!(
+            &self.texture,
+            Some((texture, _)) if texture.format() == texture_format
+                && texture.width() >= image.width
+                && texture.height() >= image.height
+        );
+        if recreate {
+            let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture_format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            self.buffer = None;
+            self.texture = Some((texture, view));
+        }
+        self.layout = layout;
+
+        let (texture, _) = self.texture.as_ref().unwrap();
+        context.queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(image.row_stride() * size_of::<T>() as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        (layout, recreate)
+    }
+
+    /// Makes room for an `f32` image of the given size and number of channels that is written on
+    /// the GPU instead of uploaded, recreating the buffer if the image doesn't fit in it.
+    /// Returns the layout and whether it was recreated.
+    fn allocate(
+        &mut self,
+        context: &GpuContext,
+        (width, height, channels): (u32, u32, u32),
+        label: &str,
+    ) -> (ImageLayout, bool) {
+        let layout = ImageLayout {
+            width,
+            height,
+            channels,
+            stride: width * channels,
+            source: Source::Buffer(SampleFormat::F32),
+            ..ImageLayout::default()
+        };
+        let size = layout.stride as u64 * height as u64 * size_of::<f32>() as u64;
+        self.layout = layout;
+        self.version += 1;
+
+        match &self.buffer {
+            Some(buffer) if buffer.size() >= size => (layout, false),
+            _ => {
+                self.texture = None;
+                self.buffer = Some(context.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size,
+                    usage: wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }));
+                (layout, true)
+            }
+        }
+    }
+
+    /// Forgets the uploaded image, e.g. when the input is given as a texture instead.
+    fn clear(&mut self, layout: ImageLayout) {
+        self.layout = layout;
+        self.version += 1;
+        self.buffer = None;
+        self.texture = None;
+    }
+
+    fn is_uploaded(&self) -> bool {
+        self.buffer.is_some() || self.texture.is_some()
+    }
+
+    /// Binding resource of the uploaded image.
+    fn binding(&self) -> wgpu::BindingResource<'_> {
+        match (&self.buffer, &self.texture) {
+            (Some(buffer), _) => buffer.as_entire_binding(),
+            (_, Some((_, view))) => wgpu::BindingResource::TextureView(view),
+            _ => unreachable!("no image uploaded"),
+        }
+    }
+}
+
+/// Where a [TemplateMatcher] runs the matching.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Compute shaders on a wgpu device.
+    #[default]
+    Gpu,
+    /// Plain loops on the calling thread, for machines where no device can be created.
+    /// Methods that deal with wgpu resources panic on this engine.
+    Cpu,
+}
+
+pub struct TemplateMatcher {
+    backend: Backend,
+}
+
+enum Backend {
+    Gpu(Box<GpuMatcher>),
+    Cpu(CpuMatcher),
+}
+
+impl Default for TemplateMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Top-left corner, search region and result of a block matched by
+/// [TemplateMatcher::match_blocks].
+type BlockMatch = ((u32, u32), Region, Image<'static>);
+
+impl TemplateMatcher {
+    /// Creates a matcher with its own device on the highest-performance adapter available.
+    /// Falls back to matching on the CPU if no adapter or device is available.
+    pub fn new() -> Self {
+        Self::builder()
+            .cpu_fallback(true)
+            .build()
+            .expect("CPU fallback failed")
+    }
+
+    /// Like [new](Self::new), but returns an error instead of falling back to the CPU if no adapter
+    /// or device is available.
+    pub fn try_new() -> Result<Self, Error> {
+        Self::builder().build()
+    }
+
+    /// Returns a builder for configuring the engine, adapter and device of a new matcher.
+    pub fn builder() -> TemplateMatcherBuilder {
+        TemplateMatcherBuilder::new()
+    }
+
+    /// Async version of [new](Self::new), for environments where blocking isn't possible, e.g. wasm.
+    pub async fn new_async() -> Self {
+        Self::builder()
+            .cpu_fallback(true)
+            .build_async()
+            .await
+            .expect("CPU fallback failed")
+    }
+
+    /// Async version of [try_new](Self::try_new).
+    pub async fn try_new_async() -> Result<Self, Error> {
+        Self::builder().build_async().await
+    }
+
+    /// Creates a matcher that runs on the CPU.
+    pub fn new_cpu() -> Self {
+        Self {
+            backend: Backend::Cpu(CpuMatcher::new()),
+        }
+    }
+
+    /// Creates a matcher that uses an existing device and queue, e.g. the ones of a renderer, instead
+    /// of creating its own. This allows sharing buffers and textures with the rest of the application.
+    pub fn from_device(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        Self::with_context(Arc::new(GpuContext::from_device(device, queue)))
+    }
+
+    /// Creates a matcher on a shared context. Matchers on the same context are independent, but
+    /// avoid the cost of creating a device for each of them.
+    pub fn with_context(context: Arc<GpuContext>) -> Self {
+        Self {
+            backend: Backend::Gpu(Box::new(GpuMatcher::new(context))),
+        }
+    }
+
+    /// Returns the engine the matcher runs on.
+    pub fn engine(&self) -> Engine {
+        match self.backend {
+            Backend::Gpu(_) => Engine::Gpu,
+            Backend::Cpu(_) => Engine::Cpu,
+        }
+    }
+
+    fn gpu(&self) -> Option<&GpuMatcher> {
+        match &self.backend {
+            Backend::Gpu(gpu) => Some(gpu),
+            Backend::Cpu(_) => None,
+        }
+    }
+
+    fn gpu_mut(&mut self) -> Result<&mut GpuMatcher, Error> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => Ok(gpu),
+            Backend::Cpu(_) => Err(Error::Unsupported),
+        }
+    }
+
+    /// Returns the device used for matching. Textures passed to [match_texture](Self::match_texture)
+    /// must be created on this device.
+    ///
+    /// Always [None] on the [CPU engine](Engine::Cpu).
+    pub fn device(&self) -> Option<&wgpu::Device> {
+        self.gpu().map(|gpu| &*gpu.context.device)
+    }
+
+    /// Returns the queue used for matching.
+    ///
+    /// Always [None] on the [CPU engine](Engine::Cpu).
+    pub fn queue(&self) -> Option<&wgpu::Queue> {
+        self.gpu().map(|gpu| &*gpu.context.queue)
+    }
+
+    /// Returns the context the matcher runs on, for creating more matchers on the same device.
+    ///
+    /// Always [None] on the [CPU engine](Engine::Cpu).
+    pub fn context(&self) -> Option<&Arc<GpuContext>> {
+        self.gpu().map(|gpu| &gpu.context)
+    }
+
+    /// Returns the buffer holding the result of the latest matching as tightly packed `f32`s at its
+    /// start, for reading it in other compute passes. The buffer is reused for smaller results and
+    /// only replaced when a larger one is needed, and is [None] before the first match and after
+    /// [match_template_on_gpu](Self::match_template_on_gpu) has handed it over.
+    /// Always [None] on the CPU engine.
+    pub fn result_buffer(&self) -> Option<&wgpu::Buffer> {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.result_buffer(),
+            Backend::Cpu(_) => None,
+        }
+    }
+
+    /// Returns the bind group layout of the latest matching pass, or [None] before the first match.
+    /// Binding 0 is the input, 1 the template, 2 the result buffer and 3 the uniform buffer.
+    /// Always [None] on the CPU engine.
+    pub fn bind_group_layout(&self) -> Option<&wgpu::BindGroupLayout> {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.bind_group_layout(),
+            Backend::Cpu(_) => None,
+        }
+    }
+
+    /// Sets where images are uploaded to for [match_template](Self::match_template).
+    /// Defaults to [ImageStorage::Buffer].
+    /// Has no effect on the CPU engine.
+    pub fn set_image_storage(&mut self, storage: ImageStorage) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.set_image_storage(storage);
+        }
+    }
+
+    /// Sets how the scores are computed on the GPU. Defaults to [MatchAlgorithm::Auto].
+    /// Has no effect on the CPU engine.
+    pub fn set_algorithm(&mut self, algorithm: MatchAlgorithm) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.set_algorithm(algorithm);
+        }
+    }
+
+    /// Sets the precision that images are uploaded with. Defaults to [Precision::Full].
+    /// Has no effect on the CPU engine.
+    pub fn set_precision(&mut self, precision: Precision) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.set_precision(precision);
+        }
+    }
+
+    /// Sets how NaN and infinite samples of the input and the template are treated by
+    /// [match_template](Self::match_template) and the other methods that sum differences.
+    /// Defaults to [NonFinite::Propagate].
+    pub fn set_non_finite(&mut self, policy: NonFinite) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.set_non_finite(policy),
+            Backend::Cpu(cpu) => cpu.set_non_finite(policy),
+        }
+    }
+
+    /// Sets whether matching is deterministic, so that the same images give bit-identical scores
+    /// on every run, and on other devices and the CPU engine as far as their float arithmetic
+    /// allows. Defaults to false.
+    ///
+    /// Deterministic matching sums the differences at each position in a fixed order: by template
+    /// tiles of 16x16 pixels, one channel at a time, and row by row within a tile. FFTs aren't
+    /// used, whatever the [MatchAlgorithm]. [match_template_pruned](Self::match_template_pruned)
+    /// sums row by row, and only prunes positions whose score exceeds the given bound instead of
+    /// the best score found so far by whichever position happened to finish first. The CPU engine
+    /// sums one sample at a time instead of with SIMD. Devices may still fuse multiplications and
+    /// additions differently, which changes the last bits of scores with
+    /// [SumOfSquaredDifferences](MatchTemplateMethod::SumOfSquaredDifferences).
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.set_deterministic(deterministic),
+            Backend::Cpu(cpu) => cpu.set_deterministic(deterministic),
+        }
+    }
+
+    /// Sets what happens when the device is lost, e.g. when the driver resets the GPU after a
+    /// timeout. Defaults to [DeviceRecovery::Disabled]. Has no effect on the CPU engine.
+    ///
+    /// With [DeviceRecovery::Recreate], a lost device is detected when a result is read back with
+    /// [wait_for_job](Self::wait_for_job) and its variants or [poll_job](Self::poll_job). The
+    /// matcher then creates a new device on the same adapter with the same options, compiles its
+    /// pipelines again and uploads the images given to [set_input](Self::set_input),
+    /// [set_input_preprocessed](Self::set_input_preprocessed) and
+    /// [set_template](Self::set_template) again, so the matcher keeps these images in memory while
+    /// recovery is enabled. Jobs in flight keep their [MatchJob]s: those started with
+    /// [match_template](Self::match_template), [match_uploaded](Self::match_uploaded) or
+    /// [match_uploaded_template](Self::match_uploaded_template) are rerun, for which their images
+    /// are kept as well, and the others fail with [Error::DeviceLost].
+    ///
+    /// Only matchers that created their own device can replace it, not those created with
+    /// [from_device](Self::from_device), and not on wasm. Matchers sharing a [GpuContext] each
+    /// replace it with a device of their own. wgpu panics when work is submitted to a lost
+    /// device, so a loss that happens between reading results back is only recovered from once
+    /// the next result is read.
+    pub fn set_device_recovery(&mut self, recovery: DeviceRecovery) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.recovery = recovery;
+        }
+    }
+
+    /// Returns the capabilities of the device used for matching.
+    pub fn capabilities(&self) -> &Capabilities {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.capabilities(),
+            Backend::Cpu(cpu) => cpu.capabilities(),
+        }
+    }
+
+    /// Returns the GPU timings of the match whose result was collected last, if the device
+    /// supports [timestamp queries](Capabilities::timestamp_query). The
+    /// [TIMESTAMP_QUERY](wgpu::Features::TIMESTAMP_QUERY) feature can be requested with
+    /// [TemplateMatcherBuilder::features].
+    ///
+    /// Only matches started as jobs are timed, and results collected with
+    /// [match_template_with_callback](Self::match_template_with_callback) don't update the timings.
+    /// Always [None] on the CPU engine.
+    pub fn last_timings(&self) -> Option<Timings> {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.last_timings,
+            Backend::Cpu(_) => None,
+        }
+    }
+
+    /// Returns the latest job started with [match_template](Self::match_template), if its result
+    /// hasn't been collected yet.
+    fn latest_job(&self) -> Option<MatchJob> {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.latest_job(),
+            Backend::Cpu(cpu) => cpu.latest_job(),
+        }
+    }
+
+    /// Waits for the latest [match_template] execution and returns the result.
+    /// Returns [None] if no matching was started or its result was already collected.
+    ///
+    /// If the GPU rejected the match or the result can't be read back, the returned image is filled
+    /// with zeros. Use [try_wait_for_result](Self::try_wait_for_result) to handle that.
+    pub fn wait_for_result(&mut self) -> Option<Image<'static>> {
+        self.wait_for_job(self.latest_job()?)
+    }
+
+    /// Like [wait_for_result](Self::wait_for_result), but returns an error if the GPU rejected the
+    /// match or the result can't be read back.
+    pub fn try_wait_for_result(&mut self) -> Result<Option<Image<'static>>, Error> {
+        match self.latest_job() {
+            Some(job) => self.try_wait_for_job(job),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [wait_for_result](Self::wait_for_result), but writes the result into `out`, reusing its
+    /// allocation. Returns the width and height of the result, or [None] if no matching was started,
+    /// in which case `out` is left untouched.
+    pub fn wait_for_result_into(&mut self, out: &mut Vec<f32>) -> Option<(u32, u32)> {
+        let job = self.latest_job()?;
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.wait_for_job_into(job, out),
+            Backend::Cpu(cpu) => {
+                let result = cpu.take_result(job)?;
+                out.clear();
+                out.extend_from_slice(&result.data);
+                Some((result.width, result.height))
+            }
+        }
+    }
+
+    /// Async version of [wait_for_result](Self::wait_for_result) that doesn't block the thread while
+    /// the GPU is working.
+    pub async fn result_async(&mut self) -> Option<Image<'static>> {
+        self.job_result_async(self.latest_job()?).await
+    }
+
+    /// Waits for the given job and returns its result. Returns [None] if the result was already
+    /// collected.
+    ///
+    /// If the GPU rejected the match or the result can't be read back, the returned image is filled
+    /// with zeros. Use [try_wait_for_job](Self::try_wait_for_job) to handle that.
+    pub fn wait_for_job(&mut self, job: MatchJob) -> Option<Image<'static>> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.wait_for_job(job),
+            Backend::Cpu(cpu) => cpu.take_result(job),
+        }
+    }
+
+    /// Like [wait_for_job](Self::wait_for_job), but returns an error if the GPU rejected the match
+    /// or the result can't be read back.
+    ///
+    /// Validation and out-of-memory errors raised while starting the match, or while uploading the
+    /// images kept with [set_input](Self::set_input) and [set_template](Self::set_template) before
+    /// it, are returned as [Error::Validation] and [Error::OutOfMemory] instead of being passed to
+    /// the device's uncaptured error handler. wgpu's error scopes belong to the device, so matchers
+    /// that share a device across threads may see each other's errors.
+    pub fn try_wait_for_job(&mut self, job: MatchJob) -> Result<Option<Image<'static>>, Error> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.try_wait_for_job(job),
+            Backend::Cpu(cpu) => Ok(cpu.take_result(job)),
+        }
+    }
+
+    /// Async version of [wait_for_job](Self::wait_for_job) that doesn't block the thread while the
+    /// GPU is working.
+    pub async fn job_result_async(&mut self, job: MatchJob) -> Option<Image<'static>> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.job_result_async(job).await,
+            Backend::Cpu(cpu) => cpu.take_result(job),
+        }
+    }
+
+    /// Like [poll_job](Self::poll_job), but returns an error if the GPU rejected the match or the
+    /// result can't be read back. Instead of having to be polled again, the task is woken once the
+    /// result can be read, while the device is polled in the background.
+    pub(crate) fn poll_job_result(
+        &mut self,
+        job: MatchJob,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<Option<Image<'static>>, Error>> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.poll_job_result(job, cx),
+            Backend::Cpu(cpu) => std::task::Poll::Ready(Ok(cpu.take_result(job))),
+        }
+    }
+
+    /// Returns the result of the given job if the GPU has finished it, without blocking.
+    /// Returns [Poll::Pending](std::task::Poll::Pending) while the GPU is still working, and
+    /// `Ready(None)` if the result was already collected.
+    pub fn poll_job(&mut self, job: MatchJob) -> std::task::Poll<Option<Image<'static>>> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.poll_job(job),
+            Backend::Cpu(cpu) => std::task::Poll::Ready(cpu.take_result(job)),
+        }
+    }
+
+    /// Abandons the given job without waiting for it, discarding its result. The GPU may still
+    /// finish the work, but the result is no longer read back. Returns false if the result was
+    /// already collected, or if the job was started with
+    /// [match_template_with_callback](Self::match_template_with_callback).
+    pub fn cancel(&mut self, job: MatchJob) -> bool {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.cancel(job),
+            Backend::Cpu(cpu) => cpu.take_result(job).is_some(),
+        }
+    }
+
+    /// Abandons all jobs whose results haven't been collected yet, like [cancel](Self::cancel).
+    pub fn cancel_all(&mut self) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.cancel_all(),
+            Backend::Cpu(cpu) => cpu.clear_results(),
+        }
+    }
+
+    /// Returns the number of jobs whose results haven't been collected yet.
+    pub fn pending_jobs(&self) -> usize {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.pending_jobs(),
+            Backend::Cpu(cpu) => cpu.pending_jobs(),
+        }
+    }
+
+    /// Starts recording the matches of [match_template](Self::match_template) into one command
+    /// buffer instead of submitting each of them, until [submit_batch](Self::submit_batch). This
+    /// saves the overhead of a submission per match when matching many small templates.
+    ///
+    /// The recorded matches are also submitted early once a result is waited for, something else
+    /// is written to the queue, or 64 matches have been recorded, and recording goes on after
+    /// that. Batched matches upload their images into buffers of their own, which are reused by
+    /// later batches, and aren't included in [last_timings](Self::last_timings). This does nothing
+    /// on the CPU engine.
+    pub fn begin_batch(&mut self) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.batching = true;
+        }
+    }
+
+    /// Submits the matches recorded since [begin_batch](Self::begin_batch) and stops batching, so
+    /// that later matches are submitted right away again.
+    pub fn submit_batch(&mut self) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.batching = false;
+            gpu.submit_batch();
+        }
+    }
+
+    /// Async version of [match_template](Self::match_template) that also awaits the result.
+    pub async fn match_template_async<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Image<'static> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.match_template_async(input, template, method).await,
+            Backend::Cpu(cpu) => {
+                let job = cpu.match_template(&input.into(), &template.into(), method);
+                cpu.take_result(job).unwrap()
+            }
+        }
+    }
+
+    /// Returns the result of the latest matching if the GPU has finished it, without blocking.
+    /// Returns [Poll::Pending](std::task::Poll::Pending) while the GPU is still working, and
+    /// `Ready(None)` if no matching was started or its result was already collected.
+    pub fn poll_result(&mut self) -> std::task::Poll<Option<Image<'static>>> {
+        match self.latest_job() {
+            Some(job) => self.poll_job(job),
+            None => std::task::Poll::Ready(None),
+        }
+    }
+
+    /// Like [match_template](Self::match_template), but only searches the given region of the input.
+    /// The result covers the template positions that fit entirely inside the region, so it is
+    /// `region.width - template.width + 1` by `region.height - template.height + 1` in size and
+    /// its origin is at `(region.x, region.y)` in input coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region isn't within the input.
+    pub fn match_template_in_region<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        region: Region,
+    ) -> MatchJob {
+        let input = input.into();
+        let template = template.into();
+        self.match_template(input.expect_region(region), template, method)
+    }
+
+    /// Like [match_template](Self::match_template), but only scores the template positions at most
+    /// `radius` pixels away from `center` in both directions, e.g. around where a tracked target
+    /// was found in the previous frame. `center` is a template position, i.e. a location in the
+    /// result of a full match.
+    ///
+    /// The result's origin in input coordinates is the top-left corner of the
+    /// [search window](Region::search_window), which is clamped to the input.
+    pub fn match_template_near<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        center: (u32, u32),
+        radius: u32,
+    ) -> MatchJob {
+        let input = input.into();
+        let template = template.into();
+        let region = Region::search_window(
+            (input.width, input.height),
+            (template.width, template.height),
+            center,
+            radius,
+        );
+        self.match_template_in_region(input, template, method, region)
+    }
+
+    /// Slides a template over the input and scores the match at each point using the requested method.
+    /// To get the result of the matching, call [wait_for_result], or pass the returned job to
+    /// [wait_for_job](Self::wait_for_job).
+    ///
+    /// Several matches can be in flight at once. Each result is kept until it is collected, so
+    /// starting a new match doesn't discard the result of a previous one.
+    ///
+    /// Between [begin_batch](Self::begin_batch) and [submit_batch](Self::submit_batch), matches
+    /// are recorded into one command buffer instead of being submitted one by one.
+    ///
+    /// For multi-channel images the differences of all channels are summed together.
+    /// Input and template must have the same number of channels.
+    ///
+    /// Input and template samples are uploaded in their own format, so e.g. `f16` images take half
+    /// the bandwidth of `f32` ones. Scores are always computed and returned as `f32`.
+    ///
+    /// Templates of up to 32x32 pixels use a shader specialized on their size, which is compiled the
+    /// first time each size is matched.
+    ///
+    /// Inputs or results that don't fit in a storage buffer binding of the device are split into
+    /// overlapping tiles, which are matched one after another and stitched back into one result.
+    /// The whole result must still fit in a single buffer.
+    ///
+    /// On the CPU engine, matching runs to completion before this returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the template is larger than the input, if they have different numbers of
+    /// channels, or if their data is too short for their dimensions.
+    /// [try_match_template](Self::try_match_template) returns errors for sizes instead.
+    pub fn match_template<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input = input.into();
+        let template = template.into();
+        assert_template_fits(
+            (input.width, input.height),
+            (template.width, template.height),
+        );
+
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_errors(|gpu| gpu.match_template(input, template, method))
+            }
+            Backend::Cpu(cpu) => cpu.match_template(&input, &template, method),
+        }
+    }
+
+    /// Like [match_template](Self::match_template), but returns [Error::TemplateTooLarge] if the
+    /// template is wider or higher than the input, [Error::ChannelMismatch] if they have different
+    /// numbers of channels, and [Error::DataLength] if the data of either image is too short for
+    /// its dimensions, instead of panicking.
+    pub fn try_match_template<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<MatchJob, Error> {
+        let input = input.into();
+        let template = template.into();
+        check_images(&input, &template)?;
+
+        Ok(self.match_template(input, template, method))
+    }
+
+    /// Finds the template near where a hint expects it to be. The hinted position is scored first,
+    /// and if its score is at most `tolerance` above the hint's last score, the template is taken
+    /// to be still there and nothing else is matched. Otherwise the positions within the hint's
+    /// radius are searched, and only their best one is read back. Blocks until the match has been
+    /// found.
+    ///
+    /// This makes the common case of a template that hasn't moved since the previous frame cost
+    /// a single position.
+    ///
+    /// Returns the errors of [try_match_template](Self::try_match_template) for invalid images,
+    /// and those of [try_wait_for_job](Self::try_wait_for_job) if scoring the hinted position
+    /// fails.
+    pub fn match_template_hinted<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        hint: MatchHint,
+        tolerance: f32,
+    ) -> Result<HintedMatch, Error> {
+        let (input, template) = (input.into(), template.into());
+        let input_size = (input.width, input.height);
+        let template_size = (template.width, template.height);
+        check_images(&input, &template)?;
+
+        // A window without a radius is the template at the hinted position, clamped to the input.
+        let at_hint = Region::search_window(input_size, template_size, hint.position, 0);
+        let job = self.match_template_in_region(&input, &template, method, at_hint);
+        // Failures to score the position are returned by the wait. The job was just started, so
+        // its result can't have been collected yet, and it holds the score of the one position.
+        let result = self
+            .try_wait_for_job(job)?
+            .expect("the result of a new job is available");
+        let score = result.data[0];
+        if score <= hint.last_score + tolerance {
+            return Ok(HintedMatch {
+                location: (at_hint.x, at_hint.y),
+                score,
+                at_hint: true,
+            });
+        }
+
+        let window = Region::search_window(input_size, template_size, hint.position, hint.radius);
+        let extremes =
+            self.match_template_extremes(input.expect_region(window), &template, method)?;
+        let (x, y) = extremes.min_value_location;
+        Ok(HintedMatch {
+            location: (window.x + x, window.y + y),
+            score: extremes.min_value,
+            at_hint: false,
+        })
+    }
+
+    /// Like [match_template](Self::match_template), but for when only the best match is needed.
+    /// Scoring a position stops as soon as its partial score exceeds the best score found so far,
+    /// or `bound` if given, which skips most of the work once a good match has been found.
+    ///
+    /// The smallest score of the result and its location are exact, while larger scores are only
+    /// partial sums that may be well below the real ones. If no position scores at most `bound`,
+    /// every score is partial.
+    pub fn match_template_pruned<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        bound: Option<f32>,
+    ) -> MatchJob {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_errors(|gpu| gpu.match_template_pruned(input, template, method, bound))
+            }
+            Backend::Cpu(cpu) => {
+                cpu.match_template_pruned(&input.into(), &template.into(), method, bound)
+            }
+        }
+    }
+
+    /// Like [match_template](Self::match_template) followed by [find_extremes], but finds the
+    /// extremes on the GPU, so that only they are read back instead of the whole result. Blocks
+    /// until they have been found.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    pub fn match_template_extremes<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Extremes, Error> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.match_template_extremes(input, template, method))
+            }
+            Backend::Cpu(cpu) => {
+                let job = cpu.match_template(&input.into(), &template.into(), method);
+                Ok(find_extremes(&cpu.take_result(job).unwrap()))
+            }
+        }
+    }
+
+    /// Matches the template with each of the given mirrorings in one dispatch, and keeps the best
+    /// score at each position along with the mirroring it was found with. Pass [Mirror::None]
+    /// among the mirrorings to also match the template as it is. Scores are those of
+    /// [match_template](Self::match_template), as all pixels of the template are compared. Blocks
+    /// until the result is ready.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no mirroring is given.
+    pub fn match_template_mirrored<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        mirrors: &[Mirror],
+    ) -> Result<MirroredMatch, Error> {
+        assert!(!mirrors.is_empty(), "at least one mirroring must be given");
+
+        let (input, template) = (input.into(), template.into());
+        let variants: Vec<_> = mirrors
+            .iter()
+            .map(|&mirror| {
+                let transform = Transform {
+                    mirror,
+                    ..Transform::new(0.0, 1.0)
+                };
+                Variant::full(template.width, template.height, transform)
+            })
+            .collect();
+
+        let (scores, indices) = self.match_variants(&input, &template, method, &variants)?;
+        Ok(MirroredMatch {
+            mirrors: indices
+                .iter()
+                .map(|&index| variants[index as usize].transform.mirror)
+                .collect(),
+            scores,
+        })
+    }
+
+    /// Matches the template rotated clockwise around its center by each angle from `angle_range`,
+    /// `angle_step` apart, and keeps the best score at each position along with its angle. Angles
+    /// are in radians, e.g. `0.0..TAU` for any orientation. Blocks until the result is ready.
+    ///
+    /// The rotated templates are resampled bilinearly on the GPU. Only the pixels within the circle
+    /// inscribed in the template are compared, so that every angle covers the same pixels, and the
+    /// result has the size it would have for the unrotated template.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty, or if the step isn't positive.
+    pub fn match_template_rotated<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        angle_range: Range<f32>,
+        angle_step: f32,
+    ) -> Result<RotatedMatch, Error> {
+        let (input, template) = (input.into(), template.into());
+        let variants: Vec<_> = transform::angles(angle_range, angle_step)
+            .into_iter()
+            .map(|angle| Variant::new(template.width, template.height, Transform::new(angle, 1.0)))
+            .collect();
+
+        let (scores, indices) = self.match_variants(&input, &template, method, &variants)?;
+        let angles: Vec<_> = indices
+            .iter()
+            .map(|&index| variants[index as usize].transform.angle)
+            .collect();
+        Ok(RotatedMatch {
+            angles: Image::new(angles, scores.width, scores.height),
+            scores,
+        })
+    }
+
+    /// Searches for the template over every combination of the rotations of
+    /// [match_template_rotated](Self::match_template_rotated) and the scales of
+    /// [match_template_scaled](Self::match_template_scaled), each given as a range and a step,
+    /// and returns the best match. All variants are generated on the GPU and matched in a single
+    /// dispatch. Blocks until the match has been found.
+    ///
+    /// Variants are compared by their score divided by the number of samples they compare. Returns
+    /// [None] if no scale of the template fits in the input, and an error if the GPU rejects the
+    /// work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either range is empty, if a scale isn't positive, or if a step isn't positive.
+    pub fn match_template_rotated_scaled<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        (angle_range, angle_step): (Range<f32>, f32),
+        (scales, scale_step): (RangeInclusive<f32>, f32),
+    ) -> Result<Option<TransformMatch>, Error> {
+        let (input, template) = (input.into(), template.into());
+        let angles = transform::angles(angle_range, angle_step);
+
+        let variants: Vec<_> = scale::scales(scales, scale_step)
+            .into_iter()
+            .flat_map(|scale| {
+                angles
+                    .iter()
+                    .map(move |&angle| Transform::new(angle, scale))
+            })
+            .map(|transform| Variant::new(template.width, template.height, transform))
+            .filter(|variant| variant.width <= input.width && variant.height <= input.height)
+            .collect();
+
+        self.best_variant(&input, &template, method, &variants)
+    }
+
+    /// Like [match_template_rotated_scaled](Self::match_template_rotated_scaled), but estimates
+    /// the rotation and scale instead of trying every combination, which is much faster for fine
+    /// grids. The template and candidate windows spread over the input are resampled on log-polar
+    /// grids, where rotating and scaling become shifts, and the best shift of the best window gives
+    /// the estimate. The template is then matched over the whole input at the estimated angle and
+    /// scale and their neighbours, and the best of those matches is returned. Blocks until it has
+    /// been found.
+    ///
+    /// The estimate is only as good as the windows are close to the template's center, so it works
+    /// best with templates that are large and distinctive. Returns [None] if the template is too
+    /// small to resample, or if no estimated scale of it fits in the input, and an error if the
+    /// GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scale range is empty, contains non-positive scales, or spans more than
+    /// about a factor of 4.
+    pub fn match_template_log_polar<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        scales: RangeInclusive<f32>,
+    ) -> Result<Option<TransformMatch>, Error> {
+        let (input, template) = (input.into(), template.into());
+        let Some(search) = LogPolarSearch::new(
+            (input.width, input.height),
+            (template.width, template.height),
+            scales,
+        ) else {
+            return Ok(None);
+        };
+
+        let (_, shift, angle) = match &mut self.backend {
+            Backend::Gpu(gpu) => gpu
+                .track_blocking(|gpu| gpu.estimate_log_polar(&input, &template, method, &search))?,
+            Backend::Cpu(cpu) => cpu.estimate_log_polar(&input, &template, method, &search),
+        };
+
+        // The final match also tries half a step to either side of the estimate.
+        let (angle, scale) = search.transform(shift, angle);
+        let angle_step = std::f32::consts::TAU / log_polar::ANGLES as f32;
+        let scale_step = search.ring_base.sqrt();
+        let variants: Vec<_> = [scale / scale_step, scale, scale * scale_step]
+            .into_iter()
+            .flat_map(|scale| {
+                [angle - angle_step * 0.5, angle, angle + angle_step * 0.5]
+                    .map(|angle| Transform::new(angle, scale))
+            })
+            .map(|transform| Variant::new(template.width, template.height, transform))
+            .filter(|variant| variant.width <= input.width && variant.height <= input.height)
+            .collect();
+
+        self.best_variant(&input, &template, method, &variants)
+    }
+
+    /// Refines a match of the template at the given position, e.g. the location of the smallest
+    /// score of [match_template](Self::match_template), into a sub-pixel affine alignment with the
+    /// input. Lucas–Kanade style Gauss–Newton steps on the sum of squared differences adjust the
+    /// translation and a small deformation of the template until a step moves no pixel more than
+    /// a hundredth of a pixel, or until `max_iterations` steps have been taken. Blocks until the
+    /// refinement has finished.
+    ///
+    /// The refinement only converges from within a pixel or two of the alignment, and positions
+    /// outside the input sample its nearest edge.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input and the template have different numbers of channels.
+    pub fn refine_match<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        position: (u32, u32),
+        max_iterations: u32,
+    ) -> Result<AffineMatch, Error> {
+        let (input, template) = (input.into(), template.into());
+        let refinement = Refinement::new(position, max_iterations);
+
+        let values = match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.refine(&input, &template, &refinement))?
+            }
+            Backend::Cpu(cpu) => cpu.refine(&input, &template, &refinement),
+        };
+        Ok(refinement.result((template.width, template.height), &values))
+    }
+
+    /// Returns whether any sample of `current` differs from the same sample of `previous` by more
+    /// than `threshold`, e.g. to skip matching frames that haven't changed. On the GPU engine the
+    /// frames are compared on the device and only the answer is read back. Blocks until the
+    /// frames have been compared.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frames have different sizes or numbers of channels.
+    pub fn has_changed<'a, P: Sample, C: Sample>(
+        &mut self,
+        previous: impl Into<Image<'a, P>>,
+        current: impl Into<Image<'a, C>>,
+        threshold: f32,
+    ) -> Result<bool, Error> {
+        let (previous, current) = (previous.into(), current.into());
+        assert!(
+            (previous.width, previous.height, previous.channels)
+                == (current.width, current.height, current.channels),
+            "frames must have the same size and number of channels"
+        );
+
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.has_changed(&previous, &current, threshold))
+            }
+            Backend::Cpu(_) => Ok(cpu::has_changed(&previous, &current, threshold)),
+        }
+    }
+
+    /// Renders the template rotated by each angle from `angle_range`, `angle_step` apart, at each
+    /// scale from `scales`, `scale_step` apart, and with each of the given mirrorings, into a bank
+    /// that can be matched against any number of inputs with [match_bank](Self::match_bank)
+    /// without rendering the variants again. Pass [Mirror::None] among the mirrorings to keep the
+    /// unmirrored variants. Blocks until the bank has been rendered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no mirroring is given, if either range is empty, or if either step isn't
+    /// positive.
+    pub fn create_bank<'a, T: Sample>(
+        &mut self,
+        template: impl Into<Image<'a, T>>,
+        (angle_range, angle_step): (Range<f32>, f32),
+        (scales, scale_step): (RangeInclusive<f32>, f32),
+        mirrors: &[Mirror],
+    ) -> TemplateBank {
+        assert!(!mirrors.is_empty(), "bank must have at least one mirroring");
+
+        let template = template.into();
+        let angles = transform::angles(angle_range, angle_step);
+        let scales = scale::scales(scales, scale_step);
+
+        let mut variants = Vec::new();
+        for &mirror in mirrors {
+            for &scale in &scales {
+                for &angle in &angles {
+                    let transform = Transform {
+                        mirror,
+                        ..Transform::new(angle, scale)
+                    };
+                    variants.push(Variant::new(template.width, template.height, transform));
+                }
+            }
+        }
+
+        self.render_bank(&template, variants)
+    }
+
+    /// Stores the template at each of the given scales, e.g. `&[1.0, 0.75, 0.5]`, for matching
+    /// against inputs whose resolution varies with [match_pyramid](Self::match_pyramid). Each
+    /// level is resampled bilinearly once and kept on the device, like the variants of a
+    /// [TemplateBank]. Blocks until the levels have been rendered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no scale is given, or if any scale isn't positive.
+    pub fn create_pyramid<'a, T: Sample>(
+        &mut self,
+        template: impl Into<Image<'a, T>>,
+        scales: &[f32],
+    ) -> TemplatePyramid {
+        assert!(!scales.is_empty(), "pyramid must have at least one level");
+        assert!(
+            scales.iter().all(|&scale| scale > 0.0),
+            "pyramid scales must be positive"
+        );
+
+        let template = template.into();
+        let levels = scales
+            .iter()
+            .map(|&scale| {
+                let transform = Transform::new(0.0, scale);
+                let variant = Variant::full(template.width, template.height, transform);
+                (scale, self.render_bank(&template, vec![variant]))
+            })
+            .collect();
+        TemplatePyramid { levels }
+    }
+
+    /// Matches the level of the pyramid whose scale is closest to `input_scale`, the size of the
+    /// input relative to the resolution the template was taken at, among the levels that fit in
+    /// the input. Returns the result along with the level, or [None] if no level fits. The result
+    /// is that of [match_template](Self::match_template) with the level as the template. Blocks
+    /// until the result is ready, and returns an error if the GPU rejects the work or the result
+    /// can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input scale isn't positive, if the pyramid was created by a matcher with a
+    /// different engine, or if the input and the pyramid's template have different numbers of
+    /// channels.
+    pub fn match_pyramid<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        pyramid: &TemplatePyramid,
+        method: MatchTemplateMethod,
+        input_scale: f32,
+    ) -> Result<Option<PyramidMatch>, Error> {
+        assert!(input_scale > 0.0, "input scale must be positive");
+
+        let input = input.into();
+        let Some(level) = pyramid.level_for(input_scale, (input.width, input.height)) else {
+            return Ok(None);
+        };
+        let (scale, bank) = &pyramid.levels[level];
+
+        let (scores, _) = self.match_rendered(&input, bank, method)?;
+        Ok(Some(PyramidMatch {
+            level,
+            scale: *scale,
+            scores,
+        }))
+    }
+
+    fn render_bank<T: Sample>(
+        &mut self,
+        template: &Image<'_, T>,
+        variants: Vec<Variant>,
+    ) -> TemplateBank {
+        let rendered = match &mut self.backend {
+            Backend::Gpu(gpu) => Rendered::Gpu(Box::new(gpu.render_variants(template, &variants))),
+            Backend::Cpu(cpu) => Rendered::Cpu(cpu.render_variants(template, &variants)),
+        };
+        TemplateBank {
+            variants,
+            channels: template.channels,
+            rendered,
+        }
+    }
+
+    /// Matches every variant of the bank against the input and returns the best match of all,
+    /// like [match_template_rotated_scaled](Self::match_template_rotated_scaled), or [None] if no
+    /// variant fits in the input. Blocks until it has been found, and returns an error if the GPU
+    /// rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bank was created by a matcher with a different engine, or if the input and
+    /// the bank's template have different numbers of channels.
+    pub fn match_bank<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        bank: &TemplateBank,
+        method: MatchTemplateMethod,
+    ) -> Result<Option<TransformMatch>, Error> {
+        let input = input.into();
+        if !transform::fits((input.width, input.height), &bank.variants) {
+            return Ok(None);
+        }
+
+        let (scores, indices) = self.match_rendered(&input, bank, method)?;
+        Ok(transform::best_match(
+            &scores,
+            &indices,
+            &bank.variants,
+            bank.channels,
+        ))
+    }
+
+    /// Matches the variants of the bank and returns the best score at each position along with
+    /// the index of its variant.
+    fn match_rendered<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        bank: &TemplateBank,
+        method: MatchTemplateMethod,
+    ) -> Result<(Image<'static>, Vec<u32>), Error> {
+        assert_eq!(
+            input.channels, bank.channels,
+            "input and template must have the same number of channels"
+        );
+
+        match (&mut self.backend, &bank.rendered) {
+            (Backend::Gpu(gpu), Rendered::Gpu(rendered)) => gpu
+                .track_blocking(|gpu| gpu.match_rendered(input, method, &bank.variants, rendered)),
+            (Backend::Cpu(cpu), Rendered::Cpu(samples)) => {
+                Ok(cpu.match_rendered(input, method, &bank.variants, samples))
+            }
+            _ => panic!("bank was created by a matcher with a different engine"),
+        }
+    }
+
+    /// Matches the variants of the template and returns the best match of all, or [None] if
+    /// there are no variants.
+    fn best_variant<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+    ) -> Result<Option<TransformMatch>, Error> {
+        if variants.is_empty() {
+            return Ok(None);
+        }
+
+        let (scores, indices) = self.match_variants(input, template, method, variants)?;
+        Ok(transform::best_match(
+            &scores,
+            &indices,
+            variants,
+            template.channels,
+        ))
+    }
+
+    /// Matches the variants of the template and returns the best score at each position along
+    /// with the index of its variant.
+    fn match_variants<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+    ) -> Result<(Image<'static>, Vec<u32>), Error> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.match_variants(input, template, method, variants))
+            }
+            Backend::Cpu(cpu) => Ok(cpu.match_variants(input, template, method, variants)),
+        }
+    }
+
+    /// Matches the template resized by each scale from `scales`, `step` apart, e.g. `0.8..=1.2`
+    /// for screenshots taken at different display scaling, and returns the best match of all
+    /// scales. Blocks until it has been found.
+    ///
+    /// The resized templates are matched in one submission like in
+    /// [match_templates](Self::match_templates). Since larger templates sum more differences, the
+    /// scales are compared by their best score divided by the number of samples in the template.
+    /// Returns [None] if no scale of the template fits in the input, and the errors of
+    /// [match_templates](Self::match_templates) if matching the scales fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty or contains non-positive scales, or if the step isn't positive.
+    pub fn match_template_scaled<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        scales: RangeInclusive<f32>,
+        step: f32,
+    ) -> Result<Option<ScaledMatch>, Error> {
+        let scales: Vec<_> = scale::scales(scales, step)
+            .into_iter()
+            .map(|scale| (scale, scale))
+            .collect();
+        self.match_template_at_scales(&input.into(), &template.into(), method, &scales)
+    }
+
+    /// Like [match_template_scaled](Self::match_template_scaled), but scales the template
+    /// horizontally and vertically independently, e.g. for UI elements that are stretched to
+    /// different aspect ratios. Every horizontal scale from `scales_x`, `step_x` apart, is
+    /// combined with every vertical scale from `scales_y`, `step_y` apart, so the number of
+    /// templates matched is the product of their counts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either range is empty or contains non-positive scales, or if either step isn't
+    /// positive.
+    pub fn match_template_scaled_anisotropic<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        (scales_x, step_x): (RangeInclusive<f32>, f32),
+        (scales_y, step_y): (RangeInclusive<f32>, f32),
+    ) -> Result<Option<ScaledMatch>, Error> {
+        let scales_y = scale::scales(scales_y, step_y);
+        let scales: Vec<_> = scale::scales(scales_x, step_x)
+            .into_iter()
+            .flat_map(|scale_x| scales_y.iter().map(move |&scale_y| (scale_x, scale_y)))
+            .collect();
+        self.match_template_at_scales(&input.into(), &template.into(), method, &scales)
+    }
+
+    /// Like [match_template_scaled](Self::match_template_scaled), but with a list of horizontal
+    /// and vertical scales.
+    pub(crate) fn match_template_at_scales<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        scales: &[(f32, f32)],
+    ) -> Result<Option<ScaledMatch>, Error> {
+        let (scales, templates): (Vec<_>, Vec<_>) = scales
+            .iter()
+            .filter_map(|&scale| {
+                let (width, height) = scale::scaled_size(template.width, template.height, scale);
+                (width <= input.width && height <= input.height)
+                    .then(|| (scale, scale::resize(template, width, height)))
+            })
+            .unzip();
+        if templates.is_empty() {
+            return Ok(None);
+        }
+
+        let results = self.match_templates(input, &templates, method)?;
+
+        Ok(scales
+            .into_iter()
+            .zip(&templates)
+            .zip(&results)
+            .map(|((scale, template), result)| {
+                let extremes = find_extremes(result);
+                ScaledMatch {
+                    location: extremes.min_value_location,
+                    score: extremes.min_value,
+                    scale_x: scale.0,
+                    scale_y: scale.1,
+                    size: (template.width, template.height),
+                }
+            })
+            .min_by(|a, b| {
+                let samples = |m: &ScaledMatch| (m.size.0 * m.size.1 * template.channels) as f32;
+                (a.score / samples(a)).total_cmp(&(b.score / samples(b)))
+            }))
+    }
+
+    /// Like [match_template](Self::match_template), but only compares the pixels kept in the
+    /// sparse template. The result is as large as for the full template.
+    pub fn match_sparse_template<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: &SparseTemplate,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_errors(|gpu| gpu.match_sparse_template(input, template, method))
+            }
+            Backend::Cpu(cpu) => cpu.match_sparse_template(&input.into(), template, method),
+        }
+    }
+
+    /// Matches binarized images: every sample of the input and the template becomes a bit that is
+    /// set if the sample is greater than `threshold`, and each position is scored by the number of
+    /// template samples whose bit differs from the input's. Like with the difference methods,
+    /// lower scores are better and zero is a perfect match.
+    ///
+    /// On the GPU engine both images are packed into bit masks on the device and compared 32
+    /// samples at a time, which is faster than comparing `f32` samples and, for high-contrast
+    /// images like glyphs or barcodes, less sensitive to lighting and blur.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input and the template don't have the same number of channels.
+    pub fn match_template_binary<'a, 'b, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'b, T>>,
+        threshold: f32,
+    ) -> MatchJob {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_errors(|gpu| gpu.match_template_binary(input, template, threshold))
+            }
+            Backend::Cpu(cpu) => {
+                cpu.match_template_binary(&input.into(), &template.into(), threshold)
+            }
+        }
+    }
+
+    /// Uploads the input and keeps it, so that any number of templates can be matched against it
+    /// with [match_uploaded](Self::match_uploaded) without uploading it again. Other matching
+    /// methods that upload their own input, like [match_template](Self::match_template), replace it.
+    pub fn set_input<'a, I: Sample>(&mut self, input: impl Into<Image<'a, I>>) {
+        let input = input.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_errors(|gpu| gpu.set_input(&input)),
+            Backend::Cpu(cpu) => cpu.set_input(&input),
+        }
+    }
+
+    /// Like [set_input](Self::set_input), but takes an RGB or RGBA frame of bytes, e.g. from a
+    /// camera, and keeps its luma as a single-channel input, weighing the color channels with the
+    /// Rec. 601 coefficients and ignoring alpha. This is
+    /// [set_input_preprocessed](Self::set_input_preprocessed) with [Preprocess::Grayscale], so on
+    /// the GPU engine the frame is uploaded as it is and converted by a compute pass.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frame doesn't have three or four channels.
+    pub fn set_input_luma<'a>(&mut self, frame: impl Into<Image<'a, u8>>) {
+        let frame = frame.into();
+        assert!(
+            matches!(frame.channels, 3 | 4),
+            "frame must have three or four channels"
+        );
+
+        self.set_input_preprocessed(frame, &[Preprocess::Grayscale]);
+    }
+
+    /// Like [set_input](Self::set_input), but runs the preprocessing steps on the input in order
+    /// and keeps the result. The steps can be given as a slice or built with a
+    /// [PreprocessPipeline]. On the GPU engine they run as chained compute passes on the uploaded
+    /// input, all in one submission, so e.g. downscaling large frames costs no time on the calling
+    /// thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameters of a step are out of range.
+    pub fn set_input_preprocessed<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        steps: &[Preprocess],
+    ) {
+        let input = input.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_errors(|gpu| gpu.set_input_preprocessed(&input, steps)),
+            Backend::Cpu(cpu) => {
+                let input = cpu.preprocess(&input, steps);
+                cpu.set_input(&input);
+            }
+        }
+    }
+
+    /// Runs the preprocessing steps on the image in order and returns the result, e.g. to
+    /// preprocess a template like the inputs given to
+    /// [set_input_preprocessed](Self::set_input_preprocessed). On the GPU engine this replaces
+    /// the input given to [set_input](Self::set_input). Blocks until the result has been read
+    /// back.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameters of a step are out of range.
+    pub fn preprocess<'a, T: Sample>(
+        &mut self,
+        image: impl Into<Image<'a, T>>,
+        steps: &[Preprocess],
+    ) -> Result<Image<'static>, Error> {
+        let image = image.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_blocking(|gpu| gpu.preprocess(&image, steps)),
+            Backend::Cpu(cpu) => Ok(cpu.preprocess(&image, steps)),
+        }
+    }
+
+    /// Forgets the running background of [Preprocess::SubtractBackground], so that the next image
+    /// preprocessed with it starts a new one, e.g. after the camera has moved.
+    pub fn reset_background(&mut self) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.background = None,
+            Backend::Cpu(cpu) => cpu.reset_background(),
+        }
+    }
+
+    /// Like [match_template](Self::match_template), but matches against the input given to
+    /// [set_input](Self::set_input), so only the template is uploaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no input has been set, or if it has been replaced since.
+    pub fn match_uploaded<'a, T: Sample>(
+        &mut self,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let template = template.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_errors(|gpu| gpu.match_uploaded(template, method)),
+            Backend::Cpu(cpu) => cpu.match_uploaded(&template, method),
+        }
+    }
+
+    /// Uploads the template and keeps it, so that it can be matched against any number of inputs,
+    /// e.g. the frames of a video, with [match_uploaded_template](Self::match_uploaded_template)
+    /// without uploading it again. Other matching methods that upload their own template, like
+    /// [match_template](Self::match_template), replace it.
+    pub fn set_template<'a, T: Sample>(&mut self, template: impl Into<Image<'a, T>>) {
+        let template = template.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_errors(|gpu| gpu.set_template(&template)),
+            Backend::Cpu(cpu) => cpu.set_template(&template),
+        }
+    }
+
+    /// Like [match_template](Self::match_template), but matches the template given to
+    /// [set_template](Self::set_template), so only the input is uploaded. As long as the input
+    /// keeps its size, the GPU resources of the previous call are reused as they are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no template has been set, or if it has been replaced since.
+    pub fn match_uploaded_template<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input = input.into();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_errors(|gpu| gpu.match_uploaded_template(&input, method))
+            }
+            Backend::Cpu(cpu) => cpu.match_uploaded_template(&input, method),
+        }
+    }
+
+    /// Like [match_template](Self::match_template), but calls `callback` with the result once the
+    /// match completes, e.g. to send it over a channel to an event loop. The result isn't kept for
+    /// [wait_for_job](Self::wait_for_job).
+    ///
+    /// On native targets the callback runs on a background thread that polls the device, or on any
+    /// other thread that happens to poll it. On the CPU engine it runs before this returns.
+    pub fn match_template_with_callback<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        callback: impl FnOnce(MatchJob, Result<Image<'static>, Error>) + Send + 'static,
+    ) -> MatchJob {
+        let job = self.match_template(input, template, method);
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.forward_result(job, callback),
+            Backend::Cpu(cpu) => callback(job, Ok(cpu.take_result(job).unwrap())),
+        }
+        job
+    }
+
+    /// Matches several templates against the same input and returns the results in the order of the
+    /// templates. The input is uploaded only once and all templates are matched in one submission,
+    /// which is much faster than matching them one by one.
+    ///
+    /// This waits for the results, and doesn't affect the result of a previous
+    /// [match_template](Self::match_template) call.
+    ///
+    /// Returns the errors of [try_match_template](Self::try_match_template) if any template can't
+    /// be matched against the input, before matching any of them, and an error if the GPU
+    /// rejects the work or the results can't be read back.
+    pub fn match_templates<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        templates: &[Image<'_, T>],
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        let input = input.into();
+        for template in templates {
+            check_images(&input, template)?;
+        }
+
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.match_templates(input, templates, method))
+            }
+            Backend::Cpu(cpu) => Ok(cpu.match_templates(&input, templates, method)),
+        }
+    }
+
+    /// Matches each template against its own region of the same input, like
+    /// [match_template_in_region](Self::match_template_in_region) does, and returns the results in
+    /// the order of the templates. The regions are uploaded together and all templates are matched
+    /// in one submission.
+    ///
+    /// This waits for the results, and doesn't affect the result of a previous
+    /// [match_template](Self::match_template) call.
+    ///
+    /// Returns the errors of [try_match_template](Self::try_match_template) if any template can't
+    /// be matched against its region, before matching any of them, and an error if the GPU
+    /// rejects the work or the results can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there isn't one region per template, or if a region isn't within the input.
+    pub fn match_templates_in_regions<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        templates: &[Image<'_, T>],
+        regions: &[Region],
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        assert_eq!(
+            templates.len(),
+            regions.len(),
+            "there must be one region per template"
+        );
+        let input = input.into();
+        for (template, &region) in templates.iter().zip(regions) {
+            check_images(&input.expect_region(region), template)?;
+        }
+
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_blocking(|gpu| {
+                gpu.match_templates_in_regions(&input, templates, regions, method)
+            }),
+            Backend::Cpu(cpu) => Ok(templates
+                .iter()
+                .zip(regions)
+                .map(|(template, &region)| {
+                    cpu::match_template(
+                        &input.expect_region(region),
+                        template,
+                        method,
+                        cpu.summation(),
+                    )
+                })
+                .collect()),
+        }
+    }
+
+    /// Estimates the motion from frame `a` to frame `b`, e.g. for stabilization. Frame `a` is
+    /// divided into square blocks of `block_size` pixels, and each block is matched against `b`
+    /// at displacements of at most `radius` pixels in both directions. Pixels at the right and
+    /// bottom edges that don't fill a whole block are left out. All blocks are matched in one
+    /// submission, which this waits for.
+    ///
+    /// Blocks with several equally good matches, such as ones without any texture, keep a
+    /// displacement of zero if it is among them.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frames have different sizes or numbers of channels, or if `block_size` is
+    /// zero.
+    pub fn estimate_motion<'a, A: Sample, B: Sample>(
+        &mut self,
+        a: impl Into<Image<'a, A>>,
+        b: impl Into<Image<'a, B>>,
+        method: MatchTemplateMethod,
+        block_size: u32,
+        radius: u32,
+    ) -> Result<MotionField, Error> {
+        let (a, b) = (a.into(), b.into());
+        let blocks = self.match_blocks(&a, &b, method, block_size, |origin| {
+            Region::search_window(
+                (b.width, b.height),
+                (block_size, block_size),
+                origin,
+                radius,
+            )
+        })?;
+
+        let vectors = blocks
+            .into_iter()
+            .map(|((x, y), window, result)| {
+                let extremes = find_extremes(&result);
+                let still = ((y - window.y) * result.width + x - window.x) as usize;
+                let (best_x, best_y) = if result.data[still] <= extremes.min_value {
+                    (x, y)
+                } else {
+                    let (best_x, best_y) = extremes.min_value_location;
+                    (window.x + best_x, window.y + best_y)
+                };
+                MotionVector {
+                    dx: best_x as i32 - x as i32,
+                    dy: best_y as i32 - y as i32,
+                    score: extremes.min_value,
+                }
+            })
+            .collect();
+
+        Ok(MotionField {
+            block_size,
+            columns: a.width / block_size,
+            rows: a.height / block_size,
+            vectors,
+        })
+    }
+
+    /// Estimates the global shift from image `a` to image `b`, e.g. between two scans of the same
+    /// page. The central crop of `a`, half of its width and height, is matched against all of
+    /// `b`, and the offset of its best match from where it lies in `a` is returned along with
+    /// the score of the match, as `(dx, dy, score)`. Content at `(x, y)` in `a` is then found at
+    /// `(x + dx, y + dy)` in `b`. Blocks until the shift has been found.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// Shifts of up to a quarter of the size of `a` in each direction are found when the images
+    /// have the same size, and larger ones if `b` is larger.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` is less than two pixels wide or high, if the crop is larger than `b`, or if
+    /// the images have different numbers of channels.
+    pub fn register_translation<'a, A: Sample, B: Sample>(
+        &mut self,
+        a: impl Into<Image<'a, A>>,
+        b: impl Into<Image<'a, B>>,
+        method: MatchTemplateMethod,
+    ) -> Result<(i32, i32, f32), Error> {
+        let (a, b) = (a.into(), b.into());
+        assert!(
+            a.width >= 2 && a.height >= 2,
+            "image must be at least two pixels wide and high"
+        );
+
+        let crop = Region::new(a.width / 4, a.height / 4, a.width / 2, a.height / 2);
+        let extremes = self.match_template_extremes(b, a.expect_region(crop), method)?;
+        let (x, y) = extremes.min_value_location;
+        Ok((
+            x as i32 - crop.x as i32,
+            y as i32 - crop.y as i32,
+            extremes.min_value,
+        ))
+    }
+
+    /// Estimates the disparities between the left and right views of a rectified stereo pair, in
+    /// which matching points lie on the same row. The left view is divided into square blocks of
+    /// `block_size` pixels, and each block is matched against the same rows of the right view,
+    /// at most `max_disparity` pixels to the left. Pixels at the right and bottom edges that don't
+    /// fill a whole block are left out. All blocks are matched in one submission, which this waits
+    /// for.
+    ///
+    /// Blocks with several equally good matches, such as ones without any texture, get the
+    /// smallest of their disparities.
+    ///
+    /// Returns an error if the GPU rejects the work or its result can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the views have different sizes or numbers of channels, or if `block_size` is
+    /// zero.
+    pub fn stereo_disparity<'a, L: Sample, R: Sample>(
+        &mut self,
+        left: impl Into<Image<'a, L>>,
+        right: impl Into<Image<'a, R>>,
+        method: MatchTemplateMethod,
+        block_size: u32,
+        max_disparity: u32,
+    ) -> Result<DisparityMap, Error> {
+        let (left, right) = (left.into(), right.into());
+        let blocks = self.match_blocks(&left, &right, method, block_size, |(x, y)| {
+            let start = x.saturating_sub(max_disparity);
+            Region::new(start, y, x - start + block_size, block_size)
+        })?;
+
+        let (disparities, scores) = blocks
+            .into_iter()
+            .map(|((x, _), window, result)| {
+                // The result is a single row, whose last position is a disparity of zero.
+                let disparities = (0..=x - window.x)
+                    .map(|disparity| (disparity, result.data[(x - window.x - disparity) as usize]));
+                disparities.fold((0, f32::INFINITY), |best, (disparity, score)| {
+                    if score < best.1 {
+                        (disparity, score)
+                    } else {
+                        best
+                    }
+                })
+            })
+            .unzip();
+
+        Ok(DisparityMap {
+            block_size,
+            columns: left.width / block_size,
+            rows: left.height / block_size,
+            disparities,
+            scores,
+        })
+    }
+
+    /// Divides frame `a` into square blocks of `block_size` pixels, and matches each block against
+    /// the region of frame `b` that `window` returns for the block's top-left corner, in one
+    /// submission. Returns the corner, region and result of each block, row by row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frames have different sizes or numbers of channels, or if `block_size` is
+    /// zero.
+    fn match_blocks<A: Sample, B: Sample>(
+        &mut self,
+        a: &Image<'_, A>,
+        b: &Image<'_, B>,
+        method: MatchTemplateMethod,
+        block_size: u32,
+        window: impl Fn((u32, u32)) -> Region,
+    ) -> Result<Vec<BlockMatch>, Error> {
+        assert!(
+            (a.width, a.height, a.channels) == (b.width, b.height, b.channels),
+            "frames must have the same size and number of channels"
+        );
+        assert!(block_size > 0, "block size must be positive");
+
+        let (columns, rows) = (a.width / block_size, a.height / block_size);
+        let origins: Vec<_> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| (column * block_size, row * block_size))
+            .collect();
+
+        // The blocks are copied out of the frame, so that only their own samples are uploaded.
+        let blocks: Vec<_> = origins
+            .iter()
+            .map(|&(x, y)| {
+                let block = a.expect_region(Region::new(x, y, block_size, block_size));
+                let samples: Vec<_> = packed_samples(&block).collect();
+                Image::with_channels(samples, block_size, block_size, a.channels)
+            })
+            .collect();
+        let windows: Vec<_> = origins.iter().map(|&origin| window(origin)).collect();
+
+        let results = self.match_templates_in_regions(b, &blocks, &windows, method)?;
+        Ok(origins
+            .into_iter()
+            .zip(windows)
+            .zip(results)
+            .map(|((origin, window), result)| (origin, window, result))
+            .collect())
+    }
+
+    /// Matches the template against a batch of inputs of the same size and number of channels, and
+    /// returns the results in the order of the inputs. The inputs are matched in a single dispatch,
+    /// unless there are more than fit in one buffer.
+    ///
+    /// This waits for the results, and doesn't affect the result of a previous
+    /// [match_template](Self::match_template) call.
+    ///
+    /// Returns the errors of [try_match_template](Self::try_match_template) if the template can't
+    /// be matched against any of the inputs, before matching it against any of them, and an error if the GPU
+    /// rejects the work or the results can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the inputs have different sizes or numbers of channels.
+    pub fn match_inputs<'a, I: Sample, T: Sample>(
+        &mut self,
+        inputs: &[Image<'_, I>],
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        let template = template.into();
+        for input in inputs {
+            check_images(input, &template)?;
+        }
+
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.track_blocking(|gpu| {
+                gpu.match_matrix(inputs, std::slice::from_ref(&template), method)
+            }),
+            Backend::Cpu(cpu) => Ok(inputs
+                .iter()
+                .map(|input| cpu::match_template(input, &template, method, cpu.summation()))
+                .collect()),
+        }
+    }
+
+    /// Matches every template against every input of a batch. The inputs must have the same size
+    /// and number of channels. Each input is uploaded once and all pairs are matched in one
+    /// submission.
+    ///
+    /// This waits for the results, and doesn't affect the result of a previous
+    /// [match_template](Self::match_template) call.
+    ///
+    /// Returns the errors of [try_match_template](Self::try_match_template) if any template can't
+    /// be matched against any input, before matching any of them, and an error if the GPU
+    /// rejects the work or the results can't be read back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the inputs have different sizes or numbers of channels.
+    pub fn match_matrix<I: Sample, T: Sample>(
+        &mut self,
+        inputs: &[Image<'_, I>],
+        templates: &[Image<'_, T>],
+        method: MatchTemplateMethod,
+    ) -> Result<MatchMatrix, Error> {
+        for input in inputs {
+            for template in templates {
+                check_images(input, template)?;
+            }
+        }
+
+        let results = match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.track_blocking(|gpu| gpu.match_matrix(inputs, templates, method))?
+            }
+            Backend::Cpu(cpu) => {
+                let summation = cpu.summation();
+                inputs
+                    .iter()
+                    .flat_map(|input| {
+                        templates.iter().map(move |template| {
+                            cpu::match_template(input, template, method, summation)
+                        })
+                    })
+                    .collect()
+            }
+        };
+
+        Ok(MatchMatrix {
+            results,
+            templates: templates.len(),
+        })
+    }
+
+    /// Like [match_template](Self::match_template), but leaves the result on the GPU instead of reading
+    /// it back, for further processing in other compute passes. The work is submitted before returning.
+    ///
+    /// The returned buffer holds the result as tightly packed `f32`s and has `STORAGE`, `COPY_SRC` and
+    /// `COPY_DST` usages.
+    ///
+    /// Returns [Error::Unsupported] on the [CPU engine](Engine::Cpu).
+    pub fn match_template_on_gpu<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<GpuImage, Error> {
+        Ok(self
+            .gpu_mut()?
+            .match_template_on_gpu(input, template, method))
+    }
+
+    /// Records matching into the given encoder without submitting it, so that it can be ordered
+    /// with the rest of an application's GPU work. The result is available in the returned buffer
+    /// once the encoder has been submitted.
+    ///
+    /// Each call uploads into freshly created buffers, so several matches can be recorded into the
+    /// same encoder.
+    ///
+    /// Returns [Error::Unsupported] on the [CPU engine](Engine::Cpu).
+    pub fn encode_match<'a, I: Sample, T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<GpuImage, Error> {
+        Ok(self
+            .gpu_mut()?
+            .encode_match(encoder, input, template, method))
+    }
+
+    /// Like [encode_match](Self::encode_match), but writes the result into `buffer` starting at
+    /// `offset` bytes, so that several results can be packed into one buffer. Returns the size of
+    /// the result, which takes `width * height` tightly packed `f32`s.
+    ///
+    /// The buffer must have [STORAGE](wgpu::BufferUsages::STORAGE) usage, and the offset must be a
+    /// multiple of the device's `min_storage_buffer_offset_alignment` limit.
+    ///
+    /// Returns [Error::Unsupported] on the [CPU engine](Engine::Cpu).
+    pub fn encode_match_into<'a, I: Sample, T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        buffer: &wgpu::Buffer,
+        offset: u64,
+    ) -> Result<(u32, u32), Error> {
+        Ok(self
+            .gpu_mut()?
+            .encode_match_into(encoder, input, template, method, buffer, offset))
+    }
+
+    /// Like [match_template](Self::match_template), but reads the input directly from a texture that
+    /// is already on the GPU, avoiding a round-trip through host memory.
+    ///
+    /// The texture must be created on this matcher's [device](Self::device) with
+    /// [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage, and have a float format that
+    /// can be loaded without a sampler, e.g. `R32Float` or `Rgba8Unorm`.
+    /// The template must have as many channels as the texture format has components.
+    ///
+    /// Returns [Error::Unsupported] on the [CPU engine](Engine::Cpu).
+    pub fn match_texture<'a, T: Sample>(
+        &mut self,
+        input: &wgpu::Texture,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<MatchJob, Error> {
+        Ok(self.gpu_mut()?.match_texture(input, template, method))
+    }
+}
+
+/// The [TemplateMatcher] state of the GPU engine.
+struct GpuMatcher {
+    context: Arc<GpuContext>,
+    kernels: Kernels,
+
+    storage: ImageStorage,
+    input: ImageSlot,
+    /// Whether the uploaded input was set with `set_input`, rather than uploaded for a single match.
+    input_retained: bool,
+    /// Image that preprocessing steps run on before it becomes the input.
+    frame: ImageSlot,
+    /// Intermediate images of preprocessing steps, which alternate between the two.
+    preprocessed: [ImageSlot; 2],
+    /// Running background of [Preprocess::SubtractBackground], with the size and number of
+    /// channels of the images it was started for.
+    background: Option<((u32, u32, u32), wgpu::Buffer)>,
+    /// Previous and current frames compared by `has_changed`, which are kept apart from the input
+    /// and the template so that comparing frames doesn't replace them.
+    compared: [ImageSlot; 2],
+    template: ImageSlot,
+    /// Whether the uploaded template was set with `set_template`.
+    template_retained: bool,
+    /// Sums over the uploaded template, for matching in the frequency domain.
+    template_sums: TemplateSums,
+    algorithm: MatchAlgorithm,
+    precision: Precision,
+    non_finite: NonFinite,
+    deterministic: bool,
+    last_result_size: (u32, u32),
+    last_key: Option<PipelineKey>,
+
+    uniform_buffer: wgpu::Buffer,
+    /// Uniforms that the uniform buffer was last written with.
+    written_uniforms: Option<ShaderUniforms>,
+    /// Buffer that results are written to, grown when a larger result is needed.
+    result_buffer: Option<wgpu::Buffer>,
+    /// Bind group of the uploaded images and result buffer, or [None] if any of them has changed.
+    bind_group: Option<wgpu::BindGroup>,
+    /// Best score of pruned matching, created on first use.
+    best_buffer: Option<wgpu::Buffer>,
+    /// Timestamp queries, if the device supports them.
+    timestamps: Option<Timestamps>,
+    last_timings: Option<Timings>,
+
+    /// Whether matches are batched, see [TemplateMatcher::begin_batch].
+    batching: bool,
+    /// Matches recorded into the batch but not yet submitted.
+    batch: Option<wgpu::CommandEncoder>,
+    batch_len: usize,
+    /// Buffers of batched matches.
+    batch_buffers: BufferPool,
+    /// Matches whose results haven't been collected yet, oldest first.
+    jobs: Vec<PendingJob>,
+    next_job_id: u64,
+    /// Jobs that were in flight when the device was lost and weren't rerun, with their result
+    /// sizes.
+    lost_jobs: Vec<(MatchJob, (u32, u32))>,
+    recovery: DeviceRecovery,
+    /// Set the images kept with `set_input` and `set_template` again after the device has been
+    /// replaced, while recovery is enabled.
+    restore_input: Option<Restore>,
+    restore_template: Option<Restore>,
+    /// Error raised while uploading an image that is kept, reported by the next job.
+    deferred_error: Option<Error>,
+    /// Staging buffers of collected jobs, kept for reuse by later ones.
+    spare_staging_buffers: Vec<wgpu::Buffer>,
+}
+
+/// How a dispatch scores the positions of the result.
+#[derive(Copy, Clone)]
+enum Scoring<'a> {
+    /// With the whole uploaded template.
+    Full,
+    /// With the uploaded template, giving up on positions that exceed the best score so far, which
+    /// starts at the given bound.
+    Pruned(f32),
+    /// With only the pixels of a sparse template, which isn't uploaded beforehand.
+    Sparse(&'a SparseTemplate),
+    /// With the uploaded template, by the number of samples on different sides of the given
+    /// threshold.
+    Binary(f32),
+}
+
+/// Most matches recorded into a batch before it is submitted.
+const MAX_BATCH_LEN: usize = 64;
+
+/// Buffers of batched matches. Each match of a batch needs buffers of its own, since all queue
+/// writes land before the batch runs, but once the batch is submitted its buffers can be written
+/// again for the next one.
+#[derive(Default)]
+struct BufferPool {
+    /// Buffers that can be taken.
+    spare: Vec<wgpu::Buffer>,
+    /// Buffers taken for the batch being recorded.
+    in_use: Vec<wgpu::Buffer>,
+}
+
+impl BufferPool {
+    /// Returns a spare buffer with the given usage and at least the given size, or a new one.
+    /// Buffers are given back with [keep](Self::keep) once the batch refers to them.
+    fn take(
+        &mut self,
+        device: &wgpu::Device,
+        size: u64,
+        usage: wgpu::BufferUsages,
+    ) -> wgpu::Buffer {
+        let smallest = (0..self.spare.len())
+            .filter(|&i| self.spare[i].usage() == usage && self.spare[i].size() >= size)
+            .min_by_key(|&i| self.spare[i].size());
+
+        smallest
+            .map(|i| self.spare.swap_remove(i))
+            .unwrap_or_else(|| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("batch_buffer"),
+                    usage,
+                    size,
+                    mapped_at_creation: false,
+                })
+            })
+    }
+
+    /// Like [take](Self::take), but writes `contents` into the start of the buffer.
+    fn upload(
+        &mut self,
+        context: &GpuContext,
+        contents: &[u8],
+        usage: wgpu::BufferUsages,
+    ) -> wgpu::Buffer {
+        let buffer = self.take(
+            &context.device,
+            contents.len() as u64,
+            usage | wgpu::BufferUsages::COPY_DST,
+        );
+        context.queue.write_buffer(&buffer, 0, contents);
+        buffer
+    }
+
+    /// Keeps a taken buffer until the batch that uses it is submitted.
+    fn keep(&mut self, buffer: wgpu::Buffer) {
+        self.in_use.push(buffer);
+    }
+
+    /// Makes the buffers of the submitted batch spare again.
+    fn recycle(&mut self) {
+        self.spare.append(&mut self.in_use);
+    }
+}
+
+/// Binds the first `size` bytes of a buffer.
+fn buffer_binding(buffer: &wgpu::Buffer, size: u64) -> wgpu::BindingResource<'_> {
+    wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+        buffer,
+        offset: 0,
+        size: wgpu::BufferSize::new(size),
+    })
+}
+
+/// Indices of the timestamps written around the stages of a match.
+const UPLOAD_START: u32 = 0;
+const COMPUTE_START: u32 = 1;
+const COMPUTE_END: u32 = 2;
+const READBACK_END: u32 = 3;
+const TIMESTAMP_COUNT: u32 = 4;
+const TIMESTAMP_BYTES: u64 = TIMESTAMP_COUNT as u64 * size_of::<u64>() as u64;
+
+/// Timestamp queries that time the stages of a match. The timestamps are resolved and copied to the
+/// staging buffer after the result.
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    /// Whether the start of the uploads of the next match has been written.
+    upload_started: bool,
+}
+
+impl Timestamps {
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: TIMESTAMP_COUNT,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("timestamp_resolve_buffer"),
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                size: TIMESTAMP_BYTES,
+                mapped_at_creation: false,
+            }),
+            upload_started: false,
+        }
+    }
+}
+
+/// A match whose result is copied into its own staging buffer, waiting to be read back.
+struct PendingJob {
+    job: MatchJob,
+    /// Buffer the result is copied to, which can be larger than the result.
+    staging_buffer: wgpu::Buffer,
+    /// Submission that copies the result, so that waiting for it doesn't also wait for matches
+    /// started later, or [None] while the job is in the unsubmitted batch.
+    submission: Option<wgpu::SubmissionIndex>,
+    size: (u32, u32),
+    /// Index of the first timestamp copied after the result, if the match was timed.
+    first_timestamp: Option<u32>,
+    mapping: Option<futures_channel::oneshot::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    /// Starts the match again on a new device, if it can be rerun after the device is lost.
+    replay: Option<Replay>,
+    /// Number of times the match has been rerun.
+    attempts: u32,
+    /// First validation or out-of-memory error raised while starting the match.
+    error: Option<Error>,
+}
+
+/// Starts a match again on a matcher whose device has been replaced.
+type Replay = Box<dyn FnOnce(&mut GpuMatcher) -> MatchJob + Send + Sync>;
+
+/// Uploads an image kept by a matcher again after its device has been replaced.
+type Restore = Box<dyn FnOnce(&mut GpuMatcher) + Send + Sync>;
+
+impl PendingJob {
+    fn result_bytes(&self) -> u64 {
+        (self.size.0 * self.size.1) as u64 * size_of::<f32>() as u64
+    }
+
+    /// The part of the staging buffer that holds the result and its timestamps.
+    fn result_slice(&self) -> wgpu::BufferSlice<'_> {
+        let timestamp_bytes = if self.first_timestamp.is_some() {
+            TIMESTAMP_BYTES
+        } else {
+            0
+        };
+        self.staging_buffer
+            .slice(..self.result_bytes() + timestamp_bytes)
+    }
+
+    /// Computes the timings from the timestamps that follow the result in the mapped staging
+    /// buffer.
+    fn timings(&self, mapped: &[u8], period: f32) -> Option<Timings> {
+        let first = self.first_timestamp?;
+        let timestamps = &mapped[self.result_bytes() as usize..];
+        let timestamp = |index: u32| -> u64 {
+            let offset = index as usize * size_of::<u64>();
+            bytemuck::pod_read_unaligned(&timestamps[offset..offset + size_of::<u64>()])
+        };
+        let elapsed = |start, end| {
+            let ticks = timestamp(end).saturating_sub(timestamp(start));
+            Duration::from_nanos((ticks as f64 * period as f64) as u64)
+        };
+
+        Some(Timings {
+            upload: if first == UPLOAD_START {
+                elapsed(UPLOAD_START, COMPUTE_START)
+            } else {
+                Duration::ZERO
+            },
+            compute: elapsed(COMPUTE_START, COMPUTE_END),
+            readback: elapsed(COMPUTE_END, READBACK_END),
+        })
+    }
+}
+
+impl GpuMatcher {
+    fn new(context: Arc<GpuContext>) -> Self {
+        let device = &context.device;
+        let kernels = Kernels::new(device, context.workgroup_size());
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("uniform_buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<ShaderUniforms>() as _,
+            mapped_at_creation: false,
+        });
+
+        let timestamps = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| Timestamps::new(device));
+
+        Self {
+            context,
+            kernels,
+            storage: ImageStorage::default(),
+            input: ImageSlot::default(),
+            input_retained: false,
+            frame: ImageSlot::default(),
+            preprocessed: Default::default(),
+            background: None,
+            compared: Default::default(),
+            template: ImageSlot::default(),
+            template_retained: false,
+            template_sums: TemplateSums::default(),
+            algorithm: MatchAlgorithm::default(),
+            precision: Precision::default(),
+            non_finite: NonFinite::default(),
+            deterministic: false,
+            last_result_size: (0, 0),
+            last_key: None,
+            uniform_buffer,
+            written_uniforms: None,
+            result_buffer: None,
+            bind_group: None,
+            best_buffer: None,
+            timestamps,
+            last_timings: None,
+            batching: false,
+            batch: None,
+            batch_len: 0,
+            batch_buffers: BufferPool::default(),
+            jobs: Vec::new(),
+            next_job_id: 0,
+            lost_jobs: Vec::new(),
+            recovery: DeviceRecovery::default(),
+            restore_input: None,
+            restore_template: None,
+            deferred_error: None,
+            spare_staging_buffers: Vec::new(),
+        }
+    }
+
+    fn device(&self) -> &wgpu::Device {
+        &self.context.device
+    }
+
+    fn queue(&self) -> &wgpu::Queue {
+        &self.context.queue
+    }
+
+    fn context(&self) -> &Arc<GpuContext> {
+        &self.context
+    }
+
+    fn result_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.result_buffer.as_ref()
+    }
+
+    fn bind_group_layout(&self) -> Option<&wgpu::BindGroupLayout> {
+        self.last_key.map(|key| self.kernels.bind_group_layout(key))
+    }
+
+    fn set_image_storage(&mut self, storage: ImageStorage) {
+        self.storage = storage;
+    }
+
+    fn set_algorithm(&mut self, algorithm: MatchAlgorithm) {
+        self.algorithm = algorithm;
+    }
+
+    fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    fn set_non_finite(&mut self, policy: NonFinite) {
+        self.non_finite = policy;
+    }
+
+    fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Algorithm that scores are computed with. Deterministic matching never uses FFTs, whose
+    /// results depend on the precision of the device's trigonometric functions.
+    fn algorithm(&self) -> MatchAlgorithm {
+        if self.deterministic {
+            MatchAlgorithm::Direct
+        } else {
+            self.algorithm
+        }
+    }
+
+    /// Whether images of the given sample type are converted to `f16` before uploading.
+    fn uploads_half<T: Sample>(&self) -> bool {
+        self.precision == Precision::HalfStorage && T::FORMAT == SampleFormat::F32
+    }
+
+    /// Whether images of the given sample type are quantized to `u8` before uploading.
+    fn uploads_quantized<T: Sample>(&self) -> bool {
+        self.precision == Precision::Quantized && T::FORMAT != SampleFormat::U8
+    }
+
+    fn capabilities(&self) -> &Capabilities {
+        self.kernels.capabilities()
+    }
+
+    fn latest_job(&self) -> Option<MatchJob> {
+        let latest = MatchJob(self.next_job_id.checked_sub(1)?);
+        (self.jobs.iter().any(|pending| pending.job == latest)
+            || self.lost_jobs.iter().any(|&(job, _)| job == latest))
+        .then_some(latest)
+    }
+
+    fn pending_jobs(&self) -> usize {
+        self.jobs.len() + self.lost_jobs.len()
+    }
+
+    fn wait_for_job(&mut self, job: MatchJob) -> Option<Image<'static>> {
+        let mut result = Vec::new();
+        let (result_width, result_height) = self.wait_for_job_into(job, &mut result)?;
+        Some(Image::new(result, result_width, result_height))
+    }
+
+    fn try_wait_for_job(&mut self, job: MatchJob) -> Result<Option<Image<'static>>, Error> {
+        let mut result = Vec::new();
+        let Some((size, read)) = pollster::block_on(self.read_job_into(job, &mut result, true))
+        else {
+            return Ok(None);
+        };
+        read?;
+        Ok(Some(Image::new(result, size.0, size.1)))
+    }
+
+    fn wait_for_job_into(&mut self, job: MatchJob, out: &mut Vec<f32>) -> Option<(u32, u32)> {
+        pollster::block_on(self.read_job_into(job, out, true)).map(|(size, _)| size)
+    }
+
+    fn poll_job_result(
+        &mut self,
+        job: MatchJob,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<Option<Image<'static>>, Error>> {
+        let mut result = Vec::new();
+        self.poll_read_job_into(job, &mut result, cx).map(|read| {
+            let Some((size, read)) = read else {
+                return Ok(None);
+            };
+            read?;
+            Ok(Some(Image::new(result, size.0, size.1)))
+        })
+    }
+
+    async fn job_result_async(&mut self, job: MatchJob) -> Option<Image<'static>> {
+        let mut result = Vec::new();
+        let ((result_width, result_height), _) =
+            self.read_job_into(job, &mut result, false).await?;
+        Some(Image::new(result, result_width, result_height))
+    }
+
+    async fn match_template_async<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Image<'static> {
+        let job = self.match_template(input, template, method);
+        self.job_result_async(job).await.unwrap()
+    }
+
+    fn poll_job(&mut self, job: MatchJob) -> std::task::Poll<Option<Image<'static>>> {
+        let mut result = Vec::new();
+        let Some(index) = self.job_index(job) else {
+            let lost = self.take_lost_job(job, &mut result);
+            return std::task::Poll::Ready(
+                lost.map(|(width, height)| Image::new(result, width, height)),
+            );
+        };
+
+        self.request_mapping(index);
+        let mapped = if self.poll_device(wgpu::Maintain::Poll) {
+            match self.jobs[index]
+                .mapping
+                .as_mut()
+                .unwrap()
+                .try_recv()
+                .transpose()
+            {
+                Some(mapped) => mapped,
+                None => return std::task::Poll::Pending,
+            }
+        } else {
+            Ok(Err(wgpu::BufferAsyncError))
+        };
+
+        if !matches!(mapped, Ok(Ok(()))) && self.recover() {
+            return self.poll_job(job);
+        }
+
+        let ((result_width, result_height), _) = self.finish_reading(index, mapped, &mut result);
+        std::task::Poll::Ready(Some(Image::new(result, result_width, result_height)))
+    }
+
+    fn cancel(&mut self, job: MatchJob) -> bool {
+        let Some(index) = self.job_index(job) else {
+            return self.take_lost_job(job, &mut Vec::new()).is_some();
+        };
+        let pending = self.jobs.remove(index);
+        self.discard(pending);
+        true
+    }
+
+    fn cancel_all(&mut self) {
+        self.lost_jobs.clear();
+        for pending in std::mem::take(&mut self.jobs) {
+            self.discard(pending);
+        }
+    }
+
+    /// Drops a job without reading its result back.
+    fn discard(&mut self, pending: PendingJob) {
+        // A buffer that is being mapped can't be reused, so it is dropped, which aborts the mapping.
+        if pending.mapping.is_none() {
+            self.spare_staging_buffers.push(pending.staging_buffer);
+        }
+    }
+
+    fn job_index(&self, job: MatchJob) -> Option<usize> {
+        self.jobs.iter().position(|pending| pending.job == job)
+    }
+
+    /// Reads the result of the given job into `out`, or returns [None] if it was already collected.
+    /// If `blocking` is set, the thread waits for the GPU, otherwise the device is polled in the
+    /// background and the task waits for the mapping callback.
+    async fn read_job_into(
+        &mut self,
+        job: MatchJob,
+        out: &mut Vec<f32>,
+        blocking: bool,
+    ) -> Option<JobRead> {
+        // The browser maps buffers on its own, so blocking for them isn't possible on wasm.
+        if !blocking || cfg!(target_arch = "wasm32") {
+            return std::future::poll_fn(|cx| self.poll_read_job_into(job, out, cx)).await;
+        }
+
+        loop {
+            if let Some(size) = self.take_lost_job(job, out) {
+                return Some((size, Err(Error::DeviceLost)));
+            }
+            let index = self.job_index(job)?;
+
+            self.request_mapping(index);
+            let submission = self.jobs[index].submission.clone().unwrap();
+            let mut maintain = wgpu::Maintain::WaitForSubmissionIndex(submission);
+            let mapped = loop {
+                if !self.poll_device(maintain) {
+                    break Ok(Err(wgpu::BufferAsyncError));
+                }
+                if let Some(mapped) = self.jobs[index]
+                    .mapping
+                    .as_mut()
+                    .unwrap()
+                    .try_recv()
+                    .transpose()
+                {
+                    break mapped;
+                }
+                maintain = wgpu::Maintain::Wait;
+            };
+
+            // The job was rerun on a new device or failed with it, so it is looked up again.
+            if !matches!(mapped, Ok(Ok(()))) && self.recover() {
+                continue;
+            }
+
+            return Some(self.finish_reading(index, mapped, out));
+        }
+    }
+
+    /// Like [read_job_into](Self::read_job_into) without blocking, but as a poll that wakes the
+    /// task once the result can be read.
+    fn poll_read_job_into(
+        &mut self,
+        job: MatchJob,
+        out: &mut Vec<f32>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<JobRead>> {
+        loop {
+            if let Some(size) = self.take_lost_job(job, out) {
+                return std::task::Poll::Ready(Some((size, Err(Error::DeviceLost))));
+            }
+            let Some(index) = self.job_index(job) else {
+                return std::task::Poll::Ready(None);
+            };
+
+            let std::task::Poll::Ready(mapped) = self.poll_mapping(index, cx) else {
+                return std::task::Poll::Pending;
+            };
+
+            // The job was rerun on a new device or failed with it, so it is looked up again.
+            if !matches!(mapped, Ok(Ok(()))) && self.recover() {
+                continue;
+            }
+
+            return std::task::Poll::Ready(Some(self.finish_reading(index, mapped, out)));
+        }
+    }
+
+    /// Polls whether the staging buffer of the job at `index` has been mapped, requesting the
+    /// mapping if it hasn't been yet. The device is polled in the background, and the task is woken
+    /// by the mapping callback, or when the device is found lost, which fails the mapping.
+    fn poll_mapping(
+        &mut self,
+        index: usize,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Mapping> {
+        self.request_mapping(index);
+
+        let mapping = self.jobs[index].mapping.as_mut().unwrap();
+        if let std::task::Poll::Ready(mapped) =
+            std::future::Future::poll(std::pin::Pin::new(mapping), cx)
+        {
+            return std::task::Poll::Ready(mapped);
+        }
+        if self.context.watch_for_loss(cx.waker()) {
+            return std::task::Poll::Ready(Ok(Err(wgpu::BufferAsyncError)));
+        }
+
+        self.context.poll_in_background();
+        std::task::Poll::Pending
+    }
+
+    /// Polls the device. If device recovery is enabled, wgpu's panic about the device having been
+    /// lost is caught, and false is returned instead so that the device can be replaced.
+    fn poll_device(&self, maintain: wgpu::Maintain) -> bool {
+        if self.recovery == DeviceRecovery::Disabled {
+            self.context.device.poll(maintain);
+            return true;
+        }
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.context.device.poll(maintain);
+        }))
+        .is_ok()
+    }
+
+    /// Completes a job that failed with a lost device, filling `out` with zeros. Returns the size of
+    /// its result, or [None] if it isn't such a job.
+    fn take_lost_job(&mut self, job: MatchJob, out: &mut Vec<f32>) -> Option<(u32, u32)> {
+        let index = self.lost_jobs.iter().position(|&(lost, _)| lost == job)?;
+        let (_, (width, height)) = self.lost_jobs.remove(index);
+        out.clear();
+        out.resize((width * height) as usize, 0.0);
+        Some((width, height))
+    }
+
+    /// Replaces the device after a failed readback, which means it was lost, with a new one created
+    /// with the same options. The images kept with `set_input` and `set_template` are uploaded
+    /// again, and the jobs in flight are rerun as far as the recovery setting allows, keeping their
+    /// ids. Jobs that aren't rerun fail with [Error::DeviceLost]. Returns false if recovery is
+    /// disabled or the device can't be replaced.
+    fn recover(&mut self) -> bool {
+        let DeviceRecovery::Recreate { retries } = self.recovery else {
+            return false;
+        };
+        let Ok(context) = self.context.recreate() else {
+            return false;
+        };
+
+        let mut lost = std::mem::replace(self, GpuMatcher::new(Arc::new(context)));
+        self.storage = lost.storage;
+        self.algorithm = lost.algorithm;
+        self.precision = lost.precision;
+        self.non_finite = lost.non_finite;
+        self.deterministic = lost.deterministic;
+        self.batching = lost.batching;
+        self.recovery = lost.recovery;
+        self.next_job_id = lost.next_job_id;
+        self.lost_jobs = std::mem::take(&mut lost.lost_jobs);
+
+        if let Some(restore) = lost.restore_input.take().filter(|_| lost.input_retained) {
+            self.track_errors(restore);
+        }
+        if let Some(restore) = lost
+            .restore_template
+            .take()
+            .filter(|_| lost.template_retained)
+        {
+            self.track_errors(restore);
+        }
+
+        for pending in std::mem::take(&mut lost.jobs) {
+            match pending.replay {
+                Some(replay) if pending.attempts < retries => {
+                    let job = self.track_errors(replay);
+                    let index = self.job_index(job).unwrap();
+                    self.jobs[index].job = pending.job;
+                    self.jobs[index].attempts = pending.attempts + 1;
+                }
+                _ => self.lost_jobs.push((pending.job, pending.size)),
+            }
+        }
+        // The ids taken by the reruns were replaced with those of the lost jobs.
+        self.next_job_id = lost.next_job_id;
+        true
+    }
+
+    /// Starts mapping the staging buffer of a job for reading, unless it already is being mapped.
+    fn request_mapping(&mut self, index: usize) {
+        self.submit_batch();
+
+        let pending = &mut self.jobs[index];
+        if pending.mapping.is_some() {
+            return;
+        }
+
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        pending
+            .result_slice()
+            .map_async(wgpu::MapMode::Read, move |v| {
+                let _ = sender.send(v);
+            });
+        pending.mapping = Some(receiver);
+    }
+
+    /// Copies the mapped staging buffer of a job into `out` and completes the job. Returns the size
+    /// of the result. If mapping failed, `out` is filled with zeros and an error is returned.
+    fn finish_reading(&mut self, index: usize, mapped: Mapping, out: &mut Vec<f32>) -> JobRead {
+        let pending = self.jobs.remove(index);
+        let (result_width, result_height) = pending.size;
+
+        out.clear();
+
+        // A dropped callback means the buffer was never mapped.
+        let mapped = mapped.unwrap_or(Err(wgpu::BufferAsyncError));
+        let result = match &pending.error {
+            Some(error) => Err(error.clone()),
+            None => mapped.clone().map_err(Error::from),
+        };
+
+        if result.is_ok() {
+            let data = pending.result_slice().get_mapped_range();
+            out.extend_from_slice(bytemuck::cast_slice(
+                &data[..pending.result_bytes() as usize],
+            ));
+            if let Some(timings) = pending.timings(&data, self.context.queue.get_timestamp_period())
+            {
+                self.last_timings = Some(timings);
+            }
+            drop(data);
+            pending.staging_buffer.unmap();
+            self.spare_staging_buffers.push(pending.staging_buffer);
+        } else {
+            if mapped.is_ok() {
+                pending.staging_buffer.unmap();
+            }
+            out.resize((result_width * result_height) as usize, 0.0);
+        }
+
+        (pending.size, result)
+    }
+
+    /// Takes the given job out of the pending jobs and calls `callback` with its result once the
+    /// staging buffer has been mapped.
+    fn forward_result(
+        &mut self,
+        job: MatchJob,
+        callback: impl FnOnce(MatchJob, Result<Image<'static>, Error>) + Send + 'static,
+    ) {
+        self.submit_batch();
+
+        let index = self.job_index(job).unwrap();
+        let pending = self.jobs.remove(index);
+        let (result_width, result_height) = pending.size;
+        let result_bytes = pending.result_bytes();
+
+        if let Some(error) = pending.error {
+            callback(job, Err(error));
+            return;
+        }
+
+        // The callback keeps the buffer alive until it has been read.
+        let staging_buffer = Arc::new(pending.staging_buffer);
+        let buffer = staging_buffer.clone();
+
+        staging_buffer
+            .slice(..result_bytes)
+            .map_async(wgpu::MapMode::Read, move |mapped| {
+                let result = mapped.map_err(Error::from).map(|()| {
+                    let data = buffer.slice(..result_bytes).get_mapped_range();
+                    let result = bytemuck::cast_slice(&data).to_vec();
+                    drop(data);
+                    buffer.unmap();
+                    Image::new(result, result_width, result_height)
+                });
+                callback(job, result);
+            });
+
+        self.context.poll_in_background();
+    }
+
+    /// Returns a staging buffer of at least the given size, reusing the smallest spare one that is
+    /// large enough if possible.
+    fn staging_buffer(&mut self, size: u64) -> wgpu::Buffer {
+        // Like the other buffers, staging buffers only grow, so smaller spares aren't needed again.
+        self.spare_staging_buffers
+            .retain(|buffer| buffer.size() >= size);
+
+        let smallest = (0..self.spare_staging_buffers.len())
+            .min_by_key(|&i| self.spare_staging_buffers[i].size());
+
+        smallest
+            .map(|i| self.spare_staging_buffers.swap_remove(i))
+            .unwrap_or_else(|| {
+                self.context.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("staging_buffer"),
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    size,
+                    mapped_at_creation: false,
+                })
+            })
+    }
+
+    fn match_template<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input = input.into();
+        let template = template.into();
+        let images = self
+            .recovers()
+            .then(|| (input.to_owned(), template.to_owned()));
+
+        let job = self.start_match(input, template, method);
+        if let Some((input, template)) = images {
+            self.set_replay(job, move |gpu| gpu.match_template(input, template, method));
+        }
+        job
+    }
+
+    fn start_match<I: Sample, T: Sample>(
+        &mut self,
+        input: Image<'_, I>,
+        template: Image<'_, T>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        if let Some(tiles) = self.result_tiles(&input, (template.width, template.height)) {
+            let template_changed = self.upload_template(&template);
+            return self.match_tiled(
+                &input,
+                (template.width, template.height),
+                method,
+                Scoring::Full,
+                template_changed,
+                &tiles,
+            );
+        }
+
+        if self.batching {
+            return self.match_template_batched(&input, &template, method);
+        }
+
+        self.start_upload_timing();
+        let (input_layout, buffers_changed) = self.upload_input(&input);
+
+        self.dispatch(input_layout, None, template, method, buffers_changed, true)
+            .unwrap()
+    }
+
+    /// Whether images are kept for replacing the device if it is lost.
+    fn recovers(&self) -> bool {
+        self.recovery != DeviceRecovery::Disabled
+    }
+
+    /// Records a match into the batch, with the images uploaded into buffers of the batch, since the
+    /// shared ones are only written between submissions.
+    fn match_template_batched<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let params = MatchParams::new(ImageLayout::of(input), ImageLayout::of(template), method);
+        let (result_size, result_bytes) = (params.result_size(), params.result_bytes());
+
+        let result_buffer = self.batch_buffers.take(
+            &self.context.device,
+            result_bytes,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let staging_buffer = self.staging_buffer(result_bytes);
+
+        let mut encoder = self.batch.take().unwrap_or_else(|| {
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("batch_encoder"),
+                })
+        });
+        self.encode_standalone(
+            &mut encoder,
+            input,
+            template,
+            method,
+            buffer_binding(&result_buffer, result_bytes),
+            true,
+        );
+        encoder.copy_buffer_to_buffer(&result_buffer, 0, &staging_buffer, 0, result_bytes);
+        self.batch_buffers.keep(result_buffer);
+
+        self.batch = Some(encoder);
+        self.batch_len += 1;
+
+        let job = self.push_job(staging_buffer, None, result_size, None);
+        if self.batch_len >= MAX_BATCH_LEN {
+            self.submit_batch();
+        }
+        job
+    }
+
+    /// Submits the batched matches, if any. Called before anything else is written to the queue,
+    /// so that the batch runs before it like it would have if it had been submitted right away.
+    fn submit_batch(&mut self) {
+        let Some(encoder) = self.batch.take() else {
+            return;
+        };
+
+        // Errors recorded into the batch are raised when it is finished.
+        self.push_error_scopes();
+        let submission = self.context.queue.submit(std::iter::once(encoder.finish()));
+        let error = self.pop_error_scopes();
+        for pending in &mut self.jobs {
+            if pending.submission.is_none() {
+                pending.submission = Some(submission.clone());
+                pending.error = pending.error.take().or_else(|| error.clone());
+            }
+        }
+        self.batch_len = 0;
+        self.batch_buffers.recycle();
+    }
+
+    /// Splits the result into tiles whose inputs and results each fit in a storage buffer binding,
+    /// or returns [None] if the whole input and result already fit.
+    fn result_tiles<I: Sample>(
+        &self,
+        input: &Image<'_, I>,
+        (template_width, template_height): (u32, u32),
+    ) -> Option<Vec<Region>> {
+        let limits = self.context.device.limits();
+        let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+
+        let sample_bytes = if self.uploads_quantized::<I>() {
+            size_of::<u8>()
+        } else if self.uploads_half::<I>() {
+            size_of::<f16>()
+        } else {
+            size_of::<I>()
+        } as u64;
+        let pixel_bytes = sample_bytes * input.channels as u64;
+        let result_width = input.width - template_width + 1;
+        let result_height = input.height - template_height + 1;
+
+        // Inputs of tiles overlap by the size of the template minus one.
+        let input_bytes = |width: u32, height: u32| {
+            (width + template_width - 1) as u64
+                * (height + template_height - 1) as u64
+                * pixel_bytes
+        };
+        let result_bytes = |width: u32, height: u32| width as u64 * height as u64 * 4;
+        let fits = |width, height| {
+            input_bytes(width, height) <= max_bytes && result_bytes(width, height) <= max_bytes
+        };
+
+        if fits(result_width, result_height) {
+            return None;
+        }
+
+        assert!(
+            result_bytes(result_width, result_height) <= limits.max_buffer_size,
+            "result of {result_width}x{result_height} exceeds the maximum buffer size of the device"
+        );
+
+        // Tiles span the whole width if a row of results fits, so that each one is a contiguous
+        // part of the result.
+        let mut tile_width = result_width;
+        while !fits(tile_width, 1) {
+            assert!(
+                tile_width > 1,
+                "template is too large for the storage buffer limits of the device"
+            );
+            tile_width = tile_width.div_ceil(2);
+        }
+
+        let input_rows = max_bytes / ((tile_width + template_width - 1) as u64 * pixel_bytes);
+        let tile_height = (input_rows - (template_height - 1) as u64)
+            .min(max_bytes / result_bytes(tile_width, 1))
+            .min(result_height as u64) as u32;
+
+        let tiles = (0..result_height)
+            .step_by(tile_height as usize)
+            .flat_map(|y| {
+                (0..result_width)
+                    .step_by(tile_width as usize)
+                    .map(move |x| Region {
+                        x,
+                        y,
+                        width: tile_width.min(result_width - x),
+                        height: tile_height.min(result_height - y),
+                    })
+            })
+            .collect();
+
+        Some(tiles)
+    }
+
+    /// Scores the parts of the input under each of the result tiles, one after another, and copies
+    /// the results into a single staging buffer. Templates other than sparse ones must already be
+    /// uploaded, with `template_changed` telling whether that changed the template buffer.
+    fn match_tiled<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        (template_width, template_height): (u32, u32),
+        method: MatchTemplateMethod,
+        scoring: Scoring,
+        mut template_changed: bool,
+        tiles: &[Region],
+    ) -> MatchJob {
+        let result_width = input.width - template_width + 1;
+        let result_height = input.height - template_height + 1;
+        let staging_buffer =
+            self.staging_buffer((result_width * result_height) as u64 * size_of::<f32>() as u64);
+
+        let mut submission = None;
+
+        for tile in tiles {
+            let tile_input = input.expect_region(Region {
+                width: tile.width + template_width - 1,
+                height: tile.height + template_height - 1,
+                ..*tile
+            });
+
+            // Narrower tiles, and tiles of strided inputs, are packed, so that the rows of the rest of
+            // the input aren't uploaded in between.
+            let packed = input.row_stride() == input.width * input.channels;
+            let (input_layout, input_changed) = if packed && tile.width == result_width {
+                self.upload_input(&tile_input)
+            } else {
+                let samples: Vec<I> = packed_samples(&tile_input).collect();
+                self.upload_input(&Image::with_channels(
+                    samples,
+                    tile_input.width,
+                    tile_input.height,
+                    tile_input.channels,
+                ))
+            };
+
+            self.dispatch_uploaded(
+                input_layout,
+                None,
+                method,
+                input_changed | template_changed,
+                false,
+                scoring,
+            );
+            template_changed = false;
+
+            // The result buffer is overwritten by the next tile, so its rows are copied into place
+            // before that.
+            let mut encoder =
+                self.context
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("tile_encoder"),
+                    });
+
+            let row_bytes = tile.width as u64 * size_of::<f32>() as u64;
+            let (rows, bytes) = if tile.width == result_width {
+                // Whole rows are contiguous in both buffers.
+                (1, tile.height as u64 * row_bytes)
+            } else {
+                (tile.height as u64, row_bytes)
+            };
+
+            for row in 0..rows {
+                let offset = ((tile.y as u64 + row) * result_width as u64 + tile.x as u64)
+                    * size_of::<f32>() as u64;
+                encoder.copy_buffer_to_buffer(
+                    self.result_buffer.as_ref().unwrap(),
+                    row * row_bytes,
+                    &staging_buffer,
+                    offset,
+                    bytes,
+                );
+            }
+
+            submission = Some(self.context.queue.submit(std::iter::once(encoder.finish())));
+        }
+
+        self.last_result_size = (result_width, result_height);
+        self.push_job(
+            staging_buffer,
+            submission,
+            (result_width, result_height),
+            None,
+        )
+    }
+
+    fn match_template_pruned<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        bound: Option<f32>,
+    ) -> MatchJob {
+        self.start_upload_timing();
+        let (input_layout, input_changed) = self.upload_input(&input.into());
+        let template_changed = self.upload_template(&template.into());
+
+        self.dispatch_uploaded(
+            input_layout,
+            None,
+            method,
+            input_changed | template_changed,
+            true,
+            // Scores are compared by their bit patterns, which only works for non-negative ones.
+            Scoring::Pruned(bound.unwrap_or(f32::INFINITY).max(0.0)),
+        )
+        .unwrap()
+    }
+
+    fn match_sparse_template<'a, I: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: &SparseTemplate,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input = input.into();
+        assert_eq!(
+            input.channels,
+            template.channels(),
+            "input and template must have the same number of channels"
+        );
+
+        let template_size = (template.width(), template.height());
+        if let Some(tiles) = self.result_tiles(&input, template_size) {
+            return self.match_tiled(
+                &input,
+                template_size,
+                method,
+                Scoring::Sparse(template),
+                false,
+                &tiles,
+            );
+        }
+
+        self.start_upload_timing();
+        let (input_layout, input_changed) = self.upload_input(&input);
+
+        self.dispatch_uploaded(
+            input_layout,
+            None,
+            method,
+            input_changed,
+            true,
+            Scoring::Sparse(template),
+        )
+        .unwrap()
+    }
+
+    fn match_template_binary<'a, 'b, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'b, T>>,
+        threshold: f32,
+    ) -> MatchJob {
+        let input = input.into();
+        let template = template.into();
+
+        // The method only decides how the scores are compared, where lower is better.
+        let method = MatchTemplateMethod::SumOfAbsoluteDifferences;
+        if let Some(tiles) = self.result_tiles(&input, (template.width, template.height)) {
+            let template_changed = self.upload_template(&template);
+            return self.match_tiled(
+                &input,
+                (template.width, template.height),
+                method,
+                Scoring::Binary(threshold),
+                template_changed,
+                &tiles,
+            );
+        }
+
+        self.start_upload_timing();
+        let (input_layout, input_changed) = self.upload_input(&input);
+        let template_changed = self.upload_template(&template);
+
+        self.dispatch_uploaded(
+            input_layout,
+            None,
+            method,
+            input_changed | template_changed,
+            true,
+            Scoring::Binary(threshold),
+        )
+        .unwrap()
+    }
+
+    fn match_variants<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+    ) -> Result<(Image<'static>, Vec<u32>), Error> {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+
+        let rendered = self.render_variants(template, variants);
+        self.match_rendered(input, method, variants, &rendered)
+    }
+
+    /// Renders the variants of the template into buffers that stay on the device.
+    fn render_variants<T: Sample>(
+        &mut self,
+        template: &Image<'_, T>,
+        variants: &[Variant],
+    ) -> GpuVariants {
+        if self.upload_template(template) {
+            self.bind_group = None;
+        }
+        // Rendering only reads the template's part of the uniforms.
+        let template_layout = self.template.layout;
+        self.write_uniforms(template_layout, template_layout);
+
+        let mut records = Vec::with_capacity(variants.len());
+        let mut offsets = Vec::new();
+        for variant in variants {
+            let first_pixel = (offsets.len() / 2) as u32;
+            records.push(variant.packed((template.width, template.height), first_pixel));
+            offsets.extend(variant.pixels.iter().flat_map(|&(x, y)| [x, y]));
+        }
+        let max_pixel_count = variants.iter().map(|variant| variant.pixels.len());
+
+        let device = &self.context.device;
+        let rendered = GpuVariants {
+            template_layout,
+            variants: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("transformed_variants"),
+                contents: bytemuck::cast_slice(&records),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
+            offsets: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("transformed_offsets"),
+                contents: bytemuck::cast_slice(&offsets),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
+            samples: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("transformed_samples"),
+                size: (offsets.len() / 2 * template.channels as usize * size_of::<f32>()) as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("transformed_render_encoder"),
+        });
+        self.kernels.encode_render_variants(
+            device,
+            &mut encoder,
+            template_layout.source,
+            RenderImages {
+                template: self.template.binding(),
+                uniforms: &self.uniform_buffer,
+                variants: &rendered.variants,
+                offsets: &rendered.offsets,
+                samples: &rendered.samples,
+            },
+            (variants.len() as u32, max_pixel_count.max().unwrap() as u32),
+        );
+        // Submitted right away, before the uniforms are rewritten for matching.
+        self.context.queue.submit(Some(encoder.finish()));
+
+        rendered
+    }
+
+    /// Matches rendered variants and returns the best score at each position along with the
+    /// index of its variant.
+    fn match_rendered<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        method: MatchTemplateMethod,
+        variants: &[Variant],
+        rendered: &GpuVariants,
+    ) -> Result<(Image<'static>, Vec<u32>), Error> {
+        let (input_layout, input_changed) = self.upload_input(input);
+        if input_changed {
+            self.bind_group = None;
+        }
+        self.write_uniforms(input_layout, rendered.template_layout);
+
+        let (result_width, result_height) =
+            transform::result_size((input.width, input.height), variants);
+        let result_len = (result_width * result_height) as usize;
+
+        let device = &self.context.device;
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("transformed_result"),
+            size: (2 * result_len * size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("transformed_encoder"),
+        });
+        self.kernels.encode_transformed(
+            device,
+            &mut encoder,
+            (method, input_layout.source),
+            TransformedImages {
+                input: self.input.binding(),
+                uniforms: &self.uniform_buffer,
+                variants: &rendered.variants,
+                offsets: &rendered.offsets,
+                samples: &rendered.samples,
+                result: result_buffer.as_entire_binding(),
+            },
+            (result_width, result_height),
+        );
+
+        let data = self.read_back(encoder, &result_buffer)?;
+        let (scores, indices) = data.split_at(result_len);
+        Ok((
+            Image::new(scores.to_vec(), result_width, result_height),
+            indices.iter().map(|&index| index as u32).collect(),
+        ))
+    }
+
+    fn estimate_log_polar<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        search: &LogPolarSearch,
+    ) -> Result<(u32, u32, u32), Error> {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+
+        let (input_layout, input_changed) = self.upload_input(input);
+        let template_changed = self.upload_template(template);
+        if input_changed || template_changed {
+            self.bind_group = None;
+        }
+        let template_layout = self.template.layout;
+        self.write_uniforms(input_layout, template_layout);
+
+        let candidate_count = search.candidate_count() as usize;
+        let device = &self.context.device;
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("log_polar_result"),
+            size: (2 * candidate_count * size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("log_polar_encoder"),
+        });
+        self.kernels.encode_log_polar(
+            device,
+            &mut encoder,
+            (method, input_layout.source, template_layout.source),
+            LogPolarImages {
+                input: self.input.binding(),
+                template: self.template.binding(),
+                result: result_buffer.as_entire_binding(),
+                uniforms: &self.uniform_buffer,
+            },
+            search,
+        );
+
+        let data = self.read_back(encoder, &result_buffer)?;
+        let mut best = (f32::INFINITY, (0, 0, 0));
+        for (candidate, result) in data.chunks_exact(2).enumerate() {
+            if result[0] < best.0 {
+                let k = result[1] as u32;
+                let angles = log_polar::ANGLES;
+                best = (result[0], (candidate as u32, k / angles, k % angles));
+            }
+        }
+        Ok(best.1)
+    }
+
+    fn has_changed<P: Sample, C: Sample>(
+        &mut self,
+        previous: &Image<'_, P>,
+        current: &Image<'_, C>,
+        threshold: f32,
+    ) -> Result<bool, Error> {
+        self.submit_batch();
+        let [previous_slot, current_slot] = &mut self.compared;
+        let (previous_layout, _) =
+            previous_slot.upload(&self.context, self.storage, previous, "previous frame");
+        let (current_layout, _) =
+            current_slot.upload(&self.context, self.storage, current, "current frame");
+        self.write_uniforms(current_layout, previous_layout);
+
+        let device = &self.context.device;
+        // New buffers are zeroed, which the shader relies on.
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("difference_result"),
+            size: size_of::<f32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("difference_encoder"),
+        });
+        self.kernels.encode_difference(
+            device,
+            &mut encoder,
+            (current_layout.source, previous_layout.source),
+            DifferenceImages {
+                input: self.compared[1].binding(),
+                template: self.compared[0].binding(),
+                result: result_buffer.as_entire_binding(),
+                uniforms: &self.uniform_buffer,
+            },
+            ((current.width, current.height), threshold),
+        );
+
+        Ok(self.read_back(encoder, &result_buffer)?[0] != 0.0)
+    }
+
+    fn refine<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        refinement: &Refinement,
+    ) -> Result<Vec<f32>, Error> {
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+
+        let (input_layout, input_changed) = self.upload_input(input);
+        let template_changed = self.upload_template(template);
+        if input_changed || template_changed {
+            self.bind_group = None;
+        }
+        let template_layout = self.template.layout;
+        self.write_uniforms(input_layout, template_layout);
+
+        let device = &self.context.device;
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("refine_result"),
+            size: (refine::RESULT_LEN * size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("refine_encoder"),
+        });
+        self.kernels.encode_refine(
+            device,
+            &mut encoder,
+            (input_layout.source, template_layout.source),
+            RefineImages {
+                input: self.input.binding(),
+                template: self.template.binding(),
+                result: result_buffer.as_entire_binding(),
+                uniforms: &self.uniform_buffer,
+            },
+            refinement,
+        );
+
+        self.read_back(encoder, &result_buffer)
+    }
+
+    fn match_template_extremes<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Extremes, Error> {
+        let input = input.into();
+        let template = template.into();
+
+        // Tiles are stitched in a staging buffer, so their extremes are found on the CPU.
+        if self
+            .result_tiles(&input, (template.width, template.height))
+            .is_some()
+        {
+            let job = self.match_template(input, template, method);
+            let result = self.try_wait_for_job(job)?.unwrap();
+            return Ok(find_extremes(&result));
+        }
+
+        let (input_layout, buffers_changed) = self.upload_input(&input);
+        self.dispatch(input_layout, None, template, method, buffers_changed, false);
+
+        let (width, height) = self.last_result_size;
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("extremes_encoder"),
+                });
+        let extremes = self.kernels.encode_extremes(
+            &self.context.device,
+            &mut encoder,
+            self.result_buffer.as_ref().unwrap().as_entire_binding(),
+            width * height,
+        );
+
+        let data = self.read_back(encoder, &extremes)?;
+        let location = |index: f32| (index.to_bits() % width, index.to_bits() / width);
+
+        Ok(Extremes {
+            min_value: data[0],
+            min_value_location: location(data[1]),
+            max_value: data[2],
+            max_value_location: location(data[3]),
+        })
+    }
+
+    fn match_template_on_gpu<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> GpuImage {
+        let input = input.into();
+        let (input_layout, buffers_changed) = self.upload_input(&input);
+
+        self.dispatch(
+            input_layout,
+            None,
+            template.into(),
+            method,
+            buffers_changed,
+            false,
+        );
+
+        let (width, height) = self.last_result_size;
+
+        // The buffer is handed over to the caller, so a new one is created on the next call.
+        GpuImage {
+            buffer: self.result_buffer.take().unwrap(),
+            width,
+            height,
+        }
+    }
+
+    fn encode_match<'a, I: Sample, T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> GpuImage {
+        let input = input.into();
+        let template = template.into();
+
+        let params = MatchParams::new(ImageLayout::of(&input), ImageLayout::of(&template), method);
+        let (width, height) = params.result_size();
+        let buffer = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("result_buffer"),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            size: params.result_bytes(),
+            mapped_at_creation: false,
+        });
+
+        self.encode_standalone(
+            encoder,
+            &input,
+            &template,
+            method,
+            buffer.as_entire_binding(),
+            false,
+        );
+
+        GpuImage {
+            buffer,
+            width,
+            height,
+        }
+    }
+
+    fn encode_match_into<'a, I: Sample, T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+        buffer: &wgpu::Buffer,
+        offset: u64,
+    ) -> (u32, u32) {
+        let input = input.into();
+        let template = template.into();
+
+        let alignment = self
+            .context
+            .device
+            .limits()
+            .min_storage_buffer_offset_alignment as u64;
+        assert!(
+            offset.is_multiple_of(alignment),
+            "result offset must be a multiple of {alignment}"
+        );
+
+        let params = MatchParams::new(ImageLayout::of(&input), ImageLayout::of(&template), method);
+        let (width, height) = params.result_size();
+        let size = params.result_bytes();
+        assert!(
+            offset + size <= buffer.size(),
+            "result does not fit in the buffer at the given offset"
+        );
+
+        self.encode_standalone(
+            encoder,
+            &input,
+            &template,
+            method,
+            wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset,
+                size: wgpu::BufferSize::new(size),
+            }),
+            false,
+        );
+
+        (width, height)
+    }
+
+    /// Uploads the images into buffers of their own and records a matching pass that writes into
+    /// `result`. The buffers are taken from the pool of the batch if `pooled` is set, and created
+    /// for the encoder otherwise, since its submission isn't up to the matcher.
+    fn encode_standalone<I: Sample, T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &Image<'_, I>,
+        template: &Image<'_, T>,
+        method: MatchTemplateMethod,
+        result: wgpu::BindingResource,
+        pooled: bool,
+    ) {
+        if self.uploads_half::<I>() {
+            let input = to_half(input);
+            return self.encode_standalone(encoder, &input, template, method, result, pooled);
+        }
+        if self.uploads_quantized::<I>() {
+            let input = to_unorm8(input);
+            return self.encode_standalone(encoder, &input, template, method, result, pooled);
+        }
+
+        assert_eq!(
+            input.channels, template.channels,
+            "input and template must have the same number of channels"
+        );
+        input.assert_valid("input");
+        template.assert_valid("template");
+
+        let input_layout = ImageLayout::of(input);
+        let input_bytes = upload_bytes(&input.data[..input.required_len()]);
+        let usage = wgpu::BufferUsages::STORAGE;
+        let input_buffer = if pooled {
+            self.batch_buffers
+                .upload(&self.context, &input_bytes, usage)
+        } else {
+            self.context
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("input_buffer"),
+                    contents: &input_bytes,
+                    usage,
+                })
+        };
+
+        let settings = (method, self.algorithm(), self.precision, self.non_finite);
+        let key = encode_template(
+            &self.context,
+            &mut self.kernels,
+            (encoder, pooled.then_some(&mut self.batch_buffers)),
+            (
+                input_layout,
+                buffer_binding(&input_buffer, input_bytes.len() as u64),
+                None,
+            ),
+            template,
+            settings,
+            result,
+        );
+        self.last_key = Some(key);
+        if pooled {
+            self.batch_buffers.keep(input_buffer);
+        }
+    }
+
+    fn match_templates<I: Sample, T: Sample>(
+        &mut self,
+        input: Image<'_, I>,
+        templates: &[Image<'_, T>],
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        let (input_layout, input_changed) = self.upload_input(&input);
+        if input_changed {
+            // The bind group of `match_template` refers to the previous input buffer.
+            self.bind_group = None;
+        }
+
+        let sizes: Vec<_> = templates
+            .iter()
+            .map(|template| {
+                (
+                    input.width - template.width + 1,
+                    input.height - template.height + 1,
+                )
+            })
+            .collect();
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("encoder"),
+                });
+
+        let (result_buffer, offsets) = self.create_packed_result_buffer(&sizes);
+
+        let settings = (method, self.algorithm(), self.precision, self.non_finite);
+        for ((template, &(width, height)), &offset) in templates.iter().zip(&sizes).zip(&offsets) {
+            let key = encode_template(
+                &self.context,
+                &mut self.kernels,
+                (&mut encoder, None),
+                (input_layout, self.input.binding(), Some(self.input.version)),
+                template,
+                settings,
+                wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &result_buffer,
+                    offset,
+                    size: wgpu::BufferSize::new((width * height) as u64 * size_of::<f32>() as u64),
+                }),
+            );
+            self.last_key = Some(key);
+        }
+
+        let data = self.read_back(encoder, &result_buffer)?;
+        Ok(unpack_results(&data, &sizes, &offsets))
+    }
+
+    fn match_templates_in_regions<I: Sample, T: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        templates: &[Image<'_, T>],
+        regions: &[Region],
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        if self.uploads_half::<I>() {
+            return self.match_templates_in_regions(&to_half(input), templates, regions, method);
+        }
+        if self.uploads_quantized::<I>() {
+            return self.match_templates_in_regions(&to_unorm8(input), templates, regions, method);
+        }
+
+        input.assert_valid("input");
+
+        // The regions are copied one after another into a single buffer, each at an offset that
+        // can be bound on its own.
+        let alignment = self
+            .context
+            .device
+            .limits()
+            .min_storage_buffer_offset_alignment as usize;
+        let mut bytes = Vec::new();
+        let mut ranges = Vec::with_capacity(regions.len());
+        for &region in regions {
+            let samples: Vec<_> = packed_samples(&input.expect_region(region)).collect();
+            let offset = bytes.len().next_multiple_of(alignment);
+            bytes.resize(offset, 0);
+            bytes.extend_from_slice(&upload_bytes(&samples));
+            ranges.push((offset as u64, (bytes.len() - offset) as u64));
+        }
+        // Empty buffers can't be bound.
+        bytes.resize(bytes.len().max(alignment), 0);
+
+        let input_buffer =
+            self.context
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("input_buffer"),
+                    contents: &bytes,
+                    usage: wgpu::BufferUsages::STORAGE,
+                });
+
+        let sizes: Vec<_> = templates
+            .iter()
+            .zip(regions)
+            .map(|(template, region)| {
+                (
+                    region.width - template.width + 1,
+                    region.height - template.height + 1,
+                )
+            })
+            .collect();
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("encoder"),
+                });
+
+        let (result_buffer, offsets) = self.create_packed_result_buffer(&sizes);
+
+        let settings = (method, self.algorithm(), self.precision, self.non_finite);
+        for (((template, &region), &(offset, size)), (&(width, height), &result_offset)) in
+            templates
+                .iter()
+                .zip(regions)
+                .zip(&ranges)
+                .zip(sizes.iter().zip(&offsets))
+        {
+            let layout = ImageLayout {
+                width: region.width,
+                height: region.height,
+                stride: region.width * input.channels,
+                ..ImageLayout::of(input)
+            };
+            let key = encode_template(
+                &self.context,
+                &mut self.kernels,
+                (&mut encoder, None),
+                (
+                    layout,
+                    wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &input_buffer,
+                        offset,
+                        size: wgpu::BufferSize::new(size),
+                    }),
+                    None,
+                ),
+                template,
+                settings,
+                wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &result_buffer,
+                    offset: result_offset,
+                    size: wgpu::BufferSize::new((width * height) as u64 * size_of::<f32>() as u64),
+                }),
+            );
+            self.last_key = Some(key);
+        }
+
+        let data = self.read_back(encoder, &result_buffer)?;
+        Ok(unpack_results(&data, &sizes, &offsets))
+    }
+
+    /// Matches every template against every input. Returns the results ordered by input and then by
+    /// template.
+    fn match_matrix<I: Sample, T: Sample>(
+        &mut self,
+        inputs: &[Image<'_, I>],
+        templates: &[Image<'_, T>],
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<Image<'static>>, Error> {
+        let Some(first) = inputs.first() else {
+            return Ok(Vec::new());
+        };
+
+        if self.uploads_half::<I>() {
+            let inputs: Vec<_> = inputs.iter().map(to_half).collect();
+            return self.match_matrix(&inputs, templates, method);
+        }
+        if self.uploads_quantized::<I>() {
+            let inputs: Vec<_> = inputs.iter().map(to_unorm8).collect();
+            return self.match_matrix(&inputs, templates, method);
+        }
+
+        // Inputs are packed tightly one after another, and the shader selects one by the z index
+        // of the dispatch.
+        let input_layout = ImageLayout {
+            stride: first.width * first.channels,
+            batch_stride: first.width * first.height * first.channels,
+            ..ImageLayout::of(first)
+        };
+        let result_sizes: Vec<_> = templates
+            .iter()
+            .map(|template| {
+                (
+                    first.width - template.width + 1,
+                    first.height - template.height + 1,
+                )
+            })
+            .collect();
+
+        // Split the batch so that each part fits in a single storage buffer binding and dispatch.
+        let limits = self.context.device.limits();
+        let input_size = input_layout.batch_stride as usize * size_of::<I>();
+        let result_size = (first.width * first.height) as usize * size_of::<f32>();
+        let chunk_len = (limits.max_storage_buffer_binding_size as usize
+            / input_size.max(result_size))
+        .clamp(1, limits.max_compute_workgroups_per_dimension as usize);
+
+        let chunks: Vec<_> = inputs.chunks(chunk_len).collect();
+
+        // Results of a chunk and template are stacked vertically, one input after another.
+        let sizes: Vec<_> = chunks
+            .iter()
+            .flat_map(|chunk| {
+                result_sizes
+                    .iter()
+                    .map(|&(width, height)| (width, height * chunk.len() as u32))
+            })
+            .collect();
+
+        let (result_buffer, offsets) = self.create_packed_result_buffer(&sizes);
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("encoder"),
+                });
+
+        let mut results = sizes.iter().zip(&offsets);
+
+        for chunk in &chunks {
+            let mut samples = Vec::with_capacity(input_layout.batch_stride as usize * chunk.len());
+            for input in *chunk {
+                assert!(
+                    (input.width, input.height, input.channels)
+                        == (first.width, first.height, first.channels),
+                    "batched inputs must have the same size and number of channels"
+                );
+                input.assert_valid("input");
+                samples.extend(packed_samples(input));
+            }
+
+            let input_buffer =
+                self.context
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("input_buffer"),
+                        contents: &upload_bytes(&samples),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    });
+
+            let layout = ImageLayout {
+                batch: chunk.len() as u32,
+                ..input_layout
+            };
+
+            let settings = (method, self.algorithm(), self.precision, self.non_finite);
+            for (template, (&(width, height), &offset)) in templates.iter().zip(&mut results) {
+                let key = encode_template(
+                    &self.context,
+                    &mut self.kernels,
+                    (&mut encoder, None),
+                    (layout, input_buffer.as_entire_binding(), None),
+                    template,
+                    settings,
+                    wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &result_buffer,
+                        offset,
+                        size: wgpu::BufferSize::new(
+                            (width * height) as u64 * size_of::<f32>() as u64,
+                        ),
+                    }),
+                );
+                self.last_key = Some(key);
+            }
+        }
+
+        let data = self.read_back(encoder, &result_buffer)?;
+        let mut stacked = unpack_results(&data, &sizes, &offsets).into_iter();
+
+        // Unstack the results and reorder them by input.
+        let mut matrix = Vec::with_capacity(inputs.len() * templates.len());
+        for chunk in &chunks {
+            let per_template: Vec<Vec<Image<'static>>> = result_sizes
+                .iter()
+                .map(|&(width, height)| {
+                    stacked
+                        .next()
+                        .unwrap()
+                        .data
+                        .chunks((width * height) as usize)
+                        .map(|result| Image::new(result.to_vec(), width, height))
+                        .collect()
+                })
+                .collect();
+
+            let mut per_template: Vec<_> = per_template.into_iter().map(Vec::into_iter).collect();
+            for _ in 0..chunk.len() {
+                matrix.extend(
+                    per_template
+                        .iter_mut()
+                        .map(|results| results.next().unwrap()),
+                );
+            }
+        }
+
+        Ok(matrix)
+    }
+
+    /// Creates a buffer that holds results of the given sizes, each starting at an offset that can
+    /// be bound as a storage buffer. Returns the buffer and the offsets in bytes.
+    fn create_packed_result_buffer(&self, sizes: &[(u32, u32)]) -> (wgpu::Buffer, Vec<u64>) {
+        let alignment = self
+            .context
+            .device
+            .limits()
+            .min_storage_buffer_offset_alignment as u64;
+
+        let mut offsets = Vec::with_capacity(sizes.len());
+        let mut total_size = 0;
+        for &(width, height) in sizes {
+            offsets.push(total_size);
+            total_size +=
+                ((width * height) as u64 * size_of::<f32>() as u64).next_multiple_of(alignment);
+        }
+
+        let buffer = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("packed_result_buffer"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            // Empty buffers can't be bound.
+            size: total_size.max(alignment),
+            mapped_at_creation: false,
+        });
+
+        (buffer, offsets)
+    }
+
+    /// Submits the encoder along with a copy of `buffer` to host memory, and waits for the copy.
+    /// If the buffer can't be read back, zeros are returned instead.
+    /// Submits the encoder with a copy of the buffer and waits until the copy has been read back.
+    fn read_back(
+        &self,
+        mut encoder: wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+    ) -> Result<Vec<f32>, Error> {
+        let staging_buffer = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging_buffer"),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            size: buffer.size(),
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+        self.context.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = staging_buffer.slice(..);
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| {
+            let _ = sender.send(v);
+        });
+
+        if !self.poll_device(wgpu::Maintain::Wait) {
+            return Err(Error::DeviceLost);
+        }
+
+        match pollster::block_on(receiver) {
+            Ok(Ok(())) => Ok(bytemuck::cast_slice(&buffer_slice.get_mapped_range()).to_vec()),
+            Ok(Err(error)) => Err(Error::BufferMapping(error)),
+            // The callback is dropped without being called if the device is lost.
+            Err(_) => Err(Error::DeviceLost),
+        }
+    }
+
+    /// Uploads the input, recreating its buffer or texture if the layout changed.
+    /// Returns the layout and whether the buffer or texture was recreated.
+    fn upload_input<I: Sample>(&mut self, input: &Image<'_, I>) -> (ImageLayout, bool) {
+        self.submit_batch();
+        self.input_retained = false;
+
+        if self.uploads_half::<I>() {
+            return self
+                .input
+                .upload(&self.context, self.storage, &to_half(input), "input");
+        }
+        if self.uploads_quantized::<I>() {
+            return self
+                .input
+                .upload(&self.context, self.storage, &to_unorm8(input), "input");
+        }
+
+        self.input
+            .upload(&self.context, self.storage, input, "input")
+    }
+
+    /// Writes the uniforms of the given layouts, unless the buffer already holds them.
+    fn write_uniforms(&mut self, input: ImageLayout, template: ImageLayout) {
+        let uniforms = ShaderUniforms::new(&input, &template)
+            .with_non_finite(self.non_finite)
+            .with_deterministic(self.deterministic);
+        if self.written_uniforms != Some(uniforms) {
+            self.context
+                .queue
+                .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+            self.written_uniforms = Some(uniforms);
+        }
+    }
+
+    /// Uploads the template, recreating its buffer or texture if the layout changed.
+    /// Returns whether the buffer or texture was recreated.
+    fn upload_template<T: Sample>(&mut self, template: &Image<'_, T>) -> bool {
+        self.submit_batch();
+        self.template_retained = false;
+        self.template_sums = TemplateSums::of(template);
+
+        let (_, template_changed) = if self.uploads_half::<T>() {
+            self.template
+                .upload(&self.context, self.storage, &to_half(template), "template")
+        } else if self.uploads_quantized::<T>() {
+            self.template.upload(
+                &self.context,
+                self.storage,
+                &to_unorm8(template),
+                "template",
+            )
+        } else {
+            self.template
+                .upload(&self.context, self.storage, template, "template")
+        };
+        template_changed
+    }
+
+    fn set_input<I: Sample>(&mut self, input: &Image<'_, I>) {
+        let (_, input_changed) = self.upload_input(input);
+        self.input_retained = true;
+        if self.recovers() {
+            let input = input.to_owned();
+            self.restore_input = Some(Box::new(move |gpu| gpu.set_input(&input)));
+        }
+
+        if input_changed {
+            // Makes the next dispatch rebuild the bind group for the new input.
+            self.bind_group = None;
+        }
+    }
+
+    /// Uploads the image and records its preprocessing steps into the encoder, leaving the result
+    /// in the input slot. Returns the layout of the result and whether its buffer was recreated.
+    fn encode_preprocess<T: Sample>(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        image: &Image<'_, T>,
+        steps: &[Preprocess],
+    ) -> (ImageLayout, bool) {
+        self.submit_batch();
+        self.input_retained = false;
+
+        let (mut layout, _) = self
+            .frame
+            .upload(&self.context, self.storage, image, "frame");
+        let mut input_changed = false;
+
+        for (i, step) in steps.iter().enumerate() {
+            let size = step.output_size((layout.width, layout.height, layout.channels));
+            let last = i + 1 == steps.len();
+
+            let output_layout = if last {
+                let (output_layout, changed) = self.input.allocate(&self.context, size, "input");
+                input_changed = changed;
+                output_layout
+            } else {
+                self.preprocessed[i % 2]
+                    .allocate(&self.context, size, "preprocessed")
+                    .0
+            };
+
+            let start_background = match step {
+                Preprocess::SubtractBackground { .. } => self.prepare_background(size),
+                _ => false,
+            };
+
+            let source = match i {
+                0 => &self.frame,
+                _ => &self.preprocessed[(i - 1) % 2],
+            };
+            let output = if last {
+                &self.input
+            } else {
+                &self.preprocessed[i % 2]
+            };
+            let state = match step {
+                Preprocess::SubtractBackground { .. } => self.background.as_ref(),
+                _ => None,
+            };
+            for pass in step.passes(start_background) {
+                self.kernels.encode_preprocess(
+                    &self.context.device,
+                    encoder,
+                    &pass,
+                    PreprocessImages {
+                        source: source.binding(),
+                        output: output.binding(),
+                        state: state.map(|(_, buffer)| buffer.as_entire_binding()),
+                    },
+                    (&layout, &output_layout),
+                );
+            }
+            layout = output_layout;
+        }
+
+        if input_changed {
+            self.bind_group = None;
+        }
+        (layout, input_changed)
+    }
+
+    /// Makes sure that the running background fits images of the given size and number of
+    /// channels, recreating it if it doesn't. Returns whether it has to be started.
+    fn prepare_background(&mut self, size: (u32, u32, u32)) -> bool {
+        if matches!(&self.background, Some((shape, _)) if *shape == size) {
+            return false;
+        }
+
+        let (width, height, channels) = size;
+        let buffer = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("background"),
+            size: (width * height * channels) as u64 * size_of::<f32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        self.background = Some((size, buffer));
+        true
+    }
+
+    fn set_input_preprocessed<I: Sample>(&mut self, input: &Image<'_, I>, steps: &[Preprocess]) {
+        if steps.is_empty() {
+            return self.set_input(input);
+        }
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("preprocess_encoder"),
+                });
+        self.encode_preprocess(&mut encoder, input, steps);
+        self.context.queue.submit(std::iter::once(encoder.finish()));
+        self.input_retained = true;
+        if self.recovers() {
+            let (input, steps) = (input.to_owned(), steps.to_vec());
+            self.restore_input = Some(Box::new(move |gpu| {
+                gpu.set_input_preprocessed(&input, &steps)
+            }));
+        }
+    }
+
+    fn preprocess<T: Sample>(
+        &mut self,
+        image: &Image<'_, T>,
+        steps: &[Preprocess],
+    ) -> Result<Image<'static>, Error> {
+        if steps.is_empty() {
+            return Ok(preprocess::apply(image, steps, &mut None));
+        }
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("preprocess_encoder"),
+                });
+        let (layout, _) = self.encode_preprocess(&mut encoder, image, steps);
+
+        let mut data = self.read_back(encoder, self.input.buffer.as_ref().unwrap())?;
+        data.truncate((layout.stride * layout.height) as usize);
+        Ok(Image::with_channels(
+            data,
+            layout.width,
+            layout.height,
+            layout.channels,
+        ))
+    }
+
+    fn match_uploaded<T: Sample>(
+        &mut self,
+        template: Image<'_, T>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        assert!(
+            self.input_retained && self.input.is_uploaded(),
+            "no input has been set with set_input"
+        );
+
+        let kept = self.recovers().then(|| template.to_owned());
+        let job = self
+            .dispatch(self.input.layout, None, template, method, false, true)
+            .unwrap();
+        if let Some(template) = kept {
+            self.set_replay(job, move |gpu| gpu.match_uploaded(template, method));
+        }
+        job
+    }
+
+    fn set_template<T: Sample>(&mut self, template: &Image<'_, T>) {
+        let template_changed = self.upload_template(template);
+        self.template_retained = true;
+        if self.recovers() {
+            let template = template.to_owned();
+            self.restore_template = Some(Box::new(move |gpu| gpu.set_template(&template)));
+        }
+
+        if template_changed {
+            // Makes the next dispatch rebuild the bind group for the new template.
+            self.bind_group = None;
+        }
+    }
+
+    fn match_uploaded_template<I: Sample>(
+        &mut self,
+        input: &Image<'_, I>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        assert!(
+            self.template_retained && self.template.is_uploaded(),
+            "no template has been set with set_template"
+        );
+
+        self.start_upload_timing();
+        let (input_layout, buffers_changed) = self.upload_input(input);
+        let job = self
+            .dispatch_uploaded(
+                input_layout,
+                None,
+                method,
+                buffers_changed,
+                true,
+                Scoring::Full,
+            )
+            .unwrap();
+        if self.recovers() {
+            let input = input.to_owned();
+            self.set_replay(job, move |gpu| gpu.match_uploaded_template(&input, method));
+        }
+        job
+    }
+
+    fn match_texture<'a, T: Sample>(
+        &mut self,
+        input: &wgpu::Texture,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> MatchJob {
+        let input_layout = ImageLayout {
+            width: input.width(),
+            height: input.height(),
+            channels: texture_channels(input.format()),
+            source: Source::Texture,
+            ..ImageLayout::default()
+        };
+
+        let buffers_changed = self.input.layout != input_layout;
+        self.input.clear(input_layout);
+        self.input_retained = false;
+
+        let view = input.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.dispatch(
+            input_layout,
+            Some(&view),
+            template.into(),
+            method,
+            buffers_changed,
+            true,
+        )
+        .unwrap()
+    }
+
+    /// Uploads the template and records and submits the matching pass for an input that has already
+    /// been uploaded (or is given as `input_view`). If `readback` is set, the result is copied to a
+    /// staging buffer and the returned job can be collected with [wait_for_job](Self::wait_for_job).
+    fn dispatch<T: Sample>(
+        &mut self,
+        input: ImageLayout,
+        input_view: Option<&wgpu::TextureView>,
+        template: Image<'_, T>,
+        method: MatchTemplateMethod,
+        buffers_changed: bool,
+        readback: bool,
+    ) -> Option<MatchJob> {
+        self.start_upload_timing();
+        let template_changed = self.upload_template(&template);
+        self.dispatch_uploaded(
+            input,
+            input_view,
+            method,
+            buffers_changed | template_changed,
+            readback,
+            Scoring::Full,
+        )
+    }
+
+    /// Writes the timestamp at the start of the uploads of the next match, unless it already has
+    /// been. It is submitted on its own, so that it comes before the uploads, which are submitted
+    /// along with the next command buffer.
+    fn start_upload_timing(&mut self) {
+        self.submit_batch();
+
+        let Some(timestamps) = &mut self.timestamps else {
+            return;
+        };
+        if timestamps.upload_started {
+            return;
+        }
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("upload_timestamp_encoder"),
+                });
+        encoder.write_timestamp(&timestamps.query_set, UPLOAD_START);
+        self.context.queue.submit(std::iter::once(encoder.finish()));
+        timestamps.upload_started = true;
+    }
+
+    /// Like [dispatch](Self::dispatch), but with the template that has already been uploaded, or
+    /// the sparse template to score with.
+    fn dispatch_uploaded(
+        &mut self,
+        input: ImageLayout,
+        input_view: Option<&wgpu::TextureView>,
+        method: MatchTemplateMethod,
+        buffers_changed: bool,
+        readback: bool,
+        scoring: Scoring,
+    ) -> Option<MatchJob> {
+        self.submit_batch();
+
+        let template_layout = match scoring {
+            Scoring::Sparse(template) => ImageLayout {
+                width: template.width(),
+                height: template.height(),
+                channels: template.channels(),
+                stride: template.width() * template.channels(),
+                source: Source::Buffer(SampleFormat::F32),
+                ..ImageLayout::default()
+            },
+            _ => self.template.layout,
+        };
+        let params = MatchParams::new(input, template_layout, method);
+
+        let key = params.key();
+        self.last_key = Some(key);
+
+        let (result_width, result_height) = params.result_size();
+        let result_buf_size = params.result_bytes();
+
+        self.write_uniforms(input, template_layout);
+
+        self.last_result_size = (result_width, result_height);
+
+        // The result buffer is missing if it was handed over by `match_template_on_gpu`.
+        if !matches!(&self.result_buffer, Some(buffer) if buffer.size() >= result_buf_size) {
+            self.result_buffer = Some(self.context.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("result_buffer"),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                size: result_buf_size,
+                mapped_at_creation: false,
+            }));
+            self.bind_group = None;
+        }
+
+        if buffers_changed {
+            self.bind_group = None;
+        }
+
+        // Texture views are provided per call, so their bind group can't be reused. Sparse
+        // templates aren't uploaded into the template slot, and binary matches bind their own
+        // buffers, so they don't use it.
+        let own_bind_group = matches!(scoring, Scoring::Sparse(_) | Scoring::Binary(_));
+        if !own_bind_group && (self.bind_group.is_none() || input_view.is_some()) {
+            let input_resource = match input_view {
+                Some(view) => wgpu::BindingResource::TextureView(view),
+                None => self.input.binding(),
+            };
+
+            self.bind_group = Some(self.kernels.create_bind_group(
+                &self.context.device,
+                key,
+                input_resource,
+                self.template.binding(),
+                self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                &self.uniform_buffer,
+            ));
+        }
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("encoder"),
+                });
+
+        if let Some(timestamps) = &self.timestamps {
+            encoder.write_timestamp(&timestamps.query_set, COMPUTE_START);
+        }
+
+        if let Scoring::Pruned(bound) = scoring {
+            let best_buffer = self.best_buffer.get_or_insert_with(|| {
+                self.context.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("best_buffer"),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    size: size_of::<f32>() as u64,
+                    mapped_at_creation: false,
+                })
+            });
+            self.context
+                .queue
+                .write_buffer(best_buffer, 0, bytemuck::bytes_of(&bound));
+
+            let input_resource = match input_view {
+                Some(view) => wgpu::BindingResource::TextureView(view),
+                None => self.input.binding(),
+            };
+
+            self.kernels.encode_pruned(
+                &self.context.device,
+                &mut encoder,
+                key,
+                PrunedImages {
+                    input: input_resource,
+                    template: self.template.binding(),
+                    result: self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                    uniforms: &self.uniform_buffer,
+                    best: best_buffer,
+                },
+                (result_width, result_height),
+            );
+        } else if let Scoring::Sparse(template) = scoring {
+            let pixels =
+                self.context
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("sparse_template_buffer"),
+                        contents: bytemuck::cast_slice(&template.packed()),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    });
+
+            let input_resource = match input_view {
+                Some(view) => wgpu::BindingResource::TextureView(view),
+                None => self.input.binding(),
+            };
+
+            self.kernels.encode_sparse(
+                &self.context.device,
+                &mut encoder,
+                (method, input.source),
+                SparseImages {
+                    input: input_resource,
+                    pixels: &pixels,
+                    result: self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                    uniforms: &self.uniform_buffer,
+                },
+                (result_width, result_height),
+            );
+        } else if let Scoring::Binary(threshold) = scoring {
+            let input_resource = match input_view {
+                Some(view) => wgpu::BindingResource::TextureView(view),
+                None => self.input.binding(),
+            };
+
+            self.kernels.encode_binary(
+                &self.context.device,
+                &mut encoder,
+                BinaryImages {
+                    input: input_resource,
+                    template: self.template.binding(),
+                    result: self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                    uniforms: &self.uniform_buffer,
+                },
+                (&input, &template_layout),
+                threshold,
+            );
+        } else if self.precision == Precision::Quantized
+            && input.source == Source::Buffer(SampleFormat::U8)
+            && template_layout.source == Source::Buffer(SampleFormat::U8)
+        {
+            self.kernels.encode_quantized(
+                &self.context.device,
+                &mut encoder,
+                method,
+                QuantizedImages {
+                    input: self.input.binding(),
+                    template: self.template.binding(),
+                    result: self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                    uniforms: &self.uniform_buffer,
+                },
+                (result_width, result_height),
+            );
+        } else if self.non_finite == NonFinite::Propagate
+            && self
+                .algorithm()
+                .use_fft(&self.kernels, method, &input, &template_layout)
+        {
+            let input_resource = match input_view {
+                Some(view) => wgpu::BindingResource::TextureView(view),
+                None => self.input.binding(),
+            };
+
+            self.kernels.encode_fft(
+                &self.context.device,
+                &mut encoder,
+                key,
+                FftImages {
+                    input: input_resource,
+                    input_version: input_view.is_none().then_some(self.input.version),
+                    template: self.template.binding(),
+                    result: self.result_buffer.as_ref().unwrap().as_entire_binding(),
+                    uniforms: &self.uniform_buffer,
+                },
+                FftSizes::new(&input, &template_layout),
+                self.template_sums,
+            );
+        } else {
+            self.kernels.encode(
+                &self.context.device,
+                &mut encoder,
+                key,
+                self.bind_group.as_ref().unwrap(),
+                (result_width, result_height),
+                1,
+            );
+        }
+
+        // The timestamps are only resolved for jobs, and the upload timestamp only if it was
+        // written for this match.
+        let first_timestamp = self.timestamps.as_mut().and_then(|timestamps| {
+            let upload_started = std::mem::take(&mut timestamps.upload_started);
+            readback.then_some(if upload_started {
+                UPLOAD_START
+            } else {
+                COMPUTE_START
+            })
+        });
+
+        if let Some(timestamps) = &self.timestamps {
+            encoder.write_timestamp(&timestamps.query_set, COMPUTE_END);
+        }
+
+        let staging_buffer = readback.then(|| {
+            let timestamp_bytes = if first_timestamp.is_some() {
+                TIMESTAMP_BYTES
+            } else {
+                0
+            };
+            let staging_buffer = self.staging_buffer(result_buf_size + timestamp_bytes);
+            encoder.copy_buffer_to_buffer(
+                self.result_buffer.as_ref().unwrap(),
+                0,
+                &staging_buffer,
+                0,
+                result_buf_size,
+            );
+
+            if let (Some(timestamps), Some(first)) = (&self.timestamps, first_timestamp) {
+                encoder.write_timestamp(&timestamps.query_set, READBACK_END);
+                encoder.resolve_query_set(
+                    &timestamps.query_set,
+                    first..TIMESTAMP_COUNT,
+                    &timestamps.resolve_buffer,
+                    0,
+                );
+
+                let skipped = first as u64 * size_of::<u64>() as u64;
+                encoder.copy_buffer_to_buffer(
+                    &timestamps.resolve_buffer,
+                    0,
+                    &staging_buffer,
+                    result_buf_size + skipped,
+                    TIMESTAMP_BYTES - skipped,
+                );
+            }
+
+            staging_buffer
+        });
+
+        let submission = self.context.queue.submit(std::iter::once(encoder.finish()));
+
+        staging_buffer.map(|staging_buffer| {
+            self.push_job(
+                staging_buffer,
+                Some(submission),
+                (result_width, result_height),
+                first_timestamp,
+            )
+        })
+    }
+
+    /// Adds a job whose result is copied to the staging buffer by the given submission, or by the
+    /// batch if there is none yet.
+    fn push_job(
+        &mut self,
+        staging_buffer: wgpu::Buffer,
+        submission: Option<wgpu::SubmissionIndex>,
+        size: (u32, u32),
+        first_timestamp: Option<u32>,
+    ) -> MatchJob {
+        let job = MatchJob(self.next_job_id);
+        self.next_job_id += 1;
+        self.jobs.push(PendingJob {
+            job,
+            staging_buffer,
+            submission,
+            size,
+            first_timestamp,
+            mapping: None,
+            replay: None,
+            attempts: 0,
+            error: None,
+        });
+        job
+    }
+
+    /// Runs `start` in error scopes, and attaches the first validation or out-of-memory error it
+    /// raises to the jobs it starts, so that reading their results fails with it. Errors raised
+    /// without starting a job, e.g. while uploading an image that is kept, are attached to the next
+    /// job instead.
+    fn track_errors<R>(&mut self, start: impl FnOnce(&mut Self) -> R) -> R {
+        let first_job = self.next_job_id;
+        self.push_error_scopes();
+        let result = start(self);
+        let error = self.pop_error_scopes().or(self.deferred_error.take());
+
+        let mut started = self
+            .jobs
+            .iter_mut()
+            .filter(|pending| pending.job.0 >= first_job)
+            .peekable();
+        if started.peek().is_none() {
+            self.deferred_error = error;
+        } else {
+            for pending in started {
+                pending.error = pending.error.take().or_else(|| error.clone());
+            }
+        }
+        result
+    }
+
+    /// Like [track_errors](Self::track_errors), but for work that blocks until its result has been
+    /// read back, which starts no jobs to attach errors to. The first validation or out-of-memory
+    /// error raised while running `run`, or deferred from before, is returned instead of its
+    /// result, since results read back after such an error can't be trusted.
+    fn track_blocking<R>(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.push_error_scopes();
+        let result = run(self);
+        match self.pop_error_scopes().or(self.deferred_error.take()) {
+            Some(error) => Err(error),
+            None => result,
+        }
+    }
+
+    fn push_error_scopes(&self) {
+        self.context
+            .device
+            .push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.context
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+    }
+
+    /// Pops the scopes pushed by `push_error_scopes` and returns the error they caught, if any.
+    /// Browsers report the errors asynchronously, so on wasm they are left to the browser.
+    fn pop_error_scopes(&self) -> Option<Error> {
+        let validation = now_or_never(self.context.device.pop_error_scope()).flatten();
+        let out_of_memory = now_or_never(self.context.device.pop_error_scope()).flatten();
+        validation.or(out_of_memory).map(Error::from)
+    }
+
+    /// Lets the given job be rerun with `replay` if the device is lost while it is in flight.
+    fn set_replay(
+        &mut self,
+        job: MatchJob,
+        replay: impl FnOnce(&mut GpuMatcher) -> MatchJob + Send + Sync + 'static,
+    ) {
+        if let Some(index) = self.job_index(job) {
+            self.jobs[index].replay = Some(Box::new(replay));
+        }
+    }
+}
+
+/// Uploads the template and its uniforms into new buffers and records the passes that match it
+/// against an already uploaded input, writing the scores into `result`. Returns the key of the
+/// pipeline used.
+fn encode_template<T: Sample>(
+    context: &GpuContext,
+    kernels: &mut Kernels,
+    // Batched matches take their buffers from the pool of the batch.
+    (encoder, mut pool): (&mut wgpu::CommandEncoder, Option<&mut BufferPool>),
+    (input_layout, input, input_version): (ImageLayout, wgpu::BindingResource, Option<u64>),
+    template: &Image<'_, T>,
+    (method, algorithm, precision, non_finite): (
+        MatchTemplateMethod,
+        MatchAlgorithm,
+        Precision,
+        NonFinite,
+    ),
+    result: wgpu::BindingResource,
+) -> PipelineKey {
+    if precision == Precision::HalfStorage && T::FORMAT == SampleFormat::F32 {
+        return encode_template(
+            context,
+            kernels,
+            (encoder, pool),
+            (input_layout, input, input_version),
+            &to_half(template),
+            (method, algorithm, precision, non_finite),
+            result,
+        );
+    }
+    if precision == Precision::Quantized && T::FORMAT != SampleFormat::U8 {
+        return encode_template(
+            context,
+            kernels,
+            (encoder, pool),
+            (input_layout, input, input_version),
+            &to_unorm8(template),
+            (method, algorithm, precision, non_finite),
+            result,
+        );
+    }
+
+    template.assert_valid("template");
+    let template_layout = ImageLayout::of(template);
+    let params = MatchParams::new(input_layout, template_layout, method);
+
+    let mut create_buffer = |label, contents: &[u8], usage| match pool.as_deref_mut() {
+        Some(pool) => pool.upload(context, contents, usage),
+        None => context
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage,
+            }),
+    };
+
+    let template_buffer = create_buffer(
+        "template_buffer",
+        &upload_bytes(&template.data[..template.required_len()]),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let uniform_buffer = create_buffer(
+        "uniform_buffer",
+        bytemuck::bytes_of(&params.uniforms(non_finite)),
+        wgpu::BufferUsages::UNIFORM,
+    );
+
+    let key = params.key();
+
+    if non_finite == NonFinite::Propagate
+        && algorithm.use_fft(kernels, method, &input_layout, &template_layout)
+    {
+        kernels.encode_fft(
+            &context.device,
+            encoder,
+            key,
+            FftImages {
+                input,
+                input_version,
+                template: template_buffer.as_entire_binding(),
+                result,
+                uniforms: &uniform_buffer,
+            },
+            FftSizes::new(&input_layout, &template_layout),
+            TemplateSums::of(template),
+        );
+    } else {
+        let bind_group = kernels.create_bind_group(
+            &context.device,
+            key,
+            input,
+            template_buffer.as_entire_binding(),
+            result,
+            &uniform_buffer,
+        );
+
+        kernels.encode(
+            &context.device,
+            encoder,
+            key,
+            &bind_group,
+            params.result_size(),
+            input_layout.batch,
+        );
+    }
+
+    if let Some(pool) = pool {
+        pool.keep(template_buffer);
+        pool.keep(uniform_buffer);
+    }
+    key
+}
+
+/// Copies the image into tightly packed `u8` samples, mapping `[0, 1]` to `0..=255`.
+fn to_unorm8<T: Sample>(image: &Image<'_, T>) -> Image<'static, u8> {
+    image.assert_valid("image");
+
+    let samples = packed_samples(image)
+        .map(|sample| (sample.to_f32().clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect::<Vec<_>>();
+    Image::with_channels(samples, image.width, image.height, image.channels)
+}
+
+/// Copies the image into tightly packed `f16` samples.
+fn to_half<T: Sample>(image: &Image<'_, T>) -> Image<'static, f16> {
+    image.assert_valid("image");
+
+    let samples = packed_samples(image)
+        .map(|sample| f16::from_f32(sample.to_f32()))
+        .collect::<Vec<_>>();
+    Image::with_channels(samples, image.width, image.height, image.channels)
+}
+
+/// Samples of the image without the padding at the ends of rows.
+fn packed_samples<'a, T: Sample>(image: &'a Image<'_, T>) -> impl Iterator<Item = T> + 'a {
+    let row_len = (image.width * image.channels) as usize;
+    let stride = image.row_stride() as usize;
+
+    (0..image.height as usize)
+        .flat_map(move |y| image.data[y * stride..y * stride + row_len].iter().copied())
+}
+
+/// Splits results of the given sizes out of a packed buffer read back from the GPU.
+fn unpack_results(data: &[f32], sizes: &[(u32, u32)], offsets: &[u64]) -> Vec<Image<'static>> {
+    sizes
+        .iter()
+        .zip(offsets)
+        .map(|(&(width, height), &offset)| {
+            let start = offset as usize / size_of::<f32>();
+            let end = start + (width * height) as usize;
+            Image::new(data[start..end].to_vec(), width, height)
+        })
+        .collect()
+}
+
+/// Size of the result of a job, and whether reading it back succeeded.
+type JobRead = ((u32, u32), Result<(), Error>);
+
+/// Outcome of mapping the staging buffer, or [Canceled](futures_channel::oneshot::Canceled) if the
+/// mapping callback was dropped without being called.
+type Mapping = Result<Result<(), wgpu::BufferAsyncError>, futures_channel::oneshot::Canceled>;
+
+/// Returns the output of the future if it is already complete, without waiting for it.
+fn now_or_never<F: std::future::Future>(future: F) -> Option<F::Output> {
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    match std::pin::pin!(future).poll(&mut context) {
+        std::task::Poll::Ready(output) => Some(output),
+        std::task::Poll::Pending => None,
+    }
+}
+
+/// Number of color components of a texture format.
+fn texture_channels(format: wgpu::TextureFormat) -> u32 {
+    use wgpu::TextureFormat::*;
+
+    match format {
+        R8Unorm | R8Snorm | R16Float | R32Float | R16Unorm | R16Snorm => 1,
+        Rg8Unorm | Rg8Snorm | Rg16Float | Rg32Float | Rg16Unorm | Rg16Snorm => 2,
+        Rg11b10Float => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_window_covers_positions_around_center() {
+        // 11 positions in both directions, and the pixels under the template at the last ones.
+        let window = Region::search_window((100, 80), (10, 6), (40, 30), 5);
+        assert_eq!(window, Region::new(35, 25, 20, 16));
+    }
+
+    #[test]
+    fn search_window_is_clamped_to_input() {
+        // Near the top-left corner.
+        let window = Region::search_window((100, 80), (10, 6), (2, 1), 5);
+        assert_eq!(window, Region::new(0, 0, 17, 12));
+
+        // Centers past the last position are moved onto it.
+        let window = Region::search_window((100, 80), (10, 6), (500, 500), 5);
+        assert_eq!(window, Region::new(85, 69, 15, 11));
+
+        // Radii past the input cover all of it.
+        let window = Region::search_window((100, 80), (10, 6), (40, 30), u32::MAX);
+        assert_eq!(window, Region::new(0, 0, 100, 80));
+    }
+
+    #[test]
+    fn search_window_with_zero_radius_is_the_template() {
+        let window = Region::search_window((100, 80), (10, 6), (40, 30), 0);
+        assert_eq!(window, Region::new(40, 30, 10, 6));
+    }
+
+    #[test]
+    #[should_panic]
+    fn search_window_needs_template_to_fit() {
+        Region::search_window((8, 8), (10, 6), (0, 0), 5);
+    }
+
+    #[test]
+    fn hinted_match_searches_around_hint() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let input = gradient(24, 16);
+        let template = input.crop(9, 6, 5, 4);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        let hint = MatchHint {
+            position: (9, 6),
+            radius: 3,
+            last_score: 0.0,
+        };
+        let found = matcher
+            .match_template_hinted(&input, &template, method, hint, 1e-3)
+            .unwrap();
+        assert_eq!((found.location, found.at_hint), ((9, 6), true));
+
+        let moved = MatchHint {
+            position: (7, 8),
+            ..hint
+        };
+        let found = matcher
+            .match_template_hinted(&input, &template, method, moved, 1e-3)
+            .unwrap();
+        assert_eq!((found.location, found.at_hint), ((9, 6), false));
+    }
+
+    #[test]
+    fn hinted_match_reports_invalid_images() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let input = gradient(8, 8);
+        let hint = MatchHint {
+            position: (0, 0),
+            radius: 3,
+            last_score: 0.0,
+        };
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        let result = matcher.match_template_hinted(&input, gradient(10, 4), method, hint, 0.0);
+        assert!(matches!(result, Err(Error::TemplateTooLarge { .. })));
+
+        let short = Image::new(&input.data[..10], 4, 4);
+        let result = matcher.match_template_hinted(&input, short, method, hint, 0.0);
+        assert!(matches!(result, Err(Error::DataLength { .. })));
+    }
+
+    #[test]
+    fn find_extremes_skips_nan() {
+        let image = Image::new(vec![f32::NAN, 0.5, 2.0, f32::NAN, -1.0, 0.0], 3, 2);
+        let extremes = find_extremes(&image);
+        assert_eq!(
+            (extremes.min_value, extremes.min_value_location),
+            (-1.0, (1, 1))
+        );
+        assert_eq!(
+            (extremes.max_value, extremes.max_value_location),
+            (2.0, (2, 0))
+        );
+    }
+
+    #[test]
+    fn find_extremes_keeps_first_of_equal_values() {
+        let image = Image::new(vec![1.0, 0.0, 1.0, 0.0], 2, 2);
+        let extremes = find_extremes(&image);
+        assert_eq!(extremes.min_value_location, (1, 0));
+        assert_eq!(extremes.max_value_location, (0, 0));
+    }
+
+    #[test]
+    fn find_extremes_of_all_nan_image() {
+        let extremes = find_extremes(&Image::new(vec![f32::NAN; 4], 2, 2));
+        assert_eq!(
+            (extremes.min_value, extremes.min_value_location),
+            (f32::INFINITY, (0, 0))
+        );
+        assert_eq!(
+            (extremes.max_value, extremes.max_value_location),
+            (f32::NEG_INFINITY, (0, 0))
+        );
+    }
+
+    #[test]
+    fn find_extremes_keeps_infinite_values() {
+        let image = Image::new(vec![f32::NAN, f32::INFINITY, 3.0, f32::NEG_INFINITY], 2, 2);
+        let extremes = find_extremes(&image);
+        assert_eq!(
+            (extremes.min_value, extremes.min_value_location),
+            (f32::NEG_INFINITY, (1, 1))
+        );
+        assert_eq!(
+            (extremes.max_value, extremes.max_value_location),
+            (f32::INFINITY, (1, 0))
+        );
+    }
+
+    #[test]
+    fn try_match_template_reports_channel_mismatch() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let input = Image::with_channels(vec![0.0; 8 * 8 * 3], 8, 8, 3);
+        let result = matcher.try_match_template(
+            &input,
+            gradient(2, 2),
+            MatchTemplateMethod::SumOfSquaredDifferences,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+    }
+
+    #[test]
+    fn batched_matching_checks_every_template() {
+        let mut matcher = TemplateMatcher::new();
+        let input = gradient(16, 12);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+        let templates = [input.crop(0, 0, 4, 4), gradient(17, 2)];
+        let too_large = Error::TemplateTooLarge {
+            input: (16, 12),
+            template: (17, 2),
+        };
+
+        let result = matcher.match_templates(&input, &templates, method);
+        assert_eq!(result.unwrap_err(), too_large.clone());
+        let result = matcher.match_matrix(std::slice::from_ref(&input), &templates, method);
+        assert_eq!(result.err(), Some(too_large.clone()));
+        let result = matcher.match_inputs(std::slice::from_ref(&input), &templates[1], method);
+        assert_eq!(result.unwrap_err(), too_large);
+
+        // Templates must fit in their regions rather than in the whole input.
+        let regions = [Region::new(0, 0, 4, 4), Region::new(2, 2, 3, 3)];
+        let result = matcher.match_templates_in_regions(
+            &input,
+            &[templates[0].clone(), templates[0].clone()],
+            &regions,
+            method,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            Error::TemplateTooLarge {
+                input: (3, 3),
+                template: (4, 4)
+            }
+        );
+
+        // Nothing was matched, so valid batches still work afterwards.
+        let results = matcher
+            .match_templates(&input, &templates[..1], method)
+            .unwrap();
+        assert_eq!((results[0].width, results[0].height), (13, 9));
+    }
+
+    #[test]
+    fn pixel_accessors_follow_stride_and_channels() {
+        // 2x2 pixels with 2 channels, and one value of padding at the end of each row.
+        let image =
+            Image::with_channels(vec![0.0, 0.1, 1.0, 1.1, 9.0, 2.0, 2.1, 3.0, 3.1], 2, 2, 2)
+                .with_stride(5);
+
+        assert_eq!(image.at(1, 0), 1.0);
+        assert_eq!(image.at(0, 1), 2.0);
+        assert_eq!(image[(1, 1)], 3.0);
+        assert_eq!(image.get_channel(1, 1, 1), Some(3.1));
+        assert_eq!(image.get(2, 0), None);
+        assert_eq!(image.get(0, 2), None);
+        assert_eq!(image.get_channel(0, 0, 2), None);
+    }
+
+    #[test]
+    fn pixel_accessors_normalize_integer_samples() {
+        let image = Image::new(vec![0u8, 51, 255, 102], 2, 2);
+        assert_eq!(image.at(1, 0), 0.2);
+        assert_eq!(image.at(0, 1), 1.0);
+        // Indexing returns the sample as it is stored.
+        assert_eq!(image[(1, 1)], 102);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (2, 0) is outside the 2x1 image")]
+    fn at_panics_outside_image() {
+        Image::new(vec![0.0, 1.0], 2, 1).at(2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (0, 1) is outside the 2x1 image")]
+    fn index_panics_outside_image() {
+        let _ = Image::new(vec![0.0, 1.0], 2, 1)[(0, 1)];
+    }
+
+    #[test]
+    fn rows_skip_stride_padding() {
+        let image = Image::new(vec![0.0, 1.0, 9.0, 2.0, 3.0, 9.0], 2, 2).with_stride(3);
+        let rows: Vec<_> = image.rows().collect();
+        assert_eq!(rows, [[0.0, 1.0], [2.0, 3.0]]);
+
+        // The last row doesn't need padding.
+        let image = Image::new(vec![0.0, 1.0, 9.0, 2.0, 3.0], 2, 2).with_stride(3);
+        assert_eq!(image.rows().count(), 2);
+    }
+
+    #[test]
+    fn iter_pixels_yields_first_channel_row_by_row() {
+        let image = Image::with_channels(vec![0u8, 255, 51, 0, 255, 0, 102, 0], 2, 2, 2);
+        let pixels: Vec<_> = image.iter_pixels().collect();
+        assert_eq!(pixels, [(0, 0, 0.0), (1, 0, 0.2), (0, 1, 1.0), (1, 1, 0.4)]);
+    }
+
+    #[test]
+    fn iter_pixels_of_empty_image_is_empty() {
+        assert_eq!(Image::<f32>::new(vec![], 0, 3).iter_pixels().count(), 0);
+        assert_eq!(Image::<f32>::new(vec![], 3, 0).iter_pixels().count(), 0);
+    }
+
+    #[test]
+    fn views_borrow_and_crops_copy_area() {
+        let frame = Image::with_channels((0..48).map(|v| v as f32).collect::<Vec<_>>(), 4, 6, 2);
+        let view = frame.view(1, 2, 2, 3);
+        assert!(matches!(view.data, Cow::Borrowed(_)));
+        assert_eq!((view.width, view.height, view.row_stride()), (2, 3, 8));
+        assert_eq!(view.get_channel(0, 0, 1), Some(19.0));
+        assert_eq!(view.at(1, 2), 36.0);
+
+        // Views of views keep the stride of the frame.
+        let inner = view.view(1, 1, 1, 2);
+        assert_eq!((inner.at(0, 0), inner.at(0, 1)), (28.0, 36.0));
+
+        let crop = frame.crop(1, 2, 2, 3);
+        assert!(matches!(crop.data, Cow::Owned(_)));
+        assert_eq!(crop.stride, None);
+        assert_eq!(
+            *crop.data,
+            [18.0, 19.0, 20.0, 21.0, 26.0, 27.0, 28.0, 29.0, 34.0, 35.0, 36.0, 37.0]
+        );
+    }
+
+    #[test]
+    fn views_must_be_within_image() {
+        let frame = gradient(4, 6);
+        assert!(frame.get_view(0, 0, 4, 6).is_some());
+        assert!(frame.get_view(4, 6, 0, 0).is_some());
+        assert!(frame.get_view(1, 0, 4, 6).is_none());
+        assert!(frame.get_view(0, 1, 4, 6).is_none());
+        // Areas whose far edge doesn't fit in u32.
+        assert!(frame.get_view(u32::MAX, 0, 2, 1).is_none());
+        assert!(frame.get_view(0, 2, 1, u32::MAX).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "is outside the 4x6 image")]
+    fn crop_panics_outside_image() {
+        gradient(4, 6).crop(3, 0, 2, 1);
+    }
+
+    #[test]
+    fn iter_pixels_of_image_without_channels_is_empty() {
+        let image = Image::<f32>::with_channels(vec![], 3, 2, 0);
+        assert_eq!(image.iter_pixels().count(), 0);
+    }
+
+    #[test]
+    fn stats_cover_all_channels_inside_stride() {
+        // The padding value 100 is outside the image.
+        let image =
+            Image::with_channels(vec![1.0, 2.0, 3.0, 100.0, 4.0, 5.0, 6.0], 1, 2, 3).with_stride(4);
+        let stats = image.stats();
+        assert_eq!((stats.min, stats.max), (1.0, 6.0));
+        assert_eq!(stats.mean, 3.5);
+        assert!((stats.std_dev - 1.707_825_1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stats_normalize_integer_samples() {
+        let stats = Image::new(vec![0u8, 255, 255, 0], 2, 2).stats();
+        assert_eq!(
+            (stats.min, stats.max, stats.mean, stats.std_dev),
+            (0.0, 1.0, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn stats_of_constant_image_have_no_deviation() {
+        let stats = Image::new(vec![0.3; 9], 3, 3).stats();
+        assert_eq!((stats.min, stats.max), (0.3, 0.3));
+        assert!((stats.mean - 0.3).abs() < 1e-7);
+        assert_eq!(stats.std_dev, 0.0);
+    }
+
+    #[test]
+    fn stats_of_empty_image_are_nan() {
+        let stats = Image::<f32>::new(vec![], 0, 4).stats();
+        assert!(stats.min.is_nan() && stats.max.is_nan());
+        assert!(stats.mean.is_nan() && stats.std_dev.is_nan());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid image: image data has 5 samples, but its dimensions need 6")]
+    fn stats_of_image_with_short_data_panic() {
+        Image::new(vec![0.0; 5], 3, 2).stats();
+    }
+
+    #[test]
+    fn debug_of_image_with_short_data_prints_its_length() {
+        let image = Image::new(vec![0.0; 5], 3, 2);
+        assert_eq!(
+            format!("{image:?}"),
+            "Image { width: 3, height: 2, channels: 1, stride: 3, format: F32, data_len: 5 }"
+        );
+    }
+
+    #[test]
+    fn try_new_requires_exact_data_length() {
+        assert!(Image::try_new(vec![0.0; 6], 3, 2).is_ok());
+        assert_eq!(
+            Image::try_new(vec![0.0; 6], 2, 2).unwrap_err(),
+            Error::DataLength {
+                expected: 4,
+                actual: 6
+            }
+        );
+        assert_eq!(
+            Image::try_with_channels(vec![0u8; 6], 3, 2, 3).unwrap_err(),
+            Error::DataLength {
+                expected: 18,
+                actual: 6
+            }
+        );
+        assert!(Image::<f32>::try_new(vec![], 0, 5).is_ok());
+    }
+
+    #[test]
+    fn validate_allows_longer_data_for_strides() {
+        // The last row doesn't need padding, and views may end before the data does.
+        let image = Image::new(vec![0.0; 5], 2, 2).with_stride(3);
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(Image::new(vec![0.0; 9], 2, 2).validate(), Ok(()));
+
+        let image = Image::new(vec![0.0; 4], 2, 2).with_stride(3);
+        assert_eq!(
+            image.validate(),
+            Err(Error::DataLength {
+                expected: 5,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn async_results_wake_the_task() {
+        let mut matcher = TemplateMatcher::new();
+        let input = gradient(32, 24);
+        let template = input.crop(9, 5, 6, 4);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        let job = matcher.match_template(&input, &template, method);
+        let expected = matcher.wait_for_job(job).unwrap();
+
+        // pollster parks the thread until the task is woken, so this hangs unless the mapping
+        // callback wakes it.
+        for _ in 0..3 {
+            let job = matcher.match_template(&input, &template, method);
+            let result = pollster::block_on(matcher.job_result_async(job)).unwrap();
+            assert_eq!(result.data, expected.data);
+        }
+    }
+
+    #[test]
+    fn gpu_only_calls_fail_on_the_cpu_engine() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        assert!(matcher.device().is_none());
+        assert!(matcher.queue().is_none());
+        assert!(matcher.context().is_none());
+
+        let input = gradient(8, 8);
+        let template = input.crop(2, 2, 3, 3);
+        let result = matcher.match_template_on_gpu(
+            &input,
+            &template,
+            MatchTemplateMethod::SumOfSquaredDifferences,
+        );
+        assert_eq!(result.err(), Some(Error::Unsupported));
+    }
+
+    /// Samples that differ at every pixel.
+    fn gradient(width: u32, height: u32) -> Image<'static> {
+        let data = (0..width * height)
+            .map(|i| ((i * 37) % 101) as f32 / 100.0)
+            .collect::<Vec<_>>();
+        Image::new(data, width, height)
+    }
+
+    #[test]
+    fn preprocess_steps_are_chained() {
+        let data: Vec<_> = gradient(48, 32)
+            .data
+            .iter()
+            .flat_map(|&v| [v, 1.0 - v, v * v])
+            .collect();
+        let input = Image::with_channels(data, 48, 32, 3);
+        let steps = PreprocessPipeline::new()
+            .grayscale()
+            .resize(0.5)
+            .blur(1.0)
+            .sobel();
+
+        let expected = TemplateMatcher::new_cpu()
+            .preprocess(&input, steps.steps())
+            .unwrap();
+        assert_eq!(
+            (expected.width, expected.height, expected.channels),
+            (24, 16, 1)
+        );
+
+        let result = TemplateMatcher::new()
+            .preprocess(&input, steps.steps())
+            .unwrap();
+        assert_eq!((result.width, result.height, result.channels), (24, 16, 1));
+        for (a, b) in result.data.iter().zip(expected.data.iter()) {
+            assert!((a - b).abs() <= 1e-3, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn batched_matches_reuse_buffers() {
+        let mut matcher = TemplateMatcher::new();
+        let input = gradient(32, 24);
+        let templates: Vec<_> = [(3, 2), (10, 7), (20, 11)]
+            .into_iter()
+            .map(|(x, y)| input.crop(x, y, 6, 5))
+            .collect();
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        let expected: Vec<_> = templates
+            .iter()
+            .map(|template| {
+                let job = matcher.match_template(&input, template, method);
+                matcher.wait_for_job(job).unwrap()
+            })
+            .collect();
+
+        for _ in 0..2 {
+            matcher.begin_batch();
+            let jobs: Vec<_> = templates
+                .iter()
+                .map(|template| matcher.match_template(&input, template, method))
+                .collect();
+            matcher.submit_batch();
+
+            for (job, expected) in jobs.into_iter().zip(&expected) {
+                assert_eq!(matcher.wait_for_job(job).unwrap().data, expected.data);
+            }
+        }
+
+        let Backend::Gpu(gpu) = &matcher.backend else {
+            unreachable!()
+        };
+        // The input, template, uniform and result buffers of each match of the first batch were
+        // reused by the second.
+        assert!(gpu.batch_buffers.in_use.is_empty());
+        assert_eq!(gpu.batch_buffers.spare.len(), 4 * templates.len());
+    }
+
+    #[test]
+    fn has_changed_keeps_uploaded_images() {
+        let mut matcher = TemplateMatcher::new();
+        let input = gradient(24, 16);
+        let template = input.crop(5, 3, 6, 4);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        matcher.set_template(&template);
+        let mut changed = input.to_owned();
+        changed.data.to_mut()[7] += 0.5;
+        assert!(matcher.has_changed(&input, &changed, 0.1).unwrap());
+        assert!(!matcher.has_changed(&input, &input, 0.1).unwrap());
+
+        let job = matcher.match_uploaded_template(&input, method);
+        let result = matcher.wait_for_job(job).unwrap();
+        assert_eq!(find_extremes(&result).min_value_location, (5, 3));
+
+        matcher.set_input(&input);
+        assert!(matcher.has_changed(&input, &changed, 0.1).unwrap());
+        let job = matcher.match_uploaded(&template, method);
+        let result = matcher.wait_for_job(job).unwrap();
+        assert_eq!(find_extremes(&result).min_value_location, (5, 3));
+    }
+
+    #[test]
+    fn matches_are_not_batched_by_default() {
+        let mut matcher = TemplateMatcher::new();
+        let input = gradient(16, 16);
+        let template = input.crop(4, 4, 4, 4);
+
+        let first = matcher.match_template(
+            &input,
+            &template,
+            MatchTemplateMethod::SumOfSquaredDifferences,
+        );
+        let second = matcher.match_template(
+            &input,
+            &template,
+            MatchTemplateMethod::SumOfSquaredDifferences,
+        );
+
+        let Backend::Gpu(gpu) = &matcher.backend else {
+            unreachable!()
+        };
+        assert!(gpu.batch.is_none());
+        assert!(gpu.jobs.iter().all(|pending| pending.submission.is_some()));
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        self.matching_ongoing = true;
+        assert!(matcher.wait_for_job(second).is_some());
+        assert!(matcher.wait_for_job(first).is_some());
     }
 }
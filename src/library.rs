@@ -0,0 +1,196 @@
+//! Named collections of templates that can be matched against an input in one go.
+
+use std::ops::RangeInclusive;
+
+use crate::{find_extremes, scale, Error, Image, MatchTemplateMethod, TemplateMatcher};
+
+/// A named point relative to the top-left corner of a template, e.g. the spot to click on a button.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Anchor {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A template stored in a [TemplateLibrary].
+pub struct Template {
+    pub name: String,
+    pub image: Image<'static>,
+    pub anchors: Vec<Anchor>,
+    /// Method used for this template instead of the one passed to [TemplateLibrary::match_all].
+    pub method: Option<MatchTemplateMethod>,
+    /// Largest score that is still considered a match.
+    pub threshold: Option<f32>,
+    /// Scales that this template is matched at, or only its own size if empty.
+    pub scales: Vec<f32>,
+}
+
+impl Template {
+    pub fn new(name: impl Into<String>, image: Image<'static>) -> Self {
+        Self {
+            name: name.into(),
+            image,
+            anchors: Vec::new(),
+            method: None,
+            threshold: None,
+            scales: Vec::new(),
+        }
+    }
+
+    /// Always matches this template using the given method.
+    pub fn with_method(mut self, method: MatchTemplateMethod) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Only reports this template if its best score is at most `threshold`.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Matches this template at each scale from `scales`, `step` apart, and reports the best one,
+    /// like [TemplateMatcher::match_template_scaled].
+    pub fn with_scales(mut self, scales: RangeInclusive<f32>, step: f32) -> Self {
+        self.scales = scale::scales(scales, step);
+        self
+    }
+
+    /// Attaches a named anchor point at the given offset from the template's top-left corner.
+    pub fn with_anchor(mut self, name: impl Into<String>, x: i32, y: i32) -> Self {
+        self.anchors.push(Anchor {
+            name: name.into(),
+            x,
+            y,
+        });
+        self
+    }
+
+    /// Returns the anchor with the given name, if any.
+    pub fn anchor(&self, name: &str) -> Option<&Anchor> {
+        self.anchors.iter().find(|anchor| anchor.name == name)
+    }
+}
+
+/// Best match of a single library template.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LibraryMatch {
+    pub name: String,
+    pub score: f32,
+    pub location: (u32, u32),
+    /// Scale the template matched at, which is 1 unless it has [scales](Template::scales).
+    pub scale: f32,
+    /// Anchors of the template in absolute input coordinates, scaled along with it.
+    pub anchors: Vec<Anchor>,
+}
+
+impl LibraryMatch {
+    /// Returns the absolute location of the anchor with the given name, if any.
+    pub fn anchor(&self, name: &str) -> Option<(i32, i32)> {
+        self.anchors
+            .iter()
+            .find(|anchor| anchor.name == name)
+            .map(|anchor| (anchor.x, anchor.y))
+    }
+}
+
+/// A collection of named templates.
+#[derive(Default)]
+pub struct TemplateLibrary {
+    templates: Vec<Template>,
+}
+
+impl TemplateLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a template to the library, replacing any existing template with the same name.
+    pub fn add(&mut self, template: Template) {
+        self.remove(&template.name);
+        self.templates.push(template);
+    }
+
+    /// Removes the template with the given name and returns it.
+    pub fn remove(&mut self, name: &str) -> Option<Template> {
+        let idx = self.templates.iter().position(|t| t.name == name)?;
+        Some(self.templates.remove(idx))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.iter().find(|t| t.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Template> {
+        self.templates.iter_mut().find(|t| t.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Template> {
+        self.templates.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Matches every template in the library against the input and returns the best match of each,
+    /// in the order the templates were added.
+    ///
+    /// `method` is used for templates that don't specify their own. Templates whose best score exceeds
+    /// their threshold are left out of the result.
+    ///
+    /// Returns the errors of [TemplateMatcher::try_match_template] if a template can't be matched
+    /// against the input, and an error if the GPU rejects the work or a result can't be read back.
+    pub fn match_all(
+        &self,
+        matcher: &mut TemplateMatcher,
+        input: &Image<'_>,
+        method: MatchTemplateMethod,
+    ) -> Result<Vec<LibraryMatch>, Error> {
+        let mut matches = Vec::new();
+        for template in &self.templates {
+            let method = template.method.unwrap_or(method);
+            let (score, (x, y), scale) = if template.scales.is_empty() {
+                let job = matcher.try_match_template(input, &template.image, method)?;
+                let result = matcher.try_wait_for_job(job)?.unwrap();
+                let extremes = find_extremes(&result);
+                (extremes.min_value, extremes.min_value_location, 1.0)
+            } else {
+                let scales: Vec<_> = template.scales.iter().map(|&s| (s, s)).collect();
+                let Some(best) =
+                    matcher.match_template_at_scales(input, &template.image, method, &scales)?
+                else {
+                    continue;
+                };
+                (best.score, best.location, best.scale_x)
+            };
+
+            if matches!(template.threshold, Some(threshold) if score > threshold) {
+                continue;
+            }
+
+            matches.push(LibraryMatch {
+                name: template.name.clone(),
+                score,
+                location: (x, y),
+                scale,
+                anchors: template
+                    .anchors
+                    .iter()
+                    .map(|anchor| Anchor {
+                        name: anchor.name.clone(),
+                        x: x as i32 + (anchor.x as f32 * scale).round() as i32,
+                        y: y as i32 + (anchor.y as f32 * scale).round() as i32,
+                    })
+                    .collect(),
+            });
+        }
+        Ok(matches)
+    }
+}
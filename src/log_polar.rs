@@ -0,0 +1,195 @@
+//! Estimating the rotation and scale of a template from log-polar resamplings, in which they
+//! become shifts along the angle and radius axes.
+
+use std::{f32::consts::TAU, ops::RangeInclusive};
+
+use crate::{Image, MatchTemplateMethod};
+
+/// Rings of the template's log-polar grid, from a quarter of its radius out to its radius.
+pub(crate) const RINGS: u32 = 16;
+/// Angles of the log-polar grids.
+pub(crate) const ANGLES: u32 = 32;
+/// Most ring shifts, i.e. scales, searched at each candidate window.
+pub(crate) const MAX_RING_SHIFTS: u32 = 24;
+
+/// Log-polar grids and candidate windows of a search, in the layout of the log-polar shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LogPolarSearch {
+    /// Ring shift of the first scale, which is negative for scales below 1.
+    pub first_shift: i32,
+    pub shift_count: u32,
+    /// Distance between candidate window centers, and the center of the first one.
+    pub stride: u32,
+    pub origin_x: u32,
+    pub origin_y: u32,
+    pub candidates_x: u32,
+    pub candidates_y: u32,
+    /// Radius of the innermost ring, and the factor between the radii of consecutive rings.
+    pub min_radius: f32,
+    pub ring_base: f32,
+    padding: [u32; 3],
+}
+
+impl LogPolarSearch {
+    /// Search for a template of the given size in an input of the given size, at scales from the
+    /// given range. Returns [None] if the template is too small to resample.
+    pub fn new(
+        (input_width, input_height): (u32, u32),
+        (template_width, template_height): (u32, u32),
+        scales: RangeInclusive<f32>,
+    ) -> Option<Self> {
+        let (min_scale, max_scale) = scales.into_inner();
+        assert!(
+            min_scale > 0.0 && min_scale <= max_scale,
+            "scale range must be non-empty and positive"
+        );
+
+        let max_radius = template_width.min(template_height) as f32 * 0.5 - 0.5;
+        if max_radius < 2.0 {
+            return None;
+        }
+        let min_radius = max_radius * 0.25;
+        let ring_base = 4f32.powf(1.0 / (RINGS - 1) as f32);
+
+        let first_shift = (min_scale.ln() / ring_base.ln()).floor() as i32;
+        let last_shift = (max_scale.ln() / ring_base.ln()).ceil() as i32;
+        let shift_count = (last_shift - first_shift + 1) as u32;
+        assert!(
+            shift_count <= MAX_RING_SHIFTS,
+            "scale range is too wide for log-polar matching"
+        );
+
+        // Candidate windows are placed so that their outermost ring stays within the input.
+        let window_radius = max_radius * ring_base.powi(last_shift);
+        let stride = (min_radius.round() as u32).max(1);
+        let candidates = |size: u32| {
+            let margin = window_radius.ceil() as u32;
+            if size > 2 * margin {
+                (margin, (size - 2 * margin) / stride + 1)
+            } else {
+                (size / 2, 1)
+            }
+        };
+        let (origin_x, candidates_x) = candidates(input_width);
+        let (origin_y, candidates_y) = candidates(input_height);
+
+        Some(Self {
+            first_shift,
+            shift_count,
+            stride,
+            origin_x,
+            origin_y,
+            candidates_x,
+            candidates_y,
+            min_radius,
+            ring_base,
+            padding: [0; 3],
+        })
+    }
+
+    pub fn candidate_count(&self) -> u32 {
+        self.candidates_x * self.candidates_y
+    }
+
+    /// Center of the given candidate window in the input.
+    pub fn center(&self, candidate: u32) -> (u32, u32) {
+        (
+            self.origin_x + candidate % self.candidates_x * self.stride,
+            self.origin_y + candidate / self.candidates_x * self.stride,
+        )
+    }
+
+    /// Angle and scale of the given angle and ring shift.
+    pub fn transform(&self, shift: u32, angle: u32) -> (f32, f32) {
+        (
+            angle as f32 * TAU / ANGLES as f32,
+            self.ring_base.powi(self.first_shift + shift as i32),
+        )
+    }
+
+    /// Resamples the image at `rings` rings starting at `first_ring` around the given center,
+    /// averaging the channels of each sample, like the log-polar shader does.
+    fn resample(
+        &self,
+        image: &Image<'_>,
+        (x, y): (f32, f32),
+        first_ring: i32,
+        rings: u32,
+    ) -> Vec<f32> {
+        let mut samples = Vec::with_capacity((rings * ANGLES) as usize);
+        for ring in 0..rings as i32 {
+            let radius = self.min_radius * self.ring_base.powi(first_ring + ring);
+            for angle in 0..ANGLES {
+                let (sin, cos) = (angle as f32 * TAU / ANGLES as f32).sin_cos();
+                samples.push(bilinear(
+                    image,
+                    x + radius * cos - 0.5,
+                    y + radius * sin - 0.5,
+                ));
+            }
+        }
+        samples
+    }
+
+    /// Finds the candidate window, ring shift and angle whose log-polar resampling best matches
+    /// the template's. Returns the candidate, shift and angle indices.
+    pub fn estimate(
+        &self,
+        input: &Image<'_>,
+        template: &Image<'_>,
+        method: MatchTemplateMethod,
+    ) -> (u32, u32, u32) {
+        let center = (template.width as f32 * 0.5, template.height as f32 * 0.5);
+        let descriptor = self.resample(template, center, 0, RINGS);
+        let window_rings = RINGS + self.shift_count - 1;
+
+        let mut best = (f32::INFINITY, (0, 0, 0));
+        for candidate in 0..self.candidate_count() {
+            let (x, y) = self.center(candidate);
+            let window = self.resample(input, (x as f32, y as f32), self.first_shift, window_rings);
+
+            for shift in 0..self.shift_count {
+                for angle in 0..ANGLES {
+                    let mut total_sum = 0.0;
+                    for ring in 0..RINGS {
+                        for j in 0..ANGLES {
+                            let window_index = (ring + shift) * ANGLES + (j + angle) % ANGLES;
+                            let diff = window[window_index as usize]
+                                - descriptor[(ring * ANGLES + j) as usize];
+                            total_sum += match method {
+                                MatchTemplateMethod::SumOfAbsoluteDifferences => diff.abs(),
+                                MatchTemplateMethod::SumOfSquaredDifferences => diff * diff,
+                            };
+                        }
+                    }
+
+                    if total_sum < best.0 {
+                        best = (total_sum, (candidate, shift, angle));
+                    }
+                }
+            }
+        }
+        best.1
+    }
+}
+
+/// Samples the image bilinearly at the given position, in pixels from the center of the top-left
+/// one, clamped to its edges and averaged over its channels.
+fn bilinear(image: &Image<'_>, x: f32, y: f32) -> f32 {
+    let channels = image.channels as usize;
+    let clamp = |value: f32, size: u32| (value.max(0.0) as u32).min(size - 1) as usize;
+    let (left, top) = (x.floor(), y.floor());
+    let (fx, fy) = (x - left, y - top);
+    let (x0, x1) = (clamp(left, image.width), clamp(left + 1.0, image.width));
+    let (y0, y1) = (clamp(top, image.height), clamp(top + 1.0, image.height));
+
+    let mut sum = 0.0;
+    for c in 0..channels {
+        let sample = |x: usize, y: usize| image.data[(y * image.width as usize + x) * channels + c];
+        let upper = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+        let lower = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+        sum += upper * (1.0 - fy) + lower * fy;
+    }
+    sum / channels as f32
+}
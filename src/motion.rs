@@ -0,0 +1,43 @@
+//! Estimating the motion between two frames by block matching.
+
+/// Displacement of a block between two frames.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MotionVector {
+    /// Offset in pixels from the block in the first frame to its best match in the second.
+    pub dx: i32,
+    pub dy: i32,
+    /// Score of the block at its best match.
+    pub score: f32,
+}
+
+/// Motion of each block of a frame, estimated by
+/// [estimate_motion](crate::TemplateMatcher::estimate_motion).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MotionField {
+    /// Width and height of the square blocks, in pixels.
+    pub block_size: u32,
+    /// Number of blocks in each row.
+    pub columns: u32,
+    /// Number of rows of blocks.
+    pub rows: u32,
+    /// Motion of each block, row by row.
+    pub vectors: Vec<MotionVector>,
+}
+
+impl MotionField {
+    /// Motion of the block in the given column and row, whose top-left corner is at
+    /// `(column * block_size, row * block_size)` in the first frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is outside the field.
+    pub fn at(&self, column: u32, row: u32) -> MotionVector {
+        assert!(
+            column < self.columns && row < self.rows,
+            "block is outside the motion field"
+        );
+        self.vectors[(row * self.columns + column) as usize]
+    }
+}
@@ -0,0 +1,161 @@
+//! Matching split across several matchers, e.g. one per GPU.
+
+use crate::{Error, Image, MatchTemplateMethod, Region, Sample, TemplateMatcher};
+
+/// Splits each match across several [TemplateMatcher]s by rows of the result, and merges their
+/// parts back into one result. With one matcher per GPU, all of them work on the same match at
+/// once.
+///
+/// [TemplateMatcherBuilder::build_multi](crate::TemplateMatcherBuilder::build_multi) creates one
+/// matcher for each adapter, but any matchers can be combined, including ones on the CPU engine.
+pub struct MultiMatcher {
+    matchers: Vec<TemplateMatcher>,
+    weights: Vec<f32>,
+}
+
+impl MultiMatcher {
+    /// Combines the given matchers, which get an equal share of the rows of each result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `matchers` is empty.
+    pub fn new(matchers: Vec<TemplateMatcher>) -> Self {
+        assert!(!matchers.is_empty(), "at least one matcher is needed");
+
+        Self {
+            weights: vec![1.0; matchers.len()],
+            matchers,
+        }
+    }
+
+    pub fn matchers(&self) -> &[TemplateMatcher] {
+        &self.matchers
+    }
+
+    pub fn matchers_mut(&mut self) -> &mut [TemplateMatcher] {
+        &mut self.matchers
+    }
+
+    pub fn into_matchers(self) -> Vec<TemplateMatcher> {
+        self.matchers
+    }
+
+    /// Sets the relative share of rows that each matcher gets, e.g. to give a faster GPU more of
+    /// the work. A matcher with a weight of zero isn't used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there isn't one weight per matcher, or if no weight is positive.
+    pub fn set_weights(&mut self, weights: &[f32]) {
+        assert_eq!(
+            weights.len(),
+            self.matchers.len(),
+            "there must be one weight per matcher"
+        );
+        assert!(
+            weights.iter().any(|&weight| weight > 0.0),
+            "at least one weight must be positive"
+        );
+
+        self.weights = weights.iter().map(|&weight| weight.max(0.0)).collect();
+    }
+
+    /// Matches the template against the input like [TemplateMatcher::match_template], with each
+    /// matcher scoring its share of the result rows, and waits for the merged result.
+    ///
+    /// Returns the errors of [try_match_template](TemplateMatcher::try_match_template) before
+    /// starting any part if the images can't be matched, and the first error of the matchers if
+    /// one of them fails to score or read back its part.
+    pub fn match_template<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Image<'static>, Error> {
+        let input = input.into();
+        let template = template.into();
+        crate::check_images(&input, &template)?;
+
+        let result_width = input.width - template.width + 1;
+        let result_height = input.height - template.height + 1;
+
+        // All matches are started before waiting for any, so that the devices run concurrently.
+        let jobs: Vec<_> = self
+            .row_bands(result_height)
+            .into_iter()
+            .zip(&mut self.matchers)
+            .filter(|((start, end), _)| start < end)
+            .map(|((start, end), matcher)| {
+                let region = Region::new(0, start, input.width, end - start + template.height - 1);
+                let job = matcher.match_template_in_region(&input, &template, method, region);
+                (matcher, job)
+            })
+            .collect();
+
+        // Every part is collected even after one fails, so that no results are left behind in
+        // the matchers.
+        let parts: Vec<_> = jobs
+            .into_iter()
+            .map(|(matcher, job)| matcher.try_wait_for_job(job))
+            .collect();
+
+        let mut data = Vec::with_capacity((result_width * result_height) as usize);
+        for part in parts {
+            data.extend_from_slice(&part?.unwrap().data);
+        }
+
+        Ok(Image::new(data, result_width, result_height))
+    }
+
+    /// Divides `rows` result rows into consecutive bands, one per matcher, in proportion to the
+    /// weights.
+    fn row_bands(&self, rows: u32) -> Vec<(u32, u32)> {
+        let total: f32 = self.weights.iter().sum();
+
+        let mut cumulative = 0.0;
+        let mut start = 0;
+        let mut bands = Vec::with_capacity(self.weights.len());
+        for weight in &self.weights {
+            cumulative += weight;
+
+            // The running sum reaches the total exactly at the last positive weight, which makes
+            // sure that rounding doesn't leave rows at the end uncovered.
+            let end = if cumulative >= total {
+                rows
+            } else {
+                ((rows as f32 * cumulative / total).round() as u32).clamp(start, rows)
+            };
+
+            bands.push((start, end));
+            start = end;
+        }
+
+        bands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_images_before_splitting() {
+        let mut multi =
+            MultiMatcher::new(vec![TemplateMatcher::new_cpu(), TemplateMatcher::new_cpu()]);
+        let input = Image::with_channels(vec![0.5; 48], 4, 4, 3);
+        let template = Image::new(vec![0.5; 4], 2, 2);
+
+        let result = multi.match_template(
+            &input,
+            &template,
+            MatchTemplateMethod::SumOfSquaredDifferences,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Saving and loading images in NumPy's `.npy` format, e.g. for analyzing result maps in Python.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{Image, Sample, SampleFormat};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Headers, including the magic string, the version and the header length, are padded to a
+/// multiple of this many bytes.
+const HEADER_ALIGN: usize = 64;
+
+/// Longest header that is read, like the default `max_header_size` of NumPy. Headers written by
+/// NumPy and [write_npy](Image::write_npy) are far shorter, so anything longer is rejected
+/// before it is allocated.
+const MAX_HEADER_LEN: usize = 10_000;
+
+/// NumPy type descriptor of samples of the given format in native byte order.
+fn descr(format: SampleFormat) -> &'static str {
+    let little = cfg!(target_endian = "little");
+    match format {
+        SampleFormat::F32 if little => "<f4",
+        SampleFormat::F32 => ">f4",
+        SampleFormat::F16 if little => "<f2",
+        SampleFormat::F16 => ">f2",
+        SampleFormat::U8 => "|u1",
+        SampleFormat::U16 if little => "<u2",
+        SampleFormat::U16 => ">u2",
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl<T: Sample> Image<'_, T> {
+    /// Writes the image in `.npy` format, as an array of shape `(height, width)` if it has a single
+    /// channel and `(height, width, channels)` otherwise. Samples are written as they are, e.g.
+    /// [u8] images as `uint8`.
+    pub fn write_npy(&self, mut writer: impl Write) -> io::Result<()> {
+        let shape = match self.channels {
+            1 => format!("({}, {})", self.height, self.width),
+            channels => format!("({}, {}, {channels})", self.height, self.width),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+            descr(T::FORMAT)
+        );
+        // Magic string, version and header length come first, and the header ends with a newline.
+        let unpadded = MAGIC.len() + 4 + header.len() + 1;
+        header.extend(std::iter::repeat_n(
+            ' ',
+            unpadded.next_multiple_of(HEADER_ALIGN) - unpadded,
+        ));
+        header.push('\n');
+
+        let header_len = u16::try_from(header.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "npy header is too long"))?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[1, 0])?;
+        writer.write_all(&header_len.to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+
+        for row in self.rows() {
+            writer.write_all(bytemuck::cast_slice(row))?;
+        }
+        writer.flush()
+    }
+
+    /// Like [write_npy](Self::write_npy), but creates or truncates the file at `path`.
+    pub fn save_npy(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_npy(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<T: Sample> Image<'static, T> {
+    /// Reads an image in `.npy` format: an array of shape `(height, width)` as a single-channel
+    /// image, or `(height, width, channels)` as an image with interleaved channels.
+    ///
+    /// The array's type must be the one that [write_npy](Image::write_npy) writes for `T`, e.g.
+    /// `float32` for [f32] images, and be stored in C order. Other arrays are rejected with an
+    /// [InvalidData](io::ErrorKind::InvalidData) error.
+    pub fn read_npy(mut reader: impl Read) -> io::Result<Self> {
+        let mut preamble = [0; 8];
+        reader.read_exact(&mut preamble)?;
+        if &preamble[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("not an npy file"));
+        }
+
+        let header_len = match preamble[MAGIC.len()] {
+            1 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_le_bytes(len) as usize
+            }
+            2 | 3 => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                u32::from_le_bytes(len) as usize
+            }
+            version => return Err(invalid_data(format!("unsupported npy version {version}"))),
+        };
+        if header_len > MAX_HEADER_LEN {
+            return Err(invalid_data(format!(
+                "npy header of {header_len} bytes is too long"
+            )));
+        }
+        let mut header = vec![0; header_len];
+        reader.read_exact(&mut header)?;
+        let header =
+            String::from_utf8(header).map_err(|_| invalid_data("npy header isn't text"))?;
+
+        let expected = descr(T::FORMAT);
+        let found = header_value(&header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+        if found != expected {
+            return Err(invalid_data(format!(
+                "npy array of type {found} can't be read as {expected} samples"
+            )));
+        }
+        if header_value(&header, "fortran_order")? != "False" {
+            return Err(invalid_data("npy arrays in Fortran order aren't supported"));
+        }
+
+        let shape = header_value(&header, "shape")?
+            .trim_matches(|c| c == '(' || c == ')')
+            .split(',')
+            .map(str::trim)
+            .filter(|dimension| !dimension.is_empty())
+            .map(|dimension| dimension.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid_data("invalid npy shape"))?;
+        let (height, width, channels) = match shape[..] {
+            [height, width] => (height, width, 1),
+            [height, width, channels] => (height, width, channels),
+            _ => return Err(invalid_data("npy array must have two or three dimensions")),
+        };
+
+        let len = (height as usize)
+            .checked_mul(width as usize)
+            .and_then(|len| len.checked_mul(channels as usize))
+            .filter(|len| len.checked_mul(std::mem::size_of::<T>()).is_some())
+            .ok_or_else(|| invalid_data("npy array is too large"))?;
+        let mut data = vec![T::zeroed(); len];
+        reader.read_exact(bytemuck::cast_slice_mut(&mut data))?;
+
+        Image::try_with_channels(data, width, height, channels)
+            .map_err(|error| invalid_data(error.to_string()))
+    }
+
+    /// Like [read_npy](Self::read_npy), but reads the file at `path`.
+    pub fn load_npy(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_npy(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Text of the value of `key` in an npy header, which is a Python dict literal like
+/// `{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }`.
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let missing = || invalid_data(format!("npy header has no {key}"));
+    let start = header
+        .find(&format!("'{key}'"))
+        .or_else(|| header.find(&format!("\"{key}\"")))
+        .ok_or_else(missing)?
+        + key.len()
+        + 2;
+    let value = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?
+        .trim_start();
+
+    // Shapes are tuples, which contain commas themselves.
+    let end = if value.starts_with('(') {
+        value.find(')').map(|end| end + 1)
+    } else {
+        value.find([',', '}'])
+    };
+    Ok(value[..end.ok_or_else(missing)?].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Sample>(image: &Image<'_, T>) -> Image<'static, T> {
+        let mut bytes = Vec::new();
+        image.write_npy(&mut bytes).unwrap();
+        Image::read_npy(&bytes[..]).unwrap()
+    }
+
+    /// File holding the header, followed by `data`.
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn images_survive_round_trip() {
+        let image = Image::new(vec![0.0, 0.5, 1.0, -2.0, 3.5, f32::MAX], 3, 2);
+        let read = round_trip(&image);
+        assert_eq!((read.width, read.height, read.channels), (3, 2, 1));
+        assert_eq!(read.data, image.data);
+
+        let image = Image::with_channels(vec![0u8, 1, 2, 3, 4, 5, 250, 251, 252], 1, 3, 3);
+        let read = round_trip(&image);
+        assert_eq!((read.width, read.height, read.channels), (1, 3, 3));
+        assert_eq!(read.data, image.data);
+    }
+
+    #[test]
+    fn views_are_written_without_stride_padding() {
+        let frame = Image::new((0..20).map(|v| v as u16).collect::<Vec<_>>(), 5, 4);
+        let read = round_trip(&frame.view(1, 1, 3, 2));
+        assert_eq!(read.stride, None);
+        assert_eq!(*read.data, [6, 7, 8, 11, 12, 13]);
+    }
+
+    #[test]
+    fn mismatched_sample_type_is_rejected() {
+        let mut bytes = Vec::new();
+        Image::new(vec![0u8; 4], 2, 2)
+            .write_npy(&mut bytes)
+            .unwrap();
+        let error = Image::<f32>::read_npy(&bytes[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn overlong_header_is_rejected() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([2, 0]);
+        bytes.extend(u32::MAX.to_le_bytes());
+        let error = Image::<f32>::read_npy(&bytes[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn overflowing_shape_is_rejected() {
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (4294967295, 4294967295, 4294967295), }\n";
+        let error = Image::<u8>::read_npy(&npy(header, &[])[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (2, 3), }\n";
+        let error = Image::<u8>::read_npy(&npy(header, &[1, 2, 3])[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,801 @@
+//! Shader and compute pipeline management.
+//!
+//! All wgpu pipeline state lives here so that the matcher itself only deals with buffers and
+//! dispatches. Shader variants are selected based on the [Capabilities] of the device.
+
+mod binary;
+mod difference;
+mod extremes;
+mod fft;
+mod log_polar;
+mod preprocess;
+mod pruned;
+mod quantized;
+mod refine;
+mod sparse;
+mod transformed;
+
+use std::collections::HashMap;
+
+use std::mem::size_of;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    log_polar::LogPolarSearch, refine::Refinement, Image, ImageLayout, MatchTemplateMethod,
+    SampleFormat, ShaderUniforms,
+};
+
+pub(crate) use binary::BinaryImages;
+use binary::BinaryKernels;
+pub(crate) use difference::DifferenceImages;
+use difference::DifferenceKernels;
+use extremes::ExtremesKernels;
+use fft::FftKernels;
+pub(crate) use fft::{FftImages, FftSizes, TemplateSums};
+pub(crate) use log_polar::LogPolarImages;
+use log_polar::LogPolarKernels;
+use preprocess::PreprocessKernels;
+pub(crate) use preprocess::{PreprocessImages, PreprocessPass};
+pub(crate) use pruned::PrunedImages;
+use pruned::PrunedKernels;
+pub(crate) use quantized::QuantizedImages;
+use quantized::QuantizedKernels;
+pub(crate) use refine::RefineImages;
+use refine::RefineKernels;
+pub(crate) use sparse::SparseImages;
+use sparse::SparseKernels;
+use transformed::TransformedKernels;
+pub(crate) use transformed::{RenderImages, TransformedImages};
+
+/// Device capabilities relevant to template matching. All are false or zero on the CPU engine.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// GPU timestamps can be recorded.
+    pub timestamp_query: bool,
+    pub max_storage_buffer_binding_size: u32,
+    pub max_compute_workgroup_size_x: u32,
+    pub max_compute_workgroup_size_y: u32,
+    pub max_compute_invocations_per_workgroup: u32,
+}
+
+impl Capabilities {
+    pub(crate) fn detect(device: &wgpu::Device) -> Self {
+        let features = device.features();
+        let limits = device.limits();
+
+        Self {
+            timestamp_query: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+            max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+            max_compute_workgroup_size_x: limits.max_compute_workgroup_size_x,
+            max_compute_workgroup_size_y: limits.max_compute_workgroup_size_y,
+            max_compute_invocations_per_workgroup: limits.max_compute_invocations_per_workgroup,
+        }
+    }
+}
+
+/// Where the shader reads an image from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Source {
+    /// A storage buffer holding samples of the given format.
+    Buffer(SampleFormat),
+    /// A float texture, with channels read from its color components.
+    Texture,
+}
+
+impl Source {
+    fn is_texture(&self) -> bool {
+        matches!(self, Source::Texture)
+    }
+}
+
+/// Largest template tile that the matching shader loads into shared memory at a time.
+pub(crate) const MAX_TILE_SIZE: u32 = 16;
+
+/// Templates up to this size in both dimensions get a shader specialized on their size.
+const MAX_SPECIALIZED_TEMPLATE_SIZE: u32 = 32;
+
+/// Identifies a compute pipeline variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    pub method: MatchTemplateMethod,
+    pub input: Source,
+    pub template: Source,
+    /// Template size the shader is specialized on, if any.
+    pub template_size: Option<(u32, u32)>,
+}
+
+/// Identifies a shader module variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderKey {
+    input: Source,
+    template: Source,
+    template_size: Option<(u32, u32)>,
+    workgroup_size: (u32, u32),
+}
+
+impl ShaderKey {
+    fn source(&self) -> String {
+        let (workgroup_width, workgroup_height) = self.workgroup_size;
+        let (tile_width, tile_height) = self.tile_size();
+        let input_tile_width = workgroup_width + tile_width - 1;
+        let input_tile_height = workgroup_height + tile_height - 1;
+
+        let mut source = String::new();
+        for (name, value) in [
+            ("WORKGROUP_WIDTH", workgroup_width),
+            ("WORKGROUP_HEIGHT", workgroup_height),
+            ("WORKGROUP_LEN", workgroup_width * workgroup_height),
+            ("TILE_WIDTH", tile_width),
+            ("TILE_HEIGHT", tile_height),
+            ("TEMPLATE_TILE_LEN", tile_width * tile_height),
+            ("INPUT_TILE_WIDTH", input_tile_width),
+            ("INPUT_TILE_LEN", input_tile_width * input_tile_height),
+        ] {
+            source += &format!("const {name}: u32 = {value}u;\n");
+        }
+        source += "\n";
+        source += &load_function(0, "input", self.input);
+        source += &load_function(1, "template", self.template);
+        source += &template_size_function(self.template_size);
+        source += include_str!("../shaders/uniforms.wgsl");
+        source += &include_str!("../shaders/matching.wgsl").replace(
+            "@workgroup_size(16, 16, 1)",
+            &format!(
+                "@workgroup_size({}, {}, 1)",
+                self.workgroup_size.0, self.workgroup_size.1
+            ),
+        );
+        source
+    }
+
+    /// Size of the template tiles that the matching shader loads into shared memory at a time. The
+    /// tiles and the input they cover must fit in the shared memory that every device provides.
+    fn tile_size(&self) -> (u32, u32) {
+        let (workgroup_width, workgroup_height) = self.workgroup_size;
+        let (mut width, mut height) = match self.template_size {
+            Some((width, height)) => (width.min(MAX_TILE_SIZE), height.min(MAX_TILE_SIZE)),
+            None => (MAX_TILE_SIZE, MAX_TILE_SIZE),
+        };
+
+        let max_len = wgpu::Limits::downlevel_defaults().max_compute_workgroup_storage_size
+            / size_of::<f32>() as u32;
+        let len = |width: u32, height: u32| {
+            (workgroup_width + width - 1) * (workgroup_height + height - 1) + width * height
+        };
+
+        while len(width, height) > max_len && (width > 1 || height > 1) {
+            if width >= height {
+                width /= 2;
+            } else {
+                height /= 2;
+            }
+        }
+
+        (width, height)
+    }
+}
+
+/// Declares `template_size()`, which returns constants for templates of a specialized size.
+fn template_size_function(template_size: Option<(u32, u32)>) -> String {
+    let size = match template_size {
+        Some((width, height)) => format!("vec2<u32>({width}u, {height}u)"),
+        None => "vec2<u32>(uniforms.template_width, uniforms.template_height)".to_string(),
+    };
+
+    format!("fn template_size() -> vec2<u32> {{\n    return {size};\n}}\n\n")
+}
+
+/// Declares the binding of image `name` and a `load_{name}(x: u32, y: u32, c: u32) -> f32` function
+/// that reads channel `c` of the pixel at `(x, y)` from it.
+fn load_function(binding: u32, name: &str, source: Source) -> String {
+    let (declaration, load) = match source {
+        Source::Buffer(format) => {
+            let (element, load) = match format {
+                SampleFormat::F32 => ("f32", format!("{name}_buf[idx]")),
+                SampleFormat::F16 => (
+                    "u32",
+                    format!("unpack2x16float({name}_buf[idx / 2u])[idx % 2u]"),
+                ),
+                SampleFormat::U8 => (
+                    "u32",
+                    format!("unpack4x8unorm({name}_buf[idx / 4u])[idx % 4u]"),
+                ),
+                SampleFormat::U16 => (
+                    "u32",
+                    format!("unpack2x16unorm({name}_buf[idx / 2u])[idx % 2u]"),
+                ),
+            };
+
+            (
+                format!("var<storage, read> {name}_buf: array<{element}>;"),
+                format!(
+                    "let idx = {name}_offset + y * uniforms.{name}_stride + x * uniforms.channels + c;\n    \
+                     return {load};"
+                ),
+            )
+        }
+        Source::Texture => (
+            format!("var {name}_tex: texture_2d<f32>;"),
+            format!("return textureLoad({name}_tex, vec2<i32>(i32(x), i32(y)), 0)[c];"),
+        ),
+    };
+
+    format!(
+        "@group(0) @binding({binding}) {declaration}\n\
+         fn load_{name}(x: u32, y: u32, c: u32) -> f32 {{\n    {load}\n}}\n\n"
+    )
+}
+
+impl PipelineKey {
+    /// Key for matching a template of the given size, specializing on the size of small templates.
+    pub fn new(
+        method: MatchTemplateMethod,
+        input: Source,
+        template: Source,
+        (template_width, template_height): (u32, u32),
+    ) -> Self {
+        let specialize = template_width <= MAX_SPECIALIZED_TEMPLATE_SIZE
+            && template_height <= MAX_SPECIALIZED_TEMPLATE_SIZE;
+
+        Self {
+            method,
+            input,
+            template,
+            template_size: specialize.then_some((template_width, template_height)),
+        }
+    }
+
+    fn shader_key(&self, workgroup_size: (u32, u32)) -> ShaderKey {
+        ShaderKey {
+            input: self.input,
+            template: self.template,
+            template_size: self.template_size,
+            workgroup_size,
+        }
+    }
+
+    fn layout_key(&self) -> LayoutKey {
+        LayoutKey {
+            input_texture: self.input.is_texture(),
+            template_texture: self.template.is_texture(),
+        }
+    }
+
+    fn entry_point(&self) -> &'static str {
+        match self.method {
+            MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad",
+            MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd",
+        }
+    }
+}
+
+/// Identifies a bind group layout variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct LayoutKey {
+    input_texture: bool,
+    template_texture: bool,
+}
+
+struct Layout {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+/// Shader modules, layouts and lazily created compute pipelines.
+pub(crate) struct Kernels {
+    capabilities: Capabilities,
+    workgroup_size: (u32, u32),
+    shaders: HashMap<ShaderKey, wgpu::ShaderModule>,
+    layouts: HashMap<LayoutKey, Layout>,
+    pipelines: HashMap<PipelineKey, wgpu::ComputePipeline>,
+    difference: DifferenceKernels,
+    fft: FftKernels,
+    pruned: PrunedKernels,
+    extremes: ExtremesKernels,
+    log_polar: LogPolarKernels,
+    preprocess: PreprocessKernels,
+    quantized: QuantizedKernels,
+    refine: RefineKernels,
+    sparse: SparseKernels,
+    binary: BinaryKernels,
+    transformed: TransformedKernels,
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn image_entry(binding: u32, texture: bool) -> wgpu::BindGroupLayoutEntry {
+    if texture {
+        texture_entry(binding)
+    } else {
+        storage_entry(binding, true)
+    }
+}
+
+impl Kernels {
+    /// Creates the kernels with the given workgroup size for the matching shader, e.g. one from
+    /// [tune_workgroup_size].
+    pub fn new(device: &wgpu::Device, workgroup_size: (u32, u32)) -> Self {
+        let capabilities = Capabilities::detect(device);
+
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: None,
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: None,
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    LayoutKey {
+                        input_texture,
+                        template_texture,
+                    },
+                    Layout {
+                        bind_group_layout,
+                        pipeline_layout,
+                    },
+                );
+            }
+        }
+
+        Self {
+            capabilities,
+            workgroup_size,
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+            difference: DifferenceKernels::new(device),
+            fft: FftKernels::new(device),
+            pruned: PrunedKernels::new(device),
+            extremes: ExtremesKernels::new(device),
+            log_polar: LogPolarKernels::new(device),
+            preprocess: PreprocessKernels::new(device),
+            quantized: QuantizedKernels::new(device),
+            refine: RefineKernels::new(device),
+            sparse: SparseKernels::new(device),
+            binary: BinaryKernels::new(device),
+            transformed: TransformedKernels::new(device),
+        }
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Returns the bind group layout used by pipelines with the given key.
+    pub fn bind_group_layout(&self, key: PipelineKey) -> &wgpu::BindGroupLayout {
+        &self.layouts[&key.layout_key()].bind_group_layout
+    }
+
+    /// Returns the pipeline for the given key, creating it on first use.
+    pub fn pipeline(&mut self, device: &wgpu::Device, key: PipelineKey) -> &wgpu::ComputePipeline {
+        let Self {
+            workgroup_size,
+            shaders,
+            layouts,
+            pipelines,
+            ..
+        } = self;
+
+        pipelines.entry(key).or_insert_with(|| {
+            let shader_key = key.shader_key(*workgroup_size);
+            let shader = shaders.entry(shader_key).or_insert_with(|| {
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("matching"),
+                    source: wgpu::ShaderSource::Wgsl(shader_key.source().into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&layouts[&key.layout_key()].pipeline_layout),
+                module: shader,
+                entry_point: key.entry_point(),
+            })
+        })
+    }
+
+    /// Creates a bind group for pipelines with the given key.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        key: PipelineKey,
+        input: wgpu::BindingResource,
+        template: wgpu::BindingResource,
+        result: wgpu::BindingResource,
+        uniforms: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: self.bind_group_layout(key),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniforms.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Whether the sum of squared differences can be computed in the frequency domain for an input
+    /// of the given size.
+    pub fn fft_supported(&self, input_size: (u32, u32)) -> bool {
+        self.fft.padded_size(input_size).is_some()
+    }
+
+    /// Whether computing the sum of squared differences in the frequency domain is expected to be
+    /// faster than the direct kernel.
+    pub fn fft_is_faster(&self, sizes: FftSizes) -> bool {
+        self.fft.is_faster(sizes)
+    }
+
+    /// Records passes that compute the sum of squared differences in the frequency domain.
+    pub fn encode_fft(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        key: PipelineKey,
+        images: FftImages,
+        sizes: FftSizes,
+        template: TemplateSums,
+    ) {
+        self.fft
+            .encode(device, encoder, key, images, sizes, template);
+    }
+
+    /// Records a compute pass that scores every position of a `result_width` by `result_height`
+    /// result until it exceeds the best score so far.
+    pub fn encode_pruned(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        key: PipelineKey,
+        images: PrunedImages,
+        result_size: (u32, u32),
+    ) {
+        self.pruned
+            .encode(device, encoder, key, images, result_size);
+    }
+
+    /// Records the passes that find the smallest and largest of the first `len` scores of
+    /// `values` and their indices. Returns the buffer they are written to.
+    pub fn encode_extremes(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        values: wgpu::BindingResource,
+        len: u32,
+    ) -> wgpu::Buffer {
+        self.extremes.encode(device, encoder, values, len)
+    }
+
+    /// Records a compute pass that flags two frames of the given size as different if any of their
+    /// samples differ by more than `threshold`.
+    pub fn encode_difference(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        sources: (Source, Source),
+        images: DifferenceImages,
+        (size, threshold): ((u32, u32), f32),
+    ) {
+        self.difference
+            .encode(device, encoder, sources, images, size, threshold);
+    }
+
+    /// Records a compute pass that finds the best ring shift and angle of every candidate window
+    /// of a log-polar search.
+    pub fn encode_log_polar(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        sources: (MatchTemplateMethod, Source, Source),
+        images: LogPolarImages,
+        search: &LogPolarSearch,
+    ) {
+        self.log_polar
+            .encode(device, encoder, sources, images, search);
+    }
+
+    /// Records a compute pass of a preprocessing step on an image of the source layout, producing
+    /// an image of the output layout.
+    pub fn encode_preprocess(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pass: &PreprocessPass,
+        images: PreprocessImages,
+        layouts: (&ImageLayout, &ImageLayout),
+    ) {
+        self.preprocess
+            .encode(device, encoder, pass, images, layouts);
+    }
+
+    /// Records a compute pass that refines a match into an affine alignment.
+    pub fn encode_refine(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        sources: (Source, Source),
+        images: RefineImages,
+        refinement: &Refinement,
+    ) {
+        self.refine
+            .encode(device, encoder, sources, images, refinement);
+    }
+
+    /// Records a compute pass that scores every position of a `result_width` by `result_height`
+    /// result with integer arithmetic on images uploaded as `u8` samples.
+    pub fn encode_quantized(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        method: MatchTemplateMethod,
+        images: QuantizedImages,
+        result_size: (u32, u32),
+    ) {
+        self.quantized
+            .encode(device, encoder, method, images, result_size);
+    }
+
+    /// Records a compute pass that scores every position of a `result_width` by `result_height`
+    /// result with a sparse template.
+    pub fn encode_sparse(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (method, input): (MatchTemplateMethod, Source),
+        images: SparseImages,
+        result_size: (u32, u32),
+    ) {
+        self.sparse
+            .encode(device, encoder, (method, input), images, result_size);
+    }
+
+    /// Records compute passes that threshold the input and the template into bit masks and score
+    /// every position of the result by the number of differing bits.
+    pub fn encode_binary(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        images: BinaryImages,
+        layouts: (&ImageLayout, &ImageLayout),
+        threshold: f32,
+    ) {
+        self.binary
+            .encode(device, encoder, images, layouts, threshold);
+    }
+
+    /// Records a compute pass that renders `variant_count` variants of the template, of which the
+    /// largest has `max_pixel_count` pixels.
+    pub fn encode_render_variants(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        template: Source,
+        images: RenderImages,
+        counts: (u32, u32),
+    ) {
+        self.transformed
+            .encode_render(device, encoder, template, images, counts);
+    }
+
+    /// Records a compute pass that writes the best scores of rendered variants over a
+    /// `result_width` by `result_height` result, followed by the indices of their variants.
+    pub fn encode_transformed(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        sources: (MatchTemplateMethod, Source),
+        images: TransformedImages,
+        result_size: (u32, u32),
+    ) {
+        self.transformed
+            .encode_match(device, encoder, sources, images, result_size);
+    }
+
+    /// Records a compute pass that matches every position of a `result_width` by `result_height`
+    /// result, for each of `batch` inputs.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        key: PipelineKey,
+        bind_group: &wgpu::BindGroup,
+        (result_width, result_height): (u32, u32),
+        batch: u32,
+    ) {
+        let (workgroup_width, workgroup_height) = self.workgroup_size;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("compute_pass"),
+        });
+        compute_pass.set_pipeline(self.pipeline(device, key));
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            result_width.div_ceil(workgroup_width),
+            result_height.div_ceil(workgroup_height),
+            batch,
+        );
+    }
+}
+
+/// Workgroup sizes of the matching shader that [tune_workgroup_size] chooses from. The first one
+/// is used where timing isn't possible.
+const WORKGROUP_SIZES: [(u32, u32); 5] = [(16, 16), (8, 8), (32, 8), (8, 32), (64, 4)];
+
+/// Size of the input and template that workgroup sizes are timed with.
+const TUNING_INPUT_SIZE: u32 = 256;
+const TUNING_TEMPLATE_SIZE: u32 = 8;
+
+/// Times the matching shader with each workgroup size the device supports and returns the
+/// fastest. The best size depends a lot on the GPU architecture.
+pub(crate) fn tune_workgroup_size(device: &wgpu::Device, queue: &wgpu::Queue) -> (u32, u32) {
+    let limits = device.limits();
+    let candidates: Vec<_> = WORKGROUP_SIZES
+        .into_iter()
+        .filter(|&(x, y)| {
+            x <= limits.max_compute_workgroup_size_x
+                && y <= limits.max_compute_workgroup_size_y
+                && x * y <= limits.max_compute_invocations_per_workgroup
+        })
+        .collect();
+
+    // There is no clock on wasm, and blocking on the device isn't possible there either.
+    if cfg!(target_arch = "wasm32") || candidates.len() < 2 {
+        return candidates.first().copied().unwrap_or((8, 8));
+    }
+
+    // A smaller input is timed on devices whose storage buffers can't hold the full one.
+    let max_samples = limits.max_storage_buffer_binding_size as usize / size_of::<f32>();
+    let input_size = TUNING_INPUT_SIZE.min((max_samples as f64).sqrt() as u32);
+
+    let input = Image::new(
+        vec![0.5; (input_size * input_size) as usize],
+        input_size,
+        input_size,
+    );
+    let template = Image::new(
+        vec![0.25; (TUNING_TEMPLATE_SIZE * TUNING_TEMPLATE_SIZE) as usize],
+        TUNING_TEMPLATE_SIZE,
+        TUNING_TEMPLATE_SIZE,
+    );
+    let result_size = input_size - TUNING_TEMPLATE_SIZE + 1;
+
+    let create_buffer = |contents: &[u8], usage| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("tuning"),
+            contents,
+            usage,
+        })
+    };
+    let input_buffer = create_buffer(
+        bytemuck::cast_slice(&input.data),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let template_buffer = create_buffer(
+        bytemuck::cast_slice(&template.data),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let uniform_buffer = create_buffer(
+        bytemuck::bytes_of(&ShaderUniforms::new(
+            &ImageLayout::of(&input),
+            &ImageLayout::of(&template),
+        )),
+        wgpu::BufferUsages::UNIFORM,
+    );
+    let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("tuning"),
+        size: (result_size * result_size) as u64 * size_of::<f32>() as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let key = PipelineKey::new(
+        MatchTemplateMethod::SumOfSquaredDifferences,
+        Source::Buffer(SampleFormat::F32),
+        Source::Buffer(SampleFormat::F32),
+        (TUNING_TEMPLATE_SIZE, TUNING_TEMPLATE_SIZE),
+    );
+
+    let time = |workgroup_size| {
+        let mut kernels = Kernels::new(device, workgroup_size);
+        let bind_group = kernels.create_bind_group(
+            device,
+            key,
+            input_buffer.as_entire_binding(),
+            template_buffer.as_entire_binding(),
+            result_buffer.as_entire_binding(),
+            &uniform_buffer,
+        );
+
+        let mut run = || {
+            let mut encoder = device.create_command_encoder(&Default::default());
+            kernels.encode(
+                device,
+                &mut encoder,
+                key,
+                &bind_group,
+                (result_size, result_size),
+                1,
+            );
+
+            let start = std::time::Instant::now();
+            queue.submit(std::iter::once(encoder.finish()));
+            device.poll(wgpu::Maintain::Wait);
+            start.elapsed()
+        };
+
+        // The first run includes compiling the pipeline.
+        run();
+        (0..3).map(|_| run()).min().unwrap()
+    };
+
+    candidates
+        .into_iter()
+        .min_by_key(|&workgroup_size| time(workgroup_size))
+        .unwrap()
+}
@@ -0,0 +1,207 @@
+//! Matching binarized images with bitwise operations.
+
+use std::{collections::HashMap, mem::size_of};
+
+use wgpu::util::DeviceExt;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::ImageLayout;
+
+/// Threads per workgroup of the binary shader in each dimension.
+const WORKGROUP_SIZE: u32 = 16;
+
+/// Entry points of the binary shader, in the order they run.
+const ENTRY_POINTS: [&str; 3] = ["main_pack_input", "main_pack_template", "main_binary"];
+
+/// Parameters of a binary match, in the layout of the binary shader.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Binary {
+    threshold: f32,
+    input_words: u32,
+    template_words: u32,
+    padding: u32,
+}
+
+/// Buffers that a binary match reads and writes.
+pub(crate) struct BinaryImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub template: wgpu::BindingResource<'a>,
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Shader modules, layouts and pipelines of binary matches.
+pub(crate) struct BinaryKernels {
+    shaders: HashMap<(Source, Source), wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts, by whether the input and the template are textures.
+    layouts: HashMap<(bool, bool), (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    /// Pipelines by sources and entry point.
+    pipelines: HashMap<(Source, Source, &'static str), wgpu::ComputePipeline>,
+}
+
+impl BinaryKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("binary"),
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                            uniform_entry(4),
+                            storage_entry(5, false),
+                            storage_entry(6, false),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("binary"),
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    (input_texture, template_texture),
+                    (bind_group_layout, pipeline_layout),
+                );
+            }
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records compute passes that threshold the input and the template into bit masks and count
+    /// the differing bits at every position of the result.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        images: BinaryImages,
+        (input, template): (&ImageLayout, &ImageLayout),
+        threshold: f32,
+    ) {
+        let sources = (input.source, template.source);
+        let layout_key = (input.source.is_texture(), template.source.is_texture());
+
+        let input_words = (input.width * input.channels).div_ceil(32) + 1;
+        let template_words = (template.width * template.channels).div_ceil(32);
+        let bits_buffer = |label, len: u32| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: len as u64 * size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let input_bits = bits_buffer("binary_input_bits", input_words * input.height);
+        let template_bits = bits_buffer("binary_template_bits", template_words * template.height);
+
+        let binary_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("binary_parameters"),
+            contents: bytemuck::bytes_of(&Binary {
+                threshold,
+                input_words,
+                template_words,
+                padding: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("binary"),
+            layout: &self.layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: binary_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: input_bits.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: template_bits.as_entire_binding(),
+                },
+            ],
+        });
+
+        let result_size = (
+            input.width - template.width + 1,
+            input.height - template.height + 1,
+        );
+        let dispatch_sizes = [
+            (input_words, input.height),
+            (template_words, template.height),
+            result_size,
+        ];
+
+        for (entry_point, (width, height)) in ENTRY_POINTS.into_iter().zip(dispatch_sizes) {
+            let Self {
+                shaders,
+                layouts,
+                pipelines,
+            } = self;
+
+            let pipeline = pipelines
+                .entry((sources.0, sources.1, entry_point))
+                .or_insert_with(|| {
+                    let shader = shaders.entry(sources).or_insert_with(|| {
+                        let mut source = load_function(0, "input", sources.0);
+                        source += &load_function(1, "template", sources.1);
+                        source += include_str!("../../shaders/uniforms.wgsl");
+                        source += include_str!("../../shaders/binary.wgsl");
+
+                        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                            label: Some("binary"),
+                            source: wgpu::ShaderSource::Wgsl(source.into()),
+                        })
+                    });
+
+                    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                        label: Some(entry_point),
+                        layout: Some(&layouts[&layout_key].1),
+                        module: shader,
+                        entry_point,
+                    })
+                });
+
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(entry_point),
+            });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
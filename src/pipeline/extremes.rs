@@ -0,0 +1,133 @@
+//! Finding the smallest and largest scores of a result on the GPU, so that only they need to be
+//! read back.
+
+use std::mem::size_of;
+
+use wgpu::util::DeviceExt;
+
+use super::{storage_entry, uniform_entry};
+
+/// Threads per workgroup of the reduction shader.
+const WORKGROUP_LEN: u32 = 256;
+
+/// Size of the extremes written by the shader: the smallest score and its index, and the largest
+/// score and its index.
+const EXTREMES_BYTES: u64 = 4 * size_of::<u32>() as u64;
+
+/// Shader module, layouts and pipelines of the reduction.
+pub(crate) struct ExtremesKernels {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Pipelines of the first and second pass, created on first use.
+    pipelines: Option<(wgpu::ComputePipeline, wgpu::ComputePipeline)>,
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+impl ExtremesKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("extremes"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                uniform_entry(2),
+                storage_entry(3, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("extremes"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            bind_group_layout,
+            pipelines: None,
+            pipeline_layout,
+        }
+    }
+
+    /// Records the passes that find the extremes of the first `len` scores of `values`. Returns
+    /// the buffer they are written to, which can be copied from.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        values: wgpu::BindingResource,
+        len: u32,
+    ) -> wgpu::Buffer {
+        let (reduce_values, reduce_partials) = self.pipelines.get_or_insert_with(|| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("extremes"),
+                source: wgpu::ShaderSource::Wgsl(
+                    include_str!("../../shaders/extremes.wgsl").into(),
+                ),
+            });
+            let pipeline = |entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("extremes"),
+                    layout: Some(&self.pipeline_layout),
+                    module: &shader,
+                    entry_point,
+                })
+            };
+            (pipeline("reduce_values"), pipeline("reduce_partials"))
+        });
+
+        // The second pass reduces the partial results with a single workgroup, one per thread.
+        let partial_count = len.div_ceil(WORKGROUP_LEN).clamp(1, WORKGROUP_LEN);
+
+        let partials = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("extremes_partials"),
+            size: partial_count as u64 * EXTREMES_BYTES,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let uniforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("extremes_uniforms"),
+            contents: bytemuck::cast_slice(&[len, partial_count, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let extremes = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("extremes"),
+            size: EXTREMES_BYTES,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("extremes"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: values,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: partials.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: extremes.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("extremes"),
+        });
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_pipeline(reduce_values);
+        compute_pass.dispatch_workgroups(partial_count, 1, 1);
+        compute_pass.set_pipeline(reduce_partials);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+        drop(compute_pass);
+
+        extremes
+    }
+}
@@ -0,0 +1,670 @@
+//! Matching with the sum of squared differences in the frequency domain, for large templates.
+//!
+//! The sliding-window shader does `template_width * template_height` loads per result, while
+//! the cost of the transforms only grows with the logarithm of the padded input size.
+
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    image_entry, load_function, storage_entry, uniform_entry, LayoutKey, PipelineKey, Source,
+};
+
+/// Roughly how many direct multiply-adds one element of one transform stage costs. The stages are
+/// bound by memory bandwidth, while the direct kernel mostly hits the cache.
+const STAGE_COST: u64 = 8;
+
+/// Parameters of one pass. Matches `Fft` in the shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct FftParams {
+    width: u32,
+    height: u32,
+    n: u32,
+    span: u32,
+    stride: u32,
+    line_stride: u32,
+    lines: u32,
+    direction: f32,
+    mode: u32,
+    channel: u32,
+    weight: f32,
+    offset: f32,
+    shift: f32,
+}
+
+const PACK_INPUT: u32 = 0;
+const PACK_TEMPLATE: u32 = 1;
+
+const FORWARD: f32 = -1.0;
+const INVERSE: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Pass {
+    Pack,
+    Stage,
+    Accumulate,
+    IntegralRows,
+    IntegralColumns,
+    Finalize,
+}
+
+impl Pass {
+    const ALL: [Pass; 6] = [
+        Pass::Pack,
+        Pass::Stage,
+        Pass::Accumulate,
+        Pass::IntegralRows,
+        Pass::IntegralColumns,
+        Pass::Finalize,
+    ];
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            Pass::Pack => "fft_pack",
+            Pass::Stage => "fft_stage",
+            Pass::Accumulate => "fft_accumulate",
+            Pass::IntegralRows => "fft_integral_rows",
+            Pass::IntegralColumns => "fft_integral_columns",
+            Pass::Finalize => "fft_finalize",
+        }
+    }
+}
+
+/// Bind groups of a match, by the buffers they read from and write to. All of them read the kept
+/// spectrum.
+const WORK_0_TO_1: usize = 0;
+const WORK_1_TO_0: usize = 1;
+const WORK_0_TO_SUM: usize = 2;
+
+/// Identifies an FFT shader module variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderKey {
+    input: Source,
+    template: Source,
+}
+
+impl ShaderKey {
+    fn source(&self) -> String {
+        let mut source = String::new();
+        source += &load_function(0, "input", self.input);
+        source += &load_function(1, "template", self.template);
+        source += include_str!("../../shaders/uniforms.wgsl");
+        source += include_str!("../../shaders/fft.wgsl");
+        source
+    }
+}
+
+enum Step {
+    /// Runs a pass with the given bind group and index of its parameters.
+    Pass {
+        pass: Pass,
+        bind_group: usize,
+        params: usize,
+        workgroups: (u32, u32),
+    },
+    /// Copies the given work buffer into the kept spectrum.
+    Keep(usize),
+    /// Copies the accumulated spectrum into the first work buffer.
+    Restart,
+}
+
+/// The passes of one match, recorded before any resources are created so that the parameters of
+/// all passes can be uploaded at once.
+struct Plan {
+    size: (u32, u32),
+    /// Value subtracted from the packed images.
+    shift: f32,
+    /// Work buffer holding the latest output.
+    current: usize,
+    params: Vec<FftParams>,
+    steps: Vec<Step>,
+}
+
+impl Plan {
+    fn new(size: (u32, u32), shift: f32) -> Self {
+        Self {
+            size,
+            shift,
+            current: 0,
+            params: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, pass: Pass, bind_group: usize, params: FftParams, workgroups: (u32, u32)) {
+        self.steps.push(Step::Pass {
+            pass,
+            bind_group,
+            params: self.params.len(),
+            workgroups,
+        });
+        self.params.push(FftParams {
+            width: self.size.0,
+            height: self.size.1,
+            shift: self.shift,
+            ..params
+        });
+    }
+
+    fn cover((width, height): (u32, u32)) -> (u32, u32) {
+        (width.div_ceil(16), height.div_ceil(16))
+    }
+
+    /// Writes an image into the first work buffer.
+    fn pack(&mut self, mode: u32, channel: u32) {
+        let params = FftParams {
+            mode,
+            channel,
+            ..Default::default()
+        };
+        self.push(Pass::Pack, WORK_1_TO_0, params, Self::cover(self.size));
+        self.current = 0;
+    }
+
+    /// Transforms the rows and then the columns of the current work buffer.
+    fn transform(&mut self, direction: f32) {
+        let (width, height) = self.size;
+
+        for (n, stride, line_stride, lines) in
+            [(width, 1, width, height), (height, width, 1, width)]
+        {
+            let mut span = 1;
+            while span < n {
+                let params = FftParams {
+                    n,
+                    span,
+                    stride,
+                    line_stride,
+                    lines,
+                    direction,
+                    ..Default::default()
+                };
+                let bind_group = if self.current == 0 {
+                    WORK_0_TO_1
+                } else {
+                    WORK_1_TO_0
+                };
+                self.push(
+                    Pass::Stage,
+                    bind_group,
+                    params,
+                    ((n / 2).div_ceil(64), lines),
+                );
+                self.current ^= 1;
+                span *= 2;
+            }
+        }
+    }
+
+    /// Keeps the current spectrum for multiplying later ones with.
+    fn keep(&mut self) {
+        self.steps.push(Step::Keep(self.current));
+    }
+
+    /// Adds the cross-correlation spectrum of the current and kept spectra to the sum.
+    fn accumulate(&mut self, weight: f32) {
+        let params = FftParams {
+            weight,
+            ..Default::default()
+        };
+        self.push(
+            Pass::Accumulate,
+            WORK_0_TO_SUM + self.current,
+            params,
+            Self::cover(self.size),
+        );
+    }
+
+    /// Computes the integral image of the input.
+    fn integral(&mut self, (width, height): (u32, u32)) {
+        self.push(
+            Pass::IntegralRows,
+            WORK_0_TO_1,
+            FftParams::default(),
+            (height.div_ceil(64), 1),
+        );
+        self.push(
+            Pass::IntegralColumns,
+            WORK_0_TO_1,
+            FftParams::default(),
+            ((width + 1).div_ceil(64), 1),
+        );
+    }
+
+    /// Transforms the summed spectrum back.
+    fn inverse(&mut self) {
+        self.steps.push(Step::Restart);
+        self.current = 0;
+        self.transform(INVERSE);
+    }
+
+    fn finalize(&mut self, result_size: (u32, u32), weight: f32, offset: f32) {
+        let params = FftParams {
+            weight,
+            offset,
+            ..Default::default()
+        };
+        let bind_group = if self.current == 0 {
+            WORK_0_TO_1
+        } else {
+            WORK_1_TO_0
+        };
+        self.push(Pass::Finalize, bind_group, params, Self::cover(result_size));
+    }
+}
+
+/// Spectra that intermediate results are kept in, reused for any padded size that fits in them.
+struct Scratch {
+    bytes: u64,
+    work: [wgpu::Buffer; 2],
+    kept: wgpu::Buffer,
+    sum: wgpu::Buffer,
+}
+
+impl Scratch {
+    fn new(device: &wgpu::Device, bytes: u64) -> Self {
+        let create_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: bytes,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            bytes,
+            work: [create_buffer("fft_work_0"), create_buffer("fft_work_1")],
+            kept: create_buffer("fft_kept"),
+            sum: create_buffer("fft_sum"),
+        }
+    }
+}
+
+fn spectrum_bytes((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64 * 2 * size_of::<f32>() as u64
+}
+
+/// Integral image of an input, kept until a different input is matched so that matching several
+/// templates against the same input only computes it once.
+struct Integral {
+    buffer: wgpu::Buffer,
+    /// Version of the uploaded input that the integral image was computed from, if it can be
+    /// reused.
+    input_version: Option<u64>,
+}
+
+fn integral_bytes((width, height): (u32, u32)) -> u64 {
+    (width as u64 + 1) * (height as u64 + 1) * 2 * size_of::<f32>() as u64
+}
+
+/// Sums over the template that the scores are built from.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct TemplateSums {
+    /// Mean of the samples, which is subtracted from both images to keep the spectra small. The
+    /// differences between the images don't change.
+    pub mean: f32,
+    /// Sum of the squared samples after subtracting the mean.
+    pub sum_sq: f32,
+}
+
+/// Images that a match reads and writes.
+pub(crate) struct FftImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    /// Identifies the contents of `input` if they stay the same until the version changes, which
+    /// lets the integral image of the input be reused.
+    pub input_version: Option<u64>,
+    pub template: wgpu::BindingResource<'a>,
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Sizes of the images of a match.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct FftSizes {
+    pub input: (u32, u32),
+    pub template: (u32, u32),
+    pub channels: u32,
+}
+
+/// Shader modules, layouts, pipelines and scratch buffers of frequency-domain matching.
+pub(crate) struct FftKernels {
+    max_spectrum_bytes: u64,
+    max_workgroups: u32,
+    params_alignment: u64,
+    shaders: HashMap<ShaderKey, wgpu::ShaderModule>,
+    layouts: HashMap<LayoutKey, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<(ShaderKey, Pass), wgpu::ComputePipeline>,
+    scratch: Option<Scratch>,
+    integral: Option<Integral>,
+}
+
+impl FftKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let limits = device.limits();
+
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("fft"),
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                            wgpu::BindGroupLayoutEntry {
+                                binding: 4,
+                                visibility: wgpu::ShaderStages::COMPUTE,
+                                ty: wgpu::BindingType::Buffer {
+                                    ty: wgpu::BufferBindingType::Uniform,
+                                    has_dynamic_offset: true,
+                                    min_binding_size: None,
+                                },
+                                count: None,
+                            },
+                            storage_entry(5, true),
+                            storage_entry(6, false),
+                            storage_entry(7, true),
+                            storage_entry(8, false),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("fft"),
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    LayoutKey {
+                        input_texture,
+                        template_texture,
+                    },
+                    (bind_group_layout, pipeline_layout),
+                );
+            }
+        }
+
+        Self {
+            max_spectrum_bytes: (limits.max_storage_buffer_binding_size as u64)
+                .min(limits.max_buffer_size),
+            max_workgroups: limits.max_compute_workgroups_per_dimension,
+            params_alignment: limits.min_uniform_buffer_offset_alignment as u64,
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+            scratch: None,
+            integral: None,
+        }
+    }
+
+    /// Size of the spectra for matching against an input of the given size, or [None] if they
+    /// don't fit in the device limits.
+    pub fn padded_size(&self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        let size = (width.next_power_of_two(), height.next_power_of_two());
+
+        (spectrum_bytes(size) <= self.max_spectrum_bytes
+            && size.0.max(size.1) <= self.max_workgroups)
+            .then_some(size)
+    }
+
+    /// Whether matching in the frequency domain is expected to be faster than the direct kernel.
+    pub fn is_faster(&self, sizes: FftSizes) -> bool {
+        let Some((width, height)) = self.padded_size(sizes.input) else {
+            return false;
+        };
+
+        let result_width = (sizes.input.0 - sizes.template.0 + 1) as u64;
+        let result_height = (sizes.input.1 - sizes.template.1 + 1) as u64;
+        let direct = result_width
+            * result_height
+            * sizes.template.0 as u64
+            * sizes.template.1 as u64
+            * sizes.channels as u64;
+
+        // Two transforms per channel, plus the inverse one of the sum.
+        let transforms = 2 * sizes.channels as u64 + 1;
+        let stages = (width.ilog2() + height.ilog2()) as u64;
+        let fft = width as u64 * height as u64 * stages * transforms * STAGE_COST;
+
+        fft < direct
+    }
+
+    /// Records the passes that write the sum of squared differences between the template and
+    /// each position of the input into the result.
+    ///
+    /// The input must fit in the [padded size](Self::padded_size).
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        key: PipelineKey,
+        images: FftImages,
+        sizes: FftSizes,
+        template: TemplateSums,
+    ) {
+        let size = self
+            .padded_size(sizes.input)
+            .expect("input is too large for matching in the frequency domain");
+
+        let result_size = (
+            sizes.input.0 - sizes.template.0 + 1,
+            sizes.input.1 - sizes.template.1 + 1,
+        );
+
+        let mut plan = Plan::new(size, template.mean);
+
+        let integral_size = integral_bytes(sizes.input);
+        let integral_cached = matches!(
+            &self.integral,
+            Some(integral) if images.input_version.is_some()
+                && integral.input_version == images.input_version
+                && integral.buffer.size() >= integral_size
+        );
+        if !integral_cached {
+            if !matches!(&self.integral, Some(integral) if integral.buffer.size() >= integral_size)
+            {
+                self.integral = Some(Integral {
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("fft_integral"),
+                        size: integral_size,
+                        usage: wgpu::BufferUsages::STORAGE,
+                        mapped_at_creation: false,
+                    }),
+                    input_version: None,
+                });
+            }
+            self.integral.as_mut().unwrap().input_version = images.input_version;
+            plan.integral(sizes.input);
+        }
+
+        // Cross-correlation of the input and template, summed over channels.
+        for channel in 0..sizes.channels {
+            plan.pack(PACK_TEMPLATE, channel);
+            plan.transform(FORWARD);
+            plan.keep();
+            plan.pack(PACK_INPUT, channel);
+            plan.transform(FORWARD);
+            plan.accumulate(-2.0);
+        }
+
+        plan.inverse();
+        plan.finalize(
+            result_size,
+            1.0 / (size.0 as f32 * size.1 as f32),
+            template.sum_sq,
+        );
+
+        let spectrum_size = spectrum_bytes(size);
+        if !matches!(&self.scratch, Some(scratch) if scratch.bytes >= spectrum_size) {
+            self.scratch = Some(Scratch::new(device, spectrum_size));
+        }
+
+        let shader_key = ShaderKey {
+            input: key.input,
+            template: key.template,
+        };
+        let layout_key = key.layout_key();
+        for pass in Pass::ALL {
+            self.create_pipeline(device, shader_key, layout_key, pass);
+        }
+
+        let params_size = size_of::<FftParams>() as u64;
+        let params_stride = params_size.next_multiple_of(self.params_alignment);
+        let mut params = vec![0; (params_stride * plan.params.len() as u64) as usize];
+        for (i, entry) in plan.params.iter().enumerate() {
+            let start = i * params_stride as usize;
+            params[start..start + params_size as usize].copy_from_slice(bytemuck::bytes_of(entry));
+        }
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("fft_params"),
+            contents: &params,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let scratch = self.scratch.as_ref().unwrap();
+        let integral = &self.integral.as_ref().unwrap().buffer;
+        let bind_groups = [
+            (&scratch.work[0], &scratch.work[1]),
+            (&scratch.work[1], &scratch.work[0]),
+            (&scratch.work[0], &scratch.sum),
+            (&scratch.work[1], &scratch.sum),
+        ]
+        .map(|(src, dst)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("fft"),
+                layout: &self.layouts[&layout_key].0,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: images.input.clone(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: images.template.clone(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: images.result.clone(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: images.uniforms.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &params_buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(params_size),
+                        }),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: src.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: dst.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: scratch.kept.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: integral.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        encoder.clear_buffer(&scratch.sum, 0, wgpu::BufferSize::new(spectrum_size));
+        let mut steps = plan.steps.iter().peekable();
+        while let Some(step) = steps.peek() {
+            match step {
+                Step::Keep(work) => {
+                    encoder.copy_buffer_to_buffer(
+                        &scratch.work[*work],
+                        0,
+                        &scratch.kept,
+                        0,
+                        spectrum_size,
+                    );
+                    steps.next();
+                }
+                Step::Restart => {
+                    encoder.copy_buffer_to_buffer(
+                        &scratch.sum,
+                        0,
+                        &scratch.work[0],
+                        0,
+                        spectrum_size,
+                    );
+                    steps.next();
+                }
+                Step::Pass { .. } => {
+                    // Consecutive passes are recorded into the same compute pass.
+                    let mut compute_pass = encoder
+                        .begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("fft") });
+
+                    while let Some(&&Step::Pass {
+                        pass,
+                        bind_group,
+                        params,
+                        workgroups: (x, y),
+                    }) = steps.peek()
+                    {
+                        compute_pass.set_pipeline(&self.pipelines[&(shader_key, pass)]);
+                        compute_pass.set_bind_group(
+                            0,
+                            &bind_groups[bind_group],
+                            &[(params as u64 * params_stride) as u32],
+                        );
+                        compute_pass.dispatch_workgroups(x, y, 1);
+                        steps.next();
+                    }
+                }
+            }
+        }
+    }
+
+    fn create_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        shader_key: ShaderKey,
+        layout_key: LayoutKey,
+        pass: Pass,
+    ) {
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+            ..
+        } = self;
+
+        pipelines.entry((shader_key, pass)).or_insert_with(|| {
+            let shader = shaders.entry(shader_key).or_insert_with(|| {
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("fft"),
+                    source: wgpu::ShaderSource::Wgsl(shader_key.source().into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("fft"),
+                layout: Some(&layouts[&layout_key].1),
+                module: shader,
+                entry_point: pass.entry_point(),
+            })
+        });
+    }
+}
@@ -0,0 +1,160 @@
+//! Estimating the rotation and scale of a template at candidate windows of the input.
+
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::{
+    log_polar::{LogPolarSearch, ANGLES, MAX_RING_SHIFTS, RINGS},
+    MatchTemplateMethod,
+};
+
+/// Buffers that a log-polar search reads and writes.
+pub(crate) struct LogPolarImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub template: wgpu::BindingResource<'a>,
+    /// Receives the best score of each candidate window followed by its shift.
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Shader modules, layouts and pipelines of log-polar searches.
+pub(crate) struct LogPolarKernels {
+    shaders: HashMap<(Source, Source), wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts, by whether the input and the template are textures.
+    layouts: HashMap<(bool, bool), (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<(MatchTemplateMethod, Source, Source), wgpu::ComputePipeline>,
+}
+
+impl LogPolarKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("log_polar"),
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                            uniform_entry(4),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("log_polar"),
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    (input_texture, template_texture),
+                    (bind_group_layout, pipeline_layout),
+                );
+            }
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a compute pass that finds the best ring shift and angle of every candidate window
+    /// of the search.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (method, input, template): (MatchTemplateMethod, Source, Source),
+        images: LogPolarImages,
+        search: &LogPolarSearch,
+    ) {
+        let layout_key = (input.is_texture(), template.is_texture());
+
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+        } = self;
+
+        let pipeline = pipelines
+            .entry((method, input, template))
+            .or_insert_with(|| {
+                let shader = shaders.entry((input, template)).or_insert_with(|| {
+                    let mut source = format!(
+                        "const RINGS: u32 = {RINGS}u;\n\
+                         const ANGLES: u32 = {ANGLES}u;\n\
+                         const TEMPLATE_GRID_LEN: u32 = {}u;\n\
+                         const WINDOW_GRID_LEN: u32 = {}u;\n",
+                        RINGS * ANGLES,
+                        (RINGS + MAX_RING_SHIFTS - 1) * ANGLES,
+                    );
+                    source += &load_function(0, "input", input);
+                    source += &load_function(1, "template", template);
+                    source += include_str!("../../shaders/uniforms.wgsl");
+                    source += include_str!("../../shaders/log_polar.wgsl");
+
+                    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("log_polar"),
+                        source: wgpu::ShaderSource::Wgsl(source.into()),
+                    })
+                });
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("log_polar"),
+                    layout: Some(&layouts[&layout_key].1),
+                    module: shader,
+                    entry_point: match method {
+                        MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad_log_polar",
+                        MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd_log_polar",
+                    },
+                })
+            });
+
+        let search_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("log_polar_search"),
+            contents: bytemuck::bytes_of(search),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("log_polar"),
+            layout: &layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: search_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("log_polar"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(search.candidates_x, search.candidates_y, 1);
+    }
+}
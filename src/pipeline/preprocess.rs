@@ -0,0 +1,193 @@
+//! Preprocessing images before matching them.
+
+use std::{collections::HashMap, mem::size_of};
+
+use wgpu::util::DeviceExt;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::{ImageLayout, ShaderUniforms};
+
+/// Threads per workgroup of the preprocessing shader in each dimension.
+const WORKGROUP_SIZE: u32 = 16;
+
+/// Values that preprocessing passes keep for the following passes of their step, e.g. the mean
+/// and standard deviation of each channel.
+const STATISTICS_LEN: u64 = 1024;
+
+/// A compute pass of a preprocessing step.
+pub(crate) struct PreprocessPass {
+    /// Entry point of the preprocessing shader.
+    pub entry_point: &'static str,
+    /// Whether a single workgroup runs over the whole image, instead of one thread per pixel of
+    /// the output.
+    pub whole_image: bool,
+    /// Parameters of the step, as the shader reads them from its uniforms.
+    pub params: [f32; 4],
+}
+
+/// Buffers that a preprocessing step reads and writes.
+pub(crate) struct PreprocessImages<'a> {
+    /// The image to process.
+    pub source: wgpu::BindingResource<'a>,
+    /// Receives the processed image, tightly packed.
+    pub output: wgpu::BindingResource<'a>,
+    /// Values that the step keeps across calls, e.g. a running background, bound in place of the
+    /// statistics.
+    pub state: Option<wgpu::BindingResource<'a>>,
+}
+
+/// Shader modules, layouts and pipelines of preprocessing steps.
+pub(crate) struct PreprocessKernels {
+    shaders: HashMap<Source, wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts, by whether the source is a texture.
+    layouts: HashMap<bool, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    /// Pipelines by entry point and source.
+    pipelines: HashMap<(&'static str, Source), wgpu::ComputePipeline>,
+    statistics: wgpu::Buffer,
+}
+
+impl PreprocessKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for source_texture in [false, true] {
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("preprocess"),
+                    entries: &[
+                        image_entry(0, source_texture),
+                        storage_entry(2, false),
+                        uniform_entry(3),
+                        uniform_entry(4),
+                        storage_entry(5, false),
+                    ],
+                });
+
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("preprocess"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            layouts.insert(source_texture, (bind_group_layout, pipeline_layout));
+        }
+
+        let statistics = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("preprocess_statistics"),
+            size: STATISTICS_LEN * size_of::<f32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+            statistics,
+        }
+    }
+
+    /// Records a compute pass of a preprocessing step on an image of the source layout, producing
+    /// an image of the output layout.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pass: &PreprocessPass,
+        images: PreprocessImages,
+        (source, output): (&ImageLayout, &ImageLayout),
+    ) {
+        let layout_key = source.source.is_texture();
+
+        let entry_point = pass.entry_point;
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+            statistics,
+        } = self;
+
+        let pipeline = pipelines
+            .entry((entry_point, source.source))
+            .or_insert_with(|| {
+                let shader = shaders.entry(source.source).or_insert_with(|| {
+                    let mut code = load_function(0, "input", source.source);
+                    code += include_str!("../../shaders/uniforms.wgsl");
+                    code += include_str!("../../shaders/preprocess.wgsl");
+
+                    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("preprocess"),
+                        source: wgpu::ShaderSource::Wgsl(code.into()),
+                    })
+                });
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&layouts[&layout_key].1),
+                    module: shader,
+                    entry_point,
+                })
+            });
+
+        // Every step of a chain is recorded before any runs, so each gets its own uniforms. They
+        // give the number of channels of the source, which the output has too unless the step
+        // writes a single channel.
+        let uniforms = ShaderUniforms {
+            channels: source.channels,
+            ..ShaderUniforms::new(source, output)
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("preprocess_uniforms"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("preprocess_params"),
+            contents: bytemuck::cast_slice(&pass.params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("preprocess"),
+            layout: &layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.source,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.output,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: images
+                        .state
+                        .unwrap_or_else(|| statistics.as_entire_binding()),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("preprocess"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        if pass.whole_image {
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        } else {
+            compute_pass.dispatch_workgroups(
+                output.width.div_ceil(WORKGROUP_SIZE),
+                output.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Matching that stops scoring positions once they exceed the best score found so far.
+//!
+//! Every position is still visited, but most of them are abandoned after a few rows of the
+//! template when a good match is found early. Only the minimum of the result is exact.
+
+use std::collections::HashMap;
+
+use super::{
+    image_entry, load_function, storage_entry, template_size_function, uniform_entry, LayoutKey,
+    PipelineKey, Source,
+};
+use crate::MatchTemplateMethod;
+
+/// Identifies a pruned shader module variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderKey {
+    input: Source,
+    template: Source,
+    template_size: Option<(u32, u32)>,
+}
+
+impl ShaderKey {
+    fn source(&self) -> String {
+        let mut source = String::new();
+        source += &load_function(0, "input", self.input);
+        source += &load_function(1, "template", self.template);
+        source += &template_size_function(self.template_size);
+        source += include_str!("../../shaders/uniforms.wgsl");
+        source += include_str!("../../shaders/pruned.wgsl");
+        source
+    }
+}
+
+/// Buffers that a pruned match reads and writes.
+pub(crate) struct PrunedImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub template: wgpu::BindingResource<'a>,
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+    /// Holds the bit pattern of the bound, and the best score after the match.
+    pub best: &'a wgpu::Buffer,
+}
+
+/// Shader modules, layouts and pipelines of pruned matching.
+pub(crate) struct PrunedKernels {
+    shaders: HashMap<ShaderKey, wgpu::ShaderModule>,
+    layouts: HashMap<LayoutKey, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<PipelineKey, wgpu::ComputePipeline>,
+}
+
+impl PrunedKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("pruned"),
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                            storage_entry(4, false),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("pruned"),
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    LayoutKey {
+                        input_texture,
+                        template_texture,
+                    },
+                    (bind_group_layout, pipeline_layout),
+                );
+            }
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a compute pass that writes the scores of a `result_width` by `result_height`
+    /// result, giving up on positions that exceed the best score in `images.best`.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        key: PipelineKey,
+        images: PrunedImages,
+        (result_width, result_height): (u32, u32),
+    ) {
+        let layout_key = key.layout_key();
+
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+        } = self;
+
+        let pipeline = pipelines.entry(key).or_insert_with(|| {
+            let shader_key = ShaderKey {
+                input: key.input,
+                template: key.template,
+                template_size: key.template_size,
+            };
+            let shader = shaders.entry(shader_key).or_insert_with(|| {
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("pruned"),
+                    source: wgpu::ShaderSource::Wgsl(shader_key.source().into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("pruned"),
+                layout: Some(&layouts[&layout_key].1),
+                module: shader,
+                entry_point: match key.method {
+                    MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad_pruned",
+                    MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd_pruned",
+                },
+            })
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pruned"),
+            layout: &layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: images.best.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("pruned"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(result_width.div_ceil(16), result_height.div_ceil(16), 1);
+    }
+}
@@ -0,0 +1,120 @@
+//! Matching of images quantized to `u8` with integer arithmetic.
+
+use std::collections::HashMap;
+
+use super::{storage_entry, uniform_entry};
+use crate::MatchTemplateMethod;
+
+/// Buffers that a quantized match reads and writes. Both images must be uploaded as `u8` samples
+/// into storage buffers.
+pub(crate) struct QuantizedImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub template: wgpu::BindingResource<'a>,
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Shader module, layouts and pipelines of quantized matching.
+pub(crate) struct QuantizedKernels {
+    shader: Option<wgpu::ShaderModule>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<MatchTemplateMethod, wgpu::ComputePipeline>,
+}
+
+impl QuantizedKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("quantized"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, true),
+                storage_entry(2, false),
+                uniform_entry(3),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("quantized"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader: None,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a compute pass that writes the scores of a `result_width` by `result_height`
+    /// result.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        method: MatchTemplateMethod,
+        images: QuantizedImages,
+        (result_width, result_height): (u32, u32),
+    ) {
+        let Self {
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines,
+        } = self;
+
+        let pipeline = pipelines.entry(method).or_insert_with(|| {
+            let shader = shader.get_or_insert_with(|| {
+                let mut source = include_str!("../../shaders/uniforms.wgsl").to_string();
+                source += include_str!("../../shaders/quantized.wgsl");
+
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("quantized"),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("quantized"),
+                layout: Some(pipeline_layout),
+                module: shader,
+                entry_point: match method {
+                    MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad_quantized",
+                    MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd_quantized",
+                },
+            })
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("quantized"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("quantized"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(result_width.div_ceil(16), result_height.div_ceil(16), 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! Refining a match into an affine alignment of the template with the input.
+
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::refine::Refinement;
+
+/// Buffers that a refinement reads and writes.
+pub(crate) struct RefineImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub template: wgpu::BindingResource<'a>,
+    /// Receives the affine parameters, the score and the number of steps taken.
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Shader modules, layouts and pipelines of refinements.
+pub(crate) struct RefineKernels {
+    shaders: HashMap<(Source, Source), wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts, by whether the input and the template are textures.
+    layouts: HashMap<(bool, bool), (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<(Source, Source), wgpu::ComputePipeline>,
+}
+
+impl RefineKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            for template_texture in [false, true] {
+                let bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("refine"),
+                        entries: &[
+                            image_entry(0, input_texture),
+                            image_entry(1, template_texture),
+                            storage_entry(2, false),
+                            uniform_entry(3),
+                            uniform_entry(4),
+                        ],
+                    });
+
+                let pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("refine"),
+                        bind_group_layouts: &[&bind_group_layout],
+                        push_constant_ranges: &[],
+                    });
+
+                layouts.insert(
+                    (input_texture, template_texture),
+                    (bind_group_layout, pipeline_layout),
+                );
+            }
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a compute pass that runs the refinement.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (input, template): (Source, Source),
+        images: RefineImages,
+        refinement: &Refinement,
+    ) {
+        let layout_key = (input.is_texture(), template.is_texture());
+
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+        } = self;
+
+        let pipeline = pipelines.entry((input, template)).or_insert_with(|| {
+            let shader = shaders.entry((input, template)).or_insert_with(|| {
+                let mut source = load_function(0, "input", input);
+                source += &load_function(1, "template", template);
+                source += include_str!("../../shaders/uniforms.wgsl");
+                source += include_str!("../../shaders/refine.wgsl");
+
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("refine"),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("refine"),
+                layout: Some(&layouts[&layout_key].1),
+                module: shader,
+                entry_point: "main_refine",
+            })
+        });
+
+        let refinement_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("refinement"),
+            contents: bytemuck::bytes_of(refinement),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("refine"),
+            layout: &layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: refinement_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("refine"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! Matching with templates of which only some pixels are matched.
+
+use std::collections::HashMap;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::MatchTemplateMethod;
+
+/// Buffers that a sparse match reads and writes.
+pub(crate) struct SparseImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    /// Matched pixels of the template, as packed by
+    /// [SparseTemplate::packed](crate::SparseTemplate::packed).
+    pub pixels: &'a wgpu::Buffer,
+    pub result: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+}
+
+/// Shader modules, layouts and pipelines of sparse matching.
+pub(crate) struct SparseKernels {
+    shaders: HashMap<Source, wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts, by whether the input is a texture.
+    layouts: HashMap<bool, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<(MatchTemplateMethod, Source), wgpu::ComputePipeline>,
+}
+
+impl SparseKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut layouts = HashMap::new();
+        for input_texture in [false, true] {
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("sparse"),
+                    entries: &[
+                        image_entry(0, input_texture),
+                        storage_entry(1, true),
+                        storage_entry(2, false),
+                        uniform_entry(3),
+                    ],
+                });
+
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("sparse"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            layouts.insert(input_texture, (bind_group_layout, pipeline_layout));
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a compute pass that writes the scores of a `result_width` by `result_height`
+    /// result.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (method, input): (MatchTemplateMethod, Source),
+        images: SparseImages,
+        (result_width, result_height): (u32, u32),
+    ) {
+        let layout_key = input.is_texture();
+
+        let Self {
+            shaders,
+            layouts,
+            pipelines,
+        } = self;
+
+        let pipeline = pipelines.entry((method, input)).or_insert_with(|| {
+            let shader = shaders.entry(input).or_insert_with(|| {
+                let mut source = load_function(0, "input", input);
+                source += include_str!("../../shaders/uniforms.wgsl");
+                source += include_str!("../../shaders/sparse.wgsl");
+
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("sparse"),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                })
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("sparse"),
+                layout: Some(&layouts[&layout_key].1),
+                module: shader,
+                entry_point: match method {
+                    MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad_sparse",
+                    MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd_sparse",
+                },
+            })
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sparse"),
+            layout: &layouts[&layout_key].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.pixels.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("sparse"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(result_width.div_ceil(16), result_height.div_ceil(16), 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Matching with rotated, scaled and mirrored copies of a template.
+
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use super::{image_entry, load_function, storage_entry, uniform_entry, Source};
+use crate::{MatchTemplateMethod, SampleFormat};
+
+const RENDER_ENTRY_POINT: &str = "transform_template";
+
+/// Buffers that rendering the variants of a template reads and writes.
+pub(crate) struct RenderImages<'a> {
+    pub template: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+    /// Variants of the template, as packed by [Variant::packed](crate::transform::Variant::packed).
+    pub variants: &'a wgpu::Buffer,
+    /// Positions of the matched pixels of all variants.
+    pub offsets: &'a wgpu::Buffer,
+    /// Receives the samples of the matched pixels of all variants.
+    pub samples: &'a wgpu::Buffer,
+}
+
+/// Buffers that matching rendered variants reads and writes.
+pub(crate) struct TransformedImages<'a> {
+    pub input: wgpu::BindingResource<'a>,
+    pub uniforms: &'a wgpu::Buffer,
+    pub variants: &'a wgpu::Buffer,
+    pub offsets: &'a wgpu::Buffer,
+    /// Samples of the variants, as rendered by [TransformedKernels::encode_render].
+    pub samples: &'a wgpu::Buffer,
+    /// Receives the best scores followed by the indices of their variants.
+    pub result: wgpu::BindingResource<'a>,
+}
+
+/// Shader modules, layouts and pipelines of transformed matching.
+pub(crate) struct TransformedKernels {
+    shaders: HashMap<(Source, Source), wgpu::ShaderModule>,
+    /// Bind group and pipeline layouts of rendering, by whether the template is a texture.
+    render_layouts: HashMap<bool, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    /// Bind group and pipeline layouts of matching, by whether the input is a texture.
+    match_layouts: HashMap<bool, (wgpu::BindGroupLayout, wgpu::PipelineLayout)>,
+    pipelines: HashMap<(&'static str, Source, Source), wgpu::ComputePipeline>,
+}
+
+fn layout(
+    device: &wgpu::Device,
+    entries: &[wgpu::BindGroupLayoutEntry],
+) -> (wgpu::BindGroupLayout, wgpu::PipelineLayout) {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("transformed"),
+        entries,
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("transformed"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    (bind_group_layout, pipeline_layout)
+}
+
+impl TransformedKernels {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mut render_layouts = HashMap::new();
+        let mut match_layouts = HashMap::new();
+        for texture in [false, true] {
+            let render_entries = [
+                image_entry(1, texture),
+                uniform_entry(3),
+                storage_entry(4, true),
+                storage_entry(5, true),
+                storage_entry(6, false),
+            ];
+            render_layouts.insert(texture, layout(device, &render_entries));
+
+            let match_entries = [
+                image_entry(0, texture),
+                storage_entry(2, false),
+                uniform_entry(3),
+                storage_entry(4, true),
+                storage_entry(5, true),
+                storage_entry(6, false),
+                uniform_entry(7),
+            ];
+            match_layouts.insert(texture, layout(device, &match_entries));
+        }
+
+        Self {
+            shaders: HashMap::new(),
+            render_layouts,
+            match_layouts,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Returns the pipeline of the given entry point, creating it on first use. Rendering uses
+    /// only the template and matching only the input, but both share a shader module.
+    fn pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        entry_point: &'static str,
+        (input, template): (Source, Source),
+    ) -> &wgpu::ComputePipeline {
+        let Self {
+            shaders,
+            render_layouts,
+            match_layouts,
+            pipelines,
+        } = self;
+
+        pipelines
+            .entry((entry_point, input, template))
+            .or_insert_with(|| {
+                let shader = shaders.entry((input, template)).or_insert_with(|| {
+                    let mut source = load_function(0, "input", input);
+                    source += &load_function(1, "template", template);
+                    source += include_str!("../../shaders/uniforms.wgsl");
+                    source += include_str!("../../shaders/transformed.wgsl");
+
+                    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("transformed"),
+                        source: wgpu::ShaderSource::Wgsl(source.into()),
+                    })
+                });
+
+                let layout = if entry_point == RENDER_ENTRY_POINT {
+                    &render_layouts[&template.is_texture()].1
+                } else {
+                    &match_layouts[&input.is_texture()].1
+                };
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("transformed"),
+                    layout: Some(layout),
+                    module: shader,
+                    entry_point,
+                })
+            })
+    }
+
+    /// Records a compute pass that renders `variant_count` variants of the template, of which
+    /// the largest has `max_pixel_count` pixels.
+    pub fn encode_render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        template: Source,
+        images: RenderImages,
+        (variant_count, max_pixel_count): (u32, u32),
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("transformed_render"),
+            layout: &self.render_layouts[&template.is_texture()].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: images.template,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: images.variants.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: images.offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: images.samples.as_entire_binding(),
+                },
+            ],
+        });
+
+        // Rendering doesn't read the input, so any source does for the module.
+        let sources = (Source::Buffer(SampleFormat::F32), template);
+        let pipeline = self.pipeline(device, RENDER_ENTRY_POINT, sources);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("transformed_render"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(max_pixel_count.div_ceil(64), variant_count, 1);
+    }
+
+    /// Records a compute pass that writes the best scores of rendered variants over a
+    /// `result_width` by `result_height` result and the indices of their variants.
+    pub fn encode_match(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (method, input): (MatchTemplateMethod, Source),
+        images: TransformedImages,
+        (result_width, result_height): (u32, u32),
+    ) {
+        let search = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("transformed_search"),
+            contents: bytemuck::cast_slice(&[result_width, result_height, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("transformed_match"),
+            layout: &self.match_layouts[&input.is_texture()].0,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: images.input,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: images.result,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: images.uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: images.variants.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: images.offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: images.samples.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: search.as_entire_binding(),
+                },
+            ],
+        });
+
+        let entry_point = match method {
+            MatchTemplateMethod::SumOfAbsoluteDifferences => "main_sad_transformed",
+            MatchTemplateMethod::SumOfSquaredDifferences => "main_ssd_transformed",
+        };
+        // Matching doesn't read the template, so any source does for the module.
+        let sources = (input, Source::Buffer(SampleFormat::F32));
+        let pipeline = self.pipeline(device, entry_point, sources);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("transformed_match"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(result_width.div_ceil(16), result_height.div_ceil(16), 1);
+    }
+}
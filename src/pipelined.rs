@@ -0,0 +1,128 @@
+//! Matching a stream of frames with several matches in flight.
+
+use std::collections::VecDeque;
+
+use crate::{Error, Image, MatchJob, MatchTemplateMethod, Sample, TemplateMatcher};
+
+/// Keeps a fixed number of matches in flight, so that the upload and matching of the next frame
+/// overlap with reading back the result of the previous one, instead of each frame waiting for
+/// the one before it to be read back.
+///
+/// Each [submit](Self::submit) starts a match and returns the result of the oldest one once the
+/// pipeline is full, so results arrive `depth - 1` frames late. Frames can change size from one
+/// submit to the next, and each result has the size of its own frame's.
+pub struct PipelinedMatcher {
+    matcher: TemplateMatcher,
+    depth: usize,
+    in_flight: VecDeque<MatchJob>,
+}
+
+impl PipelinedMatcher {
+    /// Creates a double-buffered pipeline, which returns the result of the previous frame on each
+    /// submit.
+    pub fn new(matcher: TemplateMatcher) -> Self {
+        Self::with_depth(matcher, 2)
+    }
+
+    /// Creates a pipeline that keeps up to `depth` matches in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is zero.
+    pub fn with_depth(matcher: TemplateMatcher, depth: usize) -> Self {
+        assert!(depth > 0, "pipeline depth must be at least one");
+
+        Self {
+            matcher,
+            depth,
+            in_flight: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// Starts matching the template against the next frame, and returns the result of the oldest
+    /// frame in flight if the pipeline is full.
+    ///
+    /// Returns the errors of [try_match_template](TemplateMatcher::try_match_template) without
+    /// starting a match if the frame can't be matched, and an error if the GPU rejected the match
+    /// of the oldest frame or its result can't be read back. That frame leaves the pipeline either
+    /// way.
+    pub fn submit<'a, I: Sample, T: Sample>(
+        &mut self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Option<Image<'static>>, Error> {
+        let job = self.matcher.try_match_template(input, template, method)?;
+        self.in_flight.push_back(job);
+
+        if self.in_flight.len() < self.depth {
+            return Ok(None);
+        }
+
+        self.wait_for_oldest()
+    }
+
+    /// Waits for the results of all frames in flight, oldest first.
+    ///
+    /// Returns the first error of reading back a result, in which case the frames after the
+    /// failed one are left in flight for the next flush.
+    pub fn flush(&mut self) -> Result<Vec<Image<'static>>, Error> {
+        std::iter::from_fn(|| self.wait_for_oldest().transpose()).collect()
+    }
+
+    /// Number of frames whose results haven't been returned yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn matcher(&self) -> &TemplateMatcher {
+        &self.matcher
+    }
+
+    /// Returns the matcher, discarding the results of the frames in flight.
+    pub fn into_inner(mut self) -> TemplateMatcher {
+        for job in self.in_flight.drain(..) {
+            self.matcher.cancel(job);
+        }
+        self.matcher
+    }
+
+    fn wait_for_oldest(&mut self) -> Result<Option<Image<'static>>, Error> {
+        match self.in_flight.pop_front() {
+            Some(job) => self.matcher.try_wait_for_job(job),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_frames_without_starting_them() {
+        let mut pipeline = PipelinedMatcher::new(TemplateMatcher::new_cpu());
+        let template = Image::new(vec![0.5; 4], 2, 2);
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+
+        let frame = Image::new(vec![0.5; 16], 4, 4);
+        assert!(pipeline
+            .submit(&frame, &template, method)
+            .unwrap()
+            .is_none());
+
+        let rgb = Image::with_channels(vec![0.5; 48], 4, 4, 3);
+        assert_eq!(
+            pipeline.submit(&rgb, &template, method).unwrap_err(),
+            Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+        assert_eq!(pipeline.in_flight(), 1);
+
+        let results = pipeline.flush().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(*results[0].data, [0.0; 9]);
+    }
+}
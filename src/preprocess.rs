@@ -0,0 +1,482 @@
+//! Preprocessing images before matching them.
+
+use crate::{packed_samples, pipeline::PreprocessPass, scale, Image, Sample};
+
+/// A step that transforms an image before it is matched, run as compute passes on the GPU engine
+/// by [set_input_preprocessed](crate::TemplateMatcher::set_input_preprocessed) and
+/// [preprocess](crate::TemplateMatcher::preprocess).
+///
+/// Steps that change the values of an image should usually be run on the template as well, with
+/// [preprocess](crate::TemplateMatcher::preprocess).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Preprocess {
+    /// Converts an RGB or RGBA image to single-channel luma, weighing the color channels with the
+    /// Rec. 601 coefficients and ignoring alpha. Single-channel images are kept as they are.
+    Grayscale,
+    /// Scales the image by `factor` in both dimensions, rounded to whole pixels. Shrinking averages
+    /// the pixels that each new pixel covers, and enlarging samples the image bilinearly.
+    Resize { factor: f32 },
+    /// Shifts and scales each channel to zero mean and unit standard deviation over the whole
+    /// image. Channels without any variation become zero. This makes the sums of absolute or
+    /// squared differences robust to changes in brightness and contrast, like the normalized
+    /// methods, without changing how they are computed.
+    Normalize,
+    /// Like [Normalize](Preprocess::Normalize), but normalizes each pixel with the mean and
+    /// standard deviation of the `(2 * radius + 1)²` pixels around it, clamped to the image, so
+    /// that lighting that varies across the image is evened out.
+    NormalizeLocal { radius: u32 },
+    /// Spreads the values of each channel evenly over `[0, 1]` by mapping them through the
+    /// cumulative histogram of the channel, which raises the contrast of low-contrast images.
+    /// Values are binned into 256 bins over `[0, 1]`, with values outside it clamped, so it suits
+    /// `u8` images and `f32` images of normalized values. Channels without any variation become
+    /// zero.
+    EqualizeHistogram,
+    /// Replaces each sample with the magnitude of the gradient of its channel, from 3×3 Sobel
+    /// filters with the image's edges extended. Matching gradients instead of intensities is
+    /// robust to changes in lighting that keep the edges in place.
+    Sobel,
+    /// Maps each sample `v` to `gain * v^gamma + offset`, with negative samples raised as zero.
+    /// A gamma of about 2.2 brings values encoded in sRGB, like most template assets, close to
+    /// linear light, and a gamma of about 1 / 2.2 does the opposite.
+    Tone { gamma: f32, gain: f32, offset: f32 },
+    /// Smooths the image with a Gaussian filter of standard deviation `sigma`, cut off at three
+    /// standard deviations, with the image's edges extended.
+    Blur { sigma: f32 },
+    /// Subtracts a running average of the images that the matcher has preprocessed with this step,
+    /// leaving the foreground, e.g. objects moving in front of a fixed camera. After each image the
+    /// background moves towards it by `rate`, between 0 and 1, so that changes that persist fade
+    /// into it. The first image, and the first after the size changes or
+    /// [reset_background](crate::TemplateMatcher::reset_background), only starts the background
+    /// and leaves zeros. On the GPU engine the background stays on the device.
+    SubtractBackground { rate: f32 },
+}
+
+/// Builds a chain of preprocessing steps, e.g.
+/// `PreprocessPipeline::new().grayscale().resize(0.5).blur(1.0).sobel()`, which dereferences to
+/// the slice of its steps for [set_input_preprocessed](crate::TemplateMatcher::set_input_preprocessed)
+/// and [preprocess](crate::TemplateMatcher::preprocess).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreprocessPipeline {
+    steps: Vec<Preprocess>,
+}
+
+impl PreprocessPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a step to the chain.
+    pub fn then(mut self, step: Preprocess) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Appends [Preprocess::Grayscale].
+    pub fn grayscale(self) -> Self {
+        self.then(Preprocess::Grayscale)
+    }
+
+    /// Appends [Preprocess::Resize].
+    pub fn resize(self, factor: f32) -> Self {
+        self.then(Preprocess::Resize { factor })
+    }
+
+    /// Appends [Preprocess::Normalize].
+    pub fn normalize(self) -> Self {
+        self.then(Preprocess::Normalize)
+    }
+
+    /// Appends [Preprocess::NormalizeLocal].
+    pub fn normalize_local(self, radius: u32) -> Self {
+        self.then(Preprocess::NormalizeLocal { radius })
+    }
+
+    /// Appends [Preprocess::EqualizeHistogram].
+    pub fn equalize_histogram(self) -> Self {
+        self.then(Preprocess::EqualizeHistogram)
+    }
+
+    /// Appends [Preprocess::Sobel].
+    pub fn sobel(self) -> Self {
+        self.then(Preprocess::Sobel)
+    }
+
+    /// Appends [Preprocess::Tone].
+    pub fn tone(self, gamma: f32, gain: f32, offset: f32) -> Self {
+        self.then(Preprocess::Tone {
+            gamma,
+            gain,
+            offset,
+        })
+    }
+
+    /// Appends [Preprocess::Tone] with the given gamma, without gain or offset.
+    pub fn gamma(self, gamma: f32) -> Self {
+        self.then(Preprocess::gamma(gamma))
+    }
+
+    /// Appends [Preprocess::Blur].
+    pub fn blur(self, sigma: f32) -> Self {
+        self.then(Preprocess::Blur { sigma })
+    }
+
+    pub fn steps(&self) -> &[Preprocess] {
+        &self.steps
+    }
+}
+
+impl std::ops::Deref for PreprocessPipeline {
+    type Target = [Preprocess];
+
+    fn deref(&self) -> &Self::Target {
+        &self.steps
+    }
+}
+
+impl From<Vec<Preprocess>> for PreprocessPipeline {
+    fn from(steps: Vec<Preprocess>) -> Self {
+        Self { steps }
+    }
+}
+
+/// Number of histogram bins of [Preprocess::EqualizeHistogram], which spread `[0, 1]` evenly.
+const HISTOGRAM_BINS: usize = 256;
+
+impl Preprocess {
+    /// [Tone](Preprocess::Tone) with the given gamma, without gain or offset.
+    pub fn gamma(gamma: f32) -> Self {
+        Preprocess::Tone {
+            gamma,
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /// Size and number of channels of the image that the step produces from an image of the given
+    /// size and number of channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the step's parameters are out of range, or if it can't process images with the
+    /// given number of channels.
+    pub(crate) fn output_size(
+        &self,
+        (width, height, channels): (u32, u32, u32),
+    ) -> (u32, u32, u32) {
+        match *self {
+            Preprocess::Grayscale => {
+                assert!(
+                    matches!(channels, 1 | 3 | 4),
+                    "grayscale conversion needs one, three or four channels"
+                );
+                (width, height, 1)
+            }
+            Preprocess::Resize { factor } => {
+                assert!(factor > 0.0, "resize factor must be positive");
+                let (width, height) = scale::scaled_size(width, height, (factor, factor));
+                (width, height, channels)
+            }
+            Preprocess::Normalize
+            | Preprocess::NormalizeLocal { .. }
+            | Preprocess::EqualizeHistogram
+            | Preprocess::Sobel => (width, height, channels),
+            Preprocess::Tone { gamma, .. } => {
+                assert!(gamma > 0.0, "gamma must be positive");
+                (width, height, channels)
+            }
+            Preprocess::Blur { sigma } => {
+                assert!(sigma > 0.0, "blur sigma must be positive");
+                (width, height, channels)
+            }
+            Preprocess::SubtractBackground { rate } => {
+                assert!(
+                    rate > 0.0 && rate <= 1.0,
+                    "background rate must be in (0, 1]"
+                );
+                (width, height, channels)
+            }
+        }
+    }
+
+    /// Compute passes of the preprocessing shader that run the step. `start_background` tells
+    /// [SubtractBackground](Preprocess::SubtractBackground) to start the background from the image.
+    pub(crate) fn passes(&self, start_background: bool) -> Vec<PreprocessPass> {
+        let pass = |entry_point, params| PreprocessPass {
+            entry_point,
+            whole_image: false,
+            params,
+        };
+
+        match *self {
+            Preprocess::Grayscale => vec![pass("main_grayscale", [0.0; 4])],
+            Preprocess::Resize { factor } if factor < 1.0 => {
+                vec![pass("main_resize_area", [0.0; 4])]
+            }
+            Preprocess::Resize { .. } => vec![pass("main_resize_bilinear", [0.0; 4])],
+            Preprocess::Normalize => vec![
+                PreprocessPass {
+                    whole_image: true,
+                    ..pass("main_normalize_statistics", [0.0; 4])
+                },
+                pass("main_normalize", [0.0; 4]),
+            ],
+            Preprocess::NormalizeLocal { radius } => {
+                vec![pass("main_normalize_local", [radius as f32, 0.0, 0.0, 0.0])]
+            }
+            Preprocess::EqualizeHistogram => vec![
+                PreprocessPass {
+                    whole_image: true,
+                    ..pass("main_equalize_histogram", [0.0; 4])
+                },
+                pass("main_map_histogram", [0.0; 4]),
+            ],
+            Preprocess::Sobel => vec![pass("main_sobel", [0.0; 4])],
+            Preprocess::Tone {
+                gamma,
+                gain,
+                offset,
+            } => vec![pass("main_tone", [gamma, gain, offset, 0.0])],
+            Preprocess::Blur { sigma } => vec![pass("main_blur", [sigma, 0.0, 0.0, 0.0])],
+            Preprocess::SubtractBackground { rate } => {
+                let start = if start_background { 1.0 } else { 0.0 };
+                vec![pass("main_subtract_background", [rate, start, 0.0, 0.0])]
+            }
+        }
+    }
+
+    /// Runs the step on the calling thread, like its shader does.
+    fn apply(&self, image: &Image<'_>, background: &mut Option<Image<'static>>) -> Image<'static> {
+        let (width, height, _) = self.output_size((image.width, image.height, image.channels));
+        match *self {
+            Preprocess::Grayscale if image.channels == 1 => {
+                Image::new(image.data.to_vec(), width, height)
+            }
+            Preprocess::Grayscale => {
+                let data = image
+                    .data
+                    .chunks_exact(image.channels as usize)
+                    .map(|color| 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2])
+                    .collect::<Vec<_>>();
+                Image::new(data, width, height)
+            }
+            Preprocess::Resize { factor } if factor < 1.0 => {
+                scale::resize_area(image, width, height)
+            }
+            Preprocess::Resize { .. } => scale::resize(image, width, height),
+            Preprocess::Normalize => {
+                let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)));
+                let statistics = (0..image.channels as usize)
+                    .map(|c| statistics(image, c, pixels.clone()))
+                    .collect::<Vec<_>>();
+                normalize(image, |_, _, c| statistics[c])
+            }
+            Preprocess::NormalizeLocal { radius } => normalize(image, |x, y, c| {
+                let (left, right) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+                let (top, bottom) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+                let window = (top..=bottom).flat_map(|y| (left..=right).map(move |x| (x, y)));
+                statistics(image, c, window)
+            }),
+            Preprocess::EqualizeHistogram => equalize_histogram(image),
+            Preprocess::Sobel => sobel(image),
+            Preprocess::Tone {
+                gamma,
+                gain,
+                offset,
+            } => {
+                let data = image
+                    .data
+                    .iter()
+                    .map(|&value| gain * value.max(0.0).powf(gamma) + offset)
+                    .collect::<Vec<_>>();
+                Image::with_channels(data, width, height, image.channels)
+            }
+            Preprocess::Blur { sigma } => blur(image, sigma),
+            Preprocess::SubtractBackground { rate } => subtract_background(image, rate, background),
+        }
+    }
+}
+
+/// Runs the steps on the image in order, on the calling thread, with the running background of
+/// [Preprocess::SubtractBackground].
+pub(crate) fn apply<T: Sample>(
+    image: &Image<'_, T>,
+    steps: &[Preprocess],
+    background: &mut Option<Image<'static>>,
+) -> Image<'static> {
+    image.assert_valid("image");
+
+    let samples = packed_samples(image)
+        .map(Sample::to_f32)
+        .collect::<Vec<_>>();
+    let image = Image::with_channels(samples, image.width, image.height, image.channels);
+    steps
+        .iter()
+        .fold(image, |image, step| step.apply(&image, background))
+}
+
+/// Mean and standard deviation of a channel over the given pixels.
+fn statistics(
+    image: &Image<'_>,
+    c: usize,
+    pixels: impl Iterator<Item = (u32, u32)> + Clone,
+) -> (f32, f32) {
+    let sample = |(x, y): (u32, u32)| {
+        image.data[(y * image.width + x) as usize * image.channels as usize + c]
+    };
+
+    let count = pixels.clone().count() as f32;
+    let mean = pixels.clone().map(sample).sum::<f32>() / count;
+    let variance = pixels.map(|p| (sample(p) - mean).powi(2)).sum::<f32>() / count;
+    (mean, variance.sqrt())
+}
+
+/// Normalizes each sample with the mean and standard deviation that `statistics` returns for its
+/// position and channel.
+fn normalize(
+    image: &Image<'_>,
+    statistics: impl Fn(u32, u32, usize) -> (f32, f32),
+) -> Image<'static> {
+    let channels = image.channels as usize;
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..image.height {
+        for x in 0..image.width {
+            for c in 0..channels {
+                let (mean, deviation) = statistics(x, y, c);
+                let sample = image.data[(y * image.width + x) as usize * channels + c];
+                data.push(if deviation > 0.0 {
+                    (sample - mean) / deviation
+                } else {
+                    0.0
+                });
+            }
+        }
+    }
+
+    Image::with_channels(data, image.width, image.height, image.channels)
+}
+
+/// Histogram bin of a value.
+fn bin(value: f32) -> usize {
+    ((value.clamp(0.0, 1.0) * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+/// Maps each sample through the cumulative histogram of its channel, so that the first occupied
+/// bin maps to zero and the last to one.
+fn equalize_histogram(image: &Image<'_>) -> Image<'static> {
+    let channels = image.channels as usize;
+    let mut data = image.data.to_vec();
+
+    for c in 0..channels {
+        let mut cumulative = [0u32; HISTOGRAM_BINS];
+        for sample in data.iter().skip(c).step_by(channels) {
+            cumulative[bin(*sample)] += 1;
+        }
+        for i in 1..HISTOGRAM_BINS {
+            cumulative[i] += cumulative[i - 1];
+        }
+
+        let first = *cumulative.iter().find(|&&count| count > 0).unwrap_or(&0);
+        let range = (cumulative[HISTOGRAM_BINS - 1] - first) as f32;
+        for sample in data.iter_mut().skip(c).step_by(channels) {
+            *sample = if range > 0.0 {
+                (cumulative[bin(*sample)] - first) as f32 / range
+            } else {
+                0.0
+            };
+        }
+    }
+
+    Image::with_channels(data, image.width, image.height, image.channels)
+}
+
+/// Replaces each sample with the magnitude of its channel's Sobel gradient.
+fn sobel(image: &Image<'_>) -> Image<'static> {
+    let channels = image.channels as usize;
+    let (width, height) = (image.width as i64, image.height as i64);
+    let sample = |x: i64, y: i64, c: usize| {
+        let (x, y) = (x.clamp(0, width - 1), y.clamp(0, height - 1));
+        image.data[(y * width + x) as usize * channels + c]
+    };
+
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let gx =
+                    sample(x + 1, y - 1, c) + 2.0 * sample(x + 1, y, c) + sample(x + 1, y + 1, c)
+                        - sample(x - 1, y - 1, c)
+                        - 2.0 * sample(x - 1, y, c)
+                        - sample(x - 1, y + 1, c);
+                let gy =
+                    sample(x - 1, y + 1, c) + 2.0 * sample(x, y + 1, c) + sample(x + 1, y + 1, c)
+                        - sample(x - 1, y - 1, c)
+                        - 2.0 * sample(x, y - 1, c)
+                        - sample(x + 1, y - 1, c);
+                data.push((gx * gx + gy * gy).sqrt());
+            }
+        }
+    }
+
+    Image::with_channels(data, image.width, image.height, image.channels)
+}
+
+/// Smooths the image with a Gaussian filter, like the blur shader does.
+fn blur(image: &Image<'_>, sigma: f32) -> Image<'static> {
+    let channels = image.channels as usize;
+    let (width, height) = (image.width as i64, image.height as i64);
+    let radius = (3.0 * sigma).ceil() as i64;
+    let weights = (-radius..=radius)
+        .map(|d| (-(d * d) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f32>().powi(2);
+
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let mut sum = 0.0;
+                for (dy, wy) in (-radius..=radius).zip(&weights) {
+                    let sy = (y + dy).clamp(0, height - 1);
+                    for (dx, wx) in (-radius..=radius).zip(&weights) {
+                        let sx = (x + dx).clamp(0, width - 1);
+                        sum += wx * wy * image.data[(sy * width + sx) as usize * channels + c];
+                    }
+                }
+                data.push(sum / total);
+            }
+        }
+    }
+
+    Image::with_channels(data, image.width, image.height, image.channels)
+}
+
+/// Subtracts the background from the image and moves the background towards it, like the
+/// background shader does. The background starts from the image if there is none of its size.
+fn subtract_background(
+    image: &Image<'_>,
+    rate: f32,
+    background: &mut Option<Image<'static>>,
+) -> Image<'static> {
+    let shape = |image: &Image<'_>| (image.width, image.height, image.channels);
+    let background = match background {
+        Some(background) if shape(background) == shape(image) => background,
+        _ => background.insert(Image::with_channels(
+            image.data.to_vec(),
+            image.width,
+            image.height,
+            image.channels,
+        )),
+    };
+
+    let data = image
+        .data
+        .iter()
+        .zip(background.data.to_mut())
+        .map(|(&value, background)| {
+            let foreground = value - *background;
+            *background += rate * foreground;
+            foreground
+        })
+        .collect::<Vec<_>>();
+    Image::with_channels(data, image.width, image.height, image.channels)
+}
@@ -0,0 +1,61 @@
+//! Templates stored at several resolutions, for inputs whose resolution varies.
+
+use crate::{bank::TemplateBank, Image};
+
+/// A template stored at several scales by
+/// [create_pyramid](crate::TemplateMatcher::create_pyramid), of which
+/// [match_pyramid](crate::TemplateMatcher::match_pyramid) matches the one that suits the scale of
+/// each input. With the GPU engine the levels stay on the device.
+///
+/// A pyramid can only be matched by the matcher that created it.
+pub struct TemplatePyramid {
+    /// Scale of each level, and its only variant.
+    pub(crate) levels: Vec<(f32, TemplateBank)>,
+}
+
+/// Result of [match_pyramid](crate::TemplateMatcher::match_pyramid).
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PyramidMatch {
+    /// Index of the matched level, in the order of the scales the pyramid was created with.
+    pub level: usize,
+    /// Scale of the matched level.
+    pub scale: f32,
+    pub scores: Image<'static>,
+}
+
+impl TemplatePyramid {
+    /// Number of levels.
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// Scales of the levels, in the order the pyramid was created with.
+    pub fn scales(&self) -> impl Iterator<Item = f32> + '_ {
+        self.levels.iter().map(|(scale, _)| *scale)
+    }
+
+    /// Size of the given level.
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        let variant = &self.levels[level].1.variants[0];
+        (variant.width, variant.height)
+    }
+
+    /// Index of the level whose scale is closest to the given one by ratio, among those that fit
+    /// in an input of the given size.
+    pub(crate) fn level_for(&self, scale: f32, (width, height): (u32, u32)) -> Option<usize> {
+        (0..self.levels.len())
+            .filter(|&level| {
+                let (level_width, level_height) = self.level_size(level);
+                level_width <= width && level_height <= height
+            })
+            .min_by(|&a, &b| {
+                let distance = |level: usize| (self.levels[level].0 / scale).ln().abs();
+                distance(a).total_cmp(&distance(b))
+            })
+    }
+}
@@ -0,0 +1,212 @@
+//! Refining matches into sub-pixel affine alignments with Lucas–Kanade style Gauss–Newton steps.
+
+use crate::Image;
+
+/// Parameters of an affine refinement, in the layout of the refine shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct Refinement {
+    /// Position of the template's top-left corner in the input to start from.
+    pub x: f32,
+    pub y: f32,
+    pub max_iterations: u32,
+    padding: u32,
+}
+
+/// Values that a refinement writes: the affine parameters, the final score and the number of
+/// steps taken.
+pub(crate) const RESULT_LEN: usize = 8;
+
+/// Steps that move every pixel of the template less than this many pixels end the refinement.
+const CONVERGED: f32 = 0.01;
+
+/// Result of [refine_match](crate::TemplateMatcher::refine_match): an affine transform that aligns
+/// the template with the input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AffineMatch {
+    /// Maps a pixel position `(x, y)` in the template to the position
+    /// `(m[0][0] * x + m[0][1] * y + m[0][2], m[1][0] * x + m[1][1] * y + m[1][2])` in the input,
+    /// in the same coordinates as match locations, so that a match at `(x, y)` corresponds to
+    /// `[[1, 0, x], [0, 1, y]]`.
+    pub matrix: [[f32; 3]; 2],
+    /// Sum of squared differences between the template and the input at the transform.
+    pub score: f32,
+    /// Number of Gauss–Newton steps taken.
+    pub iterations: u32,
+}
+
+impl AffineMatch {
+    /// Maps a pixel position in the template to the input.
+    pub fn map(&self, x: f32, y: f32) -> (f32, f32) {
+        let m = &self.matrix;
+        (
+            m[0][0] * x + m[0][1] * y + m[0][2],
+            m[1][0] * x + m[1][1] * y + m[1][2],
+        )
+    }
+
+    /// Translation of the template's top-left pixel, i.e. the sub-pixel match location.
+    pub fn location(&self) -> (f32, f32) {
+        self.map(0.0, 0.0)
+    }
+}
+
+impl Refinement {
+    pub fn new((x, y): (u32, u32), max_iterations: u32) -> Self {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            max_iterations,
+            padding: 0,
+        }
+    }
+
+    /// Converts the values written by a refinement of a template of the given size into a match.
+    /// The parameters are `A` and `t` of `A * (p - c) + t`, where `c` is the template's center.
+    pub fn result(&self, (width, height): (u32, u32), values: &[f32]) -> AffineMatch {
+        let (center_x, center_y) = center(width, height);
+        let [a00, a10, a01, a11, tx, ty, score, iterations] = values[..RESULT_LEN] else {
+            unreachable!()
+        };
+        AffineMatch {
+            matrix: [
+                [a00, a01, tx - a00 * center_x - a01 * center_y],
+                [a10, a11, ty - a10 * center_x - a11 * center_y],
+            ],
+            score,
+            iterations: iterations as u32,
+        }
+    }
+
+    /// Refines the match like the refine shader does.
+    pub fn refine(&self, input: &Image<'_>, template: &Image<'_>) -> [f32; RESULT_LEN] {
+        let channels = template.channels as usize;
+        let (center_x, center_y) = center(template.width, template.height);
+        let radius = template.width.max(template.height) as f32 * 0.5;
+
+        let mut params = [1.0, 0.0, 0.0, 1.0, self.x + center_x, self.y + center_y];
+        let mut iterations = 0;
+        let mut done = false;
+        let mut score = 0.0;
+
+        for i in 0..=self.max_iterations {
+            // Upper triangle of the Hessian, then the steepest descent image times the error.
+            let mut sums = [0.0f32; 28];
+            score = 0.0;
+
+            for y in 0..template.height {
+                for x in 0..template.width {
+                    let dx = x as f32 - center_x;
+                    let dy = y as f32 - center_y;
+                    let wx = params[0] * dx + params[2] * dy + params[4];
+                    let wy = params[1] * dx + params[3] * dy + params[5];
+
+                    for c in 0..channels {
+                        let t = template.data[(y * template.width + x) as usize * channels + c];
+                        let error = bilinear(input, wx, wy, c) - t;
+                        let gx = (bilinear(input, wx + 1.0, wy, c)
+                            - bilinear(input, wx - 1.0, wy, c))
+                            * 0.5;
+                        let gy = (bilinear(input, wx, wy + 1.0, c)
+                            - bilinear(input, wx, wy - 1.0, c))
+                            * 0.5;
+                        let s = [gx * dx, gy * dx, gx * dy, gy * dy, gx, gy];
+
+                        let mut k = 0;
+                        for row in 0..6 {
+                            for col in row..6 {
+                                sums[k] += s[row] * s[col];
+                                k += 1;
+                            }
+                        }
+                        for row in 0..6 {
+                            sums[21 + row] += s[row] * error;
+                        }
+                        score += error * error;
+                    }
+                }
+            }
+
+            if done || i == self.max_iterations {
+                continue;
+            }
+            match solve(&sums) {
+                Some(step) => {
+                    for (param, step) in params.iter_mut().zip(step) {
+                        *param -= step;
+                    }
+                    iterations += 1;
+                    done = step_size(&step, radius) < CONVERGED;
+                }
+                None => done = true,
+            }
+        }
+
+        let [a00, a10, a01, a11, tx, ty] = params;
+        [a00, a10, a01, a11, tx, ty, score, iterations as f32]
+    }
+}
+
+/// Center of a template of the given size, in pixel positions.
+fn center(width: u32, height: u32) -> (f32, f32) {
+    ((width as f32 - 1.0) * 0.5, (height as f32 - 1.0) * 0.5)
+}
+
+/// Largest distance that a step of the parameters moves a pixel within `radius` of the center.
+fn step_size(step: &[f32; 6], radius: f32) -> f32 {
+    let linear = step[..4].iter().map(|value| value.abs()).sum::<f32>();
+    step[4].abs().max(step[5].abs()) + linear * radius
+}
+
+/// Solves the Gauss–Newton system whose Hessian's upper triangle and right-hand side are packed
+/// in `sums`, with Gaussian elimination and partial pivoting. Returns [None] if it is singular.
+fn solve(sums: &[f32; 28]) -> Option<[f32; 6]> {
+    let mut m = [[0.0f32; 7]; 6];
+    for (row, values) in m.iter_mut().enumerate() {
+        for (col, value) in values[..6].iter_mut().enumerate() {
+            let (i, j) = (row.min(col), row.max(col));
+            *value = sums[i * (11 - i) / 2 + j];
+        }
+        values[6] = sums[21 + row];
+    }
+
+    for col in 0..6 {
+        let pivot = (col..6)
+            .max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))
+            .unwrap();
+        if m[pivot][col].abs() < 1e-6 {
+            return None;
+        }
+        m.swap(col, pivot);
+        let pivot_row = m[col];
+        for row in &mut m[col + 1..] {
+            let factor = row[col] / pivot_row[col];
+            for (value, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+        }
+    }
+
+    let mut step = [0.0; 6];
+    for row in (0..6).rev() {
+        let sum = (row + 1..6).map(|i| m[row][i] * step[i]).sum::<f32>();
+        step[row] = (m[row][6] - sum) / m[row][row];
+    }
+    Some(step)
+}
+
+/// Samples one channel of the image bilinearly at the given pixel position, clamped to its edges.
+fn bilinear(image: &Image<'_>, x: f32, y: f32, c: usize) -> f32 {
+    let channels = image.channels as usize;
+    let clamp = |value: f32, size: u32| (value.max(0.0) as u32).min(size - 1) as usize;
+    let (left, top) = (x.floor(), y.floor());
+    let (fx, fy) = (x - left, y - top);
+    let (x0, x1) = (clamp(left, image.width), clamp(left + 1.0, image.width));
+    let (y0, y1) = (clamp(top, image.height), clamp(top + 1.0, image.height));
+
+    let sample = |x: usize, y: usize| image.data[(y * image.width as usize + x) * channels + c];
+    let upper = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+    let lower = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+    upper * (1.0 - fy) + lower * fy
+}
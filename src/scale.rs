@@ -0,0 +1,132 @@
+//! Matching templates at several sizes.
+
+use std::ops::RangeInclusive;
+
+use crate::{packed_samples, Image, Sample};
+
+/// Best match of [match_template_scaled](crate::TemplateMatcher::match_template_scaled) over all
+/// scales.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScaledMatch {
+    /// Top-left corner of the scaled template at the best match.
+    pub location: (u32, u32),
+    /// Score of the best match at its scale.
+    pub score: f32,
+    /// Horizontal and vertical scale of the best match, which are equal unless they were searched
+    /// independently.
+    pub scale_x: f32,
+    pub scale_y: f32,
+    /// Size of the template at the best scale.
+    pub size: (u32, u32),
+}
+
+/// Scales from the start of the range to its end, `step` apart. The end is included even if the
+/// range isn't a multiple of the step.
+///
+/// # Panics
+///
+/// Panics if the range is empty or contains non-positive scales, or if the step isn't positive.
+pub(crate) fn scales(range: RangeInclusive<f32>, step: f32) -> Vec<f32> {
+    let (start, end) = range.into_inner();
+    assert!(step > 0.0, "scale step must be positive");
+    assert!(
+        start > 0.0 && start <= end,
+        "scale range must be non-empty and positive"
+    );
+
+    let count = ((end - start) / step + 1e-3).floor() as u32;
+    let mut scales: Vec<_> = (0..=count).map(|i| start + i as f32 * step).collect();
+    if end - scales[scales.len() - 1] > 1e-3 * step {
+        scales.push(end);
+    }
+    scales
+}
+
+/// Size of an image scaled horizontally by `scale_x` and vertically by `scale_y`, rounded to
+/// whole pixels.
+pub(crate) fn scaled_size(width: u32, height: u32, (scale_x, scale_y): (f32, f32)) -> (u32, u32) {
+    (
+        ((width as f32 * scale_x).round() as u32).max(1),
+        ((height as f32 * scale_y).round() as u32).max(1),
+    )
+}
+
+/// Resizes the image to the given size by sampling it bilinearly at the centers of the new pixels.
+pub(crate) fn resize<T: Sample>(image: &Image<'_, T>, width: u32, height: u32) -> Image<'static> {
+    image.assert_valid("image");
+
+    let samples: Vec<f32> = packed_samples(image).map(Sample::to_f32).collect();
+    let channels = image.channels as usize;
+    let scale_x = image.width as f32 / width as f32;
+    let scale_y = image.height as f32 / height as f32;
+
+    // Neighbouring source pixels and the weight of the second one, along one axis.
+    let neighbours = |position: f32, size: u32| {
+        let position = position.clamp(0.0, (size - 1) as f32);
+        let first = position as usize;
+        (first, (first + 1).min(size as usize - 1), position.fract())
+    };
+
+    let mut data = Vec::with_capacity((width * height) as usize * channels);
+    for y in 0..height {
+        let (y0, y1, fy) = neighbours((y as f32 + 0.5) * scale_y - 0.5, image.height);
+        for x in 0..width {
+            let (x0, x1, fx) = neighbours((x as f32 + 0.5) * scale_x - 0.5, image.width);
+            let sample = |x: usize, y: usize, c: usize| {
+                samples[(y * image.width as usize + x) * channels + c]
+            };
+
+            for c in 0..channels {
+                let upper = sample(x0, y0, c) * (1.0 - fx) + sample(x1, y0, c) * fx;
+                let lower = sample(x0, y1, c) * (1.0 - fx) + sample(x1, y1, c) * fx;
+                data.push(upper * (1.0 - fy) + lower * fy);
+            }
+        }
+    }
+
+    Image::with_channels(data, width, height, image.channels)
+}
+
+/// Resizes the image to the given size, averaging the pixels that each new pixel covers, weighed
+/// by the covered area.
+pub(crate) fn resize_area<T: Sample>(
+    image: &Image<'_, T>,
+    width: u32,
+    height: u32,
+) -> Image<'static> {
+    image.assert_valid("image");
+
+    let samples: Vec<f32> = packed_samples(image).map(Sample::to_f32).collect();
+    let channels = image.channels as usize;
+    let scale_x = image.width as f32 / width as f32;
+    let scale_y = image.height as f32 / height as f32;
+
+    // Source pixels that a new pixel covers along one axis, with the covered part of each.
+    let coverage = |i: u32, scale: f32, size: u32| {
+        let start = i as f32 * scale;
+        let end = ((i + 1) as f32 * scale).min(size as f32);
+        (start as usize..end.ceil() as usize)
+            .map(move |p| (p, end.min(p as f32 + 1.0) - start.max(p as f32)))
+    };
+
+    let mut data = Vec::with_capacity((width * height) as usize * channels);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sums = vec![0.0; channels];
+            let mut weight = 0.0;
+            for (sy, wy) in coverage(y, scale_y, image.height) {
+                for (sx, wx) in coverage(x, scale_x, image.width) {
+                    let pixel = (sy * image.width as usize + sx) * channels;
+                    for (sum, sample) in sums.iter_mut().zip(&samples[pixel..pixel + channels]) {
+                        *sum += wx * wy * sample;
+                    }
+                    weight += wx * wy;
+                }
+            }
+            data.extend(sums.iter().map(|sum| sum / weight));
+        }
+    }
+
+    Image::with_channels(data, width, height, image.channels)
+}
@@ -0,0 +1,145 @@
+//! Continuous matching of a stream of frames on a background thread.
+
+use std::{
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+
+use crate::{Error, Image, MatchTemplateMethod, TemplateMatcher};
+
+/// Result of matching a single frame submitted to a [MatchService].
+#[derive(Debug)]
+pub struct FrameResult {
+    /// Sequence number assigned to the frame on submission. Gaps in the sequence mean dropped frames.
+    pub sequence: u64,
+    pub captured_at: Instant,
+    pub completed_at: Instant,
+    /// The result of matching the frame, or why it couldn't be matched, e.g. because the frame
+    /// doesn't have as many channels as the template.
+    pub result: Result<Image<'static>, Error>,
+}
+
+impl FrameResult {
+    /// Time from frame capture to the result becoming available.
+    pub fn latency(&self) -> Duration {
+        self.completed_at
+            .saturating_duration_since(self.captured_at)
+    }
+}
+
+struct Frame {
+    sequence: u64,
+    captured_at: Instant,
+    image: Image<'static>,
+}
+
+/// Matches a template against every submitted frame on a dedicated thread and delivers the results
+/// through an async channel.
+///
+/// Frames submitted while the service is still busy with a previous frame are dropped.
+pub struct MatchService {
+    sender: Option<SyncSender<Frame>>,
+    thread: Option<JoinHandle<()>>,
+    next_sequence: u64,
+}
+
+impl MatchService {
+    /// Starts the service. Results can be consumed from the returned receiver, which implements
+    /// `futures::Stream`.
+    pub fn spawn(
+        template: Image<'static>,
+        method: MatchTemplateMethod,
+    ) -> (Self, UnboundedReceiver<FrameResult>) {
+        let (frame_sender, frame_receiver) = mpsc::sync_channel::<Frame>(1);
+        let (result_sender, result_receiver) = unbounded();
+
+        let thread = std::thread::spawn(move || {
+            let mut matcher = TemplateMatcher::new();
+
+            while let Ok(frame) = frame_receiver.recv() {
+                let result = matcher
+                    .try_match_template(&frame.image, &template, method)
+                    .and_then(|job| matcher.try_wait_for_job(job))
+                    .map(Option::unwrap);
+
+                let frame_result = FrameResult {
+                    sequence: frame.sequence,
+                    captured_at: frame.captured_at,
+                    completed_at: Instant::now(),
+                    result,
+                };
+
+                if result_sender.unbounded_send(frame_result).is_err() {
+                    // Receiver was dropped, nobody is interested in the results anymore.
+                    break;
+                }
+            }
+        });
+
+        let service = Self {
+            sender: Some(frame_sender),
+            thread: Some(thread),
+            next_sequence: 0,
+        };
+
+        (service, result_receiver)
+    }
+
+    /// Submits a frame for matching and returns its sequence number.
+    /// Returns [None] if the frame was dropped because the service is busy or has stopped.
+    pub fn submit(&mut self, image: Image<'static>, captured_at: Instant) -> Option<u64> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let frame = Frame {
+            sequence,
+            captured_at,
+            image,
+        };
+
+        match self.sender.as_ref()?.try_send(frame) {
+            Ok(()) => Some(sequence),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => None,
+        }
+    }
+}
+
+impl Drop for MatchService {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_frames_that_fail_to_match() {
+        let template = Image::new(vec![0.5; 4], 2, 2);
+        let (mut service, mut results) =
+            MatchService::spawn(template, MatchTemplateMethod::SumOfSquaredDifferences);
+
+        let frame = Image::with_channels(vec![0.5; 48], 4, 4, 3);
+        assert_eq!(service.submit(frame, Instant::now()), Some(0));
+        // Dropping the service waits for the frame to be matched.
+        drop(service);
+
+        let frame_result = results.try_recv().unwrap();
+        assert_eq!(frame_result.sequence, 0);
+        assert_eq!(
+            frame_result.result.unwrap_err(),
+            Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+    }
+}
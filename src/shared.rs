@@ -0,0 +1,64 @@
+//! A matcher that can be shared between threads.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::{Error, Image, MatchTemplateMethod, Sample, TemplateMatcher};
+
+/// A [TemplateMatcher] that can be shared behind an [Arc](std::sync::Arc) and used from several
+/// threads at once. Each call matches and reads the result back as one step, so concurrent calls
+/// are serialized and can't collect each other's results.
+pub struct SharedMatcher {
+    matcher: Mutex<TemplateMatcher>,
+}
+
+// The matcher only holds wgpu resources, which are thread-safe on native targets.
+#[cfg(not(target_arch = "wasm32"))]
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<SharedMatcher>;
+};
+
+impl Default for SharedMatcher {
+    fn default() -> Self {
+        Self::new(TemplateMatcher::new())
+    }
+}
+
+impl SharedMatcher {
+    pub fn new(matcher: TemplateMatcher) -> Self {
+        Self {
+            matcher: Mutex::new(matcher),
+        }
+    }
+
+    /// Matches the template against the input and waits for the result. Blocks while another
+    /// thread is matching.
+    ///
+    /// Returns the errors of [try_match_template](TemplateMatcher::try_match_template) for images
+    /// that can't be matched, and an error if the GPU rejects the match or its result can't be
+    /// read back.
+    pub fn match_template<'a, I: Sample, T: Sample>(
+        &self,
+        input: impl Into<Image<'a, I>>,
+        template: impl Into<Image<'a, T>>,
+        method: MatchTemplateMethod,
+    ) -> Result<Image<'static>, Error> {
+        let mut matcher = self.lock();
+        let job = matcher.try_match_template(input, template, method)?;
+        Ok(matcher.try_wait_for_job(job)?.unwrap())
+    }
+
+    /// Locks the matcher for exclusive use, e.g. to call methods that aren't available on the
+    /// shared handle. Results of matches started through the guard must be collected before it is
+    /// dropped, or they may be discarded by the next call from another thread.
+    pub fn lock(&self) -> MutexGuard<'_, TemplateMatcher> {
+        // A panic while matching doesn't leave the matcher in an unusable state.
+        self.matcher.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn into_inner(self) -> TemplateMatcher {
+        self.matcher
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
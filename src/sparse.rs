@@ -0,0 +1,103 @@
+//! Templates of which only some pixels are matched.
+
+use crate::{packed_samples, Image, Sample};
+
+/// A template given as a list of the pixels that are matched, e.g. the opaque pixels of an icon
+/// with a transparent background. Only the listed pixels are visited when scoring a position, so
+/// matching is faster the fewer pixels are kept.
+///
+/// Results have the size they would have for the full template, with the template's top-left
+/// corner at each position.
+#[derive(Clone, Debug)]
+pub struct SparseTemplate {
+    width: u32,
+    height: u32,
+    channels: u32,
+    /// Positions of the matched pixels in the template.
+    offsets: Vec<(u32, u32)>,
+    /// Samples of the matched pixels, `channels` for each.
+    samples: Vec<f32>,
+}
+
+impl SparseTemplate {
+    /// Keeps the pixels of the template for which `mask` is set. The mask has one value per pixel,
+    /// row by row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mask doesn't have `width * height` values, or if it doesn't select any pixel.
+    pub fn new<T: Sample>(template: &Image<'_, T>, mask: &[bool]) -> Self {
+        template.assert_valid("template");
+        assert_eq!(
+            mask.len(),
+            (template.width * template.height) as usize,
+            "mask must have one value per template pixel"
+        );
+
+        let channels = template.channels as usize;
+        let mut offsets = Vec::new();
+        let mut samples = Vec::new();
+
+        let pixels = packed_samples(template).collect::<Vec<_>>();
+        for (index, pixel) in pixels.chunks_exact(channels).enumerate() {
+            if mask[index] {
+                offsets.push((index as u32 % template.width, index as u32 / template.width));
+                samples.extend(pixel.iter().map(|sample| sample.to_f32()));
+            }
+        }
+
+        assert!(!offsets.is_empty(), "mask must select at least one pixel");
+
+        Self {
+            width: template.width,
+            height: template.height,
+            channels: template.channels,
+            offsets,
+            samples,
+        }
+    }
+
+    /// Width of the full template.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the full template.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// Number of matched pixels.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Always false, since a sparse template keeps at least one pixel.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Matched pixels as their positions and samples.
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = ((u32, u32), &[f32])> {
+        self.offsets
+            .iter()
+            .copied()
+            .zip(self.samples.chunks_exact(self.channels as usize))
+    }
+
+    /// Matched pixels in the layout of the sparse shader: the position of each pixel followed by
+    /// the bit patterns of its samples.
+    pub(crate) fn packed(&self) -> Vec<u32> {
+        self.pixels()
+            .flat_map(|((x, y), samples)| {
+                [x, y]
+                    .into_iter()
+                    .chain(samples.iter().map(|sample| sample.to_bits()))
+            })
+            .collect()
+    }
+}
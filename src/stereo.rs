@@ -0,0 +1,43 @@
+//! Estimating disparities between the views of a rectified stereo pair.
+
+use crate::Image;
+
+/// Disparity of each block of the left view of a stereo pair, estimated by
+/// [stereo_disparity](crate::TemplateMatcher::stereo_disparity).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisparityMap {
+    /// Width and height of the square blocks, in pixels.
+    pub block_size: u32,
+    /// Number of blocks in each row.
+    pub columns: u32,
+    /// Number of rows of blocks.
+    pub rows: u32,
+    /// Disparity of each block, row by row: the block at `x` in the left view matches best at
+    /// `x - disparity` in the right view.
+    pub disparities: Vec<u32>,
+    /// Score of each block at its disparity, row by row.
+    pub scores: Vec<f32>,
+}
+
+impl DisparityMap {
+    /// Disparity of the block in the given column and row, whose top-left corner is at
+    /// `(column * block_size, row * block_size)` in the left view.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is outside the map.
+    pub fn at(&self, column: u32, row: u32) -> u32 {
+        assert!(
+            column < self.columns && row < self.rows,
+            "block is outside the disparity map"
+        );
+        self.disparities[(row * self.columns + column) as usize]
+    }
+
+    /// Disparities as an image with one pixel per block.
+    pub fn to_image(&self) -> Image<'static> {
+        let disparities: Vec<_> = self.disparities.iter().map(|&d| d as f32).collect();
+        Image::new(disparities, self.columns, self.rows)
+    }
+}
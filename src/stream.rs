@@ -0,0 +1,194 @@
+//! Matching the frames of an asynchronous stream.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::{Error, Image, MatchJob, MatchTemplateMethod, Sample, TemplateMatcher};
+
+/// Matches a template against each frame of a [Stream], e.g. one fed by an async video source,
+/// and yields the results in the order of the frames.
+///
+/// Like [PipelinedMatcher](crate::PipelinedMatcher), it keeps several matches in flight: while
+/// the result of one frame is being read back, the next frames are already pulled from the source
+/// and matched. Waiting for the GPU doesn't block the executor's thread. Frames can change size
+/// within the stream, e.g. when the source's resolution changes.
+///
+/// A frame that can't be matched, e.g. because it doesn't have as many channels as the template,
+/// yields the errors of [try_match_template](TemplateMatcher::try_match_template), and one whose
+/// match the GPU rejected or whose result can't be read back yields that error. The stream goes on
+/// with the next frame either way.
+pub struct MatchStream<S, T: Sample = f32> {
+    frames: S,
+    matcher: TemplateMatcher,
+    template: Image<'static, T>,
+    method: MatchTemplateMethod,
+    depth: usize,
+    /// Jobs of the frames in flight, or the errors of frames that couldn't be matched.
+    in_flight: VecDeque<Result<MatchJob, Error>>,
+    frames_ended: bool,
+}
+
+impl<S, T: Sample> MatchStream<S, T> {
+    /// Matches the template against each frame of `frames`, with up to two frames in flight.
+    pub fn new(
+        matcher: TemplateMatcher,
+        frames: S,
+        template: Image<'static, T>,
+        method: MatchTemplateMethod,
+    ) -> Self {
+        Self {
+            frames,
+            matcher,
+            template,
+            method,
+            depth: 2,
+            in_flight: VecDeque::new(),
+            frames_ended: false,
+        }
+    }
+
+    /// Keeps up to `depth` frames in flight instead of two.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is zero.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        assert!(depth > 0, "pipeline depth must be at least one");
+        self.depth = depth;
+        self
+    }
+
+    /// Number of frames whose results haven't been yielded yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn matcher(&self) -> &TemplateMatcher {
+        &self.matcher
+    }
+
+    /// Returns the matcher, discarding the results of the frames in flight.
+    pub fn into_inner(mut self) -> TemplateMatcher {
+        for job in self.in_flight.drain(..).flatten() {
+            self.matcher.cancel(job);
+        }
+        self.matcher
+    }
+}
+
+// Nothing is pinned structurally, the frames are polled through a fresh pin each time.
+impl<S: Unpin, T: Sample> Unpin for MatchStream<S, T> {}
+
+impl<S, I, T> Stream for MatchStream<S, T>
+where
+    S: Stream<Item = Image<'static, I>> + Unpin,
+    I: Sample,
+    T: Sample,
+{
+    type Item = Result<Image<'static>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while !this.frames_ended && this.in_flight.len() < this.depth {
+            match Pin::new(&mut this.frames).poll_next(cx) {
+                Poll::Ready(Some(frame)) => {
+                    let job = this
+                        .matcher
+                        .try_match_template(frame, &this.template, this.method);
+                    this.in_flight.push_back(job);
+                }
+                Poll::Ready(None) => this.frames_ended = true,
+                Poll::Pending => break,
+            }
+        }
+
+        let job = match this.in_flight.front() {
+            Some(Ok(job)) => *job,
+            Some(Err(error)) => {
+                let error = error.clone();
+                this.in_flight.pop_front();
+                return Poll::Ready(Some(Err(error)));
+            }
+            // Either the frames have ended, or the source wakes the task with the next frame.
+            None if this.frames_ended => return Poll::Ready(None),
+            None => return Poll::Pending,
+        };
+
+        // The mapping callback of the result wakes the task once it can be read.
+        let result = std::task::ready!(this.matcher.poll_job_result(job, cx));
+        this.in_flight.pop_front();
+        Poll::Ready(result.transpose())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = if self.frames_ended {
+            (0, Some(0))
+        } else {
+            self.frames.size_hint()
+        };
+        (
+            lower + self.in_flight.len(),
+            upper.map(|upper| upper + self.in_flight.len()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source whose frames are all ready at once.
+    struct Frames(VecDeque<Image<'static>>);
+
+    impl Stream for Frames {
+        type Item = Image<'static>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    #[test]
+    fn yields_results_and_errors_in_frame_order() {
+        let template = Image::new(vec![0.25, 0.5, 0.75, 1.0], 2, 2);
+        let frame = Image::new(
+            (0..36).map(|i| (i % 5) as f32 / 4.0).collect::<Vec<_>>(),
+            6,
+            6,
+        );
+        let rgb = Image::with_channels(vec![0.5; 108], 6, 6, 3);
+        let frames = Frames(VecDeque::from([frame.clone(), rgb, frame.clone()]));
+
+        let method = MatchTemplateMethod::SumOfSquaredDifferences;
+        let mut matcher = TemplateMatcher::new();
+        let job = matcher.match_template(&frame, &template, method);
+        let expected = matcher.wait_for_job(job).unwrap();
+
+        // pollster parks the thread until the task is woken, so this hangs unless the mapping
+        // callbacks wake it.
+        let mut stream = MatchStream::new(matcher, frames, template, method);
+        let results: Vec<_> = std::iter::from_fn(|| {
+            pollster::block_on(std::future::poll_fn(|cx| {
+                Pin::new(&mut stream).poll_next(cx)
+            }))
+        })
+        .collect();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().data, expected.data);
+        assert_eq!(
+            results[1].as_ref().unwrap_err(),
+            &Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+        assert_eq!(results[2].as_ref().unwrap().data, expected.data);
+    }
+}
@@ -0,0 +1,834 @@
+//! Following a template from frame to frame.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    find_extremes, scale, Error, Image, MatchTemplateMethod, Region, Sample, TemplateMatcher,
+};
+
+/// Distance in pixels that a [TemplateTracker] searches around the last position by default.
+const DEFAULT_RADIUS: u32 = 16;
+
+/// How a [TemplateTracker] found the template in a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrackState {
+    /// Found in the window around its last position, or where it was predicted to move.
+    Tracked,
+    /// Found by searching the whole frame, because it had no last position, wasn't found near it,
+    /// or had been [drifting](TemplateTracker::with_loss_detection).
+    Acquired,
+    /// Not found anywhere in the frame.
+    Lost,
+}
+
+/// Outcome of a [TemplateTracker::update].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackUpdate {
+    pub state: TrackState,
+    /// Top-left corner of the template in the frame, or [None] if it was lost.
+    pub location: Option<(u32, u32)>,
+    /// Top-left corner of the template smoothed by the tracker's
+    /// [Kalman filter](TemplateTracker::with_kalman_filter), or just the location without one.
+    pub position: Option<(f32, f32)>,
+    /// Best score of the last search, even if it wasn't good enough. Scores of a template tracked
+    /// at another [scale](TemplateTracker::with_search_scales) are scaled to the size of the
+    /// original template, so that they can be compared with its thresholds.
+    pub score: f32,
+    /// Scale of the template relative to the original one.
+    pub scale: f32,
+}
+
+/// Follows a template through the frames of a video. Each frame is searched only around the
+/// template's last position, which is much cheaper than searching the whole frame, and the whole
+/// frame is searched only when the template isn't found there or has no last position yet.
+///
+/// Without a [threshold](Self::with_threshold) every best score counts as found, so the tracker
+/// never loses the template once it has been acquired.
+pub struct TemplateTracker {
+    template: Image<'static>,
+    method: MatchTemplateMethod,
+    radius: u32,
+    threshold: Option<f32>,
+    location: Option<(u32, u32)>,
+    kalman: Option<KalmanFilter>,
+    /// Score above which the template is drifting, and the number of frames in a row that it may
+    /// drift before the whole frame is searched again.
+    loss: Option<(f32, u32)>,
+    /// Frames in a row in which the template has been drifting.
+    drifting: u32,
+    /// Scales at which whole frames are searched.
+    scales: Option<Vec<f32>>,
+    /// Template resized to the scale it was last found at, unless that is the original scale.
+    scaled: Option<(f32, Image<'static>)>,
+}
+
+impl TemplateTracker {
+    pub fn new(template: Image<'static>, method: MatchTemplateMethod) -> Self {
+        Self {
+            template,
+            method,
+            radius: DEFAULT_RADIUS,
+            threshold: None,
+            location: None,
+            kalman: None,
+            loss: None,
+            drifting: 0,
+            scales: None,
+            scaled: None,
+        }
+    }
+
+    /// Searches template positions at most `radius` pixels away from the last position in both
+    /// directions, instead of 16. The template must not move faster than this between frames.
+    pub fn with_radius(mut self, radius: u32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Only considers the template found where its score is at most `threshold`.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Smooths the reported positions with a constant-velocity Kalman filter, and centers the
+    /// search window on where the filter predicts the template to be, so that it can move faster
+    /// than the radius as long as its velocity changes slowly.
+    ///
+    /// `process_noise` is the variance of the change of velocity per frame, and
+    /// `measurement_noise` the variance of the matched locations, both in squared pixels. The
+    /// smaller their ratio, the smoother but slower to follow the positions are.
+    pub fn with_kalman_filter(mut self, process_noise: f32, measurement_noise: f32) -> Self {
+        self.kalman = Some(KalmanFilter::new(process_noise, measurement_noise));
+        self
+    }
+
+    /// Guards against the tracker silently drifting off the template: when its best score near
+    /// the last position exceeds `threshold` for `frames` frames in a row, the whole frame is
+    /// searched again, even if the scores are still within the
+    /// [threshold](Self::with_threshold) of a match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frames` is zero.
+    pub fn with_loss_detection(mut self, threshold: f32, frames: u32) -> Self {
+        assert!(frames > 0, "loss detection needs at least one frame");
+        self.loss = Some((threshold, frames));
+        self
+    }
+
+    /// Searches whole frames for the template resized by each scale from `scales`, `step` apart,
+    /// instead of only at its original size, e.g. for a target that moves towards the camera.
+    /// After a template has been found at another scale, it is tracked at that scale.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty or contains non-positive scales, or if the step isn't positive.
+    pub fn with_search_scales(mut self, scales: RangeInclusive<f32>, step: f32) -> Self {
+        self.scales = Some(scale::scales(scales, step));
+        self
+    }
+
+    /// The original template.
+    pub fn template(&self) -> &Image<'static> {
+        &self.template
+    }
+
+    /// Scale of the template relative to the original one, at which it is tracked.
+    pub fn scale(&self) -> f32 {
+        self.scaled.as_ref().map_or(1.0, |(scale, _)| *scale)
+    }
+
+    /// Last position of the template's top-left corner, or [None] if it hasn't been found yet or
+    /// was lost.
+    pub fn location(&self) -> Option<(u32, u32)> {
+        self.location
+    }
+
+    /// Starts the search of the next frame around the given position, e.g. one picked by the user.
+    pub fn set_location(&mut self, location: (u32, u32)) {
+        self.location = Some(location);
+        if let Some(kalman) = &mut self.kalman {
+            kalman.start(location);
+        }
+    }
+
+    /// Forgets the last position and scale, so that the next frame is searched in full.
+    pub fn reset(&mut self) {
+        self.location = None;
+        self.drifting = 0;
+        self.scaled = None;
+        if let Some(kalman) = &mut self.kalman {
+            kalman.stop();
+        }
+    }
+
+    /// Finds the template in the next frame and updates its last position. Blocks until it has
+    /// been found or the search has failed.
+    ///
+    /// Returns [Error::ChannelMismatch] if the frame and the template have different numbers of
+    /// channels, and an error if the GPU rejects the work or its result can't be read back. The
+    /// tracker is left as it was in either case.
+    pub fn update<'a, I: Sample>(
+        &mut self,
+        matcher: &mut TemplateMatcher,
+        frame: impl Into<Image<'a, I>>,
+    ) -> Result<TrackUpdate, Error> {
+        let frame = frame.into();
+        check_channels(&frame, &self.template)?;
+        let frame_size = (frame.width, frame.height);
+        if !self.fits(frame_size) {
+            return Ok(self.lose(f32::INFINITY));
+        }
+
+        let mut score = f32::INFINITY;
+        if let Some(window) = self.window(frame_size) {
+            let job = matcher.match_template_in_region(&frame, self.current(), self.method, window);
+            let result = matcher.try_wait_for_job(job)?.unwrap();
+            match self.window_result(window, &result) {
+                Ok(update) => return Ok(update),
+                Err(window_score) => score = window_score,
+            }
+        }
+
+        self.search_frame(matcher, &frame, score)
+    }
+
+    /// Template at the scale it is tracked at.
+    fn current(&self) -> &Image<'static> {
+        self.scaled
+            .as_ref()
+            .map_or(&self.template, |(_, template)| template)
+    }
+
+    fn fits(&self, (width, height): (u32, u32)) -> bool {
+        let template = self.current();
+        template.width <= width && template.height <= height
+    }
+
+    /// Region of the frame to search first, or [None] if there is no last position.
+    fn window(&self, frame_size: (u32, u32)) -> Option<Region> {
+        let last = self.location?;
+        // Negative predictions saturate to the left and top edges.
+        let center = match self.kalman.as_ref().and_then(KalmanFilter::predict) {
+            Some((x, y)) => (x.round() as u32, y.round() as u32),
+            None => last,
+        };
+        let template = self.current();
+        Some(Region::search_window(
+            frame_size,
+            (template.width, template.height),
+            center,
+            self.radius,
+        ))
+    }
+
+    /// Updates the tracker with the result of a search of its window, if the template was found
+    /// there and isn't drifting. Otherwise returns the best score of the window and leaves the
+    /// tracker as it was, so that a failed search of the whole frame doesn't change it either.
+    fn window_result(&mut self, window: Region, result: &Image<'_>) -> Result<TrackUpdate, f32> {
+        let (location, score) = best(window, result);
+        let score = self.normalized(score);
+        if !self.is_found(score) {
+            return Err(score);
+        }
+
+        // Searching the whole frame resets the count, whether the template is found or lost.
+        match self.loss {
+            Some((threshold, _)) if score <= threshold => self.drifting = 0,
+            Some((_, frames)) if self.drifting + 1 >= frames => return Err(score),
+            Some(_) => self.drifting += 1,
+            None => {}
+        }
+        Ok(self.found(TrackState::Tracked, location, score))
+    }
+
+    /// Searches the whole frame for the template, after the search of its window, if any, reached
+    /// `window_score`.
+    fn search_frame<I: Sample>(
+        &mut self,
+        matcher: &mut TemplateMatcher,
+        frame: &Image<'_, I>,
+        window_score: f32,
+    ) -> Result<TrackUpdate, Error> {
+        let Some(scales) = &self.scales else {
+            let job = matcher.match_template(frame, self.current(), self.method);
+            let result = matcher.try_wait_for_job(job)?.unwrap();
+            return Ok(self.search_result((frame.width, frame.height), &result, window_score));
+        };
+
+        let scales: Vec<_> = scales.iter().map(|&scale| (scale, scale)).collect();
+        let Some(best) =
+            matcher.match_template_at_scales(frame, &self.template, self.method, &scales)?
+        else {
+            return Ok(self.lose(window_score));
+        };
+
+        let original_samples = (self.template.width * self.template.height) as f32;
+        let score = best.score * original_samples / (best.size.0 * best.size.1) as f32;
+        if !self.is_found(score) {
+            return Ok(self.lose(window_score.min(score)));
+        }
+
+        self.scaled = (best.scale_x != 1.0).then(|| {
+            let template = scale::resize(&self.template, best.size.0, best.size.1);
+            (best.scale_x, template)
+        });
+        Ok(self.found(TrackState::Acquired, best.location, score))
+    }
+
+    /// Updates the tracker with the result of a search of the whole frame at the current scale,
+    /// after the search of its window, if any, reached `window_score`.
+    fn search_result(
+        &mut self,
+        (width, height): (u32, u32),
+        result: &Image<'_>,
+        window_score: f32,
+    ) -> TrackUpdate {
+        let (location, score) = best(Region::new(0, 0, width, height), result);
+        let score = self.normalized(score);
+        if self.is_found(score) {
+            self.found(TrackState::Acquired, location, score)
+        } else {
+            self.lose(window_score.min(score))
+        }
+    }
+
+    /// Scales a score of the current template to the size of the original one.
+    fn normalized(&self, score: f32) -> f32 {
+        let current = self.current();
+        score * (self.template.width * self.template.height) as f32
+            / (current.width * current.height) as f32
+    }
+
+    fn is_found(&self, score: f32) -> bool {
+        self.threshold.is_none_or(|threshold| score <= threshold)
+    }
+
+    fn lose(&mut self, score: f32) -> TrackUpdate {
+        self.reset();
+        TrackUpdate {
+            state: TrackState::Lost,
+            location: None,
+            position: None,
+            score,
+            scale: 1.0,
+        }
+    }
+
+    fn found(&mut self, state: TrackState, location: (u32, u32), score: f32) -> TrackUpdate {
+        self.location = Some(location);
+        if state == TrackState::Acquired {
+            self.drifting = 0;
+        }
+        // The filter restarts after the template has been lost, but follows one that was found
+        // outside its window, since it may just have moved faster than predicted.
+        let position = match &mut self.kalman {
+            Some(kalman) => kalman.correct(location),
+            None => (location.0 as f32, location.1 as f32),
+        };
+        TrackUpdate {
+            state,
+            location: Some(location),
+            position: Some(position),
+            score,
+            scale: self.scale(),
+        }
+    }
+}
+
+/// Follows several templates through the same frames, e.g. a number of UI elements. Each frame is
+/// uploaded once, and the windows of all templates are searched in one submission, as are the
+/// whole frame searches of the templates that weren't found in their windows.
+#[derive(Default)]
+pub struct MultiTracker {
+    trackers: Vec<TemplateTracker>,
+}
+
+impl MultiTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tracker and returns its index, which is also the index of its updates.
+    pub fn add(&mut self, tracker: TemplateTracker) -> usize {
+        self.trackers.push(tracker);
+        self.trackers.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> TemplateTracker {
+        self.trackers.remove(index)
+    }
+
+    pub fn trackers(&self) -> &[TemplateTracker] {
+        &self.trackers
+    }
+
+    pub fn trackers_mut(&mut self) -> &mut [TemplateTracker] {
+        &mut self.trackers
+    }
+
+    pub fn len(&self) -> usize {
+        self.trackers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trackers.is_empty()
+    }
+
+    /// Finds every template in the next frame like [TemplateTracker::update] does, and returns the
+    /// updates in the order of the trackers. Templates that are matched with different methods
+    /// are searched in separate submissions.
+    ///
+    /// Returns [Error::ChannelMismatch] before updating any tracker if the frame and a template
+    /// have different numbers of channels. Returns an error if the GPU rejects the work or its
+    /// results can't be read back, in which case some of the trackers may already have been
+    /// updated.
+    pub fn update<'a, I: Sample>(
+        &mut self,
+        matcher: &mut TemplateMatcher,
+        frame: impl Into<Image<'a, I>>,
+    ) -> Result<Vec<TrackUpdate>, Error> {
+        let frame = frame.into();
+        for tracker in &self.trackers {
+            check_channels(&frame, &tracker.template)?;
+        }
+        let frame_size = (frame.width, frame.height);
+        let mut updates: Vec<Option<TrackUpdate>> = vec![None; self.trackers.len()];
+        let mut window_scores = vec![f32::INFINITY; self.trackers.len()];
+
+        for (tracker, update) in self.trackers.iter_mut().zip(&mut updates) {
+            if !tracker.fits(frame_size) {
+                *update = Some(tracker.lose(f32::INFINITY));
+            }
+        }
+
+        let windowed: Vec<_> = (0..self.trackers.len())
+            .filter(|&i| updates[i].is_none() && self.trackers[i].location.is_some())
+            .collect();
+        for (method, indices) in by_method(&self.trackers, &windowed) {
+            let regions: Vec<_> = indices
+                .iter()
+                .map(|&i| self.trackers[i].window(frame_size).unwrap())
+                .collect();
+            let templates: Vec<_> = indices
+                .iter()
+                .map(|&i| Image::from(self.trackers[i].current()))
+                .collect();
+            let results =
+                matcher.match_templates_in_regions(&frame, &templates, &regions, method)?;
+
+            for ((&i, &region), result) in indices.iter().zip(&regions).zip(&results) {
+                match self.trackers[i].window_result(region, result) {
+                    Ok(update) => updates[i] = Some(update),
+                    Err(score) => window_scores[i] = score,
+                }
+            }
+        }
+
+        // Searches at several scales are submitted for each tracker on its own.
+        for (i, tracker) in self.trackers.iter_mut().enumerate() {
+            if updates[i].is_none() && tracker.scales.is_some() {
+                updates[i] = Some(tracker.search_frame(matcher, &frame, window_scores[i])?);
+            }
+        }
+
+        let remaining: Vec<_> = (0..self.trackers.len())
+            .filter(|&i| updates[i].is_none())
+            .collect();
+        for (method, indices) in by_method(&self.trackers, &remaining) {
+            let templates: Vec<_> = indices
+                .iter()
+                .map(|&i| Image::from(self.trackers[i].current()))
+                .collect();
+            let results = matcher.match_templates(&frame, &templates, method)?;
+
+            for (&i, result) in indices.iter().zip(&results) {
+                updates[i] =
+                    Some(self.trackers[i].search_result(frame_size, result, window_scores[i]));
+            }
+        }
+
+        Ok(updates.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+/// Checks that the frame has as many channels as the template.
+fn check_channels<I: Sample>(
+    frame: &Image<'_, I>,
+    template: &Image<'static, f32>,
+) -> Result<(), Error> {
+    if frame.channels != template.channels {
+        return Err(Error::ChannelMismatch {
+            input: frame.channels,
+            template: template.channels,
+        });
+    }
+    Ok(())
+}
+
+/// Groups the indices of the given trackers by their methods.
+fn by_method(
+    trackers: &[TemplateTracker],
+    indices: &[usize],
+) -> Vec<(MatchTemplateMethod, Vec<usize>)> {
+    let mut groups: Vec<(MatchTemplateMethod, Vec<usize>)> = Vec::new();
+    for &i in indices {
+        let method = trackers[i].method;
+        match groups.iter_mut().find(|(other, _)| *other == method) {
+            Some((_, group)) => group.push(i),
+            None => groups.push((method, vec![i])),
+        }
+    }
+    groups
+}
+
+/// Constant-velocity Kalman filter of a position, with the two coordinates filtered separately.
+#[derive(Copy, Clone, Debug)]
+struct KalmanFilter {
+    process_noise: f32,
+    measurement_noise: f32,
+    /// Estimates of the x and y coordinates, or [None] before the first location.
+    axes: Option<[Axis; 2]>,
+}
+
+/// Estimate of one coordinate and its velocity per frame.
+#[derive(Copy, Clone, Debug)]
+struct Axis {
+    position: f32,
+    velocity: f32,
+    covariance: [[f32; 2]; 2],
+}
+
+impl KalmanFilter {
+    fn new(process_noise: f32, measurement_noise: f32) -> Self {
+        Self {
+            process_noise,
+            measurement_noise,
+            axes: None,
+        }
+    }
+
+    /// Restarts the filter at the given location, with an unknown velocity.
+    fn start(&mut self, (x, y): (u32, u32)) -> (f32, f32) {
+        let axis = |position| Axis {
+            position,
+            velocity: 0.0,
+            covariance: [[self.measurement_noise, 0.0], [0.0, self.measurement_noise]],
+        };
+        self.axes = Some([axis(x as f32), axis(y as f32)]);
+        (x as f32, y as f32)
+    }
+
+    fn stop(&mut self) {
+        self.axes = None;
+    }
+
+    /// Position predicted for the next frame.
+    fn predict(&self) -> Option<(f32, f32)> {
+        let [x, y] = self.axes?;
+        Some((x.position + x.velocity, y.position + y.velocity))
+    }
+
+    /// Advances the filter to the next frame, in which the template was found at the given
+    /// location, and returns the filtered position. Starts the filter if it hasn't been started.
+    fn correct(&mut self, location: (u32, u32)) -> (f32, f32) {
+        let Some([x, y]) = self.axes else {
+            return self.start(location);
+        };
+        let x = x.correct(
+            location.0 as f32,
+            self.process_noise,
+            self.measurement_noise,
+        );
+        let y = y.correct(
+            location.1 as f32,
+            self.process_noise,
+            self.measurement_noise,
+        );
+        self.axes = Some([x, y]);
+        (x.position, y.position)
+    }
+}
+
+impl Axis {
+    fn correct(self, measured: f32, process_noise: f32, measurement_noise: f32) -> Self {
+        // Prediction by one frame, with the velocity changed by white noise.
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let position = self.position + self.velocity;
+        let p00 = p00 + p01 + p10 + p11 + process_noise * 0.25;
+        let p01 = p01 + p11 + process_noise * 0.5;
+        let p10 = p10 + p11 + process_noise * 0.5;
+        let p11 = p11 + process_noise;
+
+        // Update with the measured position.
+        let innovation = measured - position;
+        let gain = (
+            p00 / (p00 + measurement_noise),
+            p10 / (p00 + measurement_noise),
+        );
+        Self {
+            position: position + gain.0 * innovation,
+            velocity: self.velocity + gain.1 * innovation,
+            covariance: [
+                [(1.0 - gain.0) * p00, (1.0 - gain.0) * p01],
+                [p10 - gain.1 * p00, p11 - gain.1 * p01],
+            ],
+        }
+    }
+}
+
+/// Best position of a result of matching in the given region, in frame coordinates, along with its
+/// score.
+fn best(region: Region, result: &Image<'_>) -> ((u32, u32), f32) {
+    let extremes = find_extremes(result);
+    let (x, y) = extremes.min_value_location;
+    ((region.x + x, region.y + y), extremes.min_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random samples in `[0, 1)`.
+    fn noise(width: u32, height: u32, seed: u32) -> Image<'static> {
+        let mut state = seed;
+        let data = (0..width * height)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 8) as f32 / (1 << 24) as f32
+            })
+            .collect::<Vec<_>>();
+        Image::new(data, width, height)
+    }
+
+    /// Frame of background noise with the template pasted at the given location, if any.
+    fn frame(template: &Image<'_>, location: Option<(u32, u32)>) -> Image<'static> {
+        let mut frame = noise(64, 48, 1).into_owned();
+        if let Some((x, y)) = location {
+            let data = frame.data.to_mut();
+            for (ty, row) in template.rows().enumerate() {
+                let start = (y as usize + ty) * 64 + x as usize;
+                data[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+        frame
+    }
+
+    fn template() -> Image<'static> {
+        noise(8, 6, 2)
+    }
+
+    const METHOD: MatchTemplateMethod = MatchTemplateMethod::SumOfSquaredDifferences;
+
+    #[test]
+    fn acquires_and_then_tracks_template() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD).with_radius(4);
+
+        let update = tracker
+            .update(&mut matcher, frame(&template(), Some((10, 10))))
+            .unwrap();
+        assert_eq!(update.state, TrackState::Acquired);
+        assert_eq!(update.location, Some((10, 10)));
+        assert_eq!(update.position, Some((10.0, 10.0)));
+
+        for location in [(13, 11), (16, 12), (16, 15)] {
+            let update = tracker
+                .update(&mut matcher, frame(&template(), Some(location)))
+                .unwrap();
+            assert_eq!(update.state, TrackState::Tracked);
+            assert_eq!(update.location, Some(location));
+            assert_eq!(update.score, 0.0);
+        }
+        assert_eq!(tracker.location(), Some((16, 15)));
+    }
+
+    #[test]
+    fn searches_whole_frame_when_not_found_nearby() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD)
+            .with_radius(2)
+            .with_threshold(1e-3);
+
+        tracker
+            .update(&mut matcher, frame(&template(), Some((10, 10))))
+            .unwrap();
+        let update = tracker
+            .update(&mut matcher, frame(&template(), Some((40, 30))))
+            .unwrap();
+
+        assert_eq!(update.state, TrackState::Acquired);
+        assert_eq!(update.location, Some((40, 30)));
+    }
+
+    #[test]
+    fn loses_template_missing_from_frame() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD).with_threshold(1e-3);
+
+        tracker
+            .update(&mut matcher, frame(&template(), Some((10, 10))))
+            .unwrap();
+        let update = tracker
+            .update(&mut matcher, frame(&template(), None))
+            .unwrap();
+
+        assert_eq!(update.state, TrackState::Lost);
+        assert_eq!(update.location, None);
+        assert!(update.score > 1e-3);
+        assert_eq!(tracker.location(), None);
+
+        // Found again in a later frame.
+        let update = tracker
+            .update(&mut matcher, frame(&template(), Some((5, 20))))
+            .unwrap();
+        assert_eq!(update.state, TrackState::Acquired);
+    }
+
+    #[test]
+    fn loses_template_larger_than_frame() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(noise(80, 6, 3), METHOD);
+
+        let update = tracker
+            .update(&mut matcher, frame(&template(), None))
+            .unwrap();
+        assert_eq!(update.state, TrackState::Lost);
+        assert_eq!(update.score, f32::INFINITY);
+    }
+
+    #[test]
+    fn rejects_frame_with_other_channels() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD);
+        tracker
+            .update(&mut matcher, frame(&template(), Some((10, 10))))
+            .unwrap();
+
+        let rgb = Image::with_channels(vec![0.0; 64 * 48 * 3], 64, 48, 3);
+        let error = tracker.update(&mut matcher, &rgb).unwrap_err();
+        assert_eq!(
+            error,
+            Error::ChannelMismatch {
+                input: 3,
+                template: 1
+            }
+        );
+        assert_eq!(tracker.location(), Some((10, 10)));
+    }
+
+    #[test]
+    fn drifting_template_is_searched_again() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD)
+            .with_radius(2)
+            .with_loss_detection(1e-3, 2);
+        tracker.set_location((10, 10));
+
+        // Without the template, its best match near the last position drifts. Every best score
+        // counts as found without a threshold, so only loss detection searches the whole frame.
+        let background = frame(&template(), None);
+        let first = tracker.update(&mut matcher, &background).unwrap();
+        assert_eq!(first.state, TrackState::Tracked);
+        let second = tracker.update(&mut matcher, &background).unwrap();
+        assert_eq!(second.state, TrackState::Acquired);
+    }
+
+    #[test]
+    fn drifting_window_leaves_tracker_unchanged() {
+        let mut tracker = TemplateTracker::new(template(), METHOD).with_loss_detection(1e-3, 2);
+        tracker.set_location((10, 10));
+
+        let window = Region::new(8, 8, 12, 10);
+        let drifted = Image::new(vec![0.5; 25], 5, 5);
+        assert!(tracker.window_result(window, &drifted).is_ok());
+        assert_eq!(tracker.drifting, 1);
+
+        assert_eq!(tracker.window_result(window, &drifted).unwrap_err(), 0.5);
+        assert_eq!(tracker.drifting, 1);
+        assert_eq!(tracker.location(), Some((8, 8)));
+    }
+
+    #[test]
+    fn kalman_filter_starts_at_first_location() {
+        let mut kalman = KalmanFilter::new(0.1, 1.0);
+        assert_eq!(kalman.predict(), None);
+
+        assert_eq!(kalman.correct((12, 7)), (12.0, 7.0));
+        // The velocity is unknown, so the template is expected to stay put.
+        assert_eq!(kalman.predict(), Some((12.0, 7.0)));
+
+        kalman.stop();
+        assert_eq!(kalman.predict(), None);
+    }
+
+    #[test]
+    fn kalman_filter_learns_constant_velocity() {
+        let mut kalman = KalmanFilter::new(0.1, 1.0);
+        for frame in 0..20 {
+            kalman.correct((10 + 3 * frame, 40 - 2 * frame));
+        }
+
+        let (x, y) = kalman.predict().unwrap();
+        assert!((x - 70.0).abs() < 0.5, "{x}");
+        assert!((y - 0.0).abs() < 0.5, "{y}");
+    }
+
+    #[test]
+    fn kalman_filter_smooths_jitter() {
+        let mut kalman = KalmanFilter::new(0.01, 4.0);
+        kalman.correct((20, 20));
+
+        // Locations jumping two pixels back and forth around a still target.
+        for frame in 1..30 {
+            let x = if frame % 2 == 0 { 22 } else { 18 };
+            let (filtered, _) = kalman.correct((x, 20));
+            if frame > 10 {
+                assert!((filtered - 20.0).abs() < 1.0, "{filtered}");
+            }
+        }
+    }
+
+    #[test]
+    fn kalman_filter_follows_template_faster_than_radius() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD)
+            .with_radius(2)
+            .with_threshold(1e-3)
+            .with_kalman_filter(0.1, 0.5);
+
+        // Moving 4 pixels per frame, twice the radius.
+        let updates: Vec<_> = (0..10)
+            .map(|frame| {
+                let location = (4 + 4 * frame, 10 + frame);
+                let update = tracker
+                    .update(&mut matcher, self::frame(&template(), Some(location)))
+                    .unwrap();
+                assert_eq!(update.location, Some(location));
+                update
+            })
+            .collect();
+
+        // Found by searching the whole frame until the filter has learned the velocity.
+        assert!(updates[5..]
+            .iter()
+            .all(|update| update.state == TrackState::Tracked));
+    }
+
+    #[test]
+    fn reset_forgets_location() {
+        let mut matcher = TemplateMatcher::new_cpu();
+        let mut tracker = TemplateTracker::new(template(), METHOD).with_radius(2);
+
+        tracker.set_location((30, 30));
+        tracker.reset();
+        let update = tracker
+            .update(&mut matcher, frame(&template(), Some((10, 10))))
+            .unwrap();
+        assert_eq!(update.state, TrackState::Acquired);
+        assert_eq!(update.location, Some((10, 10)));
+    }
+}
@@ -0,0 +1,361 @@
+//! Matching templates at several orientations and sizes.
+
+use std::ops::Range;
+
+use crate::{scale, Image, ScaledMatch};
+
+/// Result of [match_template_rotated](crate::TemplateMatcher::match_template_rotated): the best
+/// score at each position over all angles, and the angle it was found at.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RotatedMatch {
+    pub scores: Image<'static>,
+    /// Angle of the best score at each position, in radians.
+    pub angles: Image<'static>,
+}
+
+impl RotatedMatch {
+    /// Angle of the best score at the given position, e.g. at the location of the smallest score
+    /// found with [find_extremes](crate::find_extremes).
+    pub fn angle_at(&self, x: u32, y: u32) -> f32 {
+        self.angles.data[(y * self.angles.width + x) as usize]
+    }
+
+    /// Score and angle at the given position.
+    pub fn transform_at(&self, x: u32, y: u32) -> TransformMatch {
+        TransformMatch {
+            angle: self.angle_at(x, y),
+            ..TransformMatch::at(&self.scores, x, y)
+        }
+    }
+}
+
+/// Result of [match_template_mirrored](crate::TemplateMatcher::match_template_mirrored): the best
+/// score at each position over all mirrorings, and the mirroring it was found with.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MirroredMatch {
+    pub scores: Image<'static>,
+    /// Mirroring of the best score at each position, row by row.
+    pub mirrors: Vec<Mirror>,
+}
+
+impl MirroredMatch {
+    /// Mirroring of the best score at the given position.
+    pub fn mirror_at(&self, x: u32, y: u32) -> Mirror {
+        self.mirrors[(y * self.scores.width + x) as usize]
+    }
+
+    /// Score and mirroring at the given position.
+    pub fn transform_at(&self, x: u32, y: u32) -> TransformMatch {
+        TransformMatch {
+            mirror: self.mirror_at(x, y),
+            ..TransformMatch::at(&self.scores, x, y)
+        }
+    }
+}
+
+/// Match of a template that was mirrored, scaled and rotated, in that order, along with the
+/// transform it was matched with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransformMatch {
+    /// Top-left corner of the box of the transformed template, whose size is the template's size
+    /// scaled by `scale_x` and `scale_y`. The template is rotated around the center of the box.
+    pub x: u32,
+    pub y: u32,
+    /// Clockwise rotation of the template, in radians.
+    pub angle: f32,
+    /// Horizontal and vertical scale of the template, before rotating it.
+    pub scale_x: f32,
+    pub scale_y: f32,
+    /// Mirroring of the template, before scaling it.
+    pub mirror: Mirror,
+    pub score: f32,
+}
+
+impl TransformMatch {
+    /// Match of the untransformed template with the score at the given position of a result.
+    fn at(scores: &Image<'_>, x: u32, y: u32) -> Self {
+        Self {
+            x,
+            y,
+            angle: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            mirror: Mirror::None,
+            score: scores.data[(y * scores.width + x) as usize],
+        }
+    }
+
+    /// Size of the box of the transformed template, for a template of the given size.
+    pub fn size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        scale::scaled_size(width, height, (self.scale_x, self.scale_y))
+    }
+
+    /// Maps a point of a template of the given size, in pixels from its top-left corner, to the
+    /// input.
+    pub fn map_point(&self, (width, height): (u32, u32), (x, y): (f32, f32)) -> (f32, f32) {
+        let (box_width, box_height) = self.size((width, height));
+        let mut dx = (x - width as f32 * 0.5) * box_width as f32 / width as f32;
+        let mut dy = (y - height as f32 * 0.5) * box_height as f32 / height as f32;
+        match self.mirror {
+            Mirror::None => {}
+            Mirror::Horizontal => dx = -dx,
+            Mirror::Vertical => dy = -dy,
+        }
+
+        let (sin, cos) = self.angle.sin_cos();
+        (
+            self.x as f32 + box_width as f32 * 0.5 + cos * dx - sin * dy,
+            self.y as f32 + box_height as f32 * 0.5 + sin * dx + cos * dy,
+        )
+    }
+
+    /// Corners of a template of the given size in the input: where its top-left, top-right,
+    /// bottom-right and bottom-left corners ended up.
+    pub fn corners(&self, (width, height): (u32, u32)) -> [(f32, f32); 4] {
+        let (width_f, height_f) = (width as f32, height as f32);
+        [
+            (0.0, 0.0),
+            (width_f, 0.0),
+            (width_f, height_f),
+            (0.0, height_f),
+        ]
+        .map(|corner| self.map_point((width, height), corner))
+    }
+}
+
+impl From<ScaledMatch> for TransformMatch {
+    fn from(scaled: ScaledMatch) -> Self {
+        Self {
+            x: scaled.location.0,
+            y: scaled.location.1,
+            angle: 0.0,
+            scale_x: scaled.scale_x,
+            scale_y: scaled.scale_y,
+            mirror: Mirror::None,
+            score: scaled.score,
+        }
+    }
+}
+
+/// Mirroring of a template. Mirroring both horizontally and vertically is the same as rotating by
+/// half a turn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirror {
+    #[default]
+    None,
+    /// Flipped left to right.
+    Horizontal,
+    /// Flipped upside down.
+    Vertical,
+}
+
+/// Mirroring, scale and rotation of a template.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Transform {
+    pub angle: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub mirror: Mirror,
+}
+
+impl Transform {
+    pub fn new(angle: f32, scale: f32) -> Self {
+        Self {
+            angle,
+            scale_x: scale,
+            scale_y: scale,
+            mirror: Mirror::None,
+        }
+    }
+}
+
+/// A template rotated and scaled by a transform, of which the pixels within the circle inscribed
+/// in it are matched. They cover the same part of the template at every angle, so scores of
+/// different angles are comparable.
+pub(crate) struct Variant {
+    pub transform: Transform,
+    pub width: u32,
+    pub height: u32,
+    /// Positions of the matched pixels.
+    pub pixels: Vec<(u32, u32)>,
+}
+
+impl Variant {
+    pub fn new(template_width: u32, template_height: u32, transform: Transform) -> Self {
+        let (width, height) = scale::scaled_size(
+            template_width,
+            template_height,
+            (transform.scale_x, transform.scale_y),
+        );
+        Self {
+            transform,
+            width,
+            height,
+            pixels: inscribed_pixels(width, height),
+        }
+    }
+
+    /// Variant of which all pixels are matched, for transforms without rotation, under which
+    /// the corners stay within the template.
+    pub fn full(template_width: u32, template_height: u32, transform: Transform) -> Self {
+        let (width, height) = scale::scaled_size(
+            template_width,
+            template_height,
+            (transform.scale_x, transform.scale_y),
+        );
+        Self {
+            transform,
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .collect(),
+        }
+    }
+
+    /// Number of samples that are compared at each position.
+    pub fn samples(&self, channels: u32) -> u32 {
+        self.pixels.len() as u32 * channels
+    }
+
+    /// Record of the variant in the layout of the transformed shader: its angle, the factors
+    /// from its size to the template's, its size, its range of pixels in the offsets, and its
+    /// mirroring.
+    pub fn packed(&self, template: (u32, u32), first_pixel: u32) -> [u32; 8] {
+        [
+            self.transform.angle.to_bits(),
+            (template.0 as f32 / self.width as f32).to_bits(),
+            (template.1 as f32 / self.height as f32).to_bits(),
+            self.width,
+            self.height,
+            first_pixel,
+            self.pixels.len() as u32,
+            self.transform.mirror as u32,
+        ]
+    }
+
+    /// Samples of the template transformed by this variant at its matched pixels. The template is
+    /// sampled bilinearly, like the transformed shader does.
+    pub fn samples_of(&self, template: &Image<'_>) -> Vec<f32> {
+        let channels = template.channels as usize;
+        let (center_x, center_y) = (template.width as f32 * 0.5, template.height as f32 * 0.5);
+        let inverse_scale_x = template.width as f32 / self.width as f32;
+        let inverse_scale_y = template.height as f32 / self.height as f32;
+        let (sin, cos) = self.transform.angle.sin_cos();
+
+        let clamp_x = |x: f32| (x.max(0.0) as u32).min(template.width - 1) as usize;
+        let clamp_y = |y: f32| (y.max(0.0) as u32).min(template.height - 1) as usize;
+        let sample = |x: usize, y: usize, c: usize| {
+            template.data[(y * template.width as usize + x) * channels + c]
+        };
+
+        let mut samples = Vec::with_capacity(self.pixels.len() * channels);
+        for &(x, y) in &self.pixels {
+            let dx = x as f32 + 0.5 - self.width as f32 * 0.5;
+            let dy = y as f32 + 0.5 - self.height as f32 * 0.5;
+            let mut source_x = (cos * dx + sin * dy) * inverse_scale_x;
+            let mut source_y = (cos * dy - sin * dx) * inverse_scale_y;
+            match self.transform.mirror {
+                Mirror::None => {}
+                Mirror::Horizontal => source_x = -source_x,
+                Mirror::Vertical => source_y = -source_y,
+            }
+            let (source_x, source_y) = (source_x + center_x - 0.5, source_y + center_y - 0.5);
+
+            let (left, top) = (source_x.floor(), source_y.floor());
+            let (fx, fy) = (source_x - left, source_y - top);
+            let (x0, x1) = (clamp_x(left), clamp_x(left + 1.0));
+            let (y0, y1) = (clamp_y(top), clamp_y(top + 1.0));
+
+            for c in 0..channels {
+                let upper = mix(sample(x0, y0, c), sample(x1, y0, c), fx);
+                let lower = mix(sample(x0, y1, c), sample(x1, y1, c), fx);
+                samples.push(mix(upper, lower, fy));
+            }
+        }
+        samples
+    }
+}
+
+/// Size of the result of matching the variants against an input of the given size, which has a
+/// position for every variant that fits there.
+pub(crate) fn result_size((width, height): (u32, u32), variants: &[Variant]) -> (u32, u32) {
+    let min_width = variants.iter().map(|variant| variant.width).min().unwrap();
+    let min_height = variants.iter().map(|variant| variant.height).min().unwrap();
+    (width - min_width + 1, height - min_height + 1)
+}
+
+/// Whether a position of the result of matching the variants against an input of the given size
+/// could have a variant that fits.
+pub(crate) fn fits((width, height): (u32, u32), variants: &[Variant]) -> bool {
+    variants.iter().any(|variant| variant.width <= width)
+        && variants.iter().any(|variant| variant.height <= height)
+}
+
+/// Picks the best position of a result of matching the variants, by mean difference per sample,
+/// or [None] if no variant fit anywhere.
+pub(crate) fn best_match(
+    scores: &Image<'_>,
+    indices: &[u32],
+    variants: &[Variant],
+    channels: u32,
+) -> Option<TransformMatch> {
+    let mut best: Option<(f32, TransformMatch)> = None;
+    for (i, (&score, &index)) in scores.data.iter().zip(indices).enumerate() {
+        let variant = &variants[index as usize];
+        let mean = score / variant.samples(channels) as f32;
+        if best.map_or(score.is_finite(), |(best_mean, _)| mean < best_mean) {
+            let transform = variant.transform;
+            let match_ = TransformMatch {
+                x: i as u32 % scores.width,
+                y: i as u32 / scores.width,
+                angle: transform.angle,
+                scale_x: transform.scale_x,
+                scale_y: transform.scale_y,
+                mirror: transform.mirror,
+                score,
+            };
+            best = Some((mean, match_));
+        }
+    }
+    best.map(|(_, best)| best)
+}
+
+/// Angles from the start of the range up to but excluding its end, `step` apart.
+///
+/// # Panics
+///
+/// Panics if the range is empty, or if the step isn't positive.
+pub(crate) fn angles(range: Range<f32>, step: f32) -> Vec<f32> {
+    assert!(step > 0.0, "angle step must be positive");
+    assert!(range.start < range.end, "angle range must not be empty");
+
+    let count = ((range.end - range.start) / step).ceil() as u32;
+    (0..count)
+        .map(|i| range.start + i as f32 * step)
+        .take_while(|angle| *angle < range.end)
+        .collect()
+}
+
+/// Pixels of an image within the circle inscribed in it.
+fn inscribed_pixels(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let (center_x, center_y) = (width as f32 * 0.5, height as f32 * 0.5);
+    let radius = width.min(height) as f32 * 0.5;
+
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| {
+            let dx = x as f32 + 0.5 - center_x;
+            let dy = y as f32 + 0.5 - center_y;
+            dx * dx + dy * dy <= radius * radius
+        })
+        .collect()
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
@@ -0,0 +1,285 @@
+//! Golden-image validation of the full matching pipeline.
+//!
+//! Runs bundled fixtures through upload, dispatch, readback and [find_extremes] and compares the
+//! results against precomputed golden outputs. Use this to check that a GPU/driver combination
+//! produces correct results before deploying to it.
+//!
+//! The golden outputs are computed independently of the crate by `fixtures/generate.py`, which
+//! also documents the fixture format.
+
+use std::collections::HashMap;
+
+use crate::{
+    f16, find_extremes, Error, Image, MatchJob, MatchTemplateMethod, Region, SparseTemplate,
+    TemplateMatcher,
+};
+
+/// Maximum absolute error allowed per method.
+///
+/// The default of `5e-3` allows relative errors of a few `1e-4` on the scores of the fixtures,
+/// which are up to about 15, e.g. from drivers that unpack normalized integer samples with less
+/// than full `f32` precision.
+#[derive(Clone, Debug)]
+pub struct Tolerances {
+    per_method: HashMap<MatchTemplateMethod, f32>,
+    default: f32,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            per_method: HashMap::new(),
+            default: 5e-3,
+        }
+    }
+}
+
+impl Tolerances {
+    /// Sets the tolerance for a single method.
+    pub fn with(mut self, method: MatchTemplateMethod, max_abs_error: f32) -> Self {
+        self.per_method.insert(method, max_abs_error);
+        self
+    }
+
+    /// Sets the tolerance of the methods without a tolerance of their own, and of binary matches.
+    pub fn with_default(mut self, max_abs_error: f32) -> Self {
+        self.default = max_abs_error;
+        self
+    }
+
+    pub fn get(&self, method: MatchTemplateMethod) -> f32 {
+        self.per_method
+            .get(&method)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Outcome of a single fixture.
+#[derive(Clone, Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    /// [None] for binary matches, which don't use a method.
+    pub method: Option<MatchTemplateMethod>,
+    pub max_abs_error: f32,
+    pub tolerance: f32,
+    pub expected_min_location: (u32, u32),
+    pub actual_min_location: (u32, u32),
+    pub size_matches: bool,
+    /// The error that failed the match, in which case [result](Self::result) is empty.
+    pub error: Option<Error>,
+    /// The result of the match, e.g. for comparing the results of two runs.
+    pub result: Image<'static>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+            && self.size_matches
+            && self.max_abs_error <= self.tolerance
+            && self.expected_min_location == self.actual_min_location
+    }
+}
+
+/// Results of [validate].
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    pub cases: Vec<CaseResult>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(CaseResult::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.cases.iter().filter(|case| !case.passed())
+    }
+}
+
+/// Fixture format: width, height and channels as little-endian `u32`s, followed by the values as
+/// little-endian `f32`s. See `fixtures/generate.py`.
+fn load_fixture(bytes: &[u8]) -> Image<'static> {
+    let header = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+    let (width, height, channels) = (header(0), header(1), header(2));
+
+    let data: Vec<f32> = bytes[12..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+
+    Image::with_channels(data, width, height, channels)
+}
+
+macro_rules! fixture {
+    ($name:literal) => {
+        load_fixture(include_bytes!(concat!("../fixtures/", $name, ".bin")))
+    };
+}
+
+fn run_case(
+    matcher: &mut TemplateMatcher,
+    name: &'static str,
+    method: Option<MatchTemplateMethod>,
+    expected: &Image<'_>,
+    tolerances: &Tolerances,
+    start: impl FnOnce(&mut TemplateMatcher) -> MatchJob,
+) -> CaseResult {
+    let job = start(matcher);
+    let (actual, error) = match matcher.try_wait_for_job(job) {
+        Ok(Some(actual)) => (actual, None),
+        Ok(None) => (Image::new(Vec::new(), 0, 0), None),
+        Err(error) => (Image::new(Vec::new(), 0, 0), Some(error)),
+    };
+
+    let size_matches = actual.width == expected.width && actual.height == expected.height;
+    let max_abs_error = if size_matches {
+        actual
+            .data
+            .iter()
+            .zip(expected.data.iter())
+            .map(|(a, e)| (a - e).abs())
+            .fold(0.0, f32::max)
+    } else {
+        f32::INFINITY
+    };
+
+    CaseResult {
+        name,
+        method,
+        max_abs_error,
+        tolerance: method.map_or(tolerances.default, |method| tolerances.get(method)),
+        expected_min_location: find_extremes(expected).min_value_location,
+        actual_min_location: if size_matches {
+            find_extremes(&actual).min_value_location
+        } else {
+            (0, 0)
+        },
+        size_matches,
+        error,
+        result: actual,
+    }
+}
+
+/// Runs all bundled fixtures with the given matcher.
+pub fn validate(matcher: &mut TemplateMatcher, tolerances: &Tolerances) -> ValidationReport {
+    use MatchTemplateMethod::*;
+
+    let gray_input = fixture!("gray_input");
+    let gray_template = fixture!("gray_template");
+    let rgb_input = fixture!("rgb_input");
+    let rgb_template = fixture!("rgb_template");
+
+    // 32x20 view at (4, 3) of the grayscale input.
+    let gray_view = Image::new(
+        &gray_input.data[(3 * gray_input.width + 4) as usize..],
+        32,
+        20,
+    )
+    .with_stride(gray_input.width);
+
+    // The fixtures are on the k/255 grid, so integer samples hold them exactly.
+    let convert = |image: &Image<'_>, scale: f32| -> Vec<f32> {
+        image.data.iter().map(|v| (v * scale).round()).collect()
+    };
+    let gray_u8 = |image: &Image<'_>| -> Image<'static, u8> {
+        let data = convert(image, 255.0).into_iter().map(|v| v as u8);
+        Image::new(data.collect::<Vec<_>>(), image.width, image.height)
+    };
+    let gray_u16 = |image: &Image<'_>| -> Image<'static, u16> {
+        let data = convert(image, 65535.0).into_iter().map(|v| v as u16);
+        Image::new(data.collect::<Vec<_>>(), image.width, image.height)
+    };
+    let gray_f16 = |image: &Image<'_>| -> Image<'static, f16> {
+        let data = image.data.iter().map(|&v| f16::from_f32(v));
+        Image::new(data.collect::<Vec<_>>(), image.width, image.height)
+    };
+
+    let (gray_input_u8, gray_template_u8) = (gray_u8(&gray_input), gray_u8(&gray_template));
+    let (gray_input_u16, gray_template_u16) = (gray_u16(&gray_input), gray_u16(&gray_template));
+    let (gray_input_f16, gray_template_f16) = (gray_f16(&gray_input), gray_f16(&gray_template));
+
+    let mask: Vec<bool> = fixture!("gray_mask")
+        .data
+        .iter()
+        .map(|&v| v > 0.5)
+        .collect();
+    let sparse_template = SparseTemplate::new(&gray_template, &mask);
+
+    let mut cases = Vec::new();
+    let mut case =
+        |name, method, expected: Image<'_>, start: &dyn Fn(&mut TemplateMatcher) -> MatchJob| {
+            cases.push(run_case(
+                matcher, name, method, &expected, tolerances, start,
+            ));
+        };
+
+    for (method, gray, rgb) in [
+        (
+            SumOfAbsoluteDifferences,
+            fixture!("gray_sad"),
+            fixture!("rgb_sad"),
+        ),
+        (
+            SumOfSquaredDifferences,
+            fixture!("gray_ssd"),
+            fixture!("rgb_ssd"),
+        ),
+    ] {
+        case("gray", Some(method), gray, &|m| {
+            m.match_template(&gray_input, &gray_template, method)
+        });
+        case("rgb", Some(method), rgb, &|m| {
+            m.match_template(&rgb_input, &rgb_template, method)
+        });
+    }
+
+    case(
+        "gray_view",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_view_ssd"),
+        &|m| m.match_template(&gray_view, &gray_template, SumOfSquaredDifferences),
+    );
+    case(
+        "gray_u8",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_ssd"),
+        &|m| m.match_template(&gray_input_u8, &gray_template_u8, SumOfSquaredDifferences),
+    );
+    case(
+        "gray_u16",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_ssd"),
+        &|m| m.match_template(&gray_input_u16, &gray_template_u16, SumOfSquaredDifferences),
+    );
+    case(
+        "gray_f16",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_f16_ssd"),
+        &|m| m.match_template(&gray_input_f16, &gray_template_f16, SumOfSquaredDifferences),
+    );
+    case(
+        "gray_roi",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_roi_ssd"),
+        &|m| {
+            m.match_template_in_region(
+                &gray_input,
+                &gray_template,
+                SumOfSquaredDifferences,
+                Region::new(10, 5, 30, 20),
+            )
+        },
+    );
+    case(
+        "gray_sparse",
+        Some(SumOfSquaredDifferences),
+        fixture!("gray_sparse_ssd"),
+        &|m| m.match_sparse_template(&gray_input, &sparse_template, SumOfSquaredDifferences),
+    );
+    case("gray_binary", None, fixture!("gray_binary"), &|m| {
+        m.match_template_binary(&gray_input, &gray_template, 0.5)
+    });
+
+    ValidationReport { cases }
+}
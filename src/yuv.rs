@@ -0,0 +1,65 @@
+use crate::Image;
+
+/// Layout of a planar YUV frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlanarFormat {
+    /// Full-resolution Y plane followed by an interleaved half-resolution UV plane.
+    Nv12,
+    /// Full-resolution Y plane followed by separate half-resolution U and V planes.
+    I420,
+}
+
+/// A planar YUV frame, as produced by most video decoders.
+///
+/// Only the luma (Y) plane is used for matching. Converting the frame into an [Image] borrows the
+/// luma plane without copying, and the 8-bit samples are converted to `f32` on the GPU.
+#[derive(Copy, Clone, Debug)]
+pub struct PlanarFrame<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    pub format: PlanarFormat,
+    /// Distance between the starts of consecutive luma rows, in bytes.
+    pub stride: u32,
+}
+
+impl<'a> PlanarFrame<'a> {
+    pub fn new(data: &'a [u8], width: u32, height: u32, format: PlanarFormat) -> Self {
+        Self {
+            data,
+            width,
+            height,
+            format,
+            stride: width,
+        }
+    }
+
+    /// Sets the luma row pitch for frames with padded rows.
+    pub fn with_stride(mut self, stride: u32) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    /// Returns the luma plane of the frame as a normalized grayscale image.
+    pub fn luma(&self) -> Image<'a, u8> {
+        let luma_len = (self.stride * self.height) as usize;
+        Image::new(
+            &self.data[..luma_len.min(self.data.len())],
+            self.width,
+            self.height,
+        )
+        .with_stride(self.stride)
+    }
+}
+
+impl<'a> From<PlanarFrame<'a>> for Image<'a, u8> {
+    fn from(frame: PlanarFrame<'a>) -> Self {
+        frame.luma()
+    }
+}
+
+impl<'a> From<&PlanarFrame<'a>> for Image<'a, u8> {
+    fn from(frame: &PlanarFrame<'a>) -> Self {
+        frame.luma()
+    }
+}
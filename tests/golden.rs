@@ -0,0 +1,99 @@
+#![cfg(feature = "validation")]
+
+use template_matching::{
+    validation::{validate, Tolerances, ValidationReport},
+    Engine, ImageStorage, MatchAlgorithm, Precision, TemplateMatcher, TemplateMatcherBuilder,
+};
+
+fn assert_passed(report: &ValidationReport) {
+    for case in report.failures() {
+        eprintln!("{case:?}");
+    }
+
+    assert!(report.passed());
+}
+
+#[test]
+fn golden_outputs() {
+    let mut matcher = TemplateMatcher::new();
+    assert_passed(&validate(&mut matcher, &Tolerances::default()));
+}
+
+#[test]
+fn golden_outputs_on_cpu() {
+    let mut matcher = TemplateMatcherBuilder::new()
+        .engine(Engine::Cpu)
+        .build()
+        .unwrap();
+    assert_passed(&validate(&mut matcher, &Tolerances::default()));
+}
+
+#[test]
+fn golden_outputs_with_reduced_precision() {
+    let mut matcher = TemplateMatcher::new();
+
+    // Storing samples as f16 rounds them to about three significant digits.
+    matcher.set_precision(Precision::HalfStorage);
+    assert_passed(&validate(
+        &mut matcher,
+        &Tolerances::default().with_default(0.05),
+    ));
+
+    // The fixtures are on the k/255 grid, which quantization keeps, apart from rounding the
+    // samples of the `f16` case back onto it.
+    matcher.set_precision(Precision::Quantized);
+    assert_passed(&validate(&mut matcher, &Tolerances::default()));
+}
+
+#[test]
+fn golden_outputs_with_fft() {
+    let mut matcher = TemplateMatcher::new();
+    matcher.set_algorithm(MatchAlgorithm::Fft);
+    assert_passed(&validate(
+        &mut matcher,
+        &Tolerances::default().with_default(1e-2),
+    ));
+}
+
+#[test]
+fn golden_outputs_with_texture_storage() {
+    let mut matcher = TemplateMatcher::new();
+    matcher.set_image_storage(ImageStorage::Texture);
+    assert_passed(&validate(&mut matcher, &Tolerances::default()));
+}
+
+#[test]
+fn golden_outputs_with_tiling() {
+    // Small enough bindings that the inputs and results are split into tiles.
+    let mut matcher = TemplateMatcherBuilder::new()
+        .limits(wgpu::Limits {
+            max_storage_buffer_binding_size: 2048,
+            ..wgpu::Limits::default()
+        })
+        .build()
+        .unwrap();
+    assert_passed(&validate(&mut matcher, &Tolerances::default()));
+}
+
+#[test]
+fn deterministic_outputs_are_reproducible() {
+    let mut matcher = TemplateMatcher::new();
+    matcher.set_deterministic(true);
+
+    let first = validate(&mut matcher, &Tolerances::default());
+    let second = validate(&mut matcher, &Tolerances::default());
+
+    assert_passed(&first);
+    for (first, second) in first.cases.iter().zip(&second.cases) {
+        let bits = |case: &template_matching::validation::CaseResult| -> Vec<u32> {
+            case.result.data.iter().map(|v| v.to_bits()).collect()
+        };
+        assert_eq!(
+            bits(first),
+            bits(second),
+            "{} {:?} differs between runs",
+            first.name,
+            first.method
+        );
+    }
+}